@@ -0,0 +1,64 @@
+use std::alloc::Layout;
+use std::ptr::NonNull;
+
+// With the allocator_api feature (nightly only), the pointers are generic over std's Allocator
+// trait so they can be placed in arenas and other custom allocators. Otherwise a stand-in trait
+// with the same shape is used, so the code using it is the same in both cases.
+#[cfg(feature = "allocator_api")]
+pub use std::alloc::{AllocError, Allocator, Global};
+
+/// The error returned when an allocator fails to allocate memory.
+#[cfg(not(feature = "allocator_api"))]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct AllocError;
+
+/// Stable stand-in for std::alloc::Allocator, limited to what the pointers need.
+///
+/// # Safety
+/// Memory returned by allocate must stay valid until it is passed to deallocate on the same
+/// allocator (or a clone of it), and must fit the requested layout.
+#[cfg(not(feature = "allocator_api"))]
+pub unsafe trait Allocator {
+    fn allocate(&self, layout: Layout) -> Result<NonNull<[u8]>, AllocError>;
+
+    /// # Safety
+    /// `ptr` must have been allocated by this allocator with the same `layout`.
+    unsafe fn deallocate(&self, ptr: NonNull<u8>, layout: Layout);
+}
+
+/// The global memory allocator, backed by std::alloc.
+#[cfg(not(feature = "allocator_api"))]
+#[derive(Debug, Copy, Clone, Default)]
+pub struct Global;
+
+#[cfg(not(feature = "allocator_api"))]
+unsafe impl Allocator for Global {
+    fn allocate(&self, layout: Layout) -> Result<NonNull<[u8]>, AllocError> {
+        if layout.size() == 0 {
+            // Zero-sized allocations don't need memory, only a well aligned pointer.
+            let dangling = unsafe { NonNull::new_unchecked(layout.align() as *mut u8) };
+            return Ok(NonNull::slice_from_raw_parts(dangling, 0));
+        }
+        let ptr = unsafe { std::alloc::alloc(layout) };
+        NonNull::new(ptr)
+            .map(|ptr| NonNull::slice_from_raw_parts(ptr, layout.size()))
+            .ok_or(AllocError)
+    }
+
+    unsafe fn deallocate(&self, ptr: NonNull<u8>, layout: Layout) {
+        if layout.size() != 0 {
+            unsafe { std::alloc::dealloc(ptr.as_ptr(), layout) }
+        }
+    }
+}
+
+#[cfg(not(feature = "allocator_api"))]
+unsafe impl<A: Allocator + ?Sized> Allocator for &A {
+    fn allocate(&self, layout: Layout) -> Result<NonNull<[u8]>, AllocError> {
+        (**self).allocate(layout)
+    }
+
+    unsafe fn deallocate(&self, ptr: NonNull<u8>, layout: Layout) {
+        unsafe { (**self).deallocate(ptr, layout) }
+    }
+}
@@ -1,36 +1,68 @@
+use crate::allocator::{Allocator, Global};
+use std::alloc::{Layout, handle_alloc_error};
+use std::any::Any;
 use std::marker::PhantomData;
+use std::mem::{ManuallyDrop, MaybeUninit};
 use std::ptr::NonNull;
 use std::sync::atomic::{AtomicUsize, Ordering};
 
+/// Thread-safe reference-counting pointer, the allocation is made with the allocator A,
+/// the global allocator unless Arc::new_in is used.
 #[derive(Debug)]
-pub struct Arc<T> {
+pub struct Arc<T: ?Sized, A: Allocator = Global> {
     ptr: NonNull<ArcInner<T>>,
     _marker: PhantomData<ArcInner<T>>,
+    alloc: A,
 }
 
-unsafe impl<T: Send + Sync> Send for Arc<T> {}
-unsafe impl<T: Sync + Send> Sync for Arc<T> {}
+// The last Arc can be dropped on any thread, which frees the allocation through its allocator.
+unsafe impl<T: ?Sized + Send + Sync, A: Allocator + Send> Send for Arc<T, A> {}
+unsafe impl<T: ?Sized + Sync + Send, A: Allocator + Sync> Sync for Arc<T, A> {}
 
-pub struct ArcInner<T> {
-    data: T,
+// repr(C) so the counters always come first and the layout of an unsized
+// ArcInner can be computed by hand when allocating it.
+#[repr(C)]
+pub struct ArcInner<T: ?Sized> {
     owner: AtomicUsize,
+    // Number of Weak pointers, plus one shared by all the Arc pointers while any of them exist.
+    weak: AtomicUsize,
+    // Dropped when the last Arc goes away, while Weak pointers may still keep the allocation.
+    // Possibly unsized, so it has to be the last field.
+    data: ManuallyDrop<T>,
 }
 
-unsafe impl<T: Send + Sync> Send for ArcInner<T> {}
-unsafe impl<T: Sync + Send> Sync for ArcInner<T> {}
+unsafe impl<T: ?Sized + Send + Sync> Send for ArcInner<T> {}
+unsafe impl<T: ?Sized + Sync + Send> Sync for ArcInner<T> {}
 
-impl<T> Clone for Arc<T> {
+/// Counts above this abort the process. The count can only get that high by leaking Arcs
+/// (e.g. with mem::forget), and letting it wrap to 0 would free the allocation while pointers
+/// to it are still in use. Checking after the fetch_add leaves isize::MAX of headroom, which
+/// no number of threads racing between the increment and the abort can use up.
+const MAX_REFCOUNT: usize = isize::MAX as usize;
+
+/// Value of the weak count while Arc::get_mut checks for uniqueness.
+const WEAK_LOCKED: usize = usize::MAX;
+
+/// Aborts the process if a count returned by fetch_add went past MAX_REFCOUNT.
+pub(crate) fn check_refcount(old: usize) {
+    if old > MAX_REFCOUNT {
+        std::process::abort();
+    }
+}
+
+impl<T: ?Sized, A: Allocator + Clone> Clone for Arc<T, A> {
     fn clone(&self) -> Self {
         let ptr = unsafe { self.ptr.as_ref() };
-        ptr.owner.fetch_add(1, Ordering::Relaxed);
+        check_refcount(ptr.owner.fetch_add(1, Ordering::Relaxed));
         Self {
             ptr: self.ptr,
             _marker: PhantomData,
+            alloc: self.alloc.clone(),
         }
     }
 }
 
-impl<T> std::ops::Deref for Arc<T> {
+impl<T: ?Sized, A: Allocator> std::ops::Deref for Arc<T, A> {
     type Target = T;
     fn deref(&self) -> &Self::Target {
         unsafe { &self.ptr.as_ref().data }
@@ -39,28 +71,553 @@ impl<T> std::ops::Deref for Arc<T> {
 
 impl<T> Arc<T> {
     pub fn new(data: T) -> Arc<T> {
-        let inner = ArcInner {
-            data,
-            owner: AtomicUsize::new(1),
+        Arc::new_in(data, Global)
+    }
+
+    /// Constructs a new Pin<Arc<T>>. The value is never moved out of its allocation, so it can
+    /// be pinned even if T doesn't implement Unpin.
+    pub fn pin(data: T) -> std::pin::Pin<Arc<T>> {
+        // SAFETY: Arc only hands out shared references and never moves the value.
+        unsafe { std::pin::Pin::new_unchecked(Arc::new(data)) }
+    }
+
+    /// Constructs a new Arc with uninitialized contents, so a large value can be written in
+    /// place (through Arc::get_mut) instead of being built on the stack and moved in.
+    pub fn new_uninit() -> Arc<MaybeUninit<T>> {
+        let layout = Layout::new::<ArcInner<MaybeUninit<T>>>();
+        let ptr = match Global.allocate(layout) {
+            Ok(mem) => mem.cast::<ArcInner<MaybeUninit<T>>>(),
+            Err(_) => handle_alloc_error(layout),
         };
-        let data = Box::new(inner);
-        Self {
-            ptr: unsafe { NonNull::new_unchecked(Box::into_raw(data)) },
+        // Only the counters are written, the value is left as it is.
+        unsafe {
+            (&raw mut (*ptr.as_ptr()).owner).write(AtomicUsize::new(1));
+            (&raw mut (*ptr.as_ptr()).weak).write(AtomicUsize::new(1));
+        }
+        Arc::from_inner(ptr)
+    }
+
+    /// Constructs a new Arc with contents filled with 0 bytes, which is already a valid value
+    /// for many types (integers, arrays of them, etc.).
+    pub fn new_zeroed() -> Arc<MaybeUninit<T>> {
+        let arc = Arc::new_uninit();
+        unsafe { (Arc::as_ptr(&arc) as *mut MaybeUninit<T>).write(MaybeUninit::zeroed()) };
+        arc
+    }
+
+    /// Constructs a new Arc<[T]> of `len` uninitialized elements.
+    pub fn new_uninit_slice(len: usize) -> Arc<[MaybeUninit<T>]> {
+        Arc::from_inner(Arc::<[MaybeUninit<T>]>::allocate_for_slice(len))
+    }
+
+    /// Constructs a new Arc<[T]> of `len` elements filled with 0 bytes.
+    pub fn new_zeroed_slice(len: usize) -> Arc<[MaybeUninit<T>]> {
+        let arc = Arc::new_uninit_slice(len);
+        unsafe { (Arc::as_ptr(&arc) as *mut MaybeUninit<T>).write_bytes(0, len) };
+        arc
+    }
+}
+
+impl<T, A: Allocator> Arc<MaybeUninit<T>, A> {
+    /// Converts to Arc<T, A>.
+    ///
+    /// # Safety
+    /// The value must have been fully initialized, as with MaybeUninit::assume_init.
+    pub unsafe fn assume_init(self) -> Arc<T, A> {
+        let this = ManuallyDrop::new(self);
+        Arc {
+            // MaybeUninit<T> has the same layout as T.
+            ptr: this.ptr.cast::<ArcInner<T>>(),
+            _marker: PhantomData,
+            // SAFETY: `this` is never dropped, so the allocator is moved rather than duplicated.
+            alloc: unsafe { std::ptr::read(&this.alloc) },
+        }
+    }
+}
+
+impl<T> Arc<[MaybeUninit<T>]> {
+    /// Converts to Arc<[T]>.
+    ///
+    /// # Safety
+    /// Every element must have been fully initialized, as with MaybeUninit::assume_init.
+    pub unsafe fn assume_init(self) -> Arc<[T]> {
+        // The cast keeps the length metadata of the fat pointer.
+        let ptr = self.ptr.as_ptr() as *mut ArcInner<[T]>;
+        std::mem::forget(self);
+        Arc::from_inner(unsafe { NonNull::new_unchecked(ptr) })
+    }
+}
+
+impl<T, A: Allocator> Arc<T, A> {
+    /// Constructs a new Arc<T, A> in the provided allocator, which is also used to free it.
+    pub fn new_in(data: T, alloc: A) -> Arc<T, A> {
+        let layout = Layout::new::<ArcInner<T>>();
+        let ptr = match alloc.allocate(layout) {
+            Ok(mem) => mem.cast::<ArcInner<T>>(),
+            Err(_) => handle_alloc_error(layout),
+        };
+        unsafe {
+            ptr.as_ptr().write(ArcInner {
+                owner: AtomicUsize::new(1),
+                weak: AtomicUsize::new(1),
+                data: ManuallyDrop::new(data),
+            })
+        };
+        Arc {
+            ptr,
             _marker: PhantomData,
+            alloc,
         }
     }
 }
 
-impl<T> Drop for Arc<T> {
+impl<T: Any + Send + Sync> Arc<T> {
+    /// Converts the Arc into a type-erased Arc<dyn Any + Send + Sync>, which can be turned back
+    /// with downcast.
+    pub fn into_any(this: Arc<T>) -> Arc<dyn Any + Send + Sync> {
+        // Unsizing the raw pointer attaches the vtable of T.
+        let ptr: NonNull<ArcInner<dyn Any + Send + Sync>> = this.ptr;
+        std::mem::forget(this);
+        Arc::from_inner(ptr)
+    }
+}
+
+impl Arc<dyn Any + Send + Sync> {
+    /// Attempts to downcast the Arc<dyn Any + Send + Sync> to a concrete type, giving it back
+    /// on failure.
+    pub fn downcast<T: Any + Send + Sync>(self) -> Result<Arc<T>, Self> {
+        if (*self).is::<T>() {
+            // Dropping the vtable is fine, the allocation really holds an ArcInner<T>.
+            let ptr = self.ptr.cast::<ArcInner<T>>();
+            std::mem::forget(self);
+            Ok(Arc::from_inner(ptr))
+        } else {
+            Err(self)
+        }
+    }
+}
+
+/// Offset of the data inside an ArcInner, for data with the given alignment.
+fn data_offset(align: usize) -> usize {
+    // repr(C) puts the data right after the counters, padded to its alignment.
+    Layout::new::<ArcInner<()>>().size().next_multiple_of(align)
+}
+
+impl<T> Arc<[T]> {
+    /// Allocates an ArcInner<[T]> holding `len` uninitialized elements, with both counts set to 1.
+    fn allocate_for_slice(len: usize) -> NonNull<ArcInner<[T]>> {
+        let (layout, _) = Layout::new::<ArcInner<()>>()
+            .extend(Layout::array::<T>(len).expect("Arc<[T]> is too large"))
+            .expect("Arc<[T]> is too large");
+        let layout = layout.pad_to_align();
+
+        let mem = match Global.allocate(layout) {
+            Ok(mem) => mem.cast::<T>(),
+            Err(_) => handle_alloc_error(layout),
+        };
+        // The fat pointer takes the address of the allocation and `len` as metadata.
+        let ptr = std::ptr::slice_from_raw_parts_mut(mem.as_ptr(), len) as *mut ArcInner<[T]>;
+        unsafe {
+            (&raw mut (*ptr).owner).write(AtomicUsize::new(1));
+            (&raw mut (*ptr).weak).write(AtomicUsize::new(1));
+            NonNull::new_unchecked(ptr)
+        }
+    }
+}
+
+impl<T: Clone> From<&[T]> for Arc<[T]> {
+    /// Clones the elements of the slice into a new Arc<[T]> allocation.
+    /// If cloning panics, the partially filled allocation is leaked.
+    fn from(slice: &[T]) -> Self {
+        let ptr = Arc::<[T]>::allocate_for_slice(slice.len());
+        let elems = unsafe { &raw mut (*ptr.as_ptr()).data } as *mut T;
+        for (i, item) in slice.iter().enumerate() {
+            unsafe { elems.add(i).write(item.clone()) };
+        }
+        Arc::from_inner(ptr)
+    }
+}
+
+impl<T> From<Vec<T>> for Arc<[T]> {
+    /// Moves the elements of the Vec into a new Arc<[T]> allocation.
+    fn from(mut vec: Vec<T>) -> Self {
+        let ptr = Arc::<[T]>::allocate_for_slice(vec.len());
+        unsafe {
+            let elems = &raw mut (*ptr.as_ptr()).data as *mut T;
+            std::ptr::copy_nonoverlapping(vec.as_ptr(), elems, vec.len());
+            // The elements are now owned by the Arc, only free the Vec's buffer.
+            vec.set_len(0);
+        }
+        Arc::from_inner(ptr)
+    }
+}
+
+impl From<&str> for Arc<str> {
+    fn from(s: &str) -> Self {
+        let bytes: Arc<[u8]> = Arc::from(s.as_bytes());
+        // SAFETY: str has the same layout as [u8] and the bytes were copied from a valid str.
+        // The cast keeps the length metadata of the fat pointer.
+        let ptr = unsafe { NonNull::new_unchecked(bytes.ptr.as_ptr() as *mut ArcInner<str>) };
+        std::mem::forget(bytes);
+        Arc::from_inner(ptr)
+    }
+}
+
+impl From<String> for Arc<str> {
+    /// The string data has to be copied, as the String's buffer has no room for the counters.
+    fn from(s: String) -> Self {
+        Arc::from(s.as_str())
+    }
+}
+
+impl<T: ?Sized, A: Allocator> Arc<T, A> {
+    /// Creates a new Weak pointer to this allocation.
+    pub fn downgrade(this: &Arc<T, A>) -> Weak<T, A>
+    where
+        A: Clone,
+    {
+        let inner = unsafe { this.ptr.as_ref() };
+        let mut weak = inner.weak.load(Ordering::Relaxed);
+        loop {
+            // Wait while Arc::get_mut has locked the weak count.
+            if weak == WEAK_LOCKED {
+                std::hint::spin_loop();
+                weak = inner.weak.load(Ordering::Relaxed);
+                continue;
+            }
+            check_refcount(weak);
+            // Acquire synchronizes with the unlock in get_mut, so its uses of the &mut T
+            // happen before anything done through the new Weak.
+            match inner.weak.compare_exchange_weak(
+                weak,
+                weak + 1,
+                Ordering::Acquire,
+                Ordering::Relaxed,
+            ) {
+                Ok(_) => break,
+                Err(current) => weak = current,
+            }
+        }
+        Weak {
+            ptr: this.ptr,
+            alloc: this.alloc.clone(),
+        }
+    }
+
+    /// Returns a mutable reference to the value if there are no other Arc or Weak pointers
+    /// to the same allocation, and None otherwise.
+    pub fn get_mut(this: &mut Arc<T, A>) -> Option<&mut T> {
+        let inner = unsafe { this.ptr.as_ref() };
+        // Lock the weak count, so no other Arc can downgrade while the strong count is checked:
+        // an Arc could otherwise create a Weak and go away in between, and the Weak would
+        // go unnoticed.
+        if inner
+            .weak
+            .compare_exchange(1, WEAK_LOCKED, Ordering::Acquire, Ordering::Relaxed)
+            .is_err()
+        {
+            return None;
+        }
+        let unique = inner.owner.load(Ordering::Acquire) == 1;
+        inner.weak.store(1, Ordering::Release);
+        if unique {
+            // SAFETY: we hold the only pointer to the allocation, and &mut self keeps it that way.
+            Some(unsafe { &mut (*this.ptr.as_ptr()).data })
+        } else {
+            None
+        }
+    }
+
+    /// Gets the number of strong (Arc) pointers to this allocation.
+    /// Other threads can change the count at any time, so the result is only a snapshot.
+    pub fn strong_count(this: &Arc<T, A>) -> usize {
+        unsafe { this.ptr.as_ref() }.owner.load(Ordering::Relaxed)
+    }
+
+    /// Gets the number of Weak pointers to this allocation.
+    /// Other threads can change the count at any time, so the result is only a snapshot.
+    pub fn weak_count(this: &Arc<T, A>) -> usize {
+        match unsafe { this.ptr.as_ref() }.weak.load(Ordering::Relaxed) {
+            // Only locked by get_mut when there is no Weak.
+            WEAK_LOCKED => 0,
+            // Don't report the weak reference held by the strong pointers.
+            weak => weak - 1,
+        }
+    }
+
+    /// Returns true if the two Arc pointers point to the same allocation.
+    pub fn ptr_eq(this: &Arc<T, A>, other: &Arc<T, A>) -> bool {
+        // Only compare the addresses, slice lengths and vtables are irrelevant for identity.
+        std::ptr::addr_eq(this.ptr.as_ptr(), other.ptr.as_ptr())
+    }
+
+    /// Provides a raw pointer to the value, without affecting the counts.
+    /// The pointer is valid as long as there are strong pointers to the allocation.
+    pub fn as_ptr(this: &Arc<T, A>) -> *const T {
+        // ManuallyDrop<T> is repr(transparent), so the cast keeps pointing at the value.
+        unsafe { &raw const (*this.ptr.as_ptr()).data as *const T }
+    }
+
+    /// Returns a reference to the allocator the Arc was allocated with.
+    pub fn allocator(this: &Arc<T, A>) -> &A {
+        &this.alloc
+    }
+}
+
+impl<T: ?Sized> Arc<T> {
+    /// Builds an Arc from an ArcInner allocated with the global allocator.
+    fn from_inner(ptr: NonNull<ArcInner<T>>) -> Self {
+        Arc {
+            ptr,
+            _marker: PhantomData,
+            alloc: Global,
+        }
+    }
+
+    /// Consumes the Arc and returns the pointer to the value. The strong count is not
+    /// decremented, the pointer has to be converted back with Arc::from_raw to avoid a leak.
+    pub fn into_raw(this: Arc<T>) -> *const T {
+        let ptr = Arc::as_ptr(&this);
+        std::mem::forget(this);
+        ptr
+    }
+
+    /// Constructs an Arc from a pointer returned by Arc::into_raw, taking over its strong count.
+    ///
+    /// # Safety
+    /// `ptr` must come from Arc::into_raw (for the same T), and every such pointer may only be
+    /// converted back once.
+    pub unsafe fn from_raw(ptr: *const T) -> Arc<T> {
+        // The value is still alive, so its alignment can be read through a reference.
+        let offset = data_offset(std::mem::align_of_val(unsafe { &*ptr }));
+        // Step back from the value to the counters, keeping the pointer metadata.
+        let inner = unsafe { ptr.byte_sub(offset) } as *mut ArcInner<T>;
+        Arc::from_inner(unsafe { NonNull::new_unchecked(inner) })
+    }
+
+    /// Increments the strong count of the Arc behind a pointer returned by Arc::into_raw,
+    /// e.g. before handing another copy of the pointer over an FFI boundary.
+    ///
+    /// # Safety
+    /// `ptr` must come from Arc::into_raw, and its Arc must still be alive (not converted back
+    /// and dropped) for the duration of the call.
+    pub unsafe fn increment_strong_count(ptr: *const T) {
+        // ManuallyDrop keeps the temporary Arc from decrementing the count again.
+        let this = ManuallyDrop::new(unsafe { Arc::from_raw(ptr) });
+        let _clone: ManuallyDrop<Arc<T>> = this.clone();
+    }
+
+    /// Decrements the strong count of the Arc behind a pointer returned by Arc::into_raw,
+    /// dropping the value if it was the last strong pointer.
+    ///
+    /// # Safety
+    /// `ptr` must come from Arc::into_raw, and the count being released must have been taken
+    /// by into_raw or increment_strong_count. The pointer is dangling if the count reaches 0.
+    pub unsafe fn decrement_strong_count(ptr: *const T) {
+        drop(unsafe { Arc::from_raw(ptr) });
+    }
+}
+
+#[cfg(feature = "nightly")]
+impl<T: ?Sized + std::marker::Unsize<U>, U: ?Sized, A: Allocator> std::ops::CoerceUnsized<Arc<U, A>>
+    for Arc<T, A>
+{
+}
+
+#[cfg(feature = "nightly")]
+impl<T: ?Sized + std::marker::Unsize<U>, U: ?Sized> std::ops::DispatchFromDyn<Arc<U>> for Arc<T> {}
+
+impl<T: ?Sized, A: Allocator> Drop for Arc<T, A> {
     fn drop(&mut self) {
         let inner = unsafe { self.ptr.as_ref() };
         if inner.owner.fetch_sub(1, Ordering::Release) == 1 {
+            // Synchronizes with the decrements of the other Arcs, so their uses of the
+            // value happen before it is dropped.
+            std::sync::atomic::fence(Ordering::Acquire);
+            // SAFETY: this was the last strong pointer, so nobody can reach the value anymore.
+            unsafe { ManuallyDrop::drop(&mut (*self.ptr.as_ptr()).data) };
+            // Release the weak reference collectively held by the strong pointers,
+            // which frees the allocation if no Weak is left.
+            drop(Weak {
+                ptr: self.ptr,
+                alloc: &self.alloc,
+            });
+        }
+    }
+}
+
+/// Weak is a version of Arc that holds a non-owning reference to the managed allocation.
+/// It doesn't keep the value alive, only the allocation, and has to be upgraded to an Arc
+/// to access the value, which fails once the last Arc is gone.
+pub struct Weak<T: ?Sized, A: Allocator = Global> {
+    ptr: NonNull<ArcInner<T>>,
+    alloc: A,
+}
+
+unsafe impl<T: ?Sized + Send + Sync, A: Allocator + Send> Send for Weak<T, A> {}
+unsafe impl<T: ?Sized + Sync + Send, A: Allocator + Sync> Sync for Weak<T, A> {}
+
+#[cfg(feature = "nightly")]
+impl<T: ?Sized + std::marker::Unsize<U>, U: ?Sized, A: Allocator>
+    std::ops::CoerceUnsized<Weak<U, A>> for Weak<T, A>
+{
+}
+
+#[cfg(feature = "nightly")]
+impl<T: ?Sized + std::marker::Unsize<U>, U: ?Sized> std::ops::DispatchFromDyn<Weak<U>> for Weak<T> {}
+
+impl<T: ?Sized, A: Allocator + Clone> Weak<T, A> {
+    /// Attempts to upgrade the Weak pointer to an Arc, returning None if the value has been dropped.
+    pub fn upgrade(&self) -> Option<Arc<T, A>> {
+        let inner = unsafe { self.ptr.as_ref() };
+        // A plain fetch_add could revive a value whose drop already started on another
+        // thread, so only increment a count that isn't 0.
+        let mut owner = inner.owner.load(Ordering::Relaxed);
+        loop {
+            if owner == 0 {
+                return None;
+            }
+            check_refcount(owner);
+            match inner.owner.compare_exchange_weak(
+                owner,
+                owner + 1,
+                Ordering::Acquire,
+                Ordering::Relaxed,
+            ) {
+                Ok(_) => {
+                    return Some(Arc {
+                        ptr: self.ptr,
+                        _marker: PhantomData,
+                        alloc: self.alloc.clone(),
+                    });
+                }
+                Err(current) => owner = current,
+            }
+        }
+    }
+}
+
+impl<T: ?Sized, A: Allocator + Clone> Clone for Weak<T, A> {
+    fn clone(&self) -> Self {
+        let inner = unsafe { self.ptr.as_ref() };
+        check_refcount(inner.weak.fetch_add(1, Ordering::Relaxed));
+        Weak {
+            ptr: self.ptr,
+            alloc: self.alloc.clone(),
+        }
+    }
+}
+
+impl<T: ?Sized, A: Allocator> Drop for Weak<T, A> {
+    fn drop(&mut self) {
+        let inner = unsafe { self.ptr.as_ref() };
+        if inner.weak.fetch_sub(1, Ordering::Release) == 1 {
             std::sync::atomic::fence(Ordering::Acquire);
-            unsafe { drop(Box::from_raw(self.ptr.as_ptr())) };
+            // Free ArcInner, T has already been dropped. The layout is computed from the
+            // (possibly fat) pointer, which matches the one used to allocate.
+            let layout = Layout::for_value(inner);
+            unsafe { self.alloc.deallocate(self.ptr.cast(), layout) };
+        }
+    }
+}
+
+/// An Arc that is known to be the only strong pointer to its value, so it can be mutated
+/// freely before being shared with Arc::from / UniqueArc::into_arc. Weak pointers can be
+/// created early (e.g. for a node to point back to itself), they can only be upgraded once
+/// the UniqueArc has become an Arc.
+///
+/// It can be sent to another thread when T is Send and Sync:
+/// ```
+/// use pointers::arc::UniqueArc;
+/// let unique = UniqueArc::new(0);
+/// std::thread::spawn(move || drop(unique)).join().unwrap();
+/// ```
+///
+/// But not when T isn't Sync, since a Weak made by downgrade may stay behind and upgrade
+/// once the value is shared:
+/// ```compile_fail
+/// use pointers::arc::UniqueArc;
+/// let unique = UniqueArc::new(std::cell::Cell::new(0));
+/// std::thread::spawn(move || drop(unique)).join().unwrap();
+/// ```
+pub struct UniqueArc<T: ?Sized> {
+    ptr: NonNull<ArcInner<T>>,
+    _marker: PhantomData<ArcInner<T>>,
+}
+
+// Like Arc<T>: no other pointer can access the value while it is unique, but a Weak made by
+// downgrade can be left on another thread and upgraded after into_arc.
+unsafe impl<T: ?Sized + Send + Sync> Send for UniqueArc<T> {}
+unsafe impl<T: ?Sized + Send + Sync> Sync for UniqueArc<T> {}
+
+impl<T> UniqueArc<T> {
+    pub fn new(data: T) -> UniqueArc<T> {
+        let arc = ManuallyDrop::new(Arc::new(data));
+        // A strong count of 0 makes Weak::upgrade fail until into_arc.
+        unsafe { arc.ptr.as_ref() }
+            .owner
+            .store(0, Ordering::Relaxed);
+        UniqueArc {
+            ptr: arc.ptr,
+            _marker: PhantomData,
         }
     }
 }
 
+impl<T: ?Sized> UniqueArc<T> {
+    /// Creates a new Weak pointer to this allocation, which can be upgraded once the
+    /// UniqueArc is converted into an Arc.
+    pub fn downgrade(this: &UniqueArc<T>) -> Weak<T> {
+        let inner = unsafe { this.ptr.as_ref() };
+        // No Arc exists, so Arc::get_mut can't have locked the weak count.
+        check_refcount(inner.weak.fetch_add(1, Ordering::Relaxed));
+        Weak {
+            ptr: this.ptr,
+            alloc: Global,
+        }
+    }
+
+    /// Converts the UniqueArc into a regular, shareable Arc.
+    pub fn into_arc(this: UniqueArc<T>) -> Arc<T> {
+        let this = ManuallyDrop::new(this);
+        // Release makes the writes done through the UniqueArc visible to Weak::upgrade.
+        unsafe { this.ptr.as_ref() }
+            .owner
+            .store(1, Ordering::Release);
+        Arc::from_inner(this.ptr)
+    }
+}
+
+impl<T: ?Sized> From<UniqueArc<T>> for Arc<T> {
+    fn from(unique: UniqueArc<T>) -> Arc<T> {
+        UniqueArc::into_arc(unique)
+    }
+}
+
+impl<T: ?Sized> std::ops::Deref for UniqueArc<T> {
+    type Target = T;
+    fn deref(&self) -> &T {
+        unsafe { &self.ptr.as_ref().data }
+    }
+}
+
+impl<T: ?Sized> std::ops::DerefMut for UniqueArc<T> {
+    fn deref_mut(&mut self) -> &mut T {
+        // SAFETY: Weak pointers can't reach the value while the strong count is 0.
+        unsafe { &mut (*self.ptr.as_ptr()).data }
+    }
+}
+
+impl<T: ?Sized> Drop for UniqueArc<T> {
+    fn drop(&mut self) {
+        unsafe { ManuallyDrop::drop(&mut (*self.ptr.as_ptr()).data) };
+        drop(Weak {
+            ptr: self.ptr,
+            alloc: Global,
+        });
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::Arc;
@@ -117,8 +674,7 @@ mod tests {
         let b = a.clone();
         let c = b.clone();
 
-        let inner = unsafe { a.ptr.as_ref() };
-        assert_eq!(inner.owner.load(Ordering::Relaxed), 3);
+        assert_eq!(Arc::strong_count(&a), 3);
     }
 
     #[test]
@@ -141,8 +697,7 @@ mod tests {
         }
 
         // only the original Arc should remain
-        let inner = unsafe { a.ptr.as_ref() };
-        assert_eq!(inner.owner.load(Ordering::Relaxed), 1);
+        assert_eq!(Arc::strong_count(&a), 1);
     }
 
     #[test]
@@ -175,4 +730,313 @@ mod tests {
         assert_eq!(b[1], 2);
         assert_eq!(c[1], 2);
     }
+
+    #[test]
+    fn ptr_eq_and_as_ptr() {
+        let a = Arc::new(5);
+        let b = a.clone();
+        let c = Arc::new(5);
+        assert!(Arc::ptr_eq(&a, &b));
+        assert!(!Arc::ptr_eq(&a, &c));
+        assert_eq!(Arc::as_ptr(&a), &*b as *const i32);
+        assert_eq!(unsafe { *Arc::as_ptr(&c) }, 5);
+    }
+
+    #[test]
+    fn unsized_from_slice_and_str() {
+        let a: Arc<[String]> = Arc::from(&[String::from("a"), String::from("b")][..]);
+        let b = a.clone();
+        assert_eq!(b.len(), 2);
+        assert_eq!(b[1], "b");
+
+        let v: Arc<[u64]> = Arc::from(vec![1, 2, 3]);
+        assert_eq!(*v, [1, 2, 3]);
+        let w = Arc::downgrade(&v);
+        drop(v);
+        assert!(w.upgrade().is_none());
+
+        let s: Arc<str> = Arc::from("hello");
+        let t: Arc<str> = Arc::from(String::from("hello"));
+        assert_eq!(&*s, &*t);
+        assert!(!Arc::ptr_eq(&s, &t));
+
+        let empty: Arc<[u8]> = Arc::from(Vec::new());
+        assert!(empty.is_empty());
+    }
+
+    #[test]
+    fn raw_round_trip() {
+        let a = Arc::new(String::from("raw"));
+        let ptr = Arc::into_raw(a);
+        assert_eq!(unsafe { &*ptr }, "raw");
+        unsafe { Arc::increment_strong_count(ptr) };
+        let a = unsafe { Arc::from_raw(ptr) };
+        assert_eq!(Arc::strong_count(&a), 2);
+        unsafe { Arc::decrement_strong_count(ptr) };
+        assert_eq!(Arc::strong_count(&a), 1);
+        assert_eq!(*a, "raw");
+
+        // The offset of the data depends on its alignment and the pointer metadata is kept.
+        let s: Arc<[u128]> = Arc::from(vec![7, 8]);
+        let s = unsafe { Arc::from_raw(Arc::into_raw(s)) };
+        assert_eq!(*s, [7, 8]);
+    }
+
+    #[test]
+    fn clone_overflow_aborts() {
+        use std::process::Command;
+
+        // Aborting takes the whole process down, so the overflow is triggered in a child process
+        // running only this test.
+        if std::env::var_os("POINTERS_ARC_OVERFLOW_CHILD").is_some() {
+            let a = Arc::new(0);
+            // Pretend isize::MAX + 1 Arcs were leaked.
+            unsafe { a.ptr.as_ref() }
+                .owner
+                .store(isize::MAX as usize + 1, Ordering::Relaxed);
+            let _b = a.clone();
+            unreachable!("clone must abort on overflow");
+        }
+
+        let status = Command::new(std::env::current_exe().unwrap())
+            .args(["arc::tests::clone_overflow_aborts", "--exact"])
+            .env("POINTERS_ARC_OVERFLOW_CHILD", "1")
+            .output()
+            .unwrap()
+            .status;
+        assert!(!status.success());
+        #[cfg(unix)]
+        {
+            use std::os::unix::process::ExitStatusExt;
+            assert_eq!(status.signal(), Some(6), "child should die with SIGABRT");
+        }
+    }
+
+    #[test]
+    fn downcast() {
+        let registry: Vec<Arc<dyn std::any::Any + Send + Sync>> = vec![
+            Arc::into_any(Arc::new(1_u32)),
+            Arc::into_any(Arc::new(String::from("two"))),
+        ];
+        let shared = registry[1].clone();
+        let s = shared.downcast::<String>().unwrap();
+        assert_eq!(*s, "two");
+        assert_eq!(Arc::strong_count(&s), 2);
+
+        let Err(n) = registry[0].clone().downcast::<String>() else {
+            panic!("an u32 is not a String");
+        };
+        assert_eq!(*n.downcast::<u32>().unwrap(), 1);
+    }
+
+    #[test]
+    fn pin() {
+        use std::marker::PhantomPinned;
+        use std::pin::Pin;
+        use std::sync::atomic::AtomicUsize;
+
+        // Records its own address, which must not change while pinned.
+        struct SelfAware {
+            addr: AtomicUsize,
+            _pinned: PhantomPinned,
+        }
+
+        impl SelfAware {
+            fn check(self: Pin<&Self>) -> bool {
+                let addr = &*self as *const Self as usize;
+                self.addr.swap(addr, Ordering::Relaxed) == addr
+            }
+        }
+
+        let a = Arc::pin(SelfAware {
+            addr: AtomicUsize::new(0),
+            _pinned: PhantomPinned,
+        });
+        assert!(!a.as_ref().check());
+        let b = a.clone();
+        assert!(b.as_ref().check());
+    }
+
+    #[test]
+    fn new_in() {
+        use crate::allocator::{AllocError, Allocator, Global};
+        use std::alloc::Layout;
+        use std::ptr::NonNull;
+        use std::sync::atomic::AtomicUsize;
+        use std::thread;
+
+        // Counts the live allocations, so the test can check Arc frees through it.
+        #[derive(Clone)]
+        struct CountingAlloc(std::sync::Arc<AtomicUsize>);
+
+        unsafe impl Allocator for CountingAlloc {
+            fn allocate(&self, layout: Layout) -> Result<NonNull<[u8]>, AllocError> {
+                self.0.fetch_add(1, Ordering::SeqCst);
+                Global.allocate(layout)
+            }
+
+            unsafe fn deallocate(&self, ptr: NonNull<u8>, layout: Layout) {
+                self.0.fetch_sub(1, Ordering::SeqCst);
+                unsafe { Global.deallocate(ptr, layout) }
+            }
+        }
+
+        let live = std::sync::Arc::new(AtomicUsize::new(0));
+        let a = Arc::new_in(String::from("hello"), CountingAlloc(live.clone()));
+        assert_eq!(live.load(Ordering::SeqCst), 1);
+
+        let w = Arc::downgrade(&a);
+        let b = a.clone();
+        // The last Arc is dropped on another thread, through its own copy of the allocator.
+        thread::spawn(move || assert_eq!(*b, "hello"))
+            .join()
+            .unwrap();
+        drop(a);
+        // The Weak keeps the allocation alive until it goes away.
+        assert!(w.upgrade().is_none());
+        assert_eq!(live.load(Ordering::SeqCst), 1);
+        drop(w);
+        assert_eq!(live.load(Ordering::SeqCst), 0);
+    }
+
+    #[test]
+    fn get_mut() {
+        let mut a = Arc::new(5);
+        *Arc::get_mut(&mut a).unwrap() += 1;
+        assert_eq!(*a, 6);
+
+        let b = a.clone();
+        assert!(Arc::get_mut(&mut a).is_none());
+        drop(b);
+        let w = Arc::downgrade(&a);
+        assert!(Arc::get_mut(&mut a).is_none());
+        drop(w);
+        assert!(Arc::get_mut(&mut a).is_some());
+        assert_eq!(Arc::weak_count(&a), 0);
+    }
+
+    #[test]
+    fn new_uninit_and_zeroed() {
+        let mut a = Arc::<[u64; 1024]>::new_uninit();
+        let slot = Arc::get_mut(&mut a).unwrap();
+        // Initialize in place, element by element.
+        let data = slot.as_mut_ptr() as *mut u64;
+        for i in 0..1024 {
+            unsafe { data.add(i).write(i as u64) };
+        }
+        let a = unsafe { a.assume_init() };
+        assert_eq!(a[1023], 1023);
+
+        let z = unsafe { Arc::<[u32; 16]>::new_zeroed().assume_init() };
+        assert_eq!(*z, [0; 16]);
+
+        let mut s = Arc::<String>::new_uninit_slice(3);
+        for (i, slot) in Arc::get_mut(&mut s).unwrap().iter_mut().enumerate() {
+            slot.write(i.to_string());
+        }
+        let s = unsafe { s.assume_init() };
+        assert_eq!(*s, ["0", "1", "2"]);
+
+        let zs = unsafe { Arc::<u8>::new_zeroed_slice(5).assume_init() };
+        assert_eq!(*zs, [0; 5]);
+    }
+
+    #[test]
+    fn unique_arc() {
+        use super::UniqueArc;
+
+        struct Node {
+            name: String,
+            this: Option<super::Weak<Node>>,
+        }
+
+        let mut node = UniqueArc::new(Node {
+            name: String::new(),
+            this: None,
+        });
+        let weak = UniqueArc::downgrade(&node);
+        // Mutation without any lock, while nothing can see the value yet.
+        node.name.push_str("root");
+        node.this = Some(weak.clone());
+        assert!(weak.upgrade().is_none());
+
+        let node: Arc<Node> = node.into();
+        let this = node.this.as_ref().unwrap().upgrade().unwrap();
+        assert!(Arc::ptr_eq(&this, &node));
+        assert_eq!(this.name, "root");
+        assert_eq!(Arc::strong_count(&node), 2);
+        assert_eq!(Arc::weak_count(&node), 2);
+
+        let dropped = UniqueArc::new(String::from("never shared"));
+        let weak = UniqueArc::downgrade(&dropped);
+        drop(dropped);
+        assert!(weak.upgrade().is_none());
+    }
+
+    #[test]
+    fn weak_upgrade() {
+        let a = Arc::new(String::from("value"));
+        let w = Arc::downgrade(&a);
+        let w2 = w.clone();
+        assert_eq!(Arc::weak_count(&a), 2);
+        assert_eq!(*w.upgrade().unwrap(), "value");
+        assert_eq!(Arc::strong_count(&a), 1);
+        drop(a);
+        assert!(w.upgrade().is_none());
+        assert!(w2.upgrade().is_none());
+    }
+
+    #[test]
+    fn weak_keeps_allocation_not_value() {
+        use std::sync::atomic::AtomicUsize;
+
+        struct Counter<'a>(&'a AtomicUsize);
+        impl Drop for Counter<'_> {
+            fn drop(&mut self) {
+                self.0.fetch_add(1, Ordering::SeqCst);
+            }
+        }
+
+        let d = AtomicUsize::new(0);
+        let a = Arc::new(Counter(&d));
+        let w = Arc::downgrade(&a);
+        drop(a);
+        // The value is dropped right away, the allocation once the Weak is gone too.
+        assert_eq!(d.load(Ordering::SeqCst), 1);
+        drop(w);
+        assert_eq!(d.load(Ordering::SeqCst), 1);
+    }
+
+    #[test]
+    fn concurrent_upgrades_and_drops() {
+        use std::thread;
+
+        for _ in 0..100 {
+            let a = Arc::new(vec![1, 2, 3]);
+            let handles: Vec<_> = (0..4)
+                .map(|_| {
+                    let w = Arc::downgrade(&a);
+                    thread::spawn(move || {
+                        // Either the value is still fully there, or upgrade fails.
+                        if let Some(a) = w.upgrade() {
+                            assert_eq!(*a, vec![1, 2, 3]);
+                        }
+                    })
+                })
+                .collect();
+            drop(a);
+            for h in handles {
+                h.join().unwrap();
+            }
+        }
+    }
+
+    #[cfg(feature = "nightly")]
+    #[test]
+    fn coerce_to_trait_object() {
+        let a: Arc<dyn Fn() -> i32 + Send + Sync> = Arc::new(|| 42);
+        let b = a.clone();
+        assert_eq!(a(), 42);
+        assert_eq!(b(), 42);
+    }
 }
@@ -0,0 +1,199 @@
+use crate::async_mutex::AsyncMutexGuard;
+use crate::mutex::Mutex;
+use crate::waker_list::{WakerList, WakerNode};
+use std::cell::{Cell, UnsafeCell};
+use std::future::Future;
+use std::pin::Pin;
+use std::task::{Context, Poll, Waker};
+
+/// A condition variable for AsyncMutex: lets tasks wait until another task changes the
+/// protected data and notifies them.
+///
+/// As with any condition variable, the condition should be checked again in a loop after
+/// `wait` returns, another task may have changed the data before the lock was taken back.
+pub struct AsyncCondvar {
+    waiters: Mutex<WakerList>,
+}
+
+impl AsyncCondvar {
+    pub const fn new() -> AsyncCondvar {
+        AsyncCondvar {
+            waiters: Mutex::new(WakerList::new()),
+        }
+    }
+
+    /// Releases the lock held by `guard` and waits until the condvar is notified, then takes
+    /// the lock again.
+    pub async fn wait<'a, T>(&self, guard: AsyncMutexGuard<'a, T>) -> AsyncMutexGuard<'a, T> {
+        let mutex = guard.mutex;
+        Wait {
+            condvar: self,
+            node: UnsafeCell::new(WakerNode::new(())),
+            guard: Cell::new(Some(guard)),
+            queued: Cell::new(false),
+        }
+        .await;
+        mutex.lock().await
+    }
+
+    /// Wakes up one task waiting in `wait`, if any.
+    pub fn notify_one(&self) {
+        let waker = self.waiters.lock().pop_front();
+        if let Some(waker) = waker {
+            waker.wake();
+        }
+    }
+
+    /// Wakes up all the tasks waiting in `wait`.
+    pub fn notify_all(&self) {
+        let mut waiters = self.waiters.lock();
+        let wakers: Vec<Waker> = std::iter::from_fn(|| waiters.pop_front()).collect();
+        drop(waiters);
+        wakers.into_iter().for_each(Waker::wake);
+    }
+}
+
+impl Default for AsyncCondvar {
+    fn default() -> Self {
+        AsyncCondvar::new()
+    }
+}
+
+// The future of AsyncCondvar::wait until it is notified, before the lock is taken again.
+struct Wait<'a, 'b, T> {
+    condvar: &'a AsyncCondvar,
+    // In the waiter list of the condvar while queued.
+    node: UnsafeCell<WakerNode>,
+    // Released on the first poll, once we are queued.
+    guard: Cell<Option<AsyncMutexGuard<'b, T>>>,
+    queued: Cell<bool>,
+}
+
+// The node is only accessed under the lock of the waiter list.
+unsafe impl<T: Send> Send for Wait<'_, '_, T> {}
+
+impl<T> Future for Wait<'_, '_, T> {
+    type Output = ();
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+        // The node must not move once queued, so only ever use a shared reference.
+        let this = self.into_ref().get_ref();
+        let mut waiters = this.condvar.waiters.lock();
+        let node = unsafe { &mut *this.node.get() };
+        if this.queued.get() {
+            if node.is_notified() {
+                this.queued.set(false);
+                return Poll::Ready(());
+            }
+            node.set_waker(cx.waker());
+            return Poll::Pending;
+        }
+        node.set_waker(cx.waker());
+        unsafe { waiters.push_back(node) };
+        this.queued.set(true);
+        drop(waiters);
+        // Queued before releasing the lock: a task notifying after changing the data under
+        // the lock finds us in the list.
+        drop(this.guard.take());
+        Poll::Pending
+    }
+}
+
+impl<T> Drop for Wait<'_, '_, T> {
+    fn drop(&mut self) {
+        if !self.queued.get() {
+            return;
+        }
+        let mut waiters = self.condvar.waiters.lock();
+        if unsafe { (*self.node.get()).is_notified() } {
+            // Pass the notification on, to a task that still waits for it.
+            drop(waiters);
+            self.condvar.notify_one();
+            return;
+        }
+        unsafe { waiters.remove(self.node.get()) };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::AsyncCondvar;
+    use crate::arc::Arc;
+    use crate::async_mutex::AsyncMutex;
+    use std::collections::VecDeque;
+    use std::future::Future;
+    use std::pin::pin;
+    use std::task::{Context, Waker};
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+    async fn test_producer_consumer() {
+        let queue = Arc::new((AsyncMutex::new(VecDeque::new()), AsyncCondvar::new()));
+        let consumer = {
+            let queue = queue.clone();
+            tokio::spawn(async move {
+                let (items, not_empty) = &*queue;
+                let mut sum = 0;
+                loop {
+                    let mut guard = items.lock().await;
+                    while guard.is_empty() {
+                        guard = not_empty.wait(guard).await;
+                    }
+                    match guard.pop_front().unwrap() {
+                        Some(n) => sum += n,
+                        None => return sum,
+                    }
+                }
+            })
+        };
+        let (items, not_empty) = &*queue;
+        for n in 1..=1000 {
+            items.lock().await.push_back(Some(n));
+            not_empty.notify_one();
+        }
+        items.lock().await.push_back(None);
+        not_empty.notify_one();
+        assert_eq!(consumer.await.unwrap(), 500_500);
+    }
+
+    #[tokio::test]
+    async fn test_notify_all() {
+        let state = Arc::new((AsyncMutex::new((false, 0)), AsyncCondvar::new()));
+        let handles: Vec<_> = (0..8)
+            .map(|_| {
+                let state = state.clone();
+                tokio::spawn(async move {
+                    let (mutex, cvar) = &*state;
+                    let mut guard = mutex.lock().await;
+                    guard.1 += 1;
+                    while !guard.0 {
+                        guard = cvar.wait(guard).await;
+                    }
+                })
+            })
+            .collect();
+        let (mutex, cvar) = &*state;
+        // Wait for every task to be waiting.
+        while mutex.lock().await.1 != 8 {
+            tokio::task::yield_now().await;
+        }
+        mutex.lock().await.0 = true;
+        cvar.notify_all();
+        for h in handles {
+            h.await.unwrap();
+        }
+    }
+
+    #[test]
+    fn test_dropped_wait_passes_the_notification_on() {
+        let mutex = AsyncMutex::new(());
+        let cvar = AsyncCondvar::new();
+        let mut cx = Context::from_waker(Waker::noop());
+        let mut first = Box::pin(cvar.wait(mutex.try_lock().unwrap()));
+        assert!(first.as_mut().poll(&mut cx).is_pending());
+        let mut second = pin!(cvar.wait(mutex.try_lock().unwrap()));
+        assert!(second.as_mut().poll(&mut cx).is_pending());
+        cvar.notify_one();
+        drop(first);
+        assert!(second.as_mut().poll(&mut cx).is_ready());
+    }
+}
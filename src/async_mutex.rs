@@ -1,58 +1,368 @@
-use std::cell::UnsafeCell;
-use tokio::sync::{AcquireError, Semaphore, SemaphorePermit};
+use crate::mutex::Mutex;
+use crate::timer::Sleep;
+use crate::waker_list::{WakerList, WakerNode};
+use std::cell::{Cell, UnsafeCell};
+use std::future::Future;
+use std::marker::PhantomData;
+use std::pin::{Pin, pin};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::task::{Context, Poll};
+use std::time::Duration;
 
+const LOCKED: usize = 1;
+// Set while tasks are in the waiter list, so that unlock hands the lock over to one of them.
+const HAS_WAITERS: usize = 2;
+
+/// A mutex for async code: a task waiting for the lock is suspended instead of blocking its
+/// thread, and the guard can be held across .await points.
+///
+/// It only relies on std's Waker, so it works with any executor. The waiting tasks are kept
+/// in a list of the nodes in their lock futures, and unlock hands the lock over to the first
+/// of them.
+///
+/// The lock is granted in FIFO order. While tasks wait, unlock hands the lock to the one
+/// that has waited longest without releasing it, so neither try_lock nor a new call to lock
+/// can take it first (no barging). A task therefore gets the lock once each task queued
+/// before it has held it, however many tasks arrive after it. The price is throughput under
+/// contention: the lock stays unused until the woken task gets to run.
+///
+/// Locking is cancellation safe: a lock future dropped while waiting leaves the list, and one
+/// dropped after the lock was handed over to it, before it was polled again, passes the lock
+/// on to the next waiter. Either way no waiter misses its wakeup and the lock isn't leaked.
 pub struct AsyncMutex<T> {
+    raw: RawAsyncMutex,
     value: UnsafeCell<T>,
-    locked: Semaphore,
+}
+
+// The lock itself, apart from the value, so that mapped guards can release it.
+struct RawAsyncMutex {
+    // LOCKED and HAS_WAITERS.
+    state: AtomicUsize,
+    waiters: Mutex<WakerList>,
 }
 
 unsafe impl<T: Send> Send for AsyncMutex<T> {}
-unsafe impl<T: Sync> Sync for AsyncMutex<T> {}
+unsafe impl<T: Send> Sync for AsyncMutex<T> {}
 
 impl<T> AsyncMutex<T> {
-    pub fn new(value: T) -> AsyncMutex<T> {
+    pub const fn new(value: T) -> AsyncMutex<T> {
         Self {
+            raw: RawAsyncMutex {
+                state: AtomicUsize::new(0),
+                waiters: Mutex::new(WakerList::new()),
+            },
             value: UnsafeCell::new(value),
-            locked: Semaphore::new(1),
         }
     }
 
-    async fn lock(&self) -> Result<MutexGuard<'_, T>, AcquireError> {
-        let permit = self.locked.acquire().await?;
-        Ok(MutexGuard {
+    /// Waits until the lock is available, and acquires it.
+    pub async fn lock(&self) -> AsyncMutexGuard<'_, T> {
+        Lock {
             mutex: self,
-            permit,
+            node: UnsafeCell::new(WakerNode::new(())),
+            queued: Cell::new(false),
+        }
+        .await
+    }
+
+    /// Waits for the lock for at most `timeout`, returning None if it couldn't be acquired in
+    /// time. The timeout doesn't rely on the timer of any runtime.
+    pub async fn lock_timeout(&self, timeout: Duration) -> Option<AsyncMutexGuard<'_, T>> {
+        let mut lock = pin!(self.lock());
+        let mut sleep = Sleep::new(timeout);
+        std::future::poll_fn(|cx| {
+            // A lock handed over to us right at the deadline is taken rather than passed on.
+            if let Poll::Ready(guard) = lock.as_mut().poll(cx) {
+                return Poll::Ready(Some(guard));
+            }
+            Pin::new(&mut sleep).poll(cx).map(|()| None)
         })
+        .await
+    }
+
+    /// Attempts to acquire the lock without waiting, returning None if it is already held.
+    pub fn try_lock(&self) -> Option<AsyncMutexGuard<'_, T>> {
+        self.raw
+            .try_acquire()
+            .then(|| AsyncMutexGuard { mutex: self })
+    }
+
+    /// Locks a mutex behind an Arc, returning a guard that keeps the Arc instead of
+    /// borrowing the mutex, so it can be moved into spawned tasks or stored in structs.
+    pub async fn lock_owned(self: Arc<Self>) -> OwnedAsyncMutexGuard<T> {
+        // The borrowed guard is turned into the owned one, which unlocks instead.
+        std::mem::forget(self.lock().await);
+        OwnedAsyncMutexGuard { mutex: self }
+    }
+
+    /// Like try_lock, but the guard keeps the Arc instead of borrowing the mutex.
+    pub fn try_lock_owned(self: Arc<Self>) -> Option<OwnedAsyncMutexGuard<T>> {
+        self.raw
+            .try_acquire()
+            .then(|| OwnedAsyncMutexGuard { mutex: self })
+    }
+
+    pub fn into_inner(self) -> T {
+        self.value.into_inner()
+    }
+
+    pub fn get_mut(&mut self) -> &mut T {
+        self.value.get_mut()
     }
 }
 
-pub struct MutexGuard<'a, T> {
+impl<T: Default> Default for AsyncMutex<T> {
+    fn default() -> Self {
+        AsyncMutex::new(T::default())
+    }
+}
+
+impl RawAsyncMutex {
+    fn try_acquire(&self) -> bool {
+        self.state
+            .compare_exchange(0, LOCKED, Ordering::Acquire, Ordering::Relaxed)
+            .is_ok()
+    }
+
+    fn unlock(&self) {
+        if self
+            .state
+            .compare_exchange(LOCKED, 0, Ordering::Release, Ordering::Relaxed)
+            .is_ok()
+        {
+            return;
+        }
+        // Hand the lock over to the first waiter: it stays locked.
+        let mut waiters = self.waiters.lock();
+        let Some(waker) = waiters.pop_front() else {
+            // The waiters gave up meanwhile.
+            self.state.store(0, Ordering::Release);
+            return;
+        };
+        if waiters.is_empty() {
+            self.state.store(LOCKED, Ordering::Relaxed);
+        }
+        drop(waiters);
+        waker.wake();
+    }
+}
+
+// The future of AsyncMutex::lock.
+struct Lock<'a, T> {
     mutex: &'a AsyncMutex<T>,
-    permit: SemaphorePermit<'a>,
+    // In the waiter list of the mutex while queued.
+    node: UnsafeCell<WakerNode>,
+    queued: Cell<bool>,
+}
+
+// The node is only accessed under the lock of the waiter list.
+unsafe impl<T: Send> Send for Lock<'_, T> {}
+
+impl<'a, T> Future for Lock<'a, T> {
+    type Output = AsyncMutexGuard<'a, T>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        // The node must not move once queued, so only ever use a shared reference.
+        let this = self.into_ref().get_ref();
+        let mutex = this.mutex;
+        if !this.queued.get() && mutex.raw.try_acquire() {
+            return Poll::Ready(AsyncMutexGuard { mutex });
+        }
+        let mut waiters = mutex.raw.waiters.lock();
+        let node = unsafe { &mut *this.node.get() };
+        if this.queued.get() {
+            if node.is_notified() {
+                this.queued.set(false);
+                return Poll::Ready(AsyncMutexGuard { mutex });
+            }
+            node.set_waker(cx.waker());
+            return Poll::Pending;
+        }
+        // Queue up, unless the lock was released meanwhile. HAS_WAITERS is only set while
+        // the lock is held, so the unlock can't miss us.
+        let mut state = mutex.raw.state.load(Ordering::Relaxed);
+        loop {
+            let (new_state, ordering) = if state & LOCKED == 0 {
+                (state | LOCKED, Ordering::Acquire)
+            } else {
+                (state | HAS_WAITERS, Ordering::Relaxed)
+            };
+            match mutex.raw.state.compare_exchange_weak(
+                state,
+                new_state,
+                ordering,
+                Ordering::Relaxed,
+            ) {
+                Ok(_) if state & LOCKED == 0 => return Poll::Ready(AsyncMutexGuard { mutex }),
+                Ok(_) => break,
+                Err(actual) => state = actual,
+            }
+        }
+        node.set_waker(cx.waker());
+        unsafe { waiters.push_back(node) };
+        this.queued.set(true);
+        Poll::Pending
+    }
+}
+
+impl<T> Drop for Lock<'_, T> {
+    fn drop(&mut self) {
+        if !self.queued.get() {
+            return;
+        }
+        let mut waiters = self.mutex.raw.waiters.lock();
+        if unsafe { (*self.node.get()).is_notified() } {
+            // The lock was handed over to us, pass it on.
+            drop(waiters);
+            self.mutex.raw.unlock();
+            return;
+        }
+        unsafe { waiters.remove(self.node.get()) };
+        if waiters.is_empty() {
+            self.mutex
+                .raw
+                .state
+                .fetch_and(!HAS_WAITERS, Ordering::Relaxed);
+        }
+    }
+}
+
+/// The guard of a locked AsyncMutex, which releases the lock when dropped.
+pub struct AsyncMutexGuard<'a, T> {
+    pub(crate) mutex: &'a AsyncMutex<T>,
+}
+
+unsafe impl<T: Sync> Sync for AsyncMutexGuard<'_, T> {}
+
+impl<'a, T> AsyncMutexGuard<'a, T> {
+    /// Makes a guard for a part of the locked value, e.g. one field of a struct, so that an
+    /// async accessor can hand out that part without the rest of the value.
+    pub fn map<U: ?Sized>(
+        this: AsyncMutexGuard<'a, T>,
+        f: impl FnOnce(&mut T) -> &mut U,
+    ) -> MappedAsyncMutexGuard<'a, U> {
+        let mutex = this.mutex;
+        let value = f(unsafe { &mut *mutex.value.get() });
+        std::mem::forget(this);
+        MappedAsyncMutexGuard {
+            raw: &mutex.raw,
+            value,
+            _marker: PhantomData,
+        }
+    }
+}
+
+impl<T> std::ops::Deref for AsyncMutexGuard<'_, T> {
+    type Target = T;
+    fn deref(&self) -> &Self::Target {
+        unsafe { &*self.mutex.value.get() }
+    }
+}
+
+impl<T> std::ops::DerefMut for AsyncMutexGuard<'_, T> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        unsafe { &mut *self.mutex.value.get() }
+    }
+}
+
+impl<T> Drop for AsyncMutexGuard<'_, T> {
+    fn drop(&mut self) {
+        self.mutex.raw.unlock();
+    }
+}
+
+/// A guard for a part of the value of an AsyncMutex, made by AsyncMutexGuard::map. It keeps
+/// the whole mutex locked.
+pub struct MappedAsyncMutexGuard<'a, T: ?Sized> {
+    raw: &'a RawAsyncMutex,
+    value: *mut T,
+    _marker: PhantomData<&'a mut T>,
+}
+
+unsafe impl<T: ?Sized + Send> Send for MappedAsyncMutexGuard<'_, T> {}
+unsafe impl<T: ?Sized + Sync> Sync for MappedAsyncMutexGuard<'_, T> {}
+
+impl<'a, T: ?Sized> MappedAsyncMutexGuard<'a, T> {
+    /// Narrows the guard down further to a part of the mapped value.
+    pub fn map<U: ?Sized>(
+        this: MappedAsyncMutexGuard<'a, T>,
+        f: impl FnOnce(&mut T) -> &mut U,
+    ) -> MappedAsyncMutexGuard<'a, U> {
+        let guard = MappedAsyncMutexGuard {
+            raw: this.raw,
+            value: f(unsafe { &mut *this.value }),
+            _marker: PhantomData,
+        };
+        std::mem::forget(this);
+        guard
+    }
 }
 
-unsafe impl<T: Sync> Sync for MutexGuard<'_, T> {}
+impl<T: ?Sized> std::ops::Deref for MappedAsyncMutexGuard<'_, T> {
+    type Target = T;
+    fn deref(&self) -> &Self::Target {
+        unsafe { &*self.value }
+    }
+}
+
+impl<T: ?Sized> std::ops::DerefMut for MappedAsyncMutexGuard<'_, T> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        unsafe { &mut *self.value }
+    }
+}
+
+impl<T: ?Sized> Drop for MappedAsyncMutexGuard<'_, T> {
+    fn drop(&mut self) {
+        self.raw.unlock();
+    }
+}
+
+/// A guard returned by AsyncMutex::lock_owned, which holds the lock as long as it lives.
+/// It has no lifetime, so it can be moved into spawned tasks.
+pub struct OwnedAsyncMutexGuard<T> {
+    mutex: Arc<AsyncMutex<T>>,
+}
+
+unsafe impl<T: Send + Sync> Sync for OwnedAsyncMutexGuard<T> {}
+
+impl<T> OwnedAsyncMutexGuard<T> {
+    /// Returns the Arc of the locked mutex.
+    pub fn mutex(this: &OwnedAsyncMutexGuard<T>) -> &Arc<AsyncMutex<T>> {
+        &this.mutex
+    }
+}
 
-impl<T> std::ops::Deref for MutexGuard<'_, T> {
+impl<T> std::ops::Deref for OwnedAsyncMutexGuard<T> {
     type Target = T;
     fn deref(&self) -> &Self::Target {
         unsafe { &*self.mutex.value.get() }
     }
 }
 
-impl<T> std::ops::DerefMut for MutexGuard<'_, T> {
+impl<T> std::ops::DerefMut for OwnedAsyncMutexGuard<T> {
     fn deref_mut(&mut self) -> &mut Self::Target {
         unsafe { &mut *self.mutex.value.get() }
     }
 }
 
+impl<T> Drop for OwnedAsyncMutexGuard<T> {
+    fn drop(&mut self) {
+        self.mutex.raw.unlock();
+    }
+}
+
 #[cfg(test)]
 mod tests {
-    use super::AsyncMutex;
+    use super::{AsyncMutex, AsyncMutexGuard, MappedAsyncMutexGuard, OwnedAsyncMutexGuard};
     use crate::arc::Arc;
+    use std::collections::VecDeque;
+    use std::future::Future;
+    use std::pin::pin;
+    use std::sync::Barrier;
+    use std::task::{Context, Poll, Waker};
     use std::thread;
-    use std::thread::sleep;
-    use std::time::{Duration, SystemTime};
+    use std::time::{Duration, Instant, SystemTime};
 
     #[tokio::test]
     async fn test_async_mutex() {
@@ -60,11 +370,269 @@ mod tests {
         let c_mutex = Arc::clone(&mutex);
 
         tokio::spawn(async move {
-            *c_mutex.lock().await.unwrap() = 10;
+            *c_mutex.lock().await = 10;
+        })
+        .await
+        .unwrap();
+        assert_eq!(*mutex.lock().await, 10);
+    }
+
+    #[test]
+    fn test_lock_waits_for_unlock() {
+        let mutex = AsyncMutex::new(0);
+        let mut cx = Context::from_waker(Waker::noop());
+        let guard = mutex.try_lock().unwrap();
+        assert!(mutex.try_lock().is_none());
+        let mut first = pin!(mutex.lock());
+        let mut second = pin!(mutex.lock());
+        assert!(first.as_mut().poll(&mut cx).is_pending());
+        assert!(second.as_mut().poll(&mut cx).is_pending());
+        drop(guard);
+        // Handed over to the first waiter, still locked for the others.
+        assert!(mutex.try_lock().is_none());
+        let Poll::Ready(mut guard) = first.as_mut().poll(&mut cx) else {
+            panic!("the lock was handed over");
+        };
+        *guard += 1;
+        assert!(second.as_mut().poll(&mut cx).is_pending());
+        drop(guard);
+        assert!(second.as_mut().poll(&mut cx).is_ready());
+    }
+
+    #[test]
+    fn test_fifo_bounded_waiting() {
+        let mutex = AsyncMutex::new(Vec::new());
+        let mut cx = Context::from_waker(Waker::noop());
+        let mut guard = mutex.try_lock().unwrap();
+        let mut waiters = VecDeque::new();
+        for arrival in 0..200 {
+            let mut waiter = Box::pin(mutex.lock());
+            assert!(waiter.as_mut().poll(&mut cx).is_pending());
+            waiters.push_back((arrival, waiter));
+            if arrival % 2 == 0 {
+                continue;
+            }
+            // Tasks arrive twice as fast as the lock is released: the oldest waiter still
+            // gets it, and nobody takes it in between.
+            drop(guard);
+            assert!(mutex.try_lock().is_none());
+            for (_, waiter) in waiters.iter_mut().skip(1) {
+                assert!(waiter.as_mut().poll(&mut cx).is_pending());
+            }
+            let (first, mut waiter) = waiters.pop_front().unwrap();
+            let Poll::Ready(next) = waiter.as_mut().poll(&mut cx) else {
+                panic!("the lock goes to the oldest waiter");
+            };
+            guard = next;
+            guard.push(first);
+        }
+        while let Some((first, mut waiter)) = waiters.pop_front() {
+            drop(guard);
+            let Poll::Ready(next) = waiter.as_mut().poll(&mut cx) else {
+                panic!("the lock goes to the oldest waiter");
+            };
+            guard = next;
+            guard.push(first);
+        }
+        assert!(guard.iter().copied().eq(0..200));
+    }
+
+    #[test]
+    fn test_dropped_lock_future() {
+        let mutex = AsyncMutex::new(());
+        let mut cx = Context::from_waker(Waker::noop());
+        let guard = mutex.try_lock().unwrap();
+        let mut waiter = Box::pin(mutex.lock());
+        assert!(waiter.as_mut().poll(&mut cx).is_pending());
+        // Leaves the list when dropped while waiting.
+        drop(waiter);
+        drop(guard);
+        drop(mutex.try_lock().unwrap());
+        // And passes the lock on when dropped after it was handed over.
+        let guard = mutex.try_lock().unwrap();
+        let mut waiter = Box::pin(mutex.lock());
+        assert!(waiter.as_mut().poll(&mut cx).is_pending());
+        drop(guard);
+        drop(waiter);
+        assert!(mutex.try_lock().is_some());
+    }
+
+    #[test]
+    fn test_cancellation_races_unlock() {
+        let mutex = AsyncMutex::new(());
+        let mut cx = Context::from_waker(Waker::noop());
+        let barrier = Barrier::new(2);
+        for _ in 0..1000 {
+            let guard = mutex.try_lock().unwrap();
+            let mut waiter = Box::pin(mutex.lock());
+            assert!(waiter.as_mut().poll(&mut cx).is_pending());
+            thread::scope(|s| {
+                s.spawn(|| {
+                    barrier.wait();
+                    drop(guard);
+                });
+                barrier.wait();
+                drop(waiter);
+            });
+            // Whether the waiter left before or after the lock was handed over to it, the
+            // lock is free again.
+            assert!(mutex.try_lock().is_some());
+        }
+    }
+
+    #[tokio::test]
+    async fn test_lock_timeout() {
+        let mutex = Arc::new(AsyncMutex::new(0));
+        let guard = mutex.lock().await;
+        let start = Instant::now();
+        assert!(
+            mutex
+                .lock_timeout(Duration::from_millis(20))
+                .await
+                .is_none()
+        );
+        assert!(start.elapsed() >= Duration::from_millis(20));
+        // The timed out waiter left the list: the unlock doesn't hand the lock to it.
+        drop(guard);
+        drop(mutex.try_lock().unwrap());
+
+        let guard = mutex.lock().await;
+        let c_mutex = mutex.clone();
+        let waiter = tokio::spawn(async move {
+            *c_mutex.lock_timeout(Duration::from_secs(10)).await.unwrap() += 1;
+        });
+        tokio::time::sleep(Duration::from_millis(10)).await;
+        drop(guard);
+        waiter.await.unwrap();
+        assert_eq!(*mutex.lock().await, 1);
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn test_timeouts_race_unlocks() {
+        let mutex = Arc::new(AsyncMutex::new(0usize));
+        let handles: Vec<_> = (0..8)
+            .map(|_| {
+                let m = mutex.clone();
+                tokio::spawn(async move {
+                    let mut acquired = 0;
+                    for _ in 0..200 {
+                        if let Some(mut guard) = m.lock_timeout(Duration::from_micros(50)).await {
+                            *guard += 1;
+                            acquired += 1;
+                            tokio::task::yield_now().await;
+                        }
+                    }
+                    acquired
+                })
+            })
+            .collect();
+        let mut acquired = 0;
+        for h in handles {
+            acquired += h.await.unwrap();
+        }
+        assert_eq!(*mutex.try_lock().unwrap(), acquired);
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn test_tasks_on_several_threads() {
+        let mutex = Arc::new(AsyncMutex::new(0usize));
+        let handles: Vec<_> = (0..8)
+            .map(|_| {
+                let m = mutex.clone();
+                tokio::spawn(async move {
+                    for _ in 0..1000 {
+                        let mut guard = m.lock().await;
+                        *guard += 1;
+                        tokio::task::yield_now().await;
+                    }
+                })
+            })
+            .collect();
+        for h in handles {
+            h.await.unwrap();
+        }
+        assert_eq!(*mutex.lock().await, 8000);
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+    async fn test_lock_owned() {
+        // A guard stored in a struct and moved into another task.
+        struct Job {
+            counter: OwnedAsyncMutexGuard<usize>,
+        }
+        let mutex = std::sync::Arc::new(AsyncMutex::new(0));
+        let job = Job {
+            counter: mutex.clone().lock_owned().await,
+        };
+        assert!(mutex.clone().try_lock_owned().is_none());
+        let waiter = tokio::spawn(mutex.clone().lock_owned());
+        tokio::spawn(async move {
+            let mut job = job;
+            *job.counter += 1;
         })
         .await
         .unwrap();
-        assert_eq!(*mutex.lock().await.unwrap(), 10);
+        let guard = waiter.await.unwrap();
+        assert!(std::sync::Arc::ptr_eq(
+            OwnedAsyncMutexGuard::mutex(&guard),
+            &mutex
+        ));
+        assert_eq!(*guard, 1);
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+    async fn test_map() {
+        struct Server {
+            name: String,
+            stats: (usize, Vec<u32>),
+        }
+        // An async accessor exposing one field of the protected state.
+        async fn latencies(server: &AsyncMutex<Server>) -> MappedAsyncMutexGuard<'_, Vec<u32>> {
+            let stats = AsyncMutexGuard::map(server.lock().await, |s| &mut s.stats);
+            MappedAsyncMutexGuard::map(stats, |(_, latencies)| latencies)
+        }
+        let server = Arc::new(AsyncMutex::new(Server {
+            name: "api".to_string(),
+            stats: (0, vec![]),
+        }));
+        let c_server = server.clone();
+        tokio::spawn(async move {
+            let mut latencies = latencies(&c_server).await;
+            // Still locked as a whole.
+            assert!(c_server.try_lock().is_none());
+            latencies.push(12);
+        })
+        .await
+        .unwrap();
+        let server = server.lock().await;
+        assert_eq!(server.name, "api");
+        assert_eq!(server.stats.1, [12]);
+    }
+
+    #[test]
+    fn test_under_smol() {
+        // Nothing ties the mutex or its timeouts to tokio.
+        smol::block_on(async {
+            let mutex = Arc::new(AsyncMutex::new(0usize));
+            let tasks: Vec<_> = (0..8)
+                .map(|_| {
+                    let m = mutex.clone();
+                    smol::spawn(async move {
+                        for _ in 0..100 {
+                            let mut guard = m.lock().await;
+                            *guard += 1;
+                            smol::future::yield_now().await;
+                        }
+                    })
+                })
+                .collect();
+            for task in tasks {
+                task.await;
+            }
+            let guard = mutex.lock().await;
+            assert!(mutex.lock_timeout(Duration::from_millis(5)).await.is_none());
+            assert_eq!(*guard, 800);
+        });
     }
 
     #[tokio::test]
@@ -77,7 +645,7 @@ mod tests {
             let m = mutex.clone();
             handles.push(tokio::spawn(async move {
                 for _ in 0..100 {
-                    let mut guard = m.lock().await.unwrap();
+                    let mut guard = m.lock().await;
                     tokio::time::sleep(Duration::from_millis(5)).await;
                     *guard += 1;
                 }
@@ -87,7 +655,7 @@ mod tests {
         for h in handles {
             h.await.unwrap();
         }
-        assert_eq!(*mutex.lock().await.unwrap(), 4000);
+        assert_eq!(*mutex.lock().await, 4000);
         println!(
             "Time taken in my async Mutex: {}ms",
             time.elapsed().unwrap().as_millis()
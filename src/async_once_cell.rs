@@ -0,0 +1,195 @@
+use crate::async_mutex::AsyncMutex;
+use crate::once_lock::OnceLock;
+use std::cell::UnsafeCell;
+use std::future::Future;
+
+/// A cell which can be written to only once, initialized by async code, e.g. a connection
+/// opened on first use and then shared by all tasks.
+///
+/// When several tasks call get_or_init at the same time, only one of them runs its future,
+/// the others wait for its value. If that future is dropped before it completes, or panics,
+/// the next waiting task runs its own.
+pub struct AsyncOnceCell<T> {
+    value: OnceLock<T>,
+    // Held by the task initializing the value.
+    init: AsyncMutex<()>,
+}
+
+impl<T> AsyncOnceCell<T> {
+    pub const fn new() -> Self {
+        AsyncOnceCell {
+            value: OnceLock::new(),
+            init: AsyncMutex::new(()),
+        }
+    }
+
+    /// Gets a reference to the value, or None if the cell isn't initialized yet.
+    pub fn get(&self) -> Option<&T> {
+        self.value.get()
+    }
+
+    /// Gets the value, initializing it with the future returned by f if no task has done it
+    /// yet.
+    pub async fn get_or_init<F, Fut>(&self, f: F) -> &T
+    where
+        F: FnOnce() -> Fut,
+        Fut: Future<Output = T>,
+    {
+        if let Some(value) = self.get() {
+            return value;
+        }
+        let _guard = self.init.lock().await;
+        // Another task may have initialized it while we waited.
+        if let Some(value) = self.get() {
+            return value;
+        }
+        let value = f().await;
+        assert!(self.value.set(value).is_ok());
+        self.get().unwrap()
+    }
+
+    /// Sets the value, giving it back as Err if the cell was already initialized. Waits for a
+    /// task initializing it.
+    pub async fn set(&self, value: T) -> Result<(), T> {
+        let mut value = Some(value);
+        self.get_or_init(|| async { value.take().unwrap() }).await;
+        match value {
+            None => Ok(()),
+            Some(value) => Err(value),
+        }
+    }
+
+    pub fn into_inner(self) -> Option<T> {
+        self.value.into_inner()
+    }
+}
+
+impl<T> Default for AsyncOnceCell<T> {
+    fn default() -> Self {
+        AsyncOnceCell::new()
+    }
+}
+
+impl<T: std::fmt::Debug> std::fmt::Debug for AsyncOnceCell<T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.get() {
+            Some(value) => f.debug_tuple("AsyncOnceCell").field(value).finish(),
+            None => f.write_str("AsyncOnceCell(<uninit>)"),
+        }
+    }
+}
+
+/// A value which is initialized on first access, by the future of the closure given to new.
+/// If that future is dropped before it completes, or panics, the AsyncLazy is poisoned, and
+/// later accesses panic.
+pub struct AsyncLazy<T, F> {
+    cell: AsyncOnceCell<T>,
+    // Only taken under the lock of the cell.
+    init: UnsafeCell<Option<F>>,
+}
+
+unsafe impl<T: Send + Sync, F: Send> Sync for AsyncLazy<T, F> {}
+
+impl<T, F, Fut> AsyncLazy<T, F>
+where
+    F: FnOnce() -> Fut,
+    Fut: Future<Output = T>,
+{
+    pub const fn new(init: F) -> Self {
+        AsyncLazy {
+            cell: AsyncOnceCell::new(),
+            init: UnsafeCell::new(Some(init)),
+        }
+    }
+
+    /// Forces the evaluation of the lazy value and returns a reference to it.
+    pub async fn force(&self) -> &T {
+        self.cell
+            .get_or_init(|| {
+                // SAFETY: get_or_init only calls us with the lock of the cell held.
+                match unsafe { (*self.init.get()).take() } {
+                    Some(init) => init(),
+                    None => panic!("AsyncLazy instance has previously been poisoned"),
+                }
+            })
+            .await
+    }
+
+    /// Gets a reference to the value, or None if it isn't initialized yet.
+    pub fn get(&self) -> Option<&T> {
+        self.cell.get()
+    }
+}
+
+impl<T: std::fmt::Debug, F> std::fmt::Debug for AsyncLazy<T, F> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.cell.get() {
+            Some(value) => f.debug_tuple("AsyncLazy").field(value).finish(),
+            None => f.write_str("AsyncLazy(<uninit>)"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{AsyncLazy, AsyncOnceCell};
+    use crate::arc::Arc;
+    use std::future::Future;
+    use std::pin::pin;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::task::{Context, Waker};
+    use std::time::Duration;
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+    async fn test_concurrent_initializers() {
+        let cell = Arc::new(AsyncOnceCell::new());
+        let calls = Arc::new(AtomicUsize::new(0));
+        let handles: Vec<_> = (0..8)
+            .map(|i| {
+                let (cell, calls) = (cell.clone(), calls.clone());
+                tokio::spawn(async move {
+                    let value = cell
+                        .get_or_init(|| async {
+                            calls.fetch_add(1, Ordering::Relaxed);
+                            tokio::time::sleep(Duration::from_millis(20)).await;
+                            i
+                        })
+                        .await;
+                    *value
+                })
+            })
+            .collect();
+        let mut values = vec![];
+        for h in handles {
+            values.push(h.await.unwrap());
+        }
+        assert_eq!(calls.load(Ordering::Relaxed), 1);
+        assert!(values.iter().all(|&v| v == values[0]));
+        assert_eq!(cell.set(100).await, Err(100));
+    }
+
+    #[test]
+    fn test_dropped_initializer() {
+        let cell = AsyncOnceCell::new();
+        let mut cx = Context::from_waker(Waker::noop());
+        let mut first = Box::pin(cell.get_or_init(std::future::pending));
+        assert!(first.as_mut().poll(&mut cx).is_pending());
+        let mut second = pin!(cell.get_or_init(|| async { 2 }));
+        assert!(second.as_mut().poll(&mut cx).is_pending());
+        // The next task initializes it instead.
+        drop(first);
+        assert!(second.as_mut().poll(&mut cx).is_ready());
+        assert_eq!(format!("{cell:?}"), "AsyncOnceCell(2)");
+    }
+
+    #[tokio::test]
+    async fn test_async_lazy() {
+        let connection = Arc::new(AsyncLazy::new(|| async { "connected".to_string() }));
+        assert!(connection.get().is_none());
+        let c_connection = connection.clone();
+        let task = tokio::spawn(async move { c_connection.force().await.len() });
+        assert_eq!(connection.force().await, "connected");
+        assert_eq!(task.await.unwrap(), 9);
+        assert_eq!(format!("{:?}", *connection), r#"AsyncLazy("connected")"#);
+    }
+}
@@ -0,0 +1,288 @@
+use crate::mutex::Mutex;
+use crate::waker_list::{WakerList, WakerNode};
+use std::cell::{Cell, UnsafeCell};
+use std::future::Future;
+use std::pin::Pin;
+use std::task::{Context, Poll, Waker};
+
+/// A counting semaphore for async code: a pool of permits that tasks wait for, e.g. to bound
+/// the number of requests in flight.
+///
+/// Permits go to the waiting tasks in FIFO order: a task asking for many permits isn't
+/// overtaken by tasks asking for fewer, which would otherwise starve it. A permit is
+/// returned to the semaphore when its AsyncSemaphorePermit is dropped.
+pub struct AsyncSemaphore {
+    state: Mutex<State>,
+}
+
+struct State {
+    permits: usize,
+    // Each node carries the number of permits its task waits for.
+    waiters: WakerList<usize>,
+}
+
+impl AsyncSemaphore {
+    pub const fn new(permits: usize) -> AsyncSemaphore {
+        AsyncSemaphore {
+            state: Mutex::new(State {
+                permits,
+                waiters: WakerList::new(),
+            }),
+        }
+    }
+
+    /// Waits for a permit, and takes it.
+    pub async fn acquire(&self) -> AsyncSemaphorePermit<'_> {
+        self.acquire_many(1).await
+    }
+
+    /// Waits until `permits` permits are available, and takes them all at once.
+    pub async fn acquire_many(&self, permits: usize) -> AsyncSemaphorePermit<'_> {
+        Acquire {
+            semaphore: self,
+            node: UnsafeCell::new(WakerNode::new(permits)),
+            queued: Cell::new(false),
+        }
+        .await
+    }
+
+    /// Takes a permit if one is available right away and no task is waiting.
+    pub fn try_acquire(&self) -> Option<AsyncSemaphorePermit<'_>> {
+        self.try_acquire_many(1)
+    }
+
+    /// Takes `permits` permits if they are available right away and no task is waiting.
+    pub fn try_acquire_many(&self, permits: usize) -> Option<AsyncSemaphorePermit<'_>> {
+        let mut state = self.state.lock();
+        if !state.waiters.is_empty() || state.permits < permits {
+            return None;
+        }
+        state.permits -= permits;
+        Some(AsyncSemaphorePermit {
+            semaphore: self,
+            permits,
+        })
+    }
+
+    /// Adds permits to the semaphore, waking the tasks they are enough for.
+    pub fn add_permits(&self, permits: usize) {
+        let mut state = self.state.lock();
+        state.permits += permits;
+        let wakers = state.grant();
+        drop(state);
+        wakers.into_iter().for_each(Waker::wake);
+    }
+
+    pub fn available_permits(&self) -> usize {
+        self.state.lock().permits
+    }
+}
+
+impl State {
+    // Hands permits to the waiters in order, as long as there are enough for the first one,
+    // and returns the wakers of the tasks to wake.
+    fn grant(&mut self) -> Vec<Waker> {
+        let mut wakers = Vec::new();
+        while let Some(&wanted) = self.waiters.front() {
+            if self.permits < wanted {
+                break;
+            }
+            self.permits -= wanted;
+            wakers.extend(self.waiters.pop_front());
+        }
+        wakers
+    }
+}
+
+// The future of AsyncSemaphore::acquire_many.
+struct Acquire<'a> {
+    semaphore: &'a AsyncSemaphore,
+    // In the waiter list of the semaphore while queued.
+    node: UnsafeCell<WakerNode<usize>>,
+    queued: Cell<bool>,
+}
+
+// The node is only accessed under the lock of the waiter list.
+unsafe impl Send for Acquire<'_> {}
+
+impl<'a> Future for Acquire<'a> {
+    type Output = AsyncSemaphorePermit<'a>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        // The node must not move once queued, so only ever use a shared reference.
+        let this = self.into_ref().get_ref();
+        let semaphore = this.semaphore;
+        let mut state = semaphore.state.lock();
+        let node = unsafe { &mut *this.node.get() };
+        let permits = *node.value();
+        if this.queued.get() {
+            if node.is_notified() {
+                this.queued.set(false);
+                return Poll::Ready(AsyncSemaphorePermit { semaphore, permits });
+            }
+            node.set_waker(cx.waker());
+            return Poll::Pending;
+        }
+        if state.waiters.is_empty() && state.permits >= permits {
+            state.permits -= permits;
+            return Poll::Ready(AsyncSemaphorePermit { semaphore, permits });
+        }
+        node.set_waker(cx.waker());
+        unsafe { state.waiters.push_back(node) };
+        this.queued.set(true);
+        Poll::Pending
+    }
+}
+
+impl Drop for Acquire<'_> {
+    fn drop(&mut self) {
+        if !self.queued.get() {
+            return;
+        }
+        let mut state = self.semaphore.state.lock();
+        let node = self.node.get();
+        if unsafe { (*node).is_notified() } {
+            // The permits were handed over to us, give them back.
+            state.permits += unsafe { *(*node).value() };
+        } else {
+            unsafe { state.waiters.remove(node) };
+        }
+        // The tasks behind us may have enough permits now.
+        let wakers = state.grant();
+        drop(state);
+        wakers.into_iter().for_each(Waker::wake);
+    }
+}
+
+/// Permits taken from an AsyncSemaphore, given back when dropped.
+pub struct AsyncSemaphorePermit<'a> {
+    semaphore: &'a AsyncSemaphore,
+    permits: usize,
+}
+
+impl AsyncSemaphorePermit<'_> {
+    /// The number of permits held.
+    pub fn num_permits(&self) -> usize {
+        self.permits
+    }
+
+    /// Drops the permits without giving them back, shrinking the semaphore for good.
+    pub fn forget(mut self) {
+        self.permits = 0;
+    }
+}
+
+impl Drop for AsyncSemaphorePermit<'_> {
+    fn drop(&mut self) {
+        if self.permits > 0 {
+            self.semaphore.add_permits(self.permits);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::AsyncSemaphore;
+    use crate::arc::Arc;
+    use std::future::Future;
+    use std::pin::pin;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::task::{Context, Poll, Waker};
+    use std::time::Duration;
+
+    #[test]
+    fn test_permits() {
+        let semaphore = AsyncSemaphore::new(3);
+        let one = semaphore.try_acquire().unwrap();
+        let two = semaphore.try_acquire_many(2).unwrap();
+        assert_eq!(two.num_permits(), 2);
+        assert!(semaphore.try_acquire().is_none());
+        drop(two);
+        assert_eq!(semaphore.available_permits(), 2);
+        one.forget();
+        assert_eq!(semaphore.available_permits(), 2);
+        semaphore.add_permits(1);
+        assert!(semaphore.try_acquire_many(3).is_some());
+        assert_eq!(semaphore.available_permits(), 3);
+    }
+
+    #[test]
+    fn test_fifo_order() {
+        let semaphore = AsyncSemaphore::new(2);
+        let mut cx = Context::from_waker(Waker::noop());
+        let held = semaphore.try_acquire_many(2).unwrap();
+        let mut many = pin!(semaphore.acquire_many(2));
+        let mut one = pin!(semaphore.acquire());
+        assert!(many.as_mut().poll(&mut cx).is_pending());
+        assert!(one.as_mut().poll(&mut cx).is_pending());
+        // The permits go to the first waiter, even though one would do for the second.
+        drop(held);
+        let Poll::Ready(permit) = many.as_mut().poll(&mut cx) else {
+            panic!("the permits were handed over");
+        };
+        assert!(one.as_mut().poll(&mut cx).is_pending());
+        assert!(semaphore.try_acquire().is_none());
+        drop(permit);
+        assert!(one.as_mut().poll(&mut cx).is_ready());
+    }
+
+    #[test]
+    fn test_dropped_acquire_future() {
+        let semaphore = AsyncSemaphore::new(1);
+        let mut cx = Context::from_waker(Waker::noop());
+        let held = semaphore.try_acquire().unwrap();
+        let mut many = Box::pin(semaphore.acquire_many(2));
+        let mut one = pin!(semaphore.acquire());
+        assert!(many.as_mut().poll(&mut cx).is_pending());
+        assert!(one.as_mut().poll(&mut cx).is_pending());
+        drop(held);
+        assert!(one.as_mut().poll(&mut cx).is_pending());
+        // Leaving the list lets the waiter behind go.
+        drop(many);
+        assert!(one.as_mut().poll(&mut cx).is_ready());
+    }
+
+    #[test]
+    fn test_under_smol() {
+        smol::block_on(async {
+            let semaphore = Arc::new(AsyncSemaphore::new(2));
+            let running = Arc::new(AtomicUsize::new(0));
+            let tasks: Vec<_> = (0..10)
+                .map(|_| {
+                    let (semaphore, running) = (semaphore.clone(), running.clone());
+                    smol::spawn(async move {
+                        let _permit = semaphore.acquire().await;
+                        assert!(running.fetch_add(1, Ordering::SeqCst) < 2);
+                        smol::future::yield_now().await;
+                        running.fetch_sub(1, Ordering::SeqCst);
+                    })
+                })
+                .collect();
+            for task in tasks {
+                task.await;
+            }
+            assert_eq!(semaphore.available_permits(), 2);
+        });
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn test_bounds_concurrency() {
+        let semaphore = Arc::new(AsyncSemaphore::new(3));
+        let running = Arc::new(AtomicUsize::new(0));
+        let handles: Vec<_> = (0..20)
+            .map(|_| {
+                let (semaphore, running) = (semaphore.clone(), running.clone());
+                tokio::spawn(async move {
+                    let _permit = semaphore.acquire().await;
+                    assert!(running.fetch_add(1, Ordering::SeqCst) < 3);
+                    tokio::time::sleep(Duration::from_millis(5)).await;
+                    running.fetch_sub(1, Ordering::SeqCst);
+                })
+            })
+            .collect();
+        for h in handles {
+            h.await.unwrap();
+        }
+        assert_eq!(semaphore.available_permits(), 3);
+    }
+}
@@ -0,0 +1,229 @@
+use crate::arc::Arc;
+use std::hint::spin_loop;
+use std::sync::atomic::{AtomicBool, AtomicPtr, AtomicUsize, Ordering};
+
+/// An atomic slot holding an Arc<T>, for read-mostly data such as a configuration that is
+/// replaced as a whole from time to time. Loads never block and never wait for a writer.
+///
+/// A load has to take a strong count on the Arc it reads, while a concurrent swap may be
+/// dropping that very Arc. Loads therefore register as readers on one of two counters
+/// (picked by the current epoch), and a swap flips the epoch and waits for the readers of
+/// the previous epoch to be done before handing out the old Arc. New loads go to the other
+/// counter, so a swap can't be starved by a steady stream of readers.
+pub struct AtomicArc<T> {
+    // Owns one strong count, obtained with Arc::into_raw.
+    ptr: AtomicPtr<T>,
+    epoch: AtomicUsize,
+    readers: [AtomicUsize; 2],
+    // Writers are serialized, so that a swap only has to wait for the readers of one epoch.
+    writer: AtomicBool,
+}
+
+unsafe impl<T: Send + Sync> Send for AtomicArc<T> {}
+unsafe impl<T: Send + Sync> Sync for AtomicArc<T> {}
+
+impl<T> AtomicArc<T> {
+    pub fn new(value: Arc<T>) -> Self {
+        AtomicArc {
+            ptr: AtomicPtr::new(Arc::into_raw(value) as *mut T),
+            epoch: AtomicUsize::new(0),
+            readers: [AtomicUsize::new(0), AtomicUsize::new(0)],
+            writer: AtomicBool::new(false),
+        }
+    }
+
+    /// Returns a new strong pointer to the current value.
+    pub fn load(&self) -> Arc<T> {
+        // Register as a reader of the current epoch. If a swap flipped the epoch in between,
+        // the swap may already have checked the counter, so register again for the new epoch.
+        let slot = loop {
+            let epoch = self.epoch.load(Ordering::SeqCst);
+            let slot = epoch & 1;
+            self.readers[slot].fetch_add(1, Ordering::SeqCst);
+            if self.epoch.load(Ordering::SeqCst) == epoch {
+                break slot;
+            }
+            self.readers[slot].fetch_sub(1, Ordering::SeqCst);
+        };
+        let ptr = self.ptr.load(Ordering::SeqCst);
+        // SAFETY: the swap that replaces ptr waits for this reader before releasing its count,
+        // so the Arc is still alive here.
+        let value = unsafe {
+            Arc::increment_strong_count(ptr);
+            Arc::from_raw(ptr)
+        };
+        self.readers[slot].fetch_sub(1, Ordering::Release);
+        value
+    }
+
+    /// Replaces the current value, dropping the previous one (or the slot's count on it).
+    pub fn store(&self, value: Arc<T>) {
+        drop(self.swap(value));
+    }
+
+    /// Replaces the current value and returns the previous one.
+    pub fn swap(&self, value: Arc<T>) -> Arc<T> {
+        self.lock_writer();
+        let old = self.replace(value);
+        self.writer.store(false, Ordering::Release);
+        old
+    }
+
+    /// Replaces the current value with `new` if it is the same allocation as `current`.
+    /// Returns the previous value, which is `current` if and only if the swap happened.
+    pub fn compare_and_swap(&self, current: &Arc<T>, new: Arc<T>) -> Arc<T> {
+        self.lock_writer();
+        let ptr = self.ptr.load(Ordering::SeqCst);
+        let previous = if std::ptr::eq(ptr, Arc::as_ptr(current)) {
+            self.replace(new)
+        } else {
+            // Writers are locked out, so the value can't go away before its count is taken.
+            unsafe {
+                Arc::increment_strong_count(ptr);
+                Arc::from_raw(ptr)
+            }
+        };
+        self.writer.store(false, Ordering::Release);
+        previous
+    }
+
+    /// Consumes the slot, returning the Arc it holds.
+    pub fn into_inner(self) -> Arc<T> {
+        let ptr = self.ptr.load(Ordering::Relaxed);
+        std::mem::forget(self);
+        unsafe { Arc::from_raw(ptr) }
+    }
+
+    fn lock_writer(&self) {
+        while self
+            .writer
+            .compare_exchange_weak(false, true, Ordering::Acquire, Ordering::Relaxed)
+            .is_err()
+        {
+            spin_loop();
+        }
+    }
+
+    /// Swaps the pointer and waits until no load can still be using the old one.
+    /// The caller holds the writer lock.
+    fn replace(&self, value: Arc<T>) -> Arc<T> {
+        let old = self
+            .ptr
+            .swap(Arc::into_raw(value) as *mut T, Ordering::SeqCst);
+        // Loads registered after the flip read the new pointer. Loads registered for the other
+        // epoch either did so after the flip, or were waited for by the previous swap.
+        let slot = self.epoch.fetch_add(1, Ordering::SeqCst) & 1;
+        while self.readers[slot].load(Ordering::SeqCst) != 0 {
+            spin_loop();
+        }
+        // SAFETY: the slot's count on the old value is handed over to the caller.
+        unsafe { Arc::from_raw(old) }
+    }
+}
+
+impl<T> Drop for AtomicArc<T> {
+    fn drop(&mut self) {
+        // &mut self: no load is in progress.
+        drop(unsafe { Arc::from_raw(*self.ptr.get_mut()) });
+    }
+}
+
+impl<T: std::fmt::Debug> std::fmt::Debug for AtomicArc<T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("AtomicArc")
+            .field("value", &*self.load())
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::thread;
+
+    #[test]
+    fn load_store_swap() {
+        let slot = AtomicArc::new(Arc::new(String::from("a")));
+        let a = slot.load();
+        assert_eq!(*a, "a");
+        assert_eq!(Arc::strong_count(&a), 2);
+
+        let old = slot.swap(Arc::new(String::from("b")));
+        assert!(Arc::ptr_eq(&old, &a));
+        assert_eq!(*slot.load(), "b");
+
+        slot.store(Arc::new(String::from("c")));
+        assert_eq!(*slot.into_inner(), "c");
+        // Only the handles taken out of the slot are left.
+        assert_eq!(Arc::strong_count(&a), 2);
+    }
+
+    #[test]
+    fn compare_and_swap() {
+        let first = Arc::new(1);
+        let slot = AtomicArc::new(first.clone());
+
+        let stale = Arc::new(1);
+        let previous = slot.compare_and_swap(&stale, Arc::new(2));
+        assert!(Arc::ptr_eq(&previous, &first));
+        assert_eq!(*slot.load(), 1);
+
+        let previous = slot.compare_and_swap(&first, Arc::new(3));
+        assert!(Arc::ptr_eq(&previous, &first));
+        assert_eq!(*slot.load(), 3);
+    }
+
+    #[test]
+    fn concurrent_loads_and_swaps() {
+        use std::sync::atomic::AtomicUsize;
+
+        static LIVE: AtomicUsize = AtomicUsize::new(0);
+
+        // Checks that no value is used after being dropped.
+        struct Config(usize);
+        impl Config {
+            fn new(version: usize) -> Arc<Config> {
+                LIVE.fetch_add(1, Ordering::SeqCst);
+                Arc::new(Config(version))
+            }
+        }
+        impl Drop for Config {
+            fn drop(&mut self) {
+                assert_ne!(self.0, usize::MAX, "double drop");
+                self.0 = usize::MAX;
+                LIVE.fetch_sub(1, Ordering::SeqCst);
+            }
+        }
+
+        let slot = Arc::new(AtomicArc::new(Config::new(0)));
+        let readers: Vec<_> = (0..4)
+            .map(|_| {
+                let slot = slot.clone();
+                thread::spawn(move || {
+                    let mut last = 0;
+                    for _ in 0..20_000 {
+                        let config = slot.load();
+                        assert_ne!(config.0, usize::MAX);
+                        // Versions only go up.
+                        assert!(config.0 >= last);
+                        last = config.0;
+                    }
+                })
+            })
+            .collect();
+        let writer = {
+            let slot = slot.clone();
+            thread::spawn(move || {
+                for version in 1..2_000 {
+                    slot.store(Config::new(version));
+                }
+            })
+        };
+        for t in readers {
+            t.join().unwrap();
+        }
+        writer.join().unwrap();
+        drop(slot);
+        assert_eq!(LIVE.load(Ordering::SeqCst), 0);
+    }
+}
@@ -0,0 +1,334 @@
+use std::cell::UnsafeCell;
+use std::hint::spin_loop;
+use std::mem::{align_of, size_of, transmute_copy};
+use std::num::NonZero;
+use std::ptr::NonNull;
+use std::sync::atomic::{AtomicBool, AtomicU8, AtomicU16, AtomicU32, AtomicUsize, Ordering};
+
+/// A thread-safe Cell: values are moved in and out as a whole, like Cell<T>, but it is Sync.
+/// When T has the size of an atomic integer (up to a machine word) and is aligned enough,
+/// every operation is a single native atomic instruction on the bits of T. Otherwise the
+/// operations are done under a small spinlock stored in the cell.
+/// T must be NoUninit: the native case moves the values through integers, and
+/// compare_exchange compares the bytes of the values in both cases, which needs every byte
+/// of T to be initialized. So references are compared by address, not by the pointed-to
+/// values.
+pub struct AtomicCell<T> {
+    value: UnsafeCell<T>,
+    // Only used when T can't be handled by a native atomic.
+    lock: AtomicBool,
+}
+
+unsafe impl<T: NoUninit + Send> Send for AtomicCell<T> {}
+unsafe impl<T: NoUninit + Send> Sync for AtomicCell<T> {}
+
+/// Types whose values have no padding or other uninitialized bytes, like integers and
+/// pointers, so that they can be viewed as integers of the same size.
+///
+/// # Safety
+/// Every byte of every value of the type must be initialized.
+pub unsafe trait NoUninit {}
+
+macro_rules! no_uninit {
+    ($($t:ty),*) => {
+        $(unsafe impl NoUninit for $t {})*
+    };
+}
+
+no_uninit!(
+    u8, u16, u32, u64, u128, usize, i8, i16, i32, i64, i128, isize
+);
+no_uninit!(bool, char, f32, f64, (), String);
+
+unsafe impl<T: NoUninit, const N: usize> NoUninit for [T; N] {}
+unsafe impl<T: ?Sized> NoUninit for *const T {}
+unsafe impl<T: ?Sized> NoUninit for *mut T {}
+unsafe impl<T: ?Sized> NoUninit for &T {}
+unsafe impl<T: ?Sized> NoUninit for &mut T {}
+unsafe impl<T: ?Sized> NoUninit for NonNull<T> {}
+unsafe impl<T: ?Sized> NoUninit for Box<T> {}
+unsafe impl<T> NoUninit for Vec<T> {}
+// Only the niche-optimized Options, the payload of a larger None would be uninitialized.
+unsafe impl<T: ?Sized> NoUninit for Option<&T> {}
+unsafe impl<T: ?Sized> NoUninit for Option<&mut T> {}
+unsafe impl<T: ?Sized> NoUninit for Option<NonNull<T>> {}
+unsafe impl<T: ?Sized> NoUninit for Option<Box<T>> {}
+
+macro_rules! no_uninit_non_zero {
+    ($($t:ty),*) => {
+        $(
+            unsafe impl NoUninit for NonZero<$t> {}
+            unsafe impl NoUninit for Option<NonZero<$t>> {}
+        )*
+    };
+}
+
+no_uninit_non_zero!(
+    u8, u16, u32, u64, u128, usize, i8, i16, i32, i64, i128, isize
+);
+
+/// True if T can be stored in (and is aligned enough for) the atomic type A.
+const fn fits<T, A>() -> bool {
+    size_of::<T>() == size_of::<A>() && align_of::<T>() >= align_of::<A>()
+}
+
+// Runs $native with $a bound to the value viewed as the matching atomic integer, whose
+// integer type is available as $bits, or $locked when there is no such atomic.
+macro_rules! dispatch {
+    ($cell:ident, |$a:ident, $bits:ident| $native:expr, $locked:expr) => {{
+        if fits::<T, AtomicU8>() {
+            type $bits = u8;
+            let $a = unsafe { &*($cell.value.get() as *const AtomicU8) };
+            $native
+        } else if fits::<T, AtomicU16>() {
+            type $bits = u16;
+            let $a = unsafe { &*($cell.value.get() as *const AtomicU16) };
+            $native
+        } else if fits::<T, AtomicU32>() {
+            type $bits = u32;
+            let $a = unsafe { &*($cell.value.get() as *const AtomicU32) };
+            $native
+        } else if fits::<T, AtomicUsize>() {
+            type $bits = usize;
+            let $a = unsafe { &*($cell.value.get() as *const AtomicUsize) };
+            $native
+        } else {
+            $locked
+        }
+    }};
+}
+
+// Bit conversions between T and the atomic integer. Only used within dispatch, which
+// picks an integer of exactly the size of T, as transmute_copy requires.
+fn to_bits<T, B>(value: T) -> B {
+    let bits = unsafe { transmute_copy(&value) };
+    std::mem::forget(value);
+    bits
+}
+
+fn from_bits<T, B>(bits: B) -> T {
+    unsafe { transmute_copy(&bits) }
+}
+
+// The bytes of a value, for the locked compare_exchange to agree with the native one.
+fn bytes<T: NoUninit>(value: &T) -> &[u8] {
+    // SAFETY: every byte of a NoUninit value is initialized.
+    unsafe { std::slice::from_raw_parts((value as *const T).cast::<u8>(), size_of::<T>()) }
+}
+
+impl<T: NoUninit> AtomicCell<T> {
+    pub const fn new(value: T) -> Self {
+        Self {
+            value: UnsafeCell::new(value),
+            lock: AtomicBool::new(false),
+        }
+    }
+
+    /// Returns true if operations on AtomicCell<T> use native atomics instead of a lock.
+    pub const fn is_lock_free() -> bool {
+        fits::<T, AtomicU8>()
+            || fits::<T, AtomicU16>()
+            || fits::<T, AtomicU32>()
+            || fits::<T, AtomicUsize>()
+    }
+
+    /// Runs f on the value while holding the spinlock.
+    fn with_lock<R>(&self, f: impl FnOnce(&mut T) -> R) -> R {
+        while self
+            .lock
+            .compare_exchange_weak(false, true, Ordering::Acquire, Ordering::Relaxed)
+            .is_err()
+        {
+            spin_loop();
+        }
+
+        // Releases the lock even if f panics.
+        struct Unlock<'a>(&'a AtomicBool);
+        impl Drop for Unlock<'_> {
+            fn drop(&mut self) {
+                self.0.store(false, Ordering::Release);
+            }
+        }
+        let _unlock = Unlock(&self.lock);
+
+        // SAFETY: the lock gives us exclusive access to the value.
+        f(unsafe { &mut *self.value.get() })
+    }
+
+    pub fn get(&self) -> T
+    where
+        T: Copy,
+    {
+        dispatch!(
+            self,
+            |a, Bits| from_bits(a.load(Ordering::Acquire)),
+            self.with_lock(|v| *v)
+        )
+    }
+
+    pub fn set(&self, value: T) {
+        // The old value still has to be dropped.
+        drop(self.swap(value));
+    }
+
+    /// Stores `value` into the cell and returns the previous value.
+    pub fn swap(&self, value: T) -> T {
+        dispatch!(
+            self,
+            |a, Bits| from_bits(a.swap(to_bits::<T, Bits>(value), Ordering::AcqRel)),
+            self.with_lock(|v| std::mem::replace(v, value))
+        )
+    }
+
+    /// Stores `new` if the current value has the same bytes as `current`. Returns the
+    /// previous value, as Ok if the store happened and as Err otherwise.
+    pub fn compare_exchange(&self, current: T, new: T) -> Result<T, T>
+    where
+        T: Copy,
+    {
+        dispatch!(
+            self,
+            |a, Bits| a
+                .compare_exchange(
+                    to_bits::<T, Bits>(current),
+                    to_bits::<T, Bits>(new),
+                    Ordering::AcqRel,
+                    Ordering::Acquire,
+                )
+                .map(from_bits)
+                .map_err(from_bits),
+            self.with_lock(|v| {
+                if bytes(v) == bytes(&current) {
+                    Ok(std::mem::replace(v, new))
+                } else {
+                    Err(*v)
+                }
+            })
+        )
+    }
+
+    /// Updates the value with f, retrying until no other thread changed it in the meantime.
+    /// Returns the previous value.
+    pub fn fetch_update(&self, mut f: impl FnMut(T) -> T) -> T
+    where
+        T: Copy,
+    {
+        let mut current = self.get();
+        loop {
+            match self.compare_exchange(current, f(current)) {
+                Ok(previous) => return previous,
+                Err(actual) => current = actual,
+            }
+        }
+    }
+
+    pub fn into_inner(self) -> T {
+        self.value.into_inner()
+    }
+
+    /// Returns a mutable reference to the value, no synchronization is needed with &mut self.
+    pub fn get_mut(&mut self) -> &mut T {
+        self.value.get_mut()
+    }
+}
+
+impl<T: NoUninit + Default> Default for AtomicCell<T> {
+    fn default() -> Self {
+        AtomicCell::new(T::default())
+    }
+}
+
+impl<T: NoUninit + Copy + std::fmt::Debug> std::fmt::Debug for AtomicCell<T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("AtomicCell")
+            .field("value", &self.get())
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{AtomicCell, NoUninit};
+    use std::sync::Arc;
+    use std::thread;
+
+    #[test]
+    fn test_atomic_cell_native() {
+        assert!(AtomicCell::<u32>::is_lock_free());
+        assert!(AtomicCell::<Option<&u8>>::is_lock_free());
+
+        let c = AtomicCell::new(5u32);
+        c.set(6);
+        assert_eq!(c.get(), 6);
+        assert_eq!(c.swap(7), 6);
+        assert_eq!(c.compare_exchange(0, 8), Err(7));
+        assert_eq!(c.compare_exchange(7, 8), Ok(7));
+        assert_eq!(c.into_inner(), 8);
+    }
+
+    #[test]
+    fn test_atomic_cell_locked() {
+        #[derive(Debug, Copy, Clone, PartialEq, Eq)]
+        struct Big([u64; 4]);
+        unsafe impl NoUninit for Big {}
+        assert!(!AtomicCell::<Big>::is_lock_free());
+        assert!(!AtomicCell::<[u8; 3]>::is_lock_free());
+
+        let c = AtomicCell::new(Big([1; 4]));
+        assert_eq!(
+            c.compare_exchange(Big([0; 4]), Big([2; 4])),
+            Err(Big([1; 4]))
+        );
+        assert_eq!(
+            c.compare_exchange(Big([1; 4]), Big([2; 4])),
+            Ok(Big([1; 4]))
+        );
+        assert_eq!(c.get(), Big([2; 4]));
+
+        let s = AtomicCell::new(String::from("a"));
+        assert_eq!(s.swap(String::from("b")), "a");
+        s.set(String::from("c"));
+        assert_eq!(s.into_inner(), "c");
+    }
+
+    #[test]
+    fn test_compare_exchange_by_address() {
+        let (a, b) = (Box::new(1u32), Box::new(1u32));
+        let (s, t) = (String::from("x"), String::from("x"));
+        // Native for a thin reference, locked for a fat one, both compare addresses.
+        let thin = AtomicCell::new(&*a);
+        let fat = AtomicCell::new(s.as_str());
+        assert!(AtomicCell::<&u32>::is_lock_free());
+        assert!(!AtomicCell::<&str>::is_lock_free());
+        assert!(thin.compare_exchange(&b, &b).is_err());
+        assert!(fat.compare_exchange(&t, &t).is_err());
+        assert!(thin.compare_exchange(&a, &b).is_ok());
+        assert!(fat.compare_exchange(&s, &t).is_ok());
+        assert!(std::ptr::eq(thin.get(), &*b));
+        assert!(std::ptr::eq(fat.get(), t.as_str()));
+    }
+
+    #[test]
+    fn test_atomic_cell_threads() {
+        let native = Arc::new(AtomicCell::new(0usize));
+        let locked = Arc::new(AtomicCell::new([0u64; 2]));
+        let mut handles = vec![];
+
+        for _ in 0..8 {
+            let native = native.clone();
+            let locked = locked.clone();
+            handles.push(thread::spawn(move || {
+                for _ in 0..10000 {
+                    native.fetch_update(|x| x + 1);
+                    locked.fetch_update(|[a, b]| [a + 1, b + 2]);
+                }
+            }));
+        }
+
+        for h in handles {
+            h.join().unwrap();
+        }
+        assert_eq!(native.get(), 80000);
+        assert_eq!(locked.get(), [80000, 160000]);
+    }
+}
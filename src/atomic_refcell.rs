@@ -0,0 +1,260 @@
+use std::cell::UnsafeCell;
+use std::marker::PhantomData;
+use std::ops::{Deref, DerefMut};
+use std::ptr::NonNull;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+// The top bit of the state marks an exclusive borrow, the other bits count shared borrows.
+const EXCLUSIVE: usize = 1 << (usize::BITS - 1);
+
+/// A thread-safe RefCell: borrows are checked at runtime like RefCell<T>, but the borrow
+/// state is an AtomicUsize so the cell can be shared between threads.
+/// Unlike RwLock, a conflicting borrow never blocks, it fails (or panics) right away.
+pub struct AtomicRefCell<T> {
+    value: UnsafeCell<T>,
+    state: AtomicUsize,
+}
+
+unsafe impl<T: Send> Send for AtomicRefCell<T> {}
+// Shared borrows hand out &T to several threads, exclusive ones move &mut T between threads.
+unsafe impl<T: Send + Sync> Sync for AtomicRefCell<T> {}
+
+/// An error returned by AtomicRefCell::try_borrow, the value is currently mutably borrowed.
+#[derive(Debug)]
+pub struct BorrowError;
+
+impl std::fmt::Display for BorrowError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("already mutably borrowed")
+    }
+}
+
+impl std::error::Error for BorrowError {}
+
+/// An error returned by AtomicRefCell::try_borrow_mut, the value is currently borrowed.
+#[derive(Debug)]
+pub struct BorrowMutError;
+
+impl std::fmt::Display for BorrowMutError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("already borrowed")
+    }
+}
+
+impl std::error::Error for BorrowMutError {}
+
+impl<T> AtomicRefCell<T> {
+    pub const fn new(value: T) -> Self {
+        Self {
+            value: UnsafeCell::new(value),
+            state: AtomicUsize::new(0),
+        }
+    }
+
+    /// Immutably borrows the wrapped value, failing if the value is currently mutably borrowed.
+    pub fn try_borrow(&self) -> Result<AtomicRef<'_, T>, BorrowError> {
+        self.state
+            .fetch_update(Ordering::Acquire, Ordering::Relaxed, |state| {
+                if state & EXCLUSIVE != 0 {
+                    return None;
+                }
+                // Overflowing into the exclusive bit would let a writer in next to the readers.
+                assert!(state + 1 < EXCLUSIVE, "too many shared borrows");
+                Some(state + 1)
+            })
+            .map_err(|_| BorrowError)?;
+        Ok(AtomicRef {
+            value: unsafe { NonNull::new_unchecked(self.value.get()) },
+            state: &self.state,
+        })
+    }
+
+    /// Mutably borrows the wrapped value, failing if the value is currently borrowed.
+    pub fn try_borrow_mut(&self) -> Result<AtomicRefMut<'_, T>, BorrowMutError> {
+        self.state
+            .compare_exchange(0, EXCLUSIVE, Ordering::Acquire, Ordering::Relaxed)
+            .map_err(|_| BorrowMutError)?;
+        Ok(AtomicRefMut {
+            value: unsafe { NonNull::new_unchecked(self.value.get()) },
+            state: &self.state,
+            _marker: PhantomData,
+        })
+    }
+
+    /// Immutably borrows the wrapped value.
+    /// Panics if the value is currently mutably borrowed, see try_borrow for a non-panicking variant.
+    #[track_caller]
+    pub fn borrow(&self) -> AtomicRef<'_, T> {
+        match self.try_borrow() {
+            Ok(borrow) => borrow,
+            Err(err) => panic!("{err}"),
+        }
+    }
+
+    /// Mutably borrows the wrapped value.
+    /// Panics if the value is currently borrowed, see try_borrow_mut for a non-panicking variant.
+    #[track_caller]
+    pub fn borrow_mut(&self) -> AtomicRefMut<'_, T> {
+        match self.try_borrow_mut() {
+            Ok(borrow) => borrow,
+            Err(err) => panic!("{err}"),
+        }
+    }
+
+    pub fn into_inner(self) -> T {
+        self.value.into_inner()
+    }
+
+    /// Returns a mutable reference to the value, no borrow tracking is needed with &mut self.
+    pub fn get_mut(&mut self) -> &mut T {
+        self.value.get_mut()
+    }
+}
+
+impl<T: Default> Default for AtomicRefCell<T> {
+    fn default() -> Self {
+        AtomicRefCell::new(T::default())
+    }
+}
+
+/// A shared borrow of an AtomicRefCell<T>, releasing the borrow on drop.
+pub struct AtomicRef<'a, T: ?Sized> {
+    value: NonNull<T>,
+    state: &'a AtomicUsize,
+}
+
+// An AtomicRef is a &T, and the borrow can be released from any thread.
+unsafe impl<T: ?Sized + Sync> Send for AtomicRef<'_, T> {}
+unsafe impl<T: ?Sized + Sync> Sync for AtomicRef<'_, T> {}
+
+impl<'a, T: ?Sized> AtomicRef<'a, T> {
+    /// Makes a new AtomicRef for a component of the borrowed data.
+    pub fn map<U: ?Sized>(orig: AtomicRef<'a, T>, f: impl FnOnce(&T) -> &U) -> AtomicRef<'a, U> {
+        let value = NonNull::from(f(&orig));
+        let state = orig.state;
+        // The borrow is handed over to the new guard.
+        std::mem::forget(orig);
+        AtomicRef { value, state }
+    }
+}
+
+impl<T: ?Sized> Deref for AtomicRef<'_, T> {
+    type Target = T;
+    fn deref(&self) -> &Self::Target {
+        unsafe { self.value.as_ref() }
+    }
+}
+
+impl<T: ?Sized> Drop for AtomicRef<'_, T> {
+    fn drop(&mut self) {
+        let prev = self.state.fetch_sub(1, Ordering::Release);
+        debug_assert!(prev & EXCLUSIVE == 0 && prev > 0);
+    }
+}
+
+/// An exclusive borrow of an AtomicRefCell<T>, releasing the borrow on drop.
+pub struct AtomicRefMut<'a, T: ?Sized> {
+    value: NonNull<T>,
+    state: &'a AtomicUsize,
+    // An AtomicRefMut behaves like a &mut T, which is invariant in T.
+    _marker: PhantomData<&'a mut T>,
+}
+
+// An AtomicRefMut is a &mut T, and the borrow can be released from any thread.
+unsafe impl<T: ?Sized + Send> Send for AtomicRefMut<'_, T> {}
+unsafe impl<T: ?Sized + Sync> Sync for AtomicRefMut<'_, T> {}
+
+impl<'a, T: ?Sized> AtomicRefMut<'a, T> {
+    /// Makes a new AtomicRefMut for a component of the borrowed data.
+    pub fn map<U: ?Sized>(
+        mut orig: AtomicRefMut<'a, T>,
+        f: impl FnOnce(&mut T) -> &mut U,
+    ) -> AtomicRefMut<'a, U> {
+        let value = NonNull::from(f(&mut orig));
+        let state = orig.state;
+        std::mem::forget(orig);
+        AtomicRefMut {
+            value,
+            state,
+            _marker: PhantomData,
+        }
+    }
+}
+
+impl<T: ?Sized> Deref for AtomicRefMut<'_, T> {
+    type Target = T;
+    fn deref(&self) -> &Self::Target {
+        unsafe { self.value.as_ref() }
+    }
+}
+
+impl<T: ?Sized> DerefMut for AtomicRefMut<'_, T> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        unsafe { self.value.as_mut() }
+    }
+}
+
+impl<T: ?Sized> Drop for AtomicRefMut<'_, T> {
+    fn drop(&mut self) {
+        self.state.store(0, Ordering::Release);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+    use std::thread;
+
+    #[test]
+    fn test_borrow_rules() {
+        let c = AtomicRefCell::new(vec![1, 2]);
+        let b1 = c.borrow();
+        let b2 = c.borrow();
+        assert_eq!(*b1, *b2);
+        assert!(c.try_borrow_mut().is_err());
+        drop((b1, b2));
+
+        let mut first = AtomicRefMut::map(c.borrow_mut(), |v| &mut v[0]);
+        *first = 10;
+        assert!(c.try_borrow().is_err());
+        assert!(c.try_borrow_mut().is_err());
+        drop(first);
+        assert_eq!(*AtomicRef::map(c.borrow(), |v| &v[0]), 10);
+        assert_eq!(c.into_inner(), vec![10, 2]);
+    }
+
+    #[test]
+    #[should_panic(expected = "already mutably borrowed")]
+    fn test_borrow_panics() {
+        let c = AtomicRefCell::new(5);
+        let _b = c.borrow_mut();
+        c.borrow();
+    }
+
+    #[test]
+    fn test_shared_between_threads() {
+        let c = Arc::new(AtomicRefCell::new(0));
+        let threads: Vec<_> = (0..8)
+            .map(|_| {
+                let c = c.clone();
+                thread::spawn(move || {
+                    let mut done = 0;
+                    while done < 1000 {
+                        // Losers of a race retry instead of blocking.
+                        if let Ok(mut v) = c.try_borrow_mut() {
+                            *v += 1;
+                            done += 1;
+                        } else if let Ok(v) = c.try_borrow() {
+                            assert!(*v <= 8000);
+                        }
+                    }
+                })
+            })
+            .collect();
+        for t in threads {
+            t.join().unwrap();
+        }
+        assert_eq!(*c.borrow(), 8000);
+    }
+}
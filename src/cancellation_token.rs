@@ -0,0 +1,244 @@
+use crate::arc::{Arc, Weak};
+use crate::mutex::Mutex;
+use crate::waker_list::{WakerList, WakerNode};
+use std::cell::{Cell, UnsafeCell};
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::task::{Context, Poll, Waker};
+
+/// Signals cancellation to a tree of tasks, for a cooperative shutdown: the tasks check
+/// is_cancelled, or wait in cancelled, and wind down on their own.
+///
+/// Clones of a token share its state, and cancelling any clone cancels them all. A child
+/// token, made by child_token, is cancelled along with its parent, and with the parent's
+/// ancestors, but cancelling it leaves the parent alone, so a subtree of tasks can be shut
+/// down on its own.
+#[derive(Clone)]
+pub struct CancellationToken {
+    node: Arc<Node>,
+}
+
+struct Node {
+    // Set once, before the waiters and children are taken out of `inner`.
+    cancelled: AtomicBool,
+    inner: Mutex<Inner>,
+    // Kept alive while it has children, for cancel to reach them through it even once all
+    // its own tokens are dropped.
+    parent: Option<Arc<Node>>,
+}
+
+struct Inner {
+    waiters: WakerList,
+    // Weak, so that dropped children don't stay alive, they are pruned as the list grows.
+    children: Vec<Weak<Node>>,
+}
+
+impl Node {
+    fn new(cancelled: bool, parent: Option<Arc<Node>>) -> Arc<Node> {
+        Arc::new(Node {
+            cancelled: AtomicBool::new(cancelled),
+            inner: Mutex::new(Inner {
+                waiters: WakerList::new(),
+                children: Vec::new(),
+            }),
+            parent,
+        })
+    }
+}
+
+impl CancellationToken {
+    pub fn new() -> CancellationToken {
+        CancellationToken {
+            node: Node::new(false, None),
+        }
+    }
+
+    /// Makes a token cancelled when this one is. It is cancelled right away if this one
+    /// already is.
+    pub fn child_token(&self) -> CancellationToken {
+        let mut inner = self.node.inner.lock();
+        // Read under the lock: cancel sets the flag before taking the lock to cancel the
+        // children, so either it finds the child in the list, or we see the flag.
+        let cancelled = self.node.cancelled.load(Ordering::Acquire);
+        let child = Node::new(cancelled, Some(self.node.clone()));
+        if !cancelled {
+            if inner.children.len() == inner.children.capacity() {
+                inner.children.retain(|child| child.upgrade().is_some());
+            }
+            inner.children.push(Arc::downgrade(&child));
+        }
+        CancellationToken { node: child }
+    }
+
+    /// Cancels this token and all its descendants, waking the tasks waiting in cancelled.
+    pub fn cancel(&self) {
+        // Iterative, so that a deep tree doesn't overflow the stack.
+        let mut pending = vec![self.node.clone()];
+        while let Some(node) = pending.pop() {
+            if node.cancelled.swap(true, Ordering::AcqRel) {
+                continue;
+            }
+            let mut inner = node.inner.lock();
+            let wakers: Vec<Waker> = std::iter::from_fn(|| inner.waiters.pop_front()).collect();
+            let children = std::mem::take(&mut inner.children);
+            drop(inner);
+            wakers.into_iter().for_each(Waker::wake);
+            pending.extend(children.iter().filter_map(Weak::upgrade));
+        }
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.node.cancelled.load(Ordering::Acquire)
+    }
+
+    /// Waits until the token is cancelled.
+    pub async fn cancelled(&self) {
+        Cancelled {
+            node: &self.node,
+            waiter: UnsafeCell::new(WakerNode::new(())),
+            queued: Cell::new(false),
+        }
+        .await
+    }
+}
+
+impl Default for CancellationToken {
+    fn default() -> Self {
+        CancellationToken::new()
+    }
+}
+
+impl std::fmt::Debug for CancellationToken {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("CancellationToken")
+            .field("is_cancelled", &self.is_cancelled())
+            .finish()
+    }
+}
+
+// The future of CancellationToken::cancelled.
+struct Cancelled<'a> {
+    node: &'a Node,
+    // In the waiter list of the token while queued.
+    waiter: UnsafeCell<WakerNode>,
+    queued: Cell<bool>,
+}
+
+// The waiter node is only accessed under the lock of the waiter list.
+unsafe impl Send for Cancelled<'_> {}
+
+impl Future for Cancelled<'_> {
+    type Output = ();
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+        // The waiter node must not move once queued, so only ever use a shared reference.
+        let this = self.into_ref().get_ref();
+        if !this.queued.get() && this.node.cancelled.load(Ordering::Acquire) {
+            return Poll::Ready(());
+        }
+        let mut inner = this.node.inner.lock();
+        let waiter = unsafe { &mut *this.waiter.get() };
+        if this.queued.get() {
+            if waiter.is_notified() {
+                this.queued.set(false);
+                return Poll::Ready(());
+            }
+            waiter.set_waker(cx.waker());
+            return Poll::Pending;
+        }
+        // Checked again under the lock, as in child_token.
+        if this.node.cancelled.load(Ordering::Acquire) {
+            return Poll::Ready(());
+        }
+        waiter.set_waker(cx.waker());
+        unsafe { inner.waiters.push_back(waiter) };
+        this.queued.set(true);
+        Poll::Pending
+    }
+}
+
+impl Drop for Cancelled<'_> {
+    fn drop(&mut self) {
+        if !self.queued.get() {
+            return;
+        }
+        let mut inner = self.node.inner.lock();
+        if unsafe { !(*self.waiter.get()).is_notified() } {
+            unsafe { inner.waiters.remove(self.waiter.get()) };
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::CancellationToken;
+    use crate::arc::Arc;
+    use std::future::Future;
+    use std::pin::pin;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::task::{Context, Waker};
+
+    #[test]
+    fn test_child_tokens() {
+        let root = CancellationToken::new();
+        let child = root.child_token();
+        let grandchild = child.child_token();
+        let sibling = root.child_token();
+        // Cancelling a child leaves its parent alone.
+        child.cancel();
+        assert!(child.is_cancelled() && grandchild.is_cancelled());
+        assert!(!root.is_cancelled() && !sibling.is_cancelled());
+        root.clone().cancel();
+        assert!(root.is_cancelled() && sibling.is_cancelled());
+        assert!(root.child_token().is_cancelled());
+        assert_eq!(
+            format!("{root:?}"),
+            "CancellationToken { is_cancelled: true }"
+        );
+    }
+
+    #[test]
+    fn test_cancelled() {
+        let parent = CancellationToken::new();
+        let token = parent.child_token();
+        let mut cx = Context::from_waker(Waker::noop());
+        let mut waiter = pin!(token.cancelled());
+        let mut dropped = Box::pin(token.cancelled());
+        assert!(waiter.as_mut().poll(&mut cx).is_pending());
+        assert!(dropped.as_mut().poll(&mut cx).is_pending());
+        drop(dropped);
+        // Dropped children don't pile up in the parent.
+        for _ in 0..100 {
+            parent.child_token();
+        }
+        assert!(parent.node.inner.lock().children.len() <= 64);
+        parent.cancel();
+        assert!(waiter.as_mut().poll(&mut cx).is_ready());
+        assert!(pin!(token.cancelled()).poll(&mut cx).is_ready());
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+    async fn test_shutdown_task_tree() {
+        let shutdown = CancellationToken::new();
+        let stopped = Arc::new(AtomicUsize::new(0));
+        let mut handles = vec![];
+        for _ in 0..4 {
+            let group = shutdown.child_token();
+            for _ in 0..4 {
+                let (token, stopped) = (group.child_token(), stopped.clone());
+                handles.push(tokio::spawn(async move {
+                    token.cancelled().await;
+                    stopped.fetch_add(1, Ordering::Relaxed);
+                }));
+            }
+        }
+        tokio::time::sleep(std::time::Duration::from_millis(20)).await;
+        assert_eq!(stopped.load(Ordering::Relaxed), 0);
+        shutdown.cancel();
+        for h in handles {
+            h.await.unwrap();
+        }
+        assert_eq!(stopped.load(Ordering::Relaxed), 16);
+    }
+}
@@ -4,12 +4,14 @@ use std::cell::UnsafeCell;
 /// That is, an &mut T to the inner value can never be obtained, and the value itself
 /// cannot be directly obtained without replacing it with something else.
 /// Cell are also not Sync, so can't be shared across threads.
-pub struct Cell<T> {
+// repr(transparent) so a Cell<[T]> has the same layout as a [Cell<T>].
+#[repr(transparent)]
+pub struct Cell<T: ?Sized> {
     value: UnsafeCell<T>,
 }
 
 impl<T> Cell<T> {
-    pub fn new(value: T) -> Self {
+    pub const fn new(value: T) -> Self {
         Self {
             value: UnsafeCell::new(value),
         }
@@ -29,6 +31,121 @@ impl<T> Cell<T> {
         // and only get or set can be called at one time.
         unsafe { *self.value.get() }
     }
+
+    /// Updates the contained value using a function, e.g. `counter.update(|c| c + 1)`.
+    pub fn update(&self, f: impl FnOnce(T) -> T)
+    where
+        T: Copy,
+    {
+        // f gets a copy, so it can't observe the cell mid-update even if it uses the cell itself.
+        self.set(f(self.get()));
+    }
+
+    /// Replaces the contained value with `value`, and returns the old contained value.
+    pub fn replace(&self, value: T) -> T {
+        // SAFETY: same as set, no-one else can access the value while we swap it out.
+        unsafe { std::mem::replace(&mut *self.value.get(), value) }
+    }
+
+    /// Swaps the values of two Cells.
+    pub fn swap(&self, other: &Cell<T>) {
+        if std::ptr::eq(self, other) {
+            return;
+        }
+        // SAFETY: the two cells are distinct, and no references to their values were given out.
+        unsafe { std::ptr::swap(self.value.get(), other.value.get()) }
+    }
+
+    /// Takes the value of the cell, leaving Default::default() in its place.
+    pub fn take(&self) -> T
+    where
+        T: Default,
+    {
+        self.replace(T::default())
+    }
+
+    /// Unwraps the value, consuming the cell.
+    pub fn into_inner(self) -> T {
+        self.value.into_inner()
+    }
+}
+
+impl<T: ?Sized> Cell<T> {
+    /// Returns a mutable reference to the underlying data.
+    /// No runtime check is needed, as &mut self guarantees nobody else can use the cell.
+    pub fn get_mut(&mut self) -> &mut T {
+        self.value.get_mut()
+    }
+
+    /// Returns a &Cell<T> from a &mut T, e.g. to get a Cell<[T]> out of a slice.
+    pub fn from_mut(t: &mut T) -> &Cell<T> {
+        // SAFETY: Cell<T> has the same layout as T, and the exclusive borrow guarantees
+        // nothing else accesses the value while the Cell is in use.
+        unsafe { &*(t as *mut T as *const Cell<T>) }
+    }
+}
+
+impl<T> Cell<[T]> {
+    /// Returns a &[Cell<T>] from a &Cell<[T]>, giving interior mutability to each element.
+    pub fn as_slice_of_cells(&self) -> &[Cell<T>] {
+        // SAFETY: Cell<T> has the same layout as T, so [Cell<T>] has the same layout as [T],
+        // and the cells are only usable from this thread just like the original one.
+        unsafe { &*(self as *const Cell<[T]> as *const [Cell<T>]) }
+    }
+}
+
+impl<T, const N: usize> Cell<[T; N]> {
+    /// Returns a &[Cell<T>; N] from a &Cell<[T; N]>.
+    pub fn as_array_of_cells(&self) -> &[Cell<T>; N] {
+        // SAFETY: same as as_slice_of_cells, with the length known at compile time.
+        unsafe { &*(self as *const Cell<[T; N]> as *const [Cell<T>; N]) }
+    }
+}
+
+impl<T: Copy + std::fmt::Debug> std::fmt::Debug for Cell<T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Cell").field("value", &self.get()).finish()
+    }
+}
+
+impl<T: Default> Default for Cell<T> {
+    fn default() -> Self {
+        Cell::new(T::default())
+    }
+}
+
+impl<T: Copy> Clone for Cell<T> {
+    fn clone(&self) -> Self {
+        Cell::new(self.get())
+    }
+}
+
+impl<T: Copy + PartialEq> PartialEq for Cell<T> {
+    fn eq(&self, other: &Cell<T>) -> bool {
+        self.get() == other.get()
+    }
+}
+
+impl<T: Copy + Eq> Eq for Cell<T> {}
+
+impl<T> From<T> for Cell<T> {
+    fn from(value: T) -> Self {
+        Cell::new(value)
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<T: Copy + serde::Serialize> serde::Serialize for Cell<T> {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        self.get().serialize(serializer)
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de, T: serde::Deserialize<'de>> serde::Deserialize<'de> for Cell<T> {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        T::deserialize(deserializer).map(Cell::new)
+    }
 }
 
 /// 1. Getting a raw *mut T from an &T does NOT remove Rust’s aliasing guarantees — the compiler still assumes the
@@ -68,6 +185,68 @@ mod tests {
     }
 
     #[test]
+    fn test_cell_update() {
+        let c = Cell::new(5);
+        c.update(|x| x + 1);
+        assert_eq!(c.get(), 6);
+        c.update(|x| {
+            // Reentrant use sees the old value and is overwritten by the result.
+            c.set(100);
+            x * 2
+        });
+        assert_eq!(c.get(), 12);
+    }
+
+    #[test]
+    fn test_as_slice_of_cells() {
+        let mut grid = [0; 4];
+        let cells = Cell::from_mut(&mut grid[..]).as_slice_of_cells();
+        // Every cell can be read and written while the others are borrowed too.
+        for pair in cells.windows(2) {
+            pair[1].set(pair[0].get() + 1);
+        }
+        assert_eq!(grid, [0, 1, 2, 3]);
+
+        let array = Cell::new([1, 2]);
+        let [a, b] = array.as_array_of_cells();
+        a.swap(b);
+        assert_eq!(array.into_inner(), [2, 1]);
+    }
+
+    #[test]
+    fn test_cell_trait_impls() {
+        #[derive(Debug, Default, Clone, PartialEq)]
+        struct Counter {
+            hits: Cell<u32>,
+        }
+
+        let a = Counter::default();
+        a.hits.set(3);
+        let b = a.clone();
+        b.hits.set(4);
+        assert_ne!(a, b);
+        assert_eq!(a.hits, Cell::from(3));
+        assert_eq!(format!("{:?}", a.hits), "Cell { value: 3 }");
+    }
+
+    #[test]
+    fn test_cell_move_in_and_out() {
+        let c = Cell::new(String::from("a"));
+        assert_eq!(c.replace(String::from("b")), "a");
+        assert_eq!(c.take(), "b");
+
+        let d = Cell::new(String::from("d"));
+        c.swap(&d);
+        c.swap(&c);
+        assert_eq!(d.take(), "");
+
+        let mut c = c;
+        c.get_mut().push('!');
+        assert_eq!(c.into_inner(), "d!");
+    }
+
+    #[test]
+    #[allow(non_local_definitions)]
     fn test_unsafe_with_threads() {
         unsafe impl<T> Sync for Cell<T> {}
 
@@ -91,4 +270,13 @@ mod tests {
         jh2.join().unwrap();
         assert!(x.get() < 2000000)
     }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_serde_round_trip() {
+        let json = serde_json::to_string(&Cell::new(5)).unwrap();
+        assert_eq!(json, "5");
+        let cell: Cell<i32> = serde_json::from_str(&json).unwrap();
+        assert_eq!(cell.get(), 5);
+    }
 }
@@ -0,0 +1,116 @@
+use crate::lock_api::{MutexGuard, RawMutex};
+use crate::mutex::WaitQueue;
+
+/// A condition variable for the crate's mutexes: lets threads sleep until another thread
+/// changes the protected data and notifies them.
+///
+/// As with any condition variable, the condition should be checked again in a loop after
+/// `wait` returns, another thread may have changed the data before the lock was taken back.
+pub struct Condvar {
+    waiters: WaitQueue,
+}
+
+impl Condvar {
+    pub const fn new() -> Condvar {
+        Condvar {
+            waiters: WaitQueue::new(),
+        }
+    }
+
+    /// Releases the lock held by `guard` and blocks until the condvar is notified, then
+    /// takes the lock again.
+    #[cfg_attr(feature = "deadlock_detection", track_caller)]
+    pub fn wait<'a, R: RawMutex, T>(&self, guard: MutexGuard<'a, R, T>) -> MutexGuard<'a, R, T> {
+        let mutex = guard.mutex;
+        // The mutex is unlocked under the queue lock, after which a notifying thread can get
+        // the mutex, but has to wait for the queue lock to notify, by then we are queued.
+        self.waiters.park_while(|| {
+            drop(guard);
+            true
+        });
+        mutex.lock()
+    }
+
+    /// Wakes up one thread blocked in `wait`, if any.
+    pub fn notify_one(&self) {
+        self.waiters.unpark_one();
+    }
+
+    /// Wakes up all threads blocked in `wait`.
+    pub fn notify_all(&self) {
+        self.waiters.unpark_all();
+    }
+}
+
+impl Default for Condvar {
+    fn default() -> Self {
+        Condvar::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Condvar;
+    use crate::mutex::Mutex;
+    use std::collections::VecDeque;
+    use std::sync::Arc;
+    use std::thread;
+
+    #[test]
+    fn test_producer_consumer() {
+        let queue = Arc::new((Mutex::new(VecDeque::new()), Condvar::new()));
+        let consumer = {
+            let queue = queue.clone();
+            thread::spawn(move || {
+                let (items, not_empty) = &*queue;
+                let mut sum = 0;
+                loop {
+                    let mut guard = items.lock();
+                    while guard.is_empty() {
+                        guard = not_empty.wait(guard);
+                    }
+                    match guard.pop_front().unwrap() {
+                        Some(n) => sum += n,
+                        None => return sum,
+                    }
+                }
+            })
+        };
+        let (items, not_empty) = &*queue;
+        for n in 1..=1000 {
+            items.lock().push_back(Some(n));
+            not_empty.notify_one();
+        }
+        items.lock().push_back(None);
+        not_empty.notify_one();
+        assert_eq!(consumer.join().unwrap(), 500_500);
+    }
+
+    #[test]
+    fn test_notify_all() {
+        let state = Arc::new((Mutex::new((false, 0)), Condvar::new()));
+        let handles: Vec<_> = (0..8)
+            .map(|_| {
+                let state = state.clone();
+                thread::spawn(move || {
+                    let (mutex, cvar) = &*state;
+                    let mut guard = mutex.lock();
+                    guard.1 += 1;
+                    while !guard.0 {
+                        guard = cvar.wait(guard);
+                    }
+                })
+            })
+            .collect();
+        let (mutex, cvar) = &*state;
+        // Wait for every thread to be waiting, or about to.
+        while mutex.lock().1 != 8 {
+            thread::yield_now();
+        }
+        mutex.lock().0 = true;
+        cvar.notify_all();
+        for h in handles {
+            h.join().unwrap();
+        }
+    }
+}
@@ -0,0 +1,112 @@
+use crate::arc::Arc;
+use crate::rc::Rc;
+use std::mem::ManuallyDrop;
+use std::ops::{Deref, DerefMut};
+
+/// A value paired with a deleter that takes it over when it would be dropped, like the
+/// deleter of a C++ shared_ptr. Made by Rc::new_with_deleter and Arc::new_with_deleter,
+/// so the deleter runs when the last strong pointer goes away, e.g. to give a buffer back
+/// to a pool or to close an FFI handle.
+pub struct WithDeleter<T, D: FnOnce(T)> {
+    value: ManuallyDrop<T>,
+    deleter: ManuallyDrop<D>,
+}
+
+impl<T, D: FnOnce(T)> WithDeleter<T, D> {
+    pub fn new(value: T, deleter: D) -> Self {
+        WithDeleter {
+            value: ManuallyDrop::new(value),
+            deleter: ManuallyDrop::new(deleter),
+        }
+    }
+}
+
+impl<T, D: FnOnce(T)> Deref for WithDeleter<T, D> {
+    type Target = T;
+    fn deref(&self) -> &T {
+        &self.value
+    }
+}
+
+impl<T, D: FnOnce(T)> DerefMut for WithDeleter<T, D> {
+    fn deref_mut(&mut self) -> &mut T {
+        &mut self.value
+    }
+}
+
+impl<T, D: FnOnce(T)> Drop for WithDeleter<T, D> {
+    fn drop(&mut self) {
+        // SAFETY: both are taken exactly once, here, and never touched again.
+        let (value, deleter) = unsafe {
+            (
+                ManuallyDrop::take(&mut self.value),
+                ManuallyDrop::take(&mut self.deleter),
+            )
+        };
+        deleter(value);
+    }
+}
+
+impl<T: std::fmt::Debug, D: FnOnce(T)> std::fmt::Debug for WithDeleter<T, D> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        std::fmt::Debug::fmt(&*self.value, f)
+    }
+}
+
+impl<T> Rc<T> {
+    /// Constructs a new Rc whose value is handed to `deleter` instead of being dropped,
+    /// once the last Rc pointer goes away.
+    pub fn new_with_deleter<D: FnOnce(T)>(value: T, deleter: D) -> Rc<WithDeleter<T, D>> {
+        Rc::new(WithDeleter::new(value, deleter))
+    }
+}
+
+impl<T> Arc<T> {
+    /// Constructs a new Arc whose value is handed to `deleter` instead of being dropped,
+    /// once the last Arc pointer goes away, on whichever thread drops it.
+    pub fn new_with_deleter<D: FnOnce(T)>(value: T, deleter: D) -> Arc<WithDeleter<T, D>> {
+        Arc::new(WithDeleter::new(value, deleter))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Mutex;
+    use std::thread;
+
+    #[test]
+    fn test_rc_deleter_returns_buffer_to_pool() {
+        let pool = std::cell::RefCell::new(Vec::<Vec<u8>>::new());
+        let buf = Rc::new_with_deleter(vec![0_u8; 16], |mut buf| {
+            buf.clear();
+            pool.borrow_mut().push(buf);
+        });
+        let other = buf.clone();
+        assert_eq!(buf.len(), 16);
+        drop(buf);
+        assert!(pool.borrow().is_empty());
+        drop(other);
+        let pool = pool.into_inner();
+        assert_eq!(pool.len(), 1);
+        assert!(pool[0].is_empty() && pool[0].capacity() >= 16);
+    }
+
+    #[test]
+    fn test_arc_deleter_runs_once_on_last_drop() {
+        let closed = std::sync::Arc::new(Mutex::new(Vec::new()));
+        let log = closed.clone();
+        let handle = Arc::new_with_deleter(42_i32, move |fd| log.lock().unwrap().push(fd));
+        let handles: Vec<_> = (0..4)
+            .map(|_| {
+                let handle = handle.clone();
+                thread::spawn(move || assert_eq!(**handle, 42))
+            })
+            .collect();
+        drop(handle);
+        for h in handles {
+            h.join().unwrap();
+        }
+        assert_eq!(*closed.lock().unwrap(), [42]);
+    }
+}
@@ -0,0 +1,325 @@
+use crate::lock_api::{self, GuardSend};
+use crate::rwlock::backoff;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+
+/// A phase-fair reader-writer lock (the PF-T lock of Brandenburg and Anderson). Readers and
+/// writers take turns: a writer waits for the readers already in, readers arriving while a
+/// writer waits or holds the lock are all let in together once it leaves, before the next
+/// writer, and writers go in FIFO order. So a reader waits for at most one writer, and a
+/// writer for one batch of readers per writer ahead of it. RwLock bounds the writers'
+/// wait, but readers there can wait behind any number of writers.
+pub type FairRwLock<T> = lock_api::RwLock<RawFairRwLock, T>;
+
+pub type FairRwLockReadGuard<'a, T> = lock_api::RwLockReadGuard<'a, RawFairRwLock, T>;
+
+pub type FairRwLockWriteGuard<'a, T> = lock_api::RwLockWriteGuard<'a, RawFairRwLock, T>;
+
+/// The raw lock of FairRwLock.
+pub struct RawFairRwLock {
+    // Readers that came in and readers that left, counted in units of READER. The low bits
+    // of rin tell readers about a writer: WRITER_PRESENT and the writer's phase.
+    rin: AtomicUsize,
+    rout: AtomicUsize,
+    // Writer tickets: the next one to hand out and the one being served.
+    win: AtomicUsize,
+    wout: AtomicUsize,
+    // Set while a writer holds the lock, once the readers before it have left.
+    writer: AtomicBool,
+    // Readers counted in rin that wait for a writer to leave, rather than hold the lock.
+    blocked: AtomicUsize,
+}
+
+const PHASE: usize = 1;
+const WRITER_PRESENT: usize = 2;
+const WRITER_BITS: usize = PHASE | WRITER_PRESENT;
+const READER: usize = 4;
+
+unsafe impl lock_api::RawRwLock for RawFairRwLock {
+    const INIT: RawFairRwLock = RawFairRwLock {
+        rin: AtomicUsize::new(0),
+        rout: AtomicUsize::new(0),
+        win: AtomicUsize::new(0),
+        wout: AtomicUsize::new(0),
+        writer: AtomicBool::new(false),
+        blocked: AtomicUsize::new(0),
+    };
+
+    type GuardMarker = GuardSend;
+
+    fn lock_shared(&self) {
+        let writer = self.rin.fetch_add(READER, Ordering::Acquire) & WRITER_BITS;
+        if writer != 0 {
+            // Wait for the end of this writer's phase only: the bits change when it leaves,
+            // even if the next writer sets them again right away (with the other phase).
+            self.blocked.fetch_add(1, Ordering::Relaxed);
+            let mut spins = 0_u32;
+            while self.rin.load(Ordering::Acquire) & WRITER_BITS == writer {
+                backoff(&mut spins);
+            }
+            self.blocked.fetch_sub(1, Ordering::Relaxed);
+        }
+    }
+
+    fn try_lock_shared(&self) -> bool {
+        let rin = self.rin.load(Ordering::Relaxed);
+        rin & WRITER_BITS == 0
+            && self
+                .rin
+                .compare_exchange(rin, rin + READER, Ordering::Acquire, Ordering::Relaxed)
+                .is_ok()
+    }
+
+    unsafe fn unlock_shared(&self) {
+        self.rout.fetch_add(READER, Ordering::Release);
+    }
+
+    fn lock_exclusive(&self) {
+        let ticket = self.win.fetch_add(1, Ordering::Relaxed);
+        let mut spins = 0_u32;
+        while self.wout.load(Ordering::Acquire) != ticket {
+            backoff(&mut spins);
+        }
+        // Close the door to new readers, and wait for the ones in to leave.
+        let readers = self
+            .rin
+            .fetch_add(WRITER_PRESENT | (ticket & PHASE), Ordering::Acquire);
+        while self.rout.load(Ordering::Acquire) != readers {
+            backoff(&mut spins);
+        }
+        self.writer.store(true, Ordering::Relaxed);
+    }
+
+    fn try_lock_exclusive(&self) -> bool {
+        let ticket = self.wout.load(Ordering::Acquire);
+        if self
+            .win
+            .compare_exchange(ticket, ticket + 1, Ordering::Relaxed, Ordering::Relaxed)
+            .is_err()
+        {
+            return false;
+        }
+        // We are the only writer now, the lock is ours if no reader is in.
+        let readers = self.rout.load(Ordering::Acquire);
+        if self
+            .rin
+            .compare_exchange(
+                readers,
+                readers | WRITER_PRESENT | (ticket & PHASE),
+                Ordering::Acquire,
+                Ordering::Relaxed,
+            )
+            .is_err()
+        {
+            // Pass the turn on to the next writer.
+            self.wout.fetch_add(1, Ordering::Release);
+            return false;
+        }
+        self.writer.store(true, Ordering::Relaxed);
+        true
+    }
+
+    unsafe fn unlock_exclusive(&self) {
+        self.writer.store(false, Ordering::Relaxed);
+        // Let the readers that came in meanwhile go, then the next writer.
+        self.rin.fetch_and(!WRITER_BITS, Ordering::Release);
+        self.wout.fetch_add(1, Ordering::Release);
+    }
+
+    fn is_locked(&self) -> bool {
+        let rin = self.rin.load(Ordering::Relaxed);
+        rin & WRITER_BITS != 0 || rin != self.rout.load(Ordering::Relaxed)
+    }
+
+    fn is_locked_exclusive(&self) -> bool {
+        // Not WRITER_PRESENT: a writer still waiting for readers doesn't hold the lock yet.
+        self.writer.load(Ordering::Relaxed)
+    }
+
+    fn reader_count(&self) -> usize {
+        // The readers that came in and didn't leave, but those waiting for a writer to
+        // leave haven't got the lock. Loaded one after the other, so only approximate.
+        let blocked = self.blocked.load(Ordering::Relaxed);
+        let rout = self.rout.load(Ordering::Relaxed);
+        let rin = self.rin.load(Ordering::Relaxed);
+        ((rin & !WRITER_BITS).wrapping_sub(rout) / READER).saturating_sub(blocked)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{FairRwLock, WRITER_BITS, WRITER_PRESENT};
+    use crate::rwlock::RwLock;
+    use std::sync::Arc;
+    use std::sync::atomic::Ordering;
+    use std::thread;
+    use std::time::{Duration, Instant};
+
+    #[test]
+    fn test_fair_rwlock() {
+        let lock = FairRwLock::new(1);
+        let r1 = lock.read();
+        let r2 = lock.try_read().unwrap();
+        assert!(lock.try_write().is_none());
+        assert!(lock.is_locked() && !lock.is_locked_exclusive());
+        assert_eq!(lock.reader_count(), 2);
+        drop((r1, r2));
+        assert!(!lock.is_locked());
+        let mut w = lock.try_write().unwrap();
+        *w += 1;
+        assert!(lock.is_locked_exclusive());
+        assert!(lock.try_read().is_none());
+        assert!(lock.try_write().is_none());
+        drop(w);
+        *lock.write() += 1;
+        assert_eq!(*lock.read(), 3);
+        assert!(!lock.is_locked());
+    }
+
+    #[test]
+    fn test_is_locked_exclusive_with_blocked_reader() {
+        let lock = FairRwLock::new(());
+        let w = lock.write();
+        thread::scope(|s| {
+            let reader = s.spawn(|| drop(lock.read()));
+            // Wait for the reader to queue up behind the writer.
+            while lock.raw.rin.load(Ordering::Relaxed) & !WRITER_BITS == 0 {
+                thread::yield_now();
+            }
+            assert!(lock.is_locked_exclusive());
+            drop(w);
+            reader.join().unwrap();
+        });
+        assert!(!lock.is_locked_exclusive());
+        // A writer waiting for a reader to leave doesn't hold the lock yet.
+        let r = lock.read();
+        thread::scope(|s| {
+            let writer = s.spawn(|| drop(lock.write()));
+            while lock.raw.rin.load(Ordering::Relaxed) & WRITER_PRESENT == 0 {
+                thread::yield_now();
+            }
+            assert!(!lock.is_locked_exclusive());
+            drop(r);
+            writer.join().unwrap();
+        });
+    }
+
+    #[test]
+    fn test_reader_count_with_blocked_reader() {
+        let lock = FairRwLock::new(());
+        let r = lock.read();
+        thread::scope(|s| {
+            // A writer waits for r to leave, and a reader arriving after it waits for it.
+            let writer = s.spawn(|| drop(lock.write()));
+            while lock.raw.rin.load(Ordering::Relaxed) & WRITER_PRESENT == 0 {
+                thread::yield_now();
+            }
+            let reader = s.spawn(|| drop(lock.read()));
+            while lock.raw.blocked.load(Ordering::Relaxed) == 0 {
+                thread::yield_now();
+            }
+            assert_eq!(lock.reader_count(), 1);
+            drop(r);
+            writer.join().unwrap();
+            reader.join().unwrap();
+        });
+        assert_eq!(lock.reader_count(), 0);
+    }
+
+    #[test]
+    fn test_readers_and_writers() {
+        // The two halves of the pair are only ever seen equal by readers.
+        let lock = FairRwLock::new((0usize, 0usize));
+        thread::scope(|s| {
+            for _ in 0..4 {
+                s.spawn(|| {
+                    for _ in 0..1000 {
+                        let mut w = lock.write();
+                        w.0 += 1;
+                        thread::yield_now();
+                        w.1 += 1;
+                    }
+                });
+                s.spawn(|| {
+                    for _ in 0..5000 {
+                        let r = lock.read();
+                        assert_eq!(r.0, r.1);
+                    }
+                });
+                s.spawn(|| {
+                    for _ in 0..1000 {
+                        if let Some(mut w) = lock.try_write() {
+                            w.0 += 1;
+                            w.1 += 1;
+                        }
+                        if let Some(r) = lock.try_read() {
+                            assert_eq!(r.0, r.1);
+                        }
+                    }
+                });
+            }
+        });
+        let (a, b) = lock.into_inner();
+        assert_eq!(a, b);
+        assert!(a >= 4000);
+    }
+
+    // Holds the lock for a moment.
+    fn hold() {
+        thread::sleep(Duration::from_micros(100));
+    }
+
+    // Runs 4 readers and 4 writers taking the lock 100 times each, returns the worst time a
+    // reader and a writer waited for the lock (plus the time they held it).
+    fn worst_waits<L: Send + Sync + 'static>(
+        lock: L,
+        read: fn(&L),
+        write: fn(&L),
+    ) -> (Duration, Duration) {
+        let lock = Arc::new(lock);
+        let spawn = |op: fn(&L)| {
+            let lock = Arc::clone(&lock);
+            thread::spawn(move || {
+                let mut worst = Duration::ZERO;
+                for _ in 0..100 {
+                    let time = Instant::now();
+                    op(&lock);
+                    worst = worst.max(time.elapsed());
+                }
+                worst
+            })
+        };
+        let readers: Vec<_> = (0..4).map(|_| spawn(read)).collect();
+        let writers: Vec<_> = (0..4).map(|_| spawn(write)).collect();
+        let worst = |handles: Vec<thread::JoinHandle<Duration>>| {
+            handles
+                .into_iter()
+                .map(|h| h.join().unwrap())
+                .max()
+                .unwrap()
+        };
+        (worst(readers), worst(writers))
+    }
+
+    #[test]
+    fn test_fair_rwlock_worst_waits_vs_rwlock() {
+        let fair = worst_waits(
+            FairRwLock::new(()),
+            |l| drop((l.read(), hold())),
+            |l| drop((l.write(), hold())),
+        );
+        let rwlock = worst_waits(
+            RwLock::new(()),
+            |l| drop((l.read(), hold())),
+            |l| drop((l.write(), hold())),
+        );
+        println!(
+            "Worst waits in FairRwLock: readers {:?}, writers {:?}; \
+             in RwLock: readers {:?}, writers {:?}",
+            fair.0, fair.1, rwlock.0, rwlock.1
+        );
+        // Readers wait for one writer at most, rather than behind a stream of them, and
+        // writers for one batch of readers per writer ahead; RwLock is many times worse.
+        assert!(fair.0 < rwlock.0);
+        assert!(fair.1 < rwlock.1);
+    }
+}
@@ -0,0 +1,185 @@
+use crate::futex_mutex::{FutexMutexGuard, RawFutexMutex};
+use crate::platform;
+use std::hint::spin_loop;
+use std::ptr;
+use std::sync::atomic::{AtomicPtr, AtomicU32, Ordering};
+
+/// A condition variable for FutexMutex, waiting on a futex of its own.
+///
+/// With Condvar, notify_all wakes every waiter, and they all run at once only to find the
+/// mutex held by one of them and go back to sleep on it. Here notify_all wakes a single
+/// waiter and moves the others onto the mutex's futex (FUTEX_REQUEUE), so they wake one at a
+/// time as the mutex is released. That takes Linux, other platforms wake them all.
+///
+/// A FutexCondvar must always be used with the same mutex, it panics otherwise. As with any
+/// condition variable, the condition should be checked again in a loop after `wait`.
+pub struct FutexCondvar {
+    // Bumped by every notification, the waiters sleep until it changes.
+    seq: AtomicU32,
+    // The threads in wait, which borrow the mutex until they return.
+    waiters: AtomicU32,
+    // The notify_all calls using the mutex pointer, which the waiters wait for to return.
+    requeuing: AtomicU32,
+    // The state of the mutex the condvar is used with, null until the first wait.
+    mutex: AtomicPtr<AtomicU32>,
+}
+
+impl FutexCondvar {
+    pub const fn new() -> FutexCondvar {
+        FutexCondvar {
+            seq: AtomicU32::new(0),
+            waiters: AtomicU32::new(0),
+            requeuing: AtomicU32::new(0),
+            mutex: AtomicPtr::new(ptr::null_mut()),
+        }
+    }
+
+    /// Releases the lock held by `guard` and blocks until the condvar is notified, then
+    /// takes the lock again.
+    #[cfg_attr(feature = "deadlock_detection", track_caller)]
+    pub fn wait<'a, T>(&self, guard: FutexMutexGuard<'a, T>) -> FutexMutexGuard<'a, T> {
+        let mutex = guard.mutex;
+        let state = ptr::from_ref(&mutex.raw.state).cast_mut();
+        if let Err(other) = self.mutex.compare_exchange(
+            ptr::null_mut(),
+            state,
+            Ordering::Relaxed,
+            Ordering::Relaxed,
+        ) {
+            assert_eq!(other, state, "FutexCondvar used with two mutexes");
+        }
+        // Counted in first, then read the sequence number, while notifications do the
+        // opposite: with SeqCst, either they see us, or we see their new value and the futex
+        // wait returns right away.
+        self.waiters.fetch_add(1, Ordering::SeqCst);
+        let seq = self.seq.load(Ordering::SeqCst);
+        drop(guard);
+        platform::wait(&self.seq, seq);
+        // We may have been moved onto the mutex's futex, behind others, so take the lock the
+        // way that wakes the next one when we release it.
+        let guard = mutex.lock_with(RawFutexMutex::lock_contended);
+        // The mutex may be dropped once we return, so notify_all must not be using it.
+        self.waiters.fetch_sub(1, Ordering::SeqCst);
+        while self.requeuing.load(Ordering::SeqCst) != 0 {
+            spin_loop();
+        }
+        guard
+    }
+
+    /// Wakes up one thread blocked in `wait`, if any.
+    pub fn notify_one(&self) {
+        self.seq.fetch_add(1, Ordering::SeqCst);
+        if self.waiters.load(Ordering::SeqCst) != 0 {
+            platform::wake_one(&self.seq);
+        }
+    }
+
+    /// Wakes up one thread blocked in `wait`, and moves the others onto the mutex: they wake
+    /// up in turn as the mutex is released.
+    pub fn notify_all(&self) {
+        self.seq.fetch_add(1, Ordering::SeqCst);
+        self.requeuing.fetch_add(1, Ordering::SeqCst);
+        if self.waiters.load(Ordering::SeqCst) != 0 {
+            // SAFETY: there is a waiter, which borrows the mutex and doesn't return before
+            // we are done with it.
+            let mutex = unsafe { &*self.mutex.load(Ordering::Relaxed) };
+            platform::requeue(&self.seq, mutex);
+        }
+        self.requeuing.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+impl Default for FutexCondvar {
+    fn default() -> Self {
+        FutexCondvar::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::FutexCondvar;
+    use crate::futex_mutex::FutexMutex;
+    use std::collections::VecDeque;
+    use std::sync::Arc;
+    use std::sync::atomic::Ordering;
+    use std::thread;
+
+    #[test]
+    fn test_producer_consumer() {
+        let queue = Arc::new((FutexMutex::new(VecDeque::new()), FutexCondvar::new()));
+        let consumer = {
+            let queue = queue.clone();
+            thread::spawn(move || {
+                let (items, not_empty) = &*queue;
+                let mut sum = 0;
+                loop {
+                    let mut guard = items.lock();
+                    while guard.is_empty() {
+                        guard = not_empty.wait(guard);
+                    }
+                    match guard.pop_front().unwrap() {
+                        Some(n) => sum += n,
+                        None => return sum,
+                    }
+                }
+            })
+        };
+        let (items, not_empty) = &*queue;
+        for n in 1..=1000 {
+            items.lock().push_back(Some(n));
+            not_empty.notify_one();
+        }
+        items.lock().push_back(None);
+        not_empty.notify_one();
+        assert_eq!(consumer.join().unwrap(), 500_500);
+    }
+
+    #[test]
+    fn test_notify_all_requeues_onto_the_mutex() {
+        let mutex = FutexMutex::new((false, 0));
+        let cvar = FutexCondvar::new();
+        thread::scope(|s| {
+            for _ in 0..8 {
+                s.spawn(|| {
+                    let mut guard = mutex.lock();
+                    guard.1 += 1;
+                    while !guard.0 {
+                        guard = cvar.wait(guard);
+                    }
+                    // Holding the lock for a while, the others can't all be running now.
+                    thread::yield_now();
+                    guard.1 -= 1;
+                });
+            }
+            // Wait for every thread to be waiting, or about to.
+            while mutex.lock().1 != 8 {
+                thread::yield_now();
+            }
+            // Notify with the lock held: the woken thread waits for it, the others on it.
+            let mut guard = mutex.lock();
+            guard.0 = true;
+            cvar.notify_all();
+            drop(guard);
+        });
+        assert_eq!(mutex.into_inner(), (true, 0));
+    }
+
+    #[test]
+    #[should_panic(expected = "FutexCondvar used with two mutexes")]
+    fn test_one_mutex_per_condvar() {
+        let (a, b) = (FutexMutex::new(()), FutexMutex::new(()));
+        let cvar = FutexCondvar::new();
+        thread::scope(|s| {
+            s.spawn(|| {
+                let _guard = cvar.wait(a.lock());
+            });
+            while cvar.waiters.load(Ordering::Relaxed) == 0 {
+                thread::yield_now();
+            }
+            // Once the waiter has released the lock, it is waiting or about to.
+            drop(a.lock());
+            cvar.notify_one();
+        });
+        let _guard = cvar.wait(b.lock());
+    }
+}
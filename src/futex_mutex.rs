@@ -1,70 +1,124 @@
-use linux_futex::{Futex, Private};
-use std::cell::UnsafeCell;
+use crate::lock_api::{self, GuardSend, RawMutex};
+use crate::platform;
 use std::hint::spin_loop;
-use std::ops::{Deref, DerefMut};
-use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::time::{Duration, Instant};
 
-pub struct FutexMutex<T> {
-    value: UnsafeCell<T>,
-    futex: Futex<Private>,
+const UNLOCKED: u32 = 0;
+const LOCKED: u32 = 1;
+const CONTENDED: u32 = 2;
+
+// How many times lock checks the lock while spinning, before sleeping on the futex.
+const SPIN_LIMIT: usize = 100;
+
+pub type FutexMutex<T> = lock_api::Mutex<RawFutexMutex, T>;
+
+/// The guard of a locked FutexMutex, which can't be sent to another thread:
+/// ```compile_fail
+/// use pointers::futex_mutex::FutexMutex;
+/// let mutex = FutexMutex::new(0);
+/// let guard = mutex.lock();
+/// std::thread::scope(|s| {
+///     s.spawn(move || drop(guard));
+/// });
+/// ```
+pub type FutexMutexGuard<'a, T> = lock_api::MutexGuard<'a, RawFutexMutex, T>;
+
+/// A guard returned by FutexMutex::lock_owned, which holds the lock as long as it lives.
+pub type OwnedFutexMutexGuard<T> = lock_api::OwnedMutexGuard<RawFutexMutex, T>;
+
+/// The raw lock of FutexMutex: UNLOCKED, LOCKED, or CONTENDED when threads may be waiting
+/// on the futex, so that unlocking an uncontended lock needs no system call.
+pub struct RawFutexMutex {
+    pub(crate) state: AtomicU32,
 }
 
-unsafe impl<T: Send> Sync for FutexMutex<T> {}
+unsafe impl lock_api::RawMutex for RawFutexMutex {
+    const INIT: RawFutexMutex = RawFutexMutex {
+        state: AtomicU32::new(UNLOCKED),
+    };
 
-impl<T> FutexMutex<T> {
-    pub fn new(value: T) -> Self {
-        Self {
-            value: UnsafeCell::new(value),
-            futex: Futex::new(0),
+    type GuardMarker = GuardSend;
+
+    fn lock(&self) {
+        if self.spin_lock() {
+            return;
         }
+        self.lock_contended();
+    }
+
+    fn try_lock(&self) -> bool {
+        self.state
+            .compare_exchange(UNLOCKED, LOCKED, Ordering::Acquire, Ordering::Relaxed)
+            .is_ok()
     }
 
-    pub fn lock(&self) -> FutexMutexGuard<'_, T> {
-        while self
-            .futex
-            .value
-            .compare_exchange(0, 1, Ordering::Acquire, Ordering::Relaxed)
-            .is_err()
-        {
-            self.futex.wait(1);
+    unsafe fn unlock(&self) {
+        if self.state.swap(UNLOCKED, Ordering::Release) == CONTENDED {
+            platform::wake_one(&self.state);
         }
-        FutexMutexGuard { mutex: self }
     }
-}
 
-pub struct FutexMutexGuard<'a, T> {
-    mutex: &'a FutexMutex<T>,
+    fn is_locked(&self) -> bool {
+        self.state.load(Ordering::Relaxed) != UNLOCKED
+    }
 }
 
-unsafe impl<T: Sync> Sync for FutexMutexGuard<'_, T> {}
-
-impl<'a, T> Deref for FutexMutexGuard<'a, T> {
-    type Target = T;
-    fn deref(&self) -> &T {
-        unsafe { &*self.mutex.value.get() }
+unsafe impl lock_api::RawMutexTimed for RawFutexMutex {
+    fn lock_timeout(&self, timeout: Duration) -> bool {
+        let deadline = Instant::now() + timeout;
+        if self.spin_lock() {
+            return true;
+        }
+        while self.state.swap(CONTENDED, Ordering::Acquire) != UNLOCKED {
+            let now = Instant::now();
+            if now >= deadline {
+                // The lock stays marked contended, which only costs its holder a wake.
+                return false;
+            }
+            platform::wait_timeout(&self.state, CONTENDED, deadline - now);
+        }
+        true
     }
 }
 
-impl<'a, T> DerefMut for FutexMutexGuard<'a, T> {
-    fn deref_mut(&mut self) -> &mut T {
-        unsafe { &mut *self.mutex.value.get() }
+impl RawFutexMutex {
+    // Mark the lock as contended before sleeping, so the unlock knows to wake a thread. As
+    // we can't tell whether other threads are waiting, the lock stays contended once we get
+    // it. FutexCondvar waiters take the lock this way, as others may be waiting behind them.
+    pub(crate) fn lock_contended(&self) {
+        while self.state.swap(CONTENDED, Ordering::Acquire) != UNLOCKED {
+            platform::wait(&self.state, CONTENDED);
+        }
     }
-}
 
-impl<T> Drop for FutexMutexGuard<'_, T> {
-    fn drop(&mut self) {
-        self.mutex.futex.value.store(0, Ordering::Release);
-        self.mutex.futex.wake(1);
+    // Tries to take the lock, spinning a little while it is held.
+    fn spin_lock(&self) -> bool {
+        if self.try_lock() {
+            return true;
+        }
+        // The lock is usually held for a short time: spinning a little avoids two system
+        // calls, the wait and the wake that ends it. Like Mutex, only attempt the
+        // compare_exchange once the lock looks free.
+        for _ in 0..SPIN_LIMIT {
+            spin_loop();
+            if !self.is_locked() && self.try_lock() {
+                return true;
+            }
+        }
+        false
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use super::FutexMutex;
+    use super::{CONTENDED, FutexMutex, LOCKED, RawFutexMutex, UNLOCKED};
+    use crate::lock_api::{self, GuardSend, RawMutex};
+    use crate::platform;
     use std::sync::Arc;
+    use std::sync::atomic::Ordering;
     use std::thread;
-    use std::time::Duration;
-    use std::time::SystemTime;
+    use std::time::{Duration, Instant, SystemTime};
 
     #[test]
     fn test_futex_mutex() {
@@ -80,15 +134,100 @@ mod tests {
     }
 
     #[test]
-    fn test_futex_mutex_contention_increment() {
+    fn test_lock_timeout() {
+        let mutex = FutexMutex::new(0);
+        let guard = mutex.lock();
+        thread::scope(|s| {
+            s.spawn(|| {
+                let time = Instant::now();
+                assert!(mutex.lock_timeout(Duration::from_millis(20)).is_none());
+                assert!(time.elapsed() >= Duration::from_millis(20));
+            })
+            .join()
+            .unwrap();
+            let waiter = s.spawn(|| *mutex.lock_timeout(Duration::from_secs(10)).unwrap() += 1);
+            thread::sleep(Duration::from_millis(20));
+            drop(guard);
+            waiter.join().unwrap();
+        });
+        assert_eq!(*mutex.lock_timeout(Duration::ZERO).unwrap(), 1);
+    }
+
+    #[test]
+    fn test_try_lock_into_inner_and_get_mut() {
+        let mut mutex = FutexMutex::new(vec![1]);
+        mutex.get_mut().push(2);
+        let guard = mutex.try_lock().unwrap();
+        assert!(mutex.try_lock().is_none());
+        thread::scope(|s| {
+            s.spawn(|| assert!(mutex.try_lock().is_none()))
+                .join()
+                .unwrap()
+        });
+        drop(guard);
+        let mutex = Arc::new(mutex);
+        let mut guard = Arc::clone(&mutex).try_lock_owned().unwrap();
+        thread::spawn(move || guard.push(3)).join().unwrap();
+        let mutex = Arc::into_inner(mutex).unwrap();
+        assert!(!mutex.is_locked());
+        assert_eq!(mutex.into_inner(), [1, 2, 3]);
+    }
+
+    // The lock without spinning, sleeping on the futex as soon as the lock is taken.
+    struct RawNoSpinFutexMutex(RawFutexMutex);
+
+    unsafe impl lock_api::RawMutex for RawNoSpinFutexMutex {
+        const INIT: RawNoSpinFutexMutex = RawNoSpinFutexMutex(RawFutexMutex::INIT);
+        type GuardMarker = GuardSend;
+
+        fn lock(&self) {
+            if self.try_lock() {
+                return;
+            }
+            while self.0.state.swap(CONTENDED, Ordering::Acquire) != UNLOCKED {
+                platform::wait(&self.0.state, CONTENDED);
+            }
+        }
+
+        fn try_lock(&self) -> bool {
+            self.0.try_lock()
+        }
+
+        unsafe fn unlock(&self) {
+            unsafe { self.0.unlock() };
+        }
+    }
+
+    #[test]
+    fn test_wake_only_when_contended() {
+        let mutex = FutexMutex::new(0);
+        *mutex.lock() += 1;
+        assert_eq!(mutex.raw.state.load(Ordering::Relaxed), UNLOCKED);
+        let guard = mutex.lock();
+        assert_eq!(mutex.raw.state.load(Ordering::Relaxed), LOCKED);
+        thread::scope(|s| {
+            let waiter = s.spawn(|| *mutex.lock() += 1);
+            // The waiter marks the lock before sleeping on the futex.
+            while mutex.raw.state.load(Ordering::Relaxed) != CONTENDED {
+                thread::yield_now();
+            }
+            drop(guard);
+            waiter.join().unwrap();
+        });
+        assert_eq!(mutex.raw.state.load(Ordering::Relaxed), UNLOCKED);
+        assert_eq!(mutex.into_inner(), 2);
+    }
+
+    // Runs 40 threads doing 250k increments each under the mutex, returns the time in ms.
+    fn contention<R: RawMutex + Send + Sync + 'static>() -> u128 {
         let time = SystemTime::now();
-        let mutex = Arc::new(FutexMutex::new(0usize));
+        let mutex = Arc::new(lock_api::Mutex::<R, usize>::new(0));
         let mut handles = vec![];
 
         for _ in 0..40 {
             let m = Arc::clone(&mutex);
             handles.push(thread::spawn(move || {
-                for _ in 0..1000000 {
+                for _ in 0..250_000 {
                     let mut guard = m.lock();
                     *guard += 1;
                 }
@@ -98,10 +237,17 @@ mod tests {
         for h in handles {
             h.join().unwrap();
         }
-        assert_eq!(*mutex.lock(), 40000000);
+        assert_eq!(*mutex.lock(), 10_000_000);
+        time.elapsed().unwrap().as_millis()
+    }
+
+    #[test]
+    fn test_futex_mutex_contention_increment() {
+        let spinning = contention::<RawFutexMutex>();
+        let sleeping = contention::<RawNoSpinFutexMutex>();
         println!(
-            "Time taken in my futex Mutex: {}ms",
-            time.elapsed().unwrap().as_millis()
+            "Time taken in my futex Mutex: {spinning}ms, without spinning before waiting: \
+             {sleeping}ms"
         );
     }
 }
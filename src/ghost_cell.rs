@@ -0,0 +1,147 @@
+use std::cell::UnsafeCell;
+use std::marker::PhantomData;
+
+/// GhostCell separates the permission to access data from the data itself.
+/// Every GhostCell<'brand, T> belongs to exactly one GhostToken<'brand>, and reading or writing
+/// the cell requires borrowing that token: a shared borrow of the token gives &T, an exclusive
+/// borrow gives &mut T. So the usual borrow rules are checked at compile time on the token,
+/// while the cells themselves can be freely aliased (e.g. nodes of a doubly-linked graph).
+/// Unlike RefCell, there is no borrow state and no runtime check at all.
+///
+/// The 'brand lifetime ties cells to their token. GhostToken::new hands out a token with a
+/// fresh, unnameable brand that only exists inside the closure, so two tokens can never share one:
+/// ```compile_fail
+/// use pointers::ghost_cell::{GhostCell, GhostToken};
+/// GhostToken::new(|mut token1| {
+///     GhostToken::new(|token2| {
+///         let cell = GhostCell::new(0);
+///         *cell.borrow_mut(&mut token1) = 1;
+///         // The cell is branded by token1, token2 can't read it.
+///         println!("{}", cell.borrow(&token2));
+///     });
+/// });
+/// ```
+///
+/// And the token can't be lent exclusively while a shared borrow through it is alive:
+/// ```compile_fail
+/// use pointers::ghost_cell::{GhostCell, GhostToken};
+/// GhostToken::new(|mut token| {
+///     let a = GhostCell::new(0);
+///     let b = &a;
+///     let shared = a.borrow(&token);
+///     *b.borrow_mut(&mut token) = 1;
+///     println!("{shared}");
+/// });
+/// ```
+#[repr(transparent)]
+pub struct GhostCell<'brand, T: ?Sized> {
+    _brand: InvariantLifetime<'brand>,
+    value: UnsafeCell<T>,
+}
+
+/// The token granting access to all the GhostCells of the same brand.
+pub struct GhostToken<'brand> {
+    _brand: InvariantLifetime<'brand>,
+}
+
+// Invariant in 'brand, so the compiler can neither shrink nor grow a brand to make two
+// different ones match.
+#[derive(Clone, Copy, Default)]
+struct InvariantLifetime<'brand>(PhantomData<fn(&'brand ()) -> &'brand ()>);
+
+// Access to the value is synchronized by the token, just like a &T or &mut T would be.
+unsafe impl<T: ?Sized + Send> Send for GhostCell<'_, T> {}
+unsafe impl<T: ?Sized + Send + Sync> Sync for GhostCell<'_, T> {}
+
+impl GhostToken<'_> {
+    /// Runs f with a token of a brand new brand.
+    #[allow(clippy::new_ret_no_self)]
+    pub fn new<R>(f: impl for<'new_brand> FnOnce(GhostToken<'new_brand>) -> R) -> R {
+        f(GhostToken {
+            _brand: InvariantLifetime::default(),
+        })
+    }
+}
+
+impl<'brand, T> GhostCell<'brand, T> {
+    pub const fn new(value: T) -> Self {
+        Self {
+            _brand: InvariantLifetime(PhantomData),
+            value: UnsafeCell::new(value),
+        }
+    }
+
+    pub fn into_inner(self) -> T {
+        self.value.into_inner()
+    }
+}
+
+impl<'brand, T: ?Sized> GhostCell<'brand, T> {
+    /// Immutably borrows the cell's content, which requires a shared borrow of the token.
+    pub fn borrow<'a>(&'a self, _token: &'a GhostToken<'brand>) -> &'a T {
+        // SAFETY: an exclusive borrow_mut would need &mut token, which can't coexist with &token.
+        unsafe { &*self.value.get() }
+    }
+
+    /// Mutably borrows the cell's content, which requires an exclusive borrow of the token.
+    pub fn borrow_mut<'a>(&'a self, _token: &'a mut GhostToken<'brand>) -> &'a mut T {
+        // SAFETY: the token is exclusively borrowed for 'a, so no other borrow of any cell
+        // of this brand can exist for that long.
+        unsafe { &mut *self.value.get() }
+    }
+
+    /// Returns a mutable reference to the content, &mut self already proves exclusivity.
+    pub fn get_mut(&mut self) -> &mut T {
+        self.value.get_mut()
+    }
+
+    /// Turns a &mut T into a &GhostCell<T> of any brand.
+    pub fn from_mut(t: &mut T) -> &Self {
+        // SAFETY: GhostCell is repr(transparent) over T, and the exclusive borrow guarantees
+        // that only the token can grant access while the cell is in use.
+        unsafe { &*(t as *mut T as *const Self) }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{GhostCell, GhostToken};
+
+    #[test]
+    fn test_ghost_cell() {
+        GhostToken::new(|mut token| {
+            let cell = GhostCell::new(vec![1, 2]);
+            cell.borrow_mut(&mut token).push(3);
+            assert_eq!(*cell.borrow(&token), vec![1, 2, 3]);
+            assert_eq!(cell.into_inner(), vec![1, 2, 3]);
+        });
+    }
+
+    #[test]
+    fn test_ghost_cell_aliased_graph() {
+        struct Node<'brand> {
+            value: GhostCell<'brand, i32>,
+            next: Option<&'brand Node<'brand>>,
+        }
+
+        GhostToken::new(|mut token| {
+            // Both nodes point at each other through plain shared references...
+            let tail = Box::leak(Box::new(Node {
+                value: GhostCell::new(2),
+                next: None,
+            }));
+            let head = Node {
+                value: GhostCell::new(1),
+                next: Some(tail),
+            };
+            // ...and yet each can be mutated through any of its aliases.
+            let mut node = Some(&head);
+            while let Some(n) = node {
+                *n.value.borrow_mut(&mut token) *= 10;
+                node = n.next;
+            }
+            assert_eq!(*head.value.borrow(&token), 10);
+            assert_eq!(*head.next.unwrap().value.borrow(&token), 20);
+        });
+    }
+}
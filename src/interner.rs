@@ -0,0 +1,171 @@
+use crate::arc::Arc;
+use crate::rwlock::RwLock;
+use std::borrow::Borrow;
+use std::collections::HashSet;
+use std::hash::{Hash, Hasher};
+
+/// A thread-safe string interner: every distinct string is stored once, as an Arc<str>,
+/// and interning the same string again returns a handle to the same allocation.
+/// Symbols can then be compared and hashed by address, no matter how long the strings are.
+pub struct Interner {
+    strings: RwLock<HashSet<Entry>>,
+}
+
+/// An interned string. Two Symbols from the same Interner are equal exactly when they hold
+/// the same string, which is checked by comparing pointers.
+#[derive(Clone)]
+pub struct Symbol(Arc<str>);
+
+// Set entries are looked up by content, with a &str.
+struct Entry(Arc<str>);
+
+impl PartialEq for Entry {
+    fn eq(&self, other: &Entry) -> bool {
+        *self.0 == *other.0
+    }
+}
+
+impl Eq for Entry {}
+
+impl Hash for Entry {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        // Has to hash like the &str it is borrowed as.
+        (*self.0).hash(state)
+    }
+}
+
+impl Borrow<str> for Entry {
+    fn borrow(&self) -> &str {
+        &self.0
+    }
+}
+
+impl Interner {
+    pub fn new() -> Interner {
+        Interner {
+            strings: RwLock::new(HashSet::new()),
+        }
+    }
+
+    /// Returns the Symbol for `s`, adding it to the interner if it is not there yet.
+    pub fn intern(&self, s: &str) -> Symbol {
+        // Most strings are already interned, which only needs the read lock.
+        if let Some(entry) = self.strings.read().get(s) {
+            return Symbol(entry.0.clone());
+        }
+        let mut strings = self.strings.write();
+        // Another thread may have interned it between the two locks.
+        if let Some(entry) = strings.get(s) {
+            return Symbol(entry.0.clone());
+        }
+        let string: Arc<str> = Arc::from(s);
+        strings.insert(Entry(string.clone()));
+        Symbol(string)
+    }
+
+    /// Returns the Symbol for `s` if it has already been interned.
+    pub fn get(&self, s: &str) -> Option<Symbol> {
+        self.strings
+            .read()
+            .get(s)
+            .map(|entry| Symbol(entry.0.clone()))
+    }
+
+    /// Returns the number of distinct strings interned.
+    pub fn len(&self) -> usize {
+        self.strings.read().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+impl Default for Interner {
+    fn default() -> Self {
+        Interner::new()
+    }
+}
+
+impl Symbol {
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+impl PartialEq for Symbol {
+    fn eq(&self, other: &Symbol) -> bool {
+        Arc::ptr_eq(&self.0, &other.0)
+    }
+}
+
+impl Eq for Symbol {}
+
+impl Hash for Symbol {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        // Consistent with eq, which compares addresses.
+        Arc::as_ptr(&self.0).cast::<u8>().hash(state)
+    }
+}
+
+impl std::ops::Deref for Symbol {
+    type Target = str;
+    fn deref(&self) -> &str {
+        &self.0
+    }
+}
+
+impl std::fmt::Debug for Symbol {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        std::fmt::Debug::fmt(self.as_str(), f)
+    }
+}
+
+impl std::fmt::Display for Symbol {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        std::fmt::Display::fmt(self.as_str(), f)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::thread;
+
+    #[test]
+    fn test_intern() {
+        let interner = Interner::new();
+        let a = interner.intern("hello");
+        let b = interner.intern(&String::from("hello"));
+        let c = interner.intern("world");
+        assert_eq!(a, b);
+        assert!(Arc::ptr_eq(&a.0, &b.0));
+        assert_ne!(a, c);
+        assert_eq!(&*a, "hello");
+        assert_eq!(format!("{c} {c:?}"), "world \"world\"");
+        assert_eq!(interner.len(), 2);
+        assert_eq!(interner.get("world"), Some(c));
+        assert_eq!(interner.get("missing"), None);
+    }
+
+    #[test]
+    fn test_intern_from_many_threads() {
+        let interner = Arc::new(Interner::new());
+        let handles: Vec<_> = (0..8)
+            .map(|_| {
+                let interner = interner.clone();
+                thread::spawn(move || {
+                    (0..100)
+                        .map(|i| interner.intern(&format!("sym{i}")))
+                        .collect::<Vec<_>>()
+                })
+            })
+            .collect();
+        let results: Vec<Vec<Symbol>> = handles.into_iter().map(|h| h.join().unwrap()).collect();
+        // Every thread got the very same symbols.
+        for symbols in &results[1..] {
+            assert_eq!(symbols, &results[0]);
+        }
+        assert_eq!(interner.len(), 100);
+    }
+}
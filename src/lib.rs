@@ -1,13 +1,59 @@
 #![allow(unused)]
-mod arc;
-mod async_mutex;
+#![cfg_attr(
+    feature = "nightly",
+    feature(coerce_unsized, dispatch_from_dyn, unsize)
+)]
+#![cfg_attr(feature = "allocator_api", feature(allocator_api))]
+mod allocator;
+pub mod arc;
+pub mod async_condvar;
+pub mod async_mutex;
+pub mod async_once_cell;
+pub mod async_semaphore;
+mod atomic_arc;
+pub mod atomic_cell;
+mod atomic_refcell;
+pub mod cancellation_token;
 pub mod cell;
+pub mod condvar;
+mod deleter;
+pub mod fair_rwlock;
+pub mod futex_condvar;
+pub mod futex_mutex;
+pub mod ghost_cell;
+mod interner;
+pub mod lock_api;
+#[cfg(feature = "deadlock_detection")]
+mod lock_order;
+pub mod mcs_lock;
+pub mod mpsc;
+pub mod mutex;
+pub mod notify;
+mod once_cell;
+pub mod once_lock;
+mod parking;
 #[cfg(target_os = "linux")]
-mod futex_mutex;
-mod mutex;
+pub mod pi_futex_mutex;
+mod platform;
+#[cfg(feature = "poison")]
+pub mod poison;
+mod qcell;
 mod rc;
 mod refcell;
-mod rwlock;
+pub mod rwlock;
+pub mod seqlock;
+pub mod sharded_rwlock;
+#[cfg(target_os = "linux")]
+pub mod shared_futex_mutex;
+mod sync_unsafe_cell;
+mod thin_arc;
+mod thin_rc;
+pub mod ticket_mutex;
+mod timer;
+pub mod wait_group;
+mod waker_list;
+mod weak_map;
+pub mod word_lock;
 /*
 # Rc
 ## Multiple Ownership:
@@ -0,0 +1,1122 @@
+//! The locks are split in two layers, like in the lock_api crate: a raw lock, which only
+//! knows how to lock and unlock, and the generic Mutex<R, T> and RwLock<R, T>, which pair a
+//! raw lock with the data it protects and hand out the guards. Mutex, FutexMutex, WordLock
+//! and the other locks of the crate are aliases of them with their own raw lock, and any
+//! type implementing RawMutex or RawRwLock can be plugged in the same way.
+
+#[cfg(feature = "deadlock_detection")]
+use crate::lock_order::{self, LockId};
+#[cfg(feature = "poison")]
+use crate::poison::{self, LockResult, PoisonError};
+use std::cell::UnsafeCell;
+use std::marker::PhantomData;
+use std::ops::{Deref, DerefMut};
+use std::sync::Arc;
+use std::time::Duration;
+
+/// A lock without data, for Mutex<R, T>.
+///
+/// # Safety
+/// Implementations must make sure that the lock is held by at most one context at a time,
+/// and that lock and try_lock synchronize with the previous unlock (acquire/release).
+pub unsafe trait RawMutex {
+    /// An unlocked lock, used by the const constructors.
+    #[allow(clippy::declare_interior_mutable_const)]
+    const INIT: Self;
+
+    /// GuardSend if the lock can be released by another thread than the one that took it,
+    /// which lets owned guards be sent to other threads, GuardNoSend otherwise. Guards that
+    /// borrow the lock are never Send, like std's.
+    type GuardMarker;
+
+    /// Acquires the lock, blocking the current thread until it is able to do so.
+    fn lock(&self);
+
+    /// Attempts to acquire the lock without blocking, returning true on success.
+    fn try_lock(&self) -> bool;
+
+    /// Releases the lock.
+    ///
+    /// # Safety
+    /// The lock must be held by the current context.
+    unsafe fn unlock(&self);
+
+    /// Returns true if the lock is held, without acquiring it. The answer may already be
+    /// stale when it is returned, so it is only good for monitoring and assertions.
+    fn is_locked(&self) -> bool {
+        let acquired = self.try_lock();
+        if acquired {
+            unsafe { self.unlock() };
+        }
+        !acquired
+    }
+}
+
+/// A mutex that can give up waiting, for Mutex::lock_timeout.
+///
+/// # Safety
+/// Same as RawMutex, for lock_timeout when it returns true.
+pub unsafe trait RawMutexTimed: RawMutex {
+    /// Acquires the lock, waiting at most `timeout` for it. Returns true on success.
+    fn lock_timeout(&self, timeout: Duration) -> bool;
+}
+
+/// A reader-writer lock without data, for RwLock<R, T>.
+///
+/// # Safety
+/// Implementations must make sure that an exclusive lock is never held together with any
+/// other lock, and that locking synchronizes with the previous unlocks (acquire/release).
+pub unsafe trait RawRwLock {
+    /// An unlocked lock, used by the const constructors.
+    #[allow(clippy::declare_interior_mutable_const)]
+    const INIT: Self;
+
+    /// GuardSend if the lock can be released by another thread than the one that took it,
+    /// which lets owned guards be sent to other threads, GuardNoSend otherwise. Guards that
+    /// borrow the lock are never Send, like std's.
+    type GuardMarker;
+
+    /// Acquires a shared lock, blocking the current thread until it is able to do so.
+    fn lock_shared(&self);
+
+    /// Attempts to acquire a shared lock without blocking, returning true on success.
+    fn try_lock_shared(&self) -> bool;
+
+    /// Releases a shared lock.
+    ///
+    /// # Safety
+    /// A shared lock must be held by the current context.
+    unsafe fn unlock_shared(&self);
+
+    /// Acquires the exclusive lock, blocking the current thread until it is able to do so.
+    fn lock_exclusive(&self);
+
+    /// Attempts to acquire the exclusive lock without blocking, returning true on success.
+    fn try_lock_exclusive(&self) -> bool;
+
+    /// Releases the exclusive lock.
+    ///
+    /// # Safety
+    /// The exclusive lock must be held by the current context.
+    unsafe fn unlock_exclusive(&self);
+
+    /// Returns true if the lock is held, shared or exclusive, without acquiring it.
+    fn is_locked(&self) -> bool {
+        let acquired = self.try_lock_exclusive();
+        if acquired {
+            unsafe { self.unlock_exclusive() };
+        }
+        !acquired
+    }
+
+    /// Returns the number of shared locks held, upgradeable ones included.
+    fn reader_count(&self) -> usize;
+
+    /// Returns true if the exclusive lock is held, without acquiring it.
+    fn is_locked_exclusive(&self) -> bool {
+        let acquired = self.try_lock_shared();
+        if acquired {
+            unsafe { self.unlock_shared() };
+        }
+        !acquired
+    }
+}
+
+/// A reader-writer lock that also has an upgradeable lock, for RwLock::upgradeable_read.
+///
+/// # Safety
+/// The upgradeable lock must be exclusive with other upgradeable locks and with the exclusive
+/// lock, but not with shared locks. upgrade must turn it into the exclusive lock without
+/// letting any other writer or upgradeable reader in between.
+pub unsafe trait RawRwLockUpgrade: RawRwLock {
+    /// Acquires the upgradeable lock, blocking the current thread until it is able to do so.
+    fn lock_upgradeable(&self);
+
+    /// Attempts to acquire the upgradeable lock without blocking, returning true on success.
+    fn try_lock_upgradeable(&self) -> bool;
+
+    /// Releases the upgradeable lock.
+    ///
+    /// # Safety
+    /// The upgradeable lock must be held by the current context.
+    unsafe fn unlock_upgradeable(&self);
+
+    /// Turns the upgradeable lock into the exclusive lock, waiting for the readers to leave.
+    ///
+    /// # Safety
+    /// The upgradeable lock must be held by the current context.
+    unsafe fn upgrade(&self);
+
+    /// Attempts to turn the upgradeable lock into the exclusive lock without blocking,
+    /// returning true on success. The upgradeable lock is kept on failure.
+    ///
+    /// # Safety
+    /// The upgradeable lock must be held by the current context.
+    unsafe fn try_upgrade(&self) -> bool;
+}
+
+/// Marker for raw locks that can be unlocked from any thread: their owned guards are Send.
+pub struct GuardSend(());
+
+/// Marker for raw locks that must be unlocked by the thread that locked them: none of their
+/// guards are Send.
+pub struct GuardNoSend(PhantomData<*mut ()>);
+
+unsafe impl Sync for GuardNoSend {}
+
+/// A mutual exclusion primitive protecting a value of type T with the raw lock R.
+pub struct Mutex<R, T> {
+    pub(crate) raw: R,
+    value: UnsafeCell<T>,
+    #[cfg(feature = "deadlock_detection")]
+    id: LockId,
+}
+
+unsafe impl<R: RawMutex + Send, T: Send> Send for Mutex<R, T> {}
+unsafe impl<R: RawMutex + Sync, T: Send> Sync for Mutex<R, T> {}
+
+impl<R: RawMutex, T> Mutex<R, T> {
+    pub const fn new(value: T) -> Self {
+        Mutex {
+            raw: R::INIT,
+            value: UnsafeCell::new(value),
+            #[cfg(feature = "deadlock_detection")]
+            id: LockId::new(),
+        }
+    }
+
+    #[cfg_attr(feature = "deadlock_detection", track_caller)]
+    pub fn lock(&self) -> MutexGuard<'_, R, T> {
+        #[cfg(feature = "deadlock_detection")]
+        lock_order::before_lock(&self.id);
+        self.raw.lock();
+        #[cfg(feature = "deadlock_detection")]
+        lock_order::locked(&self.id);
+        MutexGuard {
+            mutex: self,
+            _marker: PhantomData,
+        }
+    }
+
+    // Like lock, with `lock` in place of R::lock, for a thread that has to take the lock in
+    // some other way, e.g. after waiting on a condvar.
+    #[cfg_attr(feature = "deadlock_detection", track_caller)]
+    pub(crate) fn lock_with(&self, lock: impl FnOnce(&R)) -> MutexGuard<'_, R, T> {
+        #[cfg(feature = "deadlock_detection")]
+        lock_order::before_lock(&self.id);
+        lock(&self.raw);
+        #[cfg(feature = "deadlock_detection")]
+        lock_order::locked(&self.id);
+        MutexGuard {
+            mutex: self,
+            _marker: PhantomData,
+        }
+    }
+
+    /// Attempts to acquire the lock without blocking, returning None if it is already held.
+    #[cfg_attr(feature = "deadlock_detection", track_caller)]
+    pub fn try_lock(&self) -> Option<MutexGuard<'_, R, T>> {
+        if !self.raw.try_lock() {
+            return None;
+        }
+        #[cfg(feature = "deadlock_detection")]
+        lock_order::locked(&self.id);
+        Some(MutexGuard {
+            mutex: self,
+            _marker: PhantomData,
+        })
+    }
+
+    /// Locks a mutex behind an Arc, returning a guard that keeps the Arc instead of
+    /// borrowing the mutex, so it can be moved into spawned threads.
+    #[cfg_attr(feature = "deadlock_detection", track_caller)]
+    pub fn lock_owned(self: Arc<Self>) -> OwnedMutexGuard<R, T> {
+        #[cfg(feature = "deadlock_detection")]
+        lock_order::before_lock(&self.id);
+        self.raw.lock();
+        OwnedMutexGuard {
+            mutex: self,
+            _marker: PhantomData,
+        }
+    }
+
+    /// Like try_lock, but the guard keeps the Arc instead of borrowing the mutex.
+    pub fn try_lock_owned(self: Arc<Self>) -> Option<OwnedMutexGuard<R, T>> {
+        self.raw.try_lock().then(|| OwnedMutexGuard {
+            mutex: self,
+            _marker: PhantomData,
+        })
+    }
+
+    /// Acquires the lock without returning a guard, e.g. for a lock managed from FFI code.
+    /// It stays locked until force_unlock is called.
+    #[cfg_attr(feature = "deadlock_detection", track_caller)]
+    pub fn raw_lock(&self) {
+        #[cfg(feature = "deadlock_detection")]
+        lock_order::before_lock(&self.id);
+        self.raw.lock();
+        #[cfg(feature = "deadlock_detection")]
+        lock_order::locked(&self.id);
+    }
+
+    /// Releases the lock without a guard, after raw_lock or after forgetting a guard with
+    /// mem::forget.
+    ///
+    /// # Safety
+    /// The mutex must be locked, and no guard of that lock may be used or dropped afterwards.
+    /// For raw locks whose GuardMarker is GuardNoSend, it must be called on the thread that
+    /// locked the mutex.
+    pub unsafe fn force_unlock(&self) {
+        unsafe { self.unlock() };
+    }
+
+    /// Returns true if the mutex is locked, without acquiring it. Another thread may lock or
+    /// unlock it right after, so this is only good for monitoring and assertions.
+    pub fn is_locked(&self) -> bool {
+        self.raw.is_locked()
+    }
+
+    /// Returns a raw pointer to the protected value, without locking. Dereferencing it is
+    /// only sound while the lock is held, or when no other thread can access the mutex.
+    pub fn data_ptr(&self) -> *mut T {
+        self.value.get()
+    }
+
+    // SAFETY: the lock must be held by the current context.
+    unsafe fn unlock(&self) {
+        #[cfg(feature = "deadlock_detection")]
+        lock_order::unlocked(&self.id);
+        unsafe { self.raw.unlock() };
+    }
+
+    /// Consumes the mutex, returning the protected value.
+    pub fn into_inner(self) -> T {
+        self.value.into_inner()
+    }
+
+    /// Returns a mutable reference to the protected value, no locking is needed with &mut self.
+    pub fn get_mut(&mut self) -> &mut T {
+        self.value.get_mut()
+    }
+}
+
+impl<R: RawMutexTimed, T> Mutex<R, T> {
+    /// Acquires the lock, waiting at most `timeout` for it, returning None on timeout.
+    #[cfg_attr(feature = "deadlock_detection", track_caller)]
+    pub fn lock_timeout(&self, timeout: Duration) -> Option<MutexGuard<'_, R, T>> {
+        if !self.raw.lock_timeout(timeout) {
+            return None;
+        }
+        #[cfg(feature = "deadlock_detection")]
+        lock_order::locked(&self.id);
+        Some(MutexGuard {
+            mutex: self,
+            _marker: PhantomData,
+        })
+    }
+}
+
+impl<R: RawMutex, T: std::fmt::Debug> std::fmt::Debug for Mutex<R, T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let mut d = f.debug_struct("Mutex");
+        // Without waiting: a locked mutex may be locked by the current thread.
+        match self.try_lock() {
+            Some(guard) => d.field("data", &&*guard),
+            None => d.field("data", &format_args!("<locked>")),
+        };
+        d.finish_non_exhaustive()
+    }
+}
+
+impl<R: RawMutex, T: Default> Default for Mutex<R, T> {
+    fn default() -> Self {
+        Mutex::new(T::default())
+    }
+}
+
+impl<R: RawMutex, T> From<T> for Mutex<R, T> {
+    fn from(value: T) -> Self {
+        Mutex::new(value)
+    }
+}
+
+/// The guard of a locked Mutex. Like std's, it is Sync when T is, and never Send: the lock is
+/// released by the thread that took it, which the deadlock detection also relies on.
+pub struct MutexGuard<'a, R: RawMutex, T> {
+    pub(crate) mutex: &'a Mutex<R, T>,
+    _marker: PhantomData<GuardNoSend>,
+}
+
+unsafe impl<R: RawMutex + Sync, T: Sync> Sync for MutexGuard<'_, R, T> {}
+
+impl<'a, R: RawMutex, T> MutexGuard<'a, R, T> {
+    /// Leaks the guard, keeping the mutex locked for good and returning a mutable reference
+    /// that lives as long as the mutex: `&'static mut T` for a mutex in a static, e.g. to keep
+    /// exclusive access to data set up by one-time initialization code.
+    pub fn leak(this: MutexGuard<'a, R, T>) -> &'a mut T {
+        let value = unsafe { &mut *this.mutex.value.get() };
+        std::mem::forget(this);
+        value
+    }
+}
+
+impl<R: RawMutex, T> Deref for MutexGuard<'_, R, T> {
+    type Target = T;
+    fn deref(&self) -> &T {
+        unsafe { &*self.mutex.value.get() }
+    }
+}
+
+impl<R: RawMutex, T> DerefMut for MutexGuard<'_, R, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        unsafe { &mut *self.mutex.value.get() }
+    }
+}
+
+impl<R: RawMutex, T> Drop for MutexGuard<'_, R, T> {
+    fn drop(&mut self) {
+        unsafe { self.mutex.unlock() };
+    }
+}
+
+/// A guard returned by Mutex::lock_owned, which holds the lock as long as it lives.
+/// It has no lifetime, and is Send unless the raw lock has to be released by the thread that
+/// took it (GuardNoSend), so it can be moved into spawned threads.
+pub struct OwnedMutexGuard<R: RawMutex, T> {
+    mutex: Arc<Mutex<R, T>>,
+    _marker: PhantomData<R::GuardMarker>,
+}
+
+unsafe impl<R: RawMutex + Sync, T: Sync> Sync for OwnedMutexGuard<R, T> {}
+
+impl<R: RawMutex, T> OwnedMutexGuard<R, T> {
+    /// Returns the Arc of the locked mutex.
+    pub fn mutex(this: &OwnedMutexGuard<R, T>) -> &Arc<Mutex<R, T>> {
+        &this.mutex
+    }
+}
+
+impl<R: RawMutex, T> Deref for OwnedMutexGuard<R, T> {
+    type Target = T;
+    fn deref(&self) -> &T {
+        unsafe { &*self.mutex.value.get() }
+    }
+}
+
+impl<R: RawMutex, T> DerefMut for OwnedMutexGuard<R, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        unsafe { &mut *self.mutex.value.get() }
+    }
+}
+
+impl<R: RawMutex, T> Drop for OwnedMutexGuard<R, T> {
+    fn drop(&mut self) {
+        unsafe { self.mutex.unlock() };
+    }
+}
+
+/// A reader-writer lock protecting a value of type T with the raw lock R: any number of
+/// readers or at most one writer at any point in time.
+pub struct RwLock<R, T> {
+    pub(crate) raw: R,
+    value: UnsafeCell<T>,
+    #[cfg(feature = "deadlock_detection")]
+    id: LockId,
+    #[cfg(feature = "poison")]
+    poison: poison::Flag,
+}
+
+unsafe impl<R: RawRwLock + Send, T: Send> Send for RwLock<R, T> {}
+unsafe impl<R: RawRwLock + Sync, T: Send + Sync> Sync for RwLock<R, T> {}
+
+impl<R: RawRwLock, T> RwLock<R, T> {
+    pub const fn new(value: T) -> Self {
+        RwLock {
+            raw: R::INIT,
+            value: UnsafeCell::new(value),
+            #[cfg(feature = "deadlock_detection")]
+            id: LockId::new(),
+            #[cfg(feature = "poison")]
+            poison: poison::Flag::new(),
+        }
+    }
+
+    #[cfg_attr(feature = "deadlock_detection", track_caller)]
+    pub fn read(&self) -> RwLockReadGuard<'_, R, T> {
+        #[cfg(feature = "deadlock_detection")]
+        lock_order::before_read(&self.id);
+        self.raw.lock_shared();
+        #[cfg(feature = "deadlock_detection")]
+        lock_order::locked(&self.id);
+        RwLockReadGuard {
+            lock: self,
+            _marker: PhantomData,
+        }
+    }
+
+    /// Attempts to acquire a read lock without blocking, returning None if a writer holds it.
+    #[cfg_attr(feature = "deadlock_detection", track_caller)]
+    pub fn try_read(&self) -> Option<RwLockReadGuard<'_, R, T>> {
+        if !self.raw.try_lock_shared() {
+            return None;
+        }
+        #[cfg(feature = "deadlock_detection")]
+        lock_order::locked(&self.id);
+        Some(RwLockReadGuard {
+            lock: self,
+            _marker: PhantomData,
+        })
+    }
+
+    #[cfg_attr(feature = "deadlock_detection", track_caller)]
+    pub fn write(&self) -> RwLockWriteGuard<'_, R, T> {
+        #[cfg(feature = "deadlock_detection")]
+        lock_order::before_lock(&self.id);
+        self.raw.lock_exclusive();
+        #[cfg(feature = "deadlock_detection")]
+        lock_order::locked(&self.id);
+        RwLockWriteGuard::new(self)
+    }
+
+    /// Attempts to acquire the write lock without blocking, returning None if it is held.
+    #[cfg_attr(feature = "deadlock_detection", track_caller)]
+    pub fn try_write(&self) -> Option<RwLockWriteGuard<'_, R, T>> {
+        if !self.raw.try_lock_exclusive() {
+            return None;
+        }
+        #[cfg(feature = "deadlock_detection")]
+        lock_order::locked(&self.id);
+        Some(RwLockWriteGuard::new(self))
+    }
+
+    /// Read-locks a lock behind an Arc, returning a guard that keeps the Arc instead of
+    /// borrowing the lock, so it can be moved into spawned threads and tasks.
+    #[cfg_attr(feature = "deadlock_detection", track_caller)]
+    pub fn read_owned(self: Arc<Self>) -> OwnedRwLockReadGuard<R, T> {
+        #[cfg(feature = "deadlock_detection")]
+        lock_order::before_read(&self.id);
+        self.raw.lock_shared();
+        OwnedRwLockReadGuard {
+            lock: self,
+            _marker: PhantomData,
+        }
+    }
+
+    /// Like try_read, but the guard keeps the Arc instead of borrowing the lock.
+    pub fn try_read_owned(self: Arc<Self>) -> Option<OwnedRwLockReadGuard<R, T>> {
+        self.raw.try_lock_shared().then(|| OwnedRwLockReadGuard {
+            lock: self,
+            _marker: PhantomData,
+        })
+    }
+
+    /// Write-locks a lock behind an Arc, returning a guard that keeps the Arc instead of
+    /// borrowing the lock, so it can be moved into spawned threads and tasks.
+    #[cfg_attr(feature = "deadlock_detection", track_caller)]
+    pub fn write_owned(self: Arc<Self>) -> OwnedRwLockWriteGuard<R, T> {
+        #[cfg(feature = "deadlock_detection")]
+        lock_order::before_lock(&self.id);
+        self.raw.lock_exclusive();
+        OwnedRwLockWriteGuard::new(self)
+    }
+
+    /// Like try_write, but the guard keeps the Arc instead of borrowing the lock.
+    pub fn try_write_owned(self: Arc<Self>) -> Option<OwnedRwLockWriteGuard<R, T>> {
+        self.raw
+            .try_lock_exclusive()
+            .then(|| OwnedRwLockWriteGuard::new(self))
+    }
+
+    /// Acquires a read lock without returning a guard, e.g. for a lock managed from FFI code.
+    /// It stays locked until force_unlock_read is called.
+    #[cfg_attr(feature = "deadlock_detection", track_caller)]
+    pub fn raw_read(&self) {
+        #[cfg(feature = "deadlock_detection")]
+        lock_order::before_read(&self.id);
+        self.raw.lock_shared();
+        #[cfg(feature = "deadlock_detection")]
+        lock_order::locked(&self.id);
+    }
+
+    /// Acquires the write lock without returning a guard, e.g. for a lock managed from FFI
+    /// code. It stays locked until force_unlock_write is called.
+    #[cfg_attr(feature = "deadlock_detection", track_caller)]
+    pub fn raw_write(&self) {
+        #[cfg(feature = "deadlock_detection")]
+        lock_order::before_lock(&self.id);
+        self.raw.lock_exclusive();
+        #[cfg(feature = "deadlock_detection")]
+        lock_order::locked(&self.id);
+    }
+
+    /// Releases a read lock without a guard, after raw_read or after forgetting a read guard.
+    ///
+    /// # Safety
+    /// A read lock must be held, and the guard it came from, if any, may not be used or
+    /// dropped afterwards. For raw locks whose GuardMarker is GuardNoSend, it must be called
+    /// on the thread that took the read lock.
+    pub unsafe fn force_unlock_read(&self) {
+        #[cfg(feature = "deadlock_detection")]
+        lock_order::unlocked(&self.id);
+        unsafe { self.raw.unlock_shared() };
+    }
+
+    /// Releases the write lock without a guard, after raw_write or after forgetting a write
+    /// guard.
+    ///
+    /// # Safety
+    /// The write lock must be held, and the guard it came from, if any, may not be used or
+    /// dropped afterwards. For raw locks whose GuardMarker is GuardNoSend, it must be called
+    /// on the thread that took the write lock.
+    pub unsafe fn force_unlock_write(&self) {
+        #[cfg(feature = "deadlock_detection")]
+        lock_order::unlocked(&self.id);
+        unsafe { self.raw.unlock_exclusive() };
+    }
+
+    /// Returns true if the lock is held by readers or a writer, without acquiring it.
+    pub fn is_locked(&self) -> bool {
+        self.raw.is_locked()
+    }
+
+    /// Returns true if the lock is held by a writer, without acquiring it.
+    pub fn is_locked_exclusive(&self) -> bool {
+        self.raw.is_locked_exclusive()
+    }
+
+    /// Same as is_locked_exclusive, named after write.
+    pub fn is_write_locked(&self) -> bool {
+        self.raw.is_locked_exclusive()
+    }
+
+    /// Returns the number of readers holding the lock, without acquiring it. Like is_locked,
+    /// it may be stale when it is returned, so it is only good for monitoring and assertions.
+    pub fn reader_count(&self) -> usize {
+        self.raw.reader_count()
+    }
+
+    /// Returns a raw pointer to the protected value, without locking. Dereferencing it is
+    /// only sound while a lock is held, a write lock to mutate the value.
+    pub fn data_ptr(&self) -> *mut T {
+        self.value.get()
+    }
+
+    /// Consumes the lock, returning the protected value.
+    pub fn into_inner(self) -> T {
+        self.value.into_inner()
+    }
+
+    /// Returns a mutable reference to the protected value, no locking is needed with &mut self.
+    pub fn get_mut(&mut self) -> &mut T {
+        self.value.get_mut()
+    }
+}
+
+#[cfg(feature = "poison")]
+impl<R: RawRwLock, T> RwLock<R, T> {
+    /// Like read, but returns an error if a writer panicked while holding the lock, with
+    /// the guard in it.
+    #[cfg_attr(feature = "deadlock_detection", track_caller)]
+    pub fn checked_read(&self) -> LockResult<RwLockReadGuard<'_, R, T>> {
+        let guard = self.read();
+        if self.is_poisoned() {
+            return Err(PoisonError::new(guard));
+        }
+        Ok(guard)
+    }
+
+    /// Like write, but returns an error if a writer panicked while holding the lock, with
+    /// the guard in it.
+    #[cfg_attr(feature = "deadlock_detection", track_caller)]
+    pub fn checked_write(&self) -> LockResult<RwLockWriteGuard<'_, R, T>> {
+        let guard = self.write();
+        if self.is_poisoned() {
+            return Err(PoisonError::new(guard));
+        }
+        Ok(guard)
+    }
+
+    /// Returns true if a writer panicked while holding the lock.
+    pub fn is_poisoned(&self) -> bool {
+        self.poison.get()
+    }
+
+    /// Marks the lock as no longer poisoned, once the value has been checked or repaired.
+    pub fn clear_poison(&self) {
+        self.poison.clear();
+    }
+}
+
+impl<R: RawRwLockUpgrade, T> RwLock<R, T> {
+    /// Acquires an upgradeable read lock: it reads along with other readers, but excludes
+    /// writers and other upgradeable readers, so it can later be upgraded to the write lock
+    /// without another writer getting in first, unlike dropping a read guard and calling write.
+    #[cfg_attr(feature = "deadlock_detection", track_caller)]
+    pub fn upgradeable_read(&self) -> RwLockUpgradeableReadGuard<'_, R, T> {
+        #[cfg(feature = "deadlock_detection")]
+        lock_order::before_lock(&self.id);
+        self.raw.lock_upgradeable();
+        #[cfg(feature = "deadlock_detection")]
+        lock_order::locked(&self.id);
+        RwLockUpgradeableReadGuard {
+            lock: self,
+            _marker: PhantomData,
+        }
+    }
+
+    /// Attempts to acquire an upgradeable read lock without blocking, returning None if a
+    /// writer or another upgradeable reader holds the lock.
+    #[cfg_attr(feature = "deadlock_detection", track_caller)]
+    pub fn try_upgradeable_read(&self) -> Option<RwLockUpgradeableReadGuard<'_, R, T>> {
+        if !self.raw.try_lock_upgradeable() {
+            return None;
+        }
+        #[cfg(feature = "deadlock_detection")]
+        lock_order::locked(&self.id);
+        Some(RwLockUpgradeableReadGuard {
+            lock: self,
+            _marker: PhantomData,
+        })
+    }
+}
+
+impl<R: RawRwLock, T: std::fmt::Debug> std::fmt::Debug for RwLock<R, T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let mut d = f.debug_struct("RwLock");
+        // Without waiting: a write-locked lock may be locked by the current thread.
+        match self.try_read() {
+            Some(guard) => d.field("data", &&*guard),
+            None => d.field("data", &format_args!("<locked>")),
+        };
+        #[cfg(feature = "poison")]
+        d.field("poisoned", &self.is_poisoned());
+        d.finish_non_exhaustive()
+    }
+}
+
+impl<R: RawRwLock, T: Default> Default for RwLock<R, T> {
+    fn default() -> Self {
+        RwLock::new(T::default())
+    }
+}
+
+impl<R: RawRwLock, T> From<T> for RwLock<R, T> {
+    fn from(value: T) -> Self {
+        RwLock::new(value)
+    }
+}
+
+/// The guard of a read-locked RwLock. It is Sync when T is, and never Send, like std's.
+pub struct RwLockReadGuard<'a, R: RawRwLock, T> {
+    lock: &'a RwLock<R, T>,
+    _marker: PhantomData<GuardNoSend>,
+}
+
+unsafe impl<R: RawRwLock + Sync, T: Sync> Sync for RwLockReadGuard<'_, R, T> {}
+
+impl<'a, R: RawRwLock, T> RwLockReadGuard<'a, R, T> {
+    /// Makes a guard for a part of the locked value, e.g. one entry of a map, to hand out
+    /// access to that part without the rest of the value.
+    pub fn map<U: ?Sized>(
+        this: RwLockReadGuard<'a, R, T>,
+        f: impl FnOnce(&T) -> &U,
+    ) -> MappedRwLockReadGuard<'a, R, U> {
+        let lock = this.lock;
+        let value = f(unsafe { &*lock.value.get() });
+        std::mem::forget(this);
+        MappedRwLockReadGuard {
+            raw: &lock.raw,
+            value,
+            #[cfg(feature = "deadlock_detection")]
+            id: &lock.id,
+            _marker: PhantomData,
+        }
+    }
+}
+
+impl<R: RawRwLock, T> Deref for RwLockReadGuard<'_, R, T> {
+    type Target = T;
+    fn deref(&self) -> &T {
+        unsafe { &*self.lock.value.get() }
+    }
+}
+
+impl<R: RawRwLock, T> Drop for RwLockReadGuard<'_, R, T> {
+    fn drop(&mut self) {
+        unsafe { self.lock.force_unlock_read() };
+    }
+}
+
+/// The guard of a write-locked RwLock. It is Sync when T is, and never Send, like std's.
+pub struct RwLockWriteGuard<'a, R: RawRwLock, T> {
+    lock: &'a RwLock<R, T>,
+    #[cfg(feature = "poison")]
+    poison: poison::Guard,
+    _marker: PhantomData<GuardNoSend>,
+}
+
+unsafe impl<R: RawRwLock + Sync, T: Sync> Sync for RwLockWriteGuard<'_, R, T> {}
+
+impl<'a, R: RawRwLock, T> RwLockWriteGuard<'a, R, T> {
+    // The write lock must be held.
+    fn new(lock: &'a RwLock<R, T>) -> Self {
+        RwLockWriteGuard {
+            lock,
+            #[cfg(feature = "poison")]
+            poison: lock.poison.guard(),
+            _marker: PhantomData,
+        }
+    }
+
+    /// Makes a guard for a part of the locked value, e.g. one entry of a map, to hand out
+    /// mutable access to that part without the rest of the value.
+    pub fn map<U: ?Sized>(
+        this: RwLockWriteGuard<'a, R, T>,
+        f: impl FnOnce(&mut T) -> &mut U,
+    ) -> MappedRwLockWriteGuard<'a, R, U> {
+        let lock = this.lock;
+        let value = f(unsafe { &mut *lock.value.get() });
+        let this = std::mem::ManuallyDrop::new(this);
+        MappedRwLockWriteGuard {
+            raw: &lock.raw,
+            value,
+            #[cfg(feature = "deadlock_detection")]
+            id: &lock.id,
+            #[cfg(feature = "poison")]
+            poison: (&lock.poison, this.poison),
+            _marker: PhantomData,
+        }
+    }
+}
+
+impl<R: RawRwLock, T> Deref for RwLockWriteGuard<'_, R, T> {
+    type Target = T;
+    fn deref(&self) -> &T {
+        unsafe { &*self.lock.value.get() }
+    }
+}
+
+impl<R: RawRwLock, T> DerefMut for RwLockWriteGuard<'_, R, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        unsafe { &mut *self.lock.value.get() }
+    }
+}
+
+impl<R: RawRwLock, T> Drop for RwLockWriteGuard<'_, R, T> {
+    fn drop(&mut self) {
+        #[cfg(feature = "poison")]
+        self.lock.poison.done(&self.poison);
+        unsafe { self.lock.force_unlock_write() };
+    }
+}
+
+/// A guard returned by RwLock::read_owned, which holds a read lock as long as it lives.
+/// It has no lifetime, and is Send unless the raw lock has to be released by the thread that
+/// took it (GuardNoSend).
+pub struct OwnedRwLockReadGuard<R: RawRwLock, T> {
+    lock: Arc<RwLock<R, T>>,
+    _marker: PhantomData<R::GuardMarker>,
+}
+
+impl<R: RawRwLock, T> OwnedRwLockReadGuard<R, T> {
+    /// Returns the Arc of the locked RwLock.
+    pub fn rwlock(this: &OwnedRwLockReadGuard<R, T>) -> &Arc<RwLock<R, T>> {
+        &this.lock
+    }
+}
+
+impl<R: RawRwLock, T> Deref for OwnedRwLockReadGuard<R, T> {
+    type Target = T;
+    fn deref(&self) -> &T {
+        unsafe { &*self.lock.value.get() }
+    }
+}
+
+impl<R: RawRwLock, T> Drop for OwnedRwLockReadGuard<R, T> {
+    fn drop(&mut self) {
+        unsafe { self.lock.force_unlock_read() };
+    }
+}
+
+/// A guard returned by RwLock::write_owned, which holds the write lock as long as it lives.
+/// It has no lifetime, and is Send unless the raw lock has to be released by the thread that
+/// took it (GuardNoSend).
+pub struct OwnedRwLockWriteGuard<R: RawRwLock, T> {
+    lock: Arc<RwLock<R, T>>,
+    #[cfg(feature = "poison")]
+    poison: poison::Guard,
+    _marker: PhantomData<R::GuardMarker>,
+}
+
+impl<R: RawRwLock, T> OwnedRwLockWriteGuard<R, T> {
+    // The write lock must be held.
+    fn new(lock: Arc<RwLock<R, T>>) -> Self {
+        OwnedRwLockWriteGuard {
+            #[cfg(feature = "poison")]
+            poison: lock.poison.guard(),
+            lock,
+            _marker: PhantomData,
+        }
+    }
+
+    /// Returns the Arc of the locked RwLock.
+    pub fn rwlock(this: &OwnedRwLockWriteGuard<R, T>) -> &Arc<RwLock<R, T>> {
+        &this.lock
+    }
+}
+
+impl<R: RawRwLock, T> Deref for OwnedRwLockWriteGuard<R, T> {
+    type Target = T;
+    fn deref(&self) -> &T {
+        unsafe { &*self.lock.value.get() }
+    }
+}
+
+impl<R: RawRwLock, T> DerefMut for OwnedRwLockWriteGuard<R, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        unsafe { &mut *self.lock.value.get() }
+    }
+}
+
+impl<R: RawRwLock, T> Drop for OwnedRwLockWriteGuard<R, T> {
+    fn drop(&mut self) {
+        #[cfg(feature = "poison")]
+        self.lock.poison.done(&self.poison);
+        unsafe { self.lock.force_unlock_write() };
+    }
+}
+
+/// A read guard for a part of the value of an RwLock, made by RwLockReadGuard::map. It keeps
+/// the whole lock read-locked.
+pub struct MappedRwLockReadGuard<'a, R: RawRwLock, T: ?Sized> {
+    raw: &'a R,
+    value: &'a T,
+    #[cfg(feature = "deadlock_detection")]
+    id: &'a LockId,
+    _marker: PhantomData<GuardNoSend>,
+}
+
+impl<'a, R: RawRwLock, T: ?Sized> MappedRwLockReadGuard<'a, R, T> {
+    /// Narrows the guard down further to a part of the mapped value.
+    pub fn map<U: ?Sized>(
+        this: MappedRwLockReadGuard<'a, R, T>,
+        f: impl FnOnce(&T) -> &U,
+    ) -> MappedRwLockReadGuard<'a, R, U> {
+        let guard = MappedRwLockReadGuard {
+            raw: this.raw,
+            value: f(this.value),
+            #[cfg(feature = "deadlock_detection")]
+            id: this.id,
+            _marker: PhantomData,
+        };
+        std::mem::forget(this);
+        guard
+    }
+}
+
+impl<R: RawRwLock, T: ?Sized> Deref for MappedRwLockReadGuard<'_, R, T> {
+    type Target = T;
+    fn deref(&self) -> &T {
+        self.value
+    }
+}
+
+impl<R: RawRwLock, T: ?Sized> Drop for MappedRwLockReadGuard<'_, R, T> {
+    fn drop(&mut self) {
+        #[cfg(feature = "deadlock_detection")]
+        lock_order::unlocked(self.id);
+        unsafe { self.raw.unlock_shared() };
+    }
+}
+
+/// A write guard for a part of the value of an RwLock, made by RwLockWriteGuard::map. It keeps
+/// the whole lock write-locked.
+pub struct MappedRwLockWriteGuard<'a, R: RawRwLock, T: ?Sized> {
+    raw: &'a R,
+    value: *mut T,
+    #[cfg(feature = "deadlock_detection")]
+    id: &'a LockId,
+    #[cfg(feature = "poison")]
+    poison: (&'a poison::Flag, poison::Guard),
+    _marker: PhantomData<(&'a mut T, GuardNoSend)>,
+}
+
+unsafe impl<R: RawRwLock + Sync, T: ?Sized + Sync> Sync for MappedRwLockWriteGuard<'_, R, T> {}
+
+impl<'a, R: RawRwLock, T: ?Sized> MappedRwLockWriteGuard<'a, R, T> {
+    /// Narrows the guard down further to a part of the mapped value.
+    pub fn map<U: ?Sized>(
+        this: MappedRwLockWriteGuard<'a, R, T>,
+        f: impl FnOnce(&mut T) -> &mut U,
+    ) -> MappedRwLockWriteGuard<'a, R, U> {
+        let guard = MappedRwLockWriteGuard {
+            raw: this.raw,
+            value: f(unsafe { &mut *this.value }),
+            #[cfg(feature = "deadlock_detection")]
+            id: this.id,
+            #[cfg(feature = "poison")]
+            poison: this.poison,
+            _marker: PhantomData,
+        };
+        std::mem::forget(this);
+        guard
+    }
+}
+
+impl<R: RawRwLock, T: ?Sized> Deref for MappedRwLockWriteGuard<'_, R, T> {
+    type Target = T;
+    fn deref(&self) -> &T {
+        unsafe { &*self.value }
+    }
+}
+
+impl<R: RawRwLock, T: ?Sized> DerefMut for MappedRwLockWriteGuard<'_, R, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        unsafe { &mut *self.value }
+    }
+}
+
+impl<R: RawRwLock, T: ?Sized> Drop for MappedRwLockWriteGuard<'_, R, T> {
+    fn drop(&mut self) {
+        #[cfg(feature = "deadlock_detection")]
+        lock_order::unlocked(self.id);
+        #[cfg(feature = "poison")]
+        self.poison.0.done(&self.poison.1);
+        unsafe { self.raw.unlock_exclusive() };
+    }
+}
+
+/// The guard of an upgradeable read lock of an RwLock. It is Sync when T is, and never Send.
+pub struct RwLockUpgradeableReadGuard<'a, R: RawRwLockUpgrade, T> {
+    lock: &'a RwLock<R, T>,
+    _marker: PhantomData<GuardNoSend>,
+}
+
+unsafe impl<R: RawRwLockUpgrade + Sync, T: Sync> Sync for RwLockUpgradeableReadGuard<'_, R, T> {}
+
+impl<'a, R: RawRwLockUpgrade, T> RwLockUpgradeableReadGuard<'a, R, T> {
+    /// Upgrades to the write lock, waiting for the other readers to leave. No writer can
+    /// have changed the value in between.
+    pub fn upgrade(this: RwLockUpgradeableReadGuard<'a, R, T>) -> RwLockWriteGuard<'a, R, T> {
+        let lock = this.lock;
+        std::mem::forget(this);
+        unsafe { lock.raw.upgrade() };
+        RwLockWriteGuard::new(lock)
+    }
+
+    /// Upgrades to the write lock if there are no other readers, giving the guard back
+    /// otherwise.
+    pub fn try_upgrade(
+        this: RwLockUpgradeableReadGuard<'a, R, T>,
+    ) -> Result<RwLockWriteGuard<'a, R, T>, RwLockUpgradeableReadGuard<'a, R, T>> {
+        if !unsafe { this.lock.raw.try_upgrade() } {
+            return Err(this);
+        }
+        let lock = this.lock;
+        std::mem::forget(this);
+        Ok(RwLockWriteGuard::new(lock))
+    }
+}
+
+impl<R: RawRwLockUpgrade, T> Deref for RwLockUpgradeableReadGuard<'_, R, T> {
+    type Target = T;
+    fn deref(&self) -> &T {
+        unsafe { &*self.lock.value.get() }
+    }
+}
+
+impl<R: RawRwLockUpgrade, T> Drop for RwLockUpgradeableReadGuard<'_, R, T> {
+    fn drop(&mut self) {
+        #[cfg(feature = "deadlock_detection")]
+        lock_order::unlocked(&self.lock.id);
+        unsafe { self.lock.raw.unlock_upgradeable() };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{GuardSend, Mutex, RawMutex};
+    use std::thread;
+
+    // A user-provided raw lock: a plain spinlock.
+    struct RawSpinLock(std::sync::atomic::AtomicBool);
+
+    unsafe impl RawMutex for RawSpinLock {
+        const INIT: RawSpinLock = RawSpinLock(std::sync::atomic::AtomicBool::new(false));
+        type GuardMarker = GuardSend;
+
+        fn lock(&self) {
+            while !self.try_lock() {
+                thread::yield_now();
+            }
+        }
+
+        fn try_lock(&self) -> bool {
+            !self.0.swap(true, std::sync::atomic::Ordering::Acquire)
+        }
+
+        unsafe fn unlock(&self) {
+            self.0.store(false, std::sync::atomic::Ordering::Release);
+        }
+    }
+
+    #[test]
+    fn test_custom_raw_mutex() {
+        type SpinMutex<T> = Mutex<RawSpinLock, T>;
+        static TOTAL: SpinMutex<usize> = SpinMutex::new(0);
+        let handles: Vec<_> = (0..4)
+            .map(|_| {
+                thread::spawn(|| {
+                    for _ in 0..1000 {
+                        *TOTAL.lock() += 1;
+                    }
+                })
+            })
+            .collect();
+        for h in handles {
+            h.join().unwrap();
+        }
+        assert_eq!(*TOTAL.lock(), 4000);
+        let _guard = TOTAL.try_lock().unwrap();
+        assert!(TOTAL.try_lock().is_none());
+    }
+
+    #[test]
+    fn test_debug_default_and_from() {
+        use crate::rwlock::RwLock;
+
+        #[derive(Debug, Default)]
+        struct State {
+            count: Mutex<RawSpinLock, usize>,
+            names: RwLock<Vec<&'static str>>,
+        }
+
+        let state = State::default();
+        state.names.write().push("a");
+        assert_eq!(format!("{:?}", state.count), "Mutex { data: 0, .. }");
+        // With the poison feature, the poisoned flag comes after the data.
+        let names = format!("{:?}", state.names);
+        assert!(names.starts_with(r#"RwLock { data: ["a"], "#));
+        assert!(format!("{state:?}").starts_with("State { count: Mutex { data: 0, .. }, "));
+        let _guard = state.count.lock();
+        let _writer = state.names.write();
+        assert_eq!(format!("{:?}", state.count), "Mutex { data: <locked>, .. }");
+        assert!(format!("{:?}", state.names).starts_with("RwLock { data: <locked>, "));
+
+        let mutex: Mutex<RawSpinLock, _> = 5.into();
+        assert_eq!(mutex.into_inner(), 5);
+        assert_eq!(RwLock::from("x").into_inner(), "x");
+    }
+
+    #[test]
+    fn test_is_locked_and_data_ptr() {
+        let mutex = Mutex::<RawSpinLock, _>::new(5);
+        // The default is_locked, built on try_lock.
+        assert!(!mutex.is_locked());
+        let guard = mutex.lock();
+        assert!(mutex.is_locked());
+        assert_eq!(unsafe { *mutex.data_ptr() }, 5);
+        drop(guard);
+        assert!(!mutex.is_locked());
+    }
+}
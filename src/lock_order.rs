@@ -0,0 +1,258 @@
+//! Deadlock detection for Mutex and RwLock, enabled by the `deadlock_detection` feature.
+//!
+//! Every time a thread blocks on a lock while holding others, the order is recorded as
+//! edges of a global graph: held lock -> lock being taken. Two threads taking the same locks
+//! in opposite orders can deadlock, so an edge that closes a cycle in the graph panics with
+//! the acquisitions that make up the cycle, even if the run at hand didn't deadlock.
+//!
+//! Read and write locks are treated alike, except that a thread can read-lock an RwLock it
+//! already holds for reading. Guards are expected to be dropped on the thread that took
+//! them, and owned guards are only checked when they are taken.
+
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::panic::Location;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Mutex, PoisonError};
+
+type Caller = &'static Location<'static>;
+
+/// Where a lock was taken while another one was held.
+#[derive(Clone, Copy)]
+struct Edge {
+    held_at: Caller,
+    acquired_at: Caller,
+}
+
+// Edges of the lock-order graph, by lock id. std's Mutex, as ours are the ones being checked.
+static GRAPH: Mutex<BTreeMap<usize, BTreeMap<usize, Edge>>> = Mutex::new(BTreeMap::new());
+
+static NEXT_ID: AtomicUsize = AtomicUsize::new(1);
+
+thread_local! {
+    // Locks held by the current thread, with where they were taken, in order.
+    static HELD: RefCell<Vec<(usize, Caller)>> = const { RefCell::new(Vec::new()) };
+}
+
+/// Identifies a lock in the graph. The id is assigned the first time the lock is taken,
+/// and removed from the graph when the lock is dropped.
+pub(crate) struct LockId(AtomicUsize);
+
+impl LockId {
+    pub(crate) const fn new() -> LockId {
+        LockId(AtomicUsize::new(0))
+    }
+
+    fn get(&self) -> usize {
+        let id = self.0.load(Ordering::Relaxed);
+        if id != 0 {
+            return id;
+        }
+        let new = NEXT_ID.fetch_add(1, Ordering::Relaxed);
+        match self
+            .0
+            .compare_exchange(0, new, Ordering::Relaxed, Ordering::Relaxed)
+        {
+            Ok(_) => new,
+            Err(id) => id,
+        }
+    }
+}
+
+impl Drop for LockId {
+    fn drop(&mut self) {
+        let id = *self.0.get_mut();
+        if id != 0 {
+            let mut graph = GRAPH.lock().unwrap_or_else(PoisonError::into_inner);
+            graph.remove(&id);
+            for edges in graph.values_mut() {
+                edges.remove(&id);
+            }
+        }
+    }
+}
+
+/// Records that the current thread is about to block on `lock`, panicking if that could
+/// deadlock with the lock orders seen so far.
+#[track_caller]
+pub(crate) fn before_lock(lock: &LockId) {
+    check(lock, Location::caller(), false);
+}
+
+/// Like before_lock, for a read lock that the current thread may already hold for reading.
+#[track_caller]
+pub(crate) fn before_read(lock: &LockId) {
+    check(lock, Location::caller(), true);
+}
+
+fn check(lock: &LockId, at: Caller, shared: bool) {
+    let id = lock.get();
+    let held = HELD.with_borrow(|held| held.clone());
+    let mut graph = GRAPH.lock().unwrap_or_else(PoisonError::into_inner);
+    for (held_id, held_at) in held {
+        if held_id == id && shared {
+            continue;
+        }
+        if held_id == id {
+            drop(graph);
+            panic!(
+                "deadlock: lock #{id} taken at {at} is already held by this thread, \
+                 taken at {held_at}"
+            );
+        }
+        if graph
+            .get(&held_id)
+            .is_some_and(|edges| edges.contains_key(&id))
+        {
+            continue;
+        }
+        if let Some(path) = find_path(&graph, id, held_id) {
+            drop(graph);
+            let mut report = format!(
+                "possible deadlock, lock order inversion:\n  lock #{id} taken at {at} \
+                 while holding lock #{held_id}, taken at {held_at}"
+            );
+            for (from, to, edge) in path {
+                let _ = write!(
+                    report,
+                    "\n  but lock #{to} was taken at {} while holding lock #{from}, taken at {}",
+                    edge.acquired_at, edge.held_at
+                );
+            }
+            panic!("{report}");
+        }
+        graph.entry(held_id).or_default().insert(
+            id,
+            Edge {
+                held_at,
+                acquired_at: at,
+            },
+        );
+    }
+}
+
+/// Records that the current thread now holds `lock`.
+#[track_caller]
+pub(crate) fn locked(lock: &LockId) {
+    let entry = (lock.get(), Location::caller());
+    HELD.with_borrow_mut(|held| held.push(entry));
+}
+
+/// Records that the current thread released `lock`, no-op if it doesn't hold it.
+pub(crate) fn unlocked(lock: &LockId) {
+    let id = lock.get();
+    HELD.with_borrow_mut(|held| {
+        if let Some(i) = held.iter().rposition(|&(held_id, _)| held_id == id) {
+            held.remove(i);
+        }
+    });
+}
+
+/// Depth-first search for a path of edges from `from` to `to`.
+fn find_path(
+    graph: &BTreeMap<usize, BTreeMap<usize, Edge>>,
+    from: usize,
+    to: usize,
+) -> Option<Vec<(usize, usize, Edge)>> {
+    let mut visited = vec![from];
+    let mut path = Vec::new();
+    let mut stack = vec![graph.get(&from)?.iter()];
+    while let Some(edges) = stack.last_mut() {
+        let Some((&next, &edge)) = edges.next() else {
+            stack.pop();
+            path.pop();
+            continue;
+        };
+        if visited.contains(&next) {
+            continue;
+        }
+        visited.push(next);
+        let from = path.last().map_or(from, |&(_, to, _)| to);
+        path.push((from, next, edge));
+        if next == to {
+            return Some(path);
+        }
+        match graph.get(&next) {
+            Some(edges) => stack.push(edges.iter()),
+            None => {
+                path.pop();
+            }
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::mutex::Mutex;
+    use crate::rwlock::RwLock;
+    use std::panic::{AssertUnwindSafe, catch_unwind};
+    use std::thread;
+
+    fn panic_message(f: impl FnOnce()) -> String {
+        let err = catch_unwind(AssertUnwindSafe(f)).unwrap_err();
+        err.downcast::<String>().map(|s| *s).unwrap()
+    }
+
+    #[test]
+    fn test_consistent_order_is_fine() {
+        let (a, b) = (Mutex::new(1), Mutex::new(2));
+        for _ in 0..3 {
+            let _a = a.lock();
+            let _b = b.lock();
+        }
+        // Taking them separately doesn't add any order.
+        drop(b.lock());
+        drop(a.lock());
+    }
+
+    #[test]
+    fn test_inversion_panics() {
+        let (a, b) = (Mutex::new(1), Mutex::new(2));
+        {
+            let _a = a.lock();
+            let _b = b.lock();
+        }
+        let message = panic_message(|| {
+            let _b = b.lock();
+            let _a = a.lock();
+        });
+        assert!(message.starts_with("possible deadlock, lock order inversion"));
+        assert!(message.contains(&format!("src/lock_order.rs:{}", line!() - 4)));
+        assert_eq!(message.lines().count(), 3, "{message}");
+    }
+
+    #[test]
+    fn test_cycle_across_threads_and_lock_kinds() {
+        let (a, b, c) = (Mutex::new(()), RwLock::new(()), Mutex::new(()));
+        thread::scope(|s| {
+            s.spawn(|| {
+                let _a = a.lock();
+                let _b = b.read();
+            });
+        });
+        thread::scope(|s| {
+            s.spawn(|| {
+                let _b = b.write();
+                let _c = c.lock();
+            });
+        });
+        let message = panic_message(|| {
+            let _c = c.lock();
+            let _a = a.lock();
+        });
+        // The new edge and the two it closes the cycle with.
+        assert_eq!(message.lines().count(), 4, "{message}");
+    }
+
+    #[test]
+    fn test_relocking_panics_instead_of_hanging() {
+        let a = Mutex::new(());
+        let message = panic_message(|| {
+            let _a = a.lock();
+            let _again = a.lock();
+        });
+        assert!(message.contains("is already held by this thread"));
+    }
+}
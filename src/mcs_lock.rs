@@ -0,0 +1,224 @@
+use std::cell::UnsafeCell;
+use std::hint::spin_loop;
+use std::ops::{Deref, DerefMut};
+use std::ptr;
+use std::sync::atomic::{AtomicBool, AtomicPtr, Ordering};
+use std::thread;
+
+/// The MCS queue lock (Mellor-Crummey and Scott): waiting threads form a linked queue, and
+/// each of them spins on a flag in its own queue node instead of on the lock itself.
+/// When the lock is released, only the next thread's cache line is touched, so the traffic
+/// between cores doesn't grow with the number of waiters, which is what makes it scale on
+/// machines with many cores. Threads also get the lock in FIFO order.
+pub struct McsLock<T> {
+    value: UnsafeCell<T>,
+    // Last node of the queue, null if the lock is free.
+    tail: AtomicPtr<McsNode>,
+}
+
+unsafe impl<T: Send> Sync for McsLock<T> {}
+
+// Aligned to its own cache line (two on some CPUs, which prefetch lines in pairs),
+// so that spinning threads don't disturb each other.
+#[repr(align(128))]
+struct McsNode {
+    next: AtomicPtr<McsNode>,
+    // Cleared by the previous thread in the queue when it hands over the lock.
+    waiting: AtomicBool,
+}
+
+impl<T> McsLock<T> {
+    pub const fn new(value: T) -> Self {
+        Self {
+            value: UnsafeCell::new(value),
+            tail: AtomicPtr::new(ptr::null_mut()),
+        }
+    }
+
+    pub fn lock(&self) -> McsLockGuard<'_, T> {
+        // The node has to stay in place while it is in the queue, and guards can be moved,
+        // so it lives on the heap.
+        let node = Box::new(McsNode {
+            next: AtomicPtr::new(ptr::null_mut()),
+            waiting: AtomicBool::new(true),
+        });
+        let node_ptr = &*node as *const McsNode as *mut McsNode;
+        // AcqRel: acquire the previous holder's writes if the queue was empty, and publish
+        // the node's initialization to the thread that will link to it.
+        let prev = self.tail.swap(node_ptr, Ordering::AcqRel);
+        if !prev.is_null() {
+            // SAFETY: the previous node stays alive until its owner has handed the lock
+            // over to us, which it can only do after seeing this link.
+            unsafe { (*prev).next.store(node_ptr, Ordering::Release) };
+            let mut spins = 0_u32;
+            while node.waiting.load(Ordering::Acquire) {
+                spins += 1;
+                // Let the lock holder run if it was preempted.
+                if spins.is_multiple_of(64) {
+                    thread::yield_now();
+                } else {
+                    spin_loop();
+                }
+            }
+        }
+        McsLockGuard { lock: self, node }
+    }
+
+    /// Attempts to acquire the lock, returning None if it is held or other threads are waiting.
+    pub fn try_lock(&self) -> Option<McsLockGuard<'_, T>> {
+        let node = Box::new(McsNode {
+            next: AtomicPtr::new(ptr::null_mut()),
+            waiting: AtomicBool::new(false),
+        });
+        let node_ptr = &*node as *const McsNode as *mut McsNode;
+        self.tail
+            .compare_exchange(
+                ptr::null_mut(),
+                node_ptr,
+                Ordering::Acquire,
+                Ordering::Relaxed,
+            )
+            .ok()
+            .map(|_| McsLockGuard { lock: self, node })
+    }
+
+    /// Returns true if the lock is held or waited for, without acquiring it.
+    pub fn is_locked(&self) -> bool {
+        !self.tail.load(Ordering::Relaxed).is_null()
+    }
+
+    /// Returns a raw pointer to the protected value, without locking. Dereferencing it is
+    /// only sound while the lock is held, or when no other thread can access the lock.
+    pub fn data_ptr(&self) -> *mut T {
+        self.value.get()
+    }
+
+    pub fn into_inner(self) -> T {
+        self.value.into_inner()
+    }
+
+    /// Returns a mutable reference to the protected value, no locking is needed with &mut self.
+    pub fn get_mut(&mut self) -> &mut T {
+        self.value.get_mut()
+    }
+}
+
+pub struct McsLockGuard<'a, T> {
+    lock: &'a McsLock<T>,
+    node: Box<McsNode>,
+}
+
+unsafe impl<T: Sync> Sync for McsLockGuard<'_, T> {}
+
+impl<T> Deref for McsLockGuard<'_, T> {
+    type Target = T;
+    fn deref(&self) -> &T {
+        unsafe { &*self.lock.value.get() }
+    }
+}
+
+impl<T> DerefMut for McsLockGuard<'_, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        unsafe { &mut *self.lock.value.get() }
+    }
+}
+
+impl<T> Drop for McsLockGuard<'_, T> {
+    fn drop(&mut self) {
+        let node_ptr = &*self.node as *const McsNode as *mut McsNode;
+        let mut next = self.node.next.load(Ordering::Acquire);
+        if next.is_null() {
+            // No known successor: if we are still the tail, the queue becomes empty.
+            if self
+                .lock
+                .tail
+                .compare_exchange(
+                    node_ptr,
+                    ptr::null_mut(),
+                    Ordering::Release,
+                    Ordering::Relaxed,
+                )
+                .is_ok()
+            {
+                return;
+            }
+            // A thread has swapped itself in as the tail but not linked to us yet.
+            loop {
+                next = self.node.next.load(Ordering::Acquire);
+                if !next.is_null() {
+                    break;
+                }
+                spin_loop();
+            }
+        }
+        // SAFETY: the next thread is spinning on its node, which it keeps alive until this
+        // store. Our node is freed after, nobody else refers to it anymore.
+        unsafe { (*next).waiting.store(false, Ordering::Release) };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::McsLock;
+    use crate::futex_mutex::FutexMutex;
+    use std::sync::Arc;
+    use std::thread;
+    use std::time::SystemTime;
+
+    #[test]
+    fn test_lock_and_try_lock() {
+        let lock = McsLock::new(0);
+        let guard = lock.lock();
+        assert!(lock.try_lock().is_none());
+        assert!(lock.is_locked());
+        drop(guard);
+        assert!(!lock.is_locked());
+        *lock.try_lock().unwrap() += 1;
+        *lock.lock() += 1;
+        assert_eq!(lock.into_inner(), 2);
+    }
+
+    // Runs 8 threads doing 20k increments each under the lock, returns the time in ms.
+    fn contention<L: Send + Sync + 'static>(lock: L, increment: fn(&L)) -> u128 {
+        let time = SystemTime::now();
+        let lock = Arc::new(lock);
+        let handles: Vec<_> = (0..8)
+            .map(|_| {
+                let lock = Arc::clone(&lock);
+                thread::spawn(move || {
+                    for _ in 0..20_000 {
+                        increment(&lock);
+                    }
+                })
+            })
+            .collect();
+        for h in handles {
+            h.join().unwrap();
+        }
+        time.elapsed().unwrap().as_millis()
+    }
+
+    #[test]
+    fn test_mcs_lock_contention_vs_other_mutexes() {
+        let mcs = contention(McsLock::new(0usize), |l| *l.lock() += 1);
+        let spin = contention(crate::mutex::Mutex::new(0usize), |l| *l.lock() += 1);
+        let futex = contention(FutexMutex::new(0usize), |l| *l.lock() += 1);
+        println!("Time taken in McsLock: {mcs}ms, in Mutex: {spin}ms, in FutexMutex: {futex}ms");
+
+        let lock = Arc::new(McsLock::new(0usize));
+        let handles: Vec<_> = (0..8)
+            .map(|_| {
+                let lock = Arc::clone(&lock);
+                thread::spawn(move || {
+                    for _ in 0..20_000 {
+                        *lock.lock() += 1;
+                    }
+                })
+            })
+            .collect();
+        for h in handles {
+            h.join().unwrap();
+        }
+        assert_eq!(*lock.lock(), 160_000);
+    }
+}
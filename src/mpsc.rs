@@ -0,0 +1,392 @@
+//! A bounded multi-producer, single-consumer channel for async code.
+//!
+//! The channel holds at most `capacity` values: send waits for the receiver to make room,
+//! which slows fast producers down to the pace of the consumer (backpressure). Senders
+//! waiting for room are let in in FIFO order.
+//!
+//! When the Receiver is dropped or closed, sending fails and gives the value back. When all
+//! the Senders are dropped, recv returns the values left, then None.
+
+use crate::arc::Arc;
+use crate::mutex::Mutex;
+use crate::waker_list::{WakerList, WakerNode};
+use std::cell::{Cell, UnsafeCell};
+use std::collections::VecDeque;
+use std::future::Future;
+use std::pin::Pin;
+use std::task::{Context, Poll, Waker};
+
+/// Creates a channel holding at most `capacity` values. Panics if `capacity` is 0.
+pub fn channel<T>(capacity: usize) -> (Sender<T>, Receiver<T>) {
+    assert!(capacity > 0, "a channel needs room for at least one value");
+    let chan = Arc::new(Chan {
+        state: Mutex::new(State {
+            queue: VecDeque::with_capacity(capacity),
+            free: capacity,
+            senders: 1,
+            closed: false,
+            receiver: None,
+            send_waiters: WakerList::new(),
+        }),
+    });
+    (
+        Sender {
+            chan: Arc::clone(&chan),
+        },
+        Receiver { chan },
+    )
+}
+
+struct Chan<T> {
+    state: Mutex<State<T>>,
+}
+
+struct State<T> {
+    queue: VecDeque<T>,
+    // Room left, not counting the slots handed to waiting senders that haven't sent yet.
+    free: usize,
+    senders: usize,
+    // Set when the receiver is closed or dropped.
+    closed: bool,
+    receiver: Option<Waker>,
+    send_waiters: WakerList,
+}
+
+impl<T> State<T> {
+    // Hands the free slots to the waiting senders, and returns their wakers.
+    fn grant(&mut self) -> Vec<Waker> {
+        let mut wakers = Vec::new();
+        while self.free > 0 && !self.send_waiters.is_empty() {
+            self.free -= 1;
+            wakers.extend(self.send_waiters.pop_front());
+        }
+        wakers
+    }
+
+    fn close(&mut self) -> Vec<Waker> {
+        self.closed = true;
+        std::iter::from_fn(|| self.send_waiters.pop_front()).collect()
+    }
+}
+
+/// The error of send when the receiver is gone, with the value that couldn't be sent.
+#[derive(Debug, PartialEq, Eq)]
+pub struct SendError<T>(pub T);
+
+/// The error of try_send, with the value that couldn't be sent.
+#[derive(Debug, PartialEq, Eq)]
+pub enum TrySendError<T> {
+    /// The channel has no room left.
+    Full(T),
+    /// The receiver is gone.
+    Closed(T),
+}
+
+/// The error of try_recv.
+#[derive(Debug, PartialEq, Eq)]
+pub enum TryRecvError {
+    /// No value is waiting, but senders are left.
+    Empty,
+    /// No value is waiting, and all the senders are gone.
+    Disconnected,
+}
+
+impl<T> std::fmt::Display for SendError<T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("sending on a closed channel")
+    }
+}
+
+impl<T: std::fmt::Debug> std::error::Error for SendError<T> {}
+
+/// The sending half of a channel, which can be cloned to send from several tasks.
+pub struct Sender<T> {
+    chan: Arc<Chan<T>>,
+}
+
+impl<T> Sender<T> {
+    /// Sends a value, waiting for room in the channel. Fails if the receiver is gone.
+    pub async fn send(&self, value: T) -> Result<(), SendError<T>> {
+        Send {
+            chan: &self.chan,
+            value: Cell::new(Some(value)),
+            node: UnsafeCell::new(WakerNode::new(())),
+            queued: Cell::new(false),
+        }
+        .await
+    }
+
+    /// Sends a value if there is room in the channel right away.
+    pub fn try_send(&self, value: T) -> Result<(), TrySendError<T>> {
+        let mut state = self.chan.state.lock();
+        if state.closed {
+            return Err(TrySendError::Closed(value));
+        }
+        if state.free == 0 || !state.send_waiters.is_empty() {
+            return Err(TrySendError::Full(value));
+        }
+        state.free -= 1;
+        push(state, value);
+        Ok(())
+    }
+
+    /// Returns true once the receiver is closed or dropped.
+    pub fn is_closed(&self) -> bool {
+        self.chan.state.lock().closed
+    }
+}
+
+// Queues a value in a slot taken by the caller, and wakes the receiver.
+fn push<T>(mut state: crate::mutex::MutexGuard<'_, State<T>>, value: T) {
+    state.queue.push_back(value);
+    let receiver = state.receiver.take();
+    drop(state);
+    if let Some(receiver) = receiver {
+        receiver.wake();
+    }
+}
+
+impl<T> Clone for Sender<T> {
+    fn clone(&self) -> Self {
+        self.chan.state.lock().senders += 1;
+        Sender {
+            chan: Arc::clone(&self.chan),
+        }
+    }
+}
+
+impl<T> Drop for Sender<T> {
+    fn drop(&mut self) {
+        let mut state = self.chan.state.lock();
+        state.senders -= 1;
+        if state.senders == 0 {
+            // Let the receiver see that no more values are coming.
+            let receiver = state.receiver.take();
+            drop(state);
+            if let Some(receiver) = receiver {
+                receiver.wake();
+            }
+        }
+    }
+}
+
+// The future of Sender::send.
+struct Send<'a, T> {
+    chan: &'a Chan<T>,
+    value: Cell<Option<T>>,
+    // In the list of senders waiting for room while queued.
+    node: UnsafeCell<WakerNode>,
+    queued: Cell<bool>,
+}
+
+// The node is only accessed under the lock of the waiter list.
+unsafe impl<T: std::marker::Send> std::marker::Send for Send<'_, T> {}
+
+impl<T> Future for Send<'_, T> {
+    type Output = Result<(), SendError<T>>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        // The node must not move once queued, so only ever use a shared reference.
+        let this = self.into_ref().get_ref();
+        let mut state = this.chan.state.lock();
+        let node = unsafe { &mut *this.node.get() };
+        if state.closed {
+            if this.queued.get() {
+                unsafe { state.send_waiters.remove(node) };
+                this.queued.set(false);
+            }
+            return Poll::Ready(Err(SendError(this.value.take().unwrap())));
+        }
+        if this.queued.get() {
+            if !node.is_notified() {
+                node.set_waker(cx.waker());
+                return Poll::Pending;
+            }
+            // A slot was handed over to us.
+            this.queued.set(false);
+        } else if state.free > 0 && state.send_waiters.is_empty() {
+            state.free -= 1;
+        } else {
+            node.set_waker(cx.waker());
+            unsafe { state.send_waiters.push_back(node) };
+            this.queued.set(true);
+            return Poll::Pending;
+        }
+        push(state, this.value.take().unwrap());
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl<T> Drop for Send<'_, T> {
+    fn drop(&mut self) {
+        if !self.queued.get() {
+            return;
+        }
+        let mut state = self.chan.state.lock();
+        if unsafe { (*self.node.get()).is_notified() } {
+            // Give the slot handed over to us to the next sender.
+            state.free += 1;
+        } else {
+            unsafe { state.send_waiters.remove(self.node.get()) };
+        }
+        let wakers = state.grant();
+        drop(state);
+        wakers.into_iter().for_each(Waker::wake);
+    }
+}
+
+/// The receiving half of a channel.
+pub struct Receiver<T> {
+    chan: Arc<Chan<T>>,
+}
+
+impl<T> Receiver<T> {
+    /// Receives the next value, waiting for one to be sent. Returns None once the channel is
+    /// empty and all the senders are gone, or it is empty and closed.
+    pub async fn recv(&mut self) -> Option<T> {
+        std::future::poll_fn(|cx| match self.try_recv() {
+            Ok(value) => Poll::Ready(Some(value)),
+            Err(TryRecvError::Disconnected) => Poll::Ready(None),
+            Err(TryRecvError::Empty) => {
+                let mut state = self.chan.state.lock();
+                // A value may have been sent since try_recv released the lock.
+                if !state.queue.is_empty() || state.senders == 0 || state.closed {
+                    cx.waker().wake_by_ref();
+                } else {
+                    state.receiver = Some(cx.waker().clone());
+                }
+                Poll::Pending
+            }
+        })
+        .await
+    }
+
+    /// Receives the next value if one is waiting.
+    pub fn try_recv(&mut self) -> Result<T, TryRecvError> {
+        let mut state = self.chan.state.lock();
+        let Some(value) = state.queue.pop_front() else {
+            return Err(if state.senders == 0 || state.closed {
+                TryRecvError::Disconnected
+            } else {
+                TryRecvError::Empty
+            });
+        };
+        state.free += 1;
+        let wakers = state.grant();
+        drop(state);
+        wakers.into_iter().for_each(Waker::wake);
+        Ok(value)
+    }
+
+    /// Closes the channel: sending fails from now on, the values already sent can still be
+    /// received.
+    pub fn close(&mut self) {
+        let wakers = self.chan.state.lock().close();
+        wakers.into_iter().for_each(Waker::wake);
+    }
+}
+
+impl<T> Drop for Receiver<T> {
+    fn drop(&mut self) {
+        self.close();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{SendError, TryRecvError, TrySendError, channel};
+    use std::future::Future;
+    use std::pin::pin;
+    use std::task::{Context, Poll, Waker};
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+    async fn test_producers_and_consumer() {
+        let (tx, mut rx) = channel(4);
+        for p in 0..4 {
+            let tx = tx.clone();
+            tokio::spawn(async move {
+                for n in 0..250 {
+                    tx.send(p * 1000 + n).await.unwrap();
+                }
+            });
+        }
+        drop(tx);
+        let mut last = [None; 4];
+        let mut count = 0;
+        while let Some(value) = rx.recv().await {
+            // Each producer's values arrive in order.
+            let (p, n) = (value / 1000, value % 1000);
+            assert!(last[p].is_none_or(|last| last < n));
+            last[p] = Some(n);
+            count += 1;
+        }
+        assert_eq!(count, 1000);
+    }
+
+    #[test]
+    fn test_under_smol() {
+        smol::block_on(async {
+            let (tx, mut rx) = channel(2);
+            let producer = smol::spawn(async move {
+                for n in 0..100 {
+                    tx.send(n).await.unwrap();
+                }
+            });
+            let mut sum = 0;
+            while let Some(n) = rx.recv().await {
+                sum += n;
+            }
+            producer.await;
+            assert_eq!(sum, 4950);
+        });
+    }
+
+    #[test]
+    fn test_backpressure() {
+        let (tx, mut rx) = channel(1);
+        let mut cx = Context::from_waker(Waker::noop());
+        tx.try_send(1).unwrap();
+        assert_eq!(tx.try_send(2), Err(TrySendError::Full(2)));
+        let mut first = pin!(tx.send(2));
+        let mut second = pin!(tx.send(3));
+        assert!(first.as_mut().poll(&mut cx).is_pending());
+        assert!(second.as_mut().poll(&mut cx).is_pending());
+        // The room made goes to the first sender waiting.
+        assert_eq!(rx.try_recv(), Ok(1));
+        assert_eq!(tx.try_send(4), Err(TrySendError::Full(4)));
+        assert!(second.as_mut().poll(&mut cx).is_pending());
+        assert!(first.as_mut().poll(&mut cx).is_ready());
+        assert_eq!(rx.try_recv(), Ok(2));
+        assert!(second.as_mut().poll(&mut cx).is_ready());
+        assert_eq!(rx.try_recv(), Ok(3));
+        assert_eq!(rx.try_recv(), Err(TryRecvError::Empty));
+    }
+
+    #[test]
+    fn test_close_and_disconnect() {
+        let (tx, mut rx) = channel(1);
+        let mut cx = Context::from_waker(Waker::noop());
+        tx.try_send(1).unwrap();
+        let mut blocked = pin!(tx.send(2));
+        assert!(blocked.as_mut().poll(&mut cx).is_pending());
+        rx.close();
+        assert!(tx.is_closed());
+        assert_eq!(
+            blocked.as_mut().poll(&mut cx),
+            Poll::Ready(Err(SendError(2)))
+        );
+        assert_eq!(tx.try_send(3), Err(TrySendError::Closed(3)));
+        // What was sent before can still be received.
+        assert_eq!(rx.try_recv(), Ok(1));
+        assert_eq!(rx.try_recv(), Err(TryRecvError::Disconnected));
+
+        let (tx, mut rx) = channel(1);
+        let mut recv = Box::pin(rx.recv());
+        assert!(recv.as_mut().poll(&mut cx).is_pending());
+        tx.try_send(5).unwrap();
+        drop(tx);
+        assert_eq!(recv.as_mut().poll(&mut cx), Poll::Ready(Some(5)));
+        drop(recv);
+        assert_eq!(pin!(rx.recv()).poll(&mut cx), Poll::Ready(None));
+    }
+}
@@ -1,68 +1,216 @@
+use crate::lock_api::{self, GuardSend};
 use std::cell::UnsafeCell;
+use std::collections::VecDeque;
 use std::hint::spin_loop;
-use std::ops::{Deref, DerefMut};
-use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, AtomicU8, Ordering};
+use std::thread::{self, Thread};
+
+const UNLOCKED: u8 = 0;
+const LOCKED: u8 = 1;
+// Locked, and some threads may be parked waiting for the lock.
+const CONTENDED: u8 = 2;
+
+// Rounds of spinning, then of yielding, before a thread parks itself.
+const SPIN_LIMIT: u32 = 100;
+const YIELD_LIMIT: u32 = 10;
 
 /// A mutual exclusion primitive useful for protecting shared data
 ///
 /// This mutex will block threads waiting for the lock to become available.
-pub struct Mutex<T> {
-    value: UnsafeCell<T>,
+/// A waiting thread first spins for a short while, as locks are usually held for a short
+/// time, then yields its time slice a few times, and finally parks until the lock is released.
+pub type Mutex<T> = lock_api::Mutex<RawMutex, T>;
+
+/// The guard of a locked Mutex. It can be shared with other threads when T is Sync:
+/// ```
+/// use pointers::mutex::Mutex;
+/// let mutex = Mutex::new(0);
+/// let guard = mutex.lock();
+/// std::thread::scope(|s| {
+///     s.spawn(|| assert_eq!(*guard, 0));
+/// });
+/// ```
+///
+/// But not when T isn't:
+/// ```compile_fail
+/// use pointers::mutex::Mutex;
+/// let mutex = Mutex::new(std::cell::Cell::new(0));
+/// let guard = mutex.lock();
+/// std::thread::scope(|s| {
+///     s.spawn(|| guard.set(1));
+/// });
+/// ```
+///
+/// And it can't be sent to another thread, the lock is released by the thread that took it:
+/// ```compile_fail
+/// use pointers::mutex::Mutex;
+/// let mutex = Mutex::new(0);
+/// let guard = mutex.lock();
+/// std::thread::scope(|s| {
+///     s.spawn(move || drop(guard));
+/// });
+/// ```
+pub type MutexGuard<'a, T> = lock_api::MutexGuard<'a, RawMutex, T>;
+
+/// A guard returned by Mutex::lock_owned, which holds the lock as long as it lives.
+/// It has no lifetime, so it can be moved into spawned threads:
+/// ```
+/// use pointers::mutex::Mutex;
+/// use std::sync::Arc;
+/// let mutex = Arc::new(Mutex::new(0));
+/// let mut guard = mutex.clone().lock_owned();
+/// std::thread::spawn(move || *guard += 1).join().unwrap();
+/// assert_eq!(*mutex.lock(), 1);
+/// ```
+pub type OwnedMutexGuard<T> = lock_api::OwnedMutexGuard<RawMutex, T>;
+
+/// The raw lock of Mutex.
+pub struct RawMutex {
+    state: AtomicU8,
+    waiters: WaitQueue,
+}
+
+unsafe impl lock_api::RawMutex for RawMutex {
+    const INIT: RawMutex = RawMutex {
+        state: AtomicU8::new(UNLOCKED),
+        waiters: WaitQueue::new(),
+    };
+
+    type GuardMarker = GuardSend;
+
+    fn lock(&self) {
+        if self.try_lock() {
+            return;
+        }
+        // Test-and-test-and-set: only attempt the compare_exchange once the lock looks free.
+        // The load keeps the cache line shared between the waiting cores, while every
+        // compare_exchange takes it exclusive, even when it fails.
+        for _ in 0..SPIN_LIMIT {
+            spin_loop();
+            if self.is_unlocked() && self.try_lock() {
+                return;
+            }
+        }
+        for _ in 0..YIELD_LIMIT {
+            thread::yield_now();
+            if self.is_unlocked() && self.try_lock() {
+                return;
+            }
+        }
+        // Mark the lock as contended, so the unlock knows to wake a thread up. As we can't
+        // tell whether other threads are parked, the lock stays contended once we get it.
+        while self.state.swap(CONTENDED, Ordering::Acquire) != UNLOCKED {
+            self.waiters
+                .park_while(|| self.state.load(Ordering::Relaxed) == CONTENDED);
+        }
+    }
+
+    fn try_lock(&self) -> bool {
+        self.state
+            .compare_exchange(UNLOCKED, LOCKED, Ordering::Acquire, Ordering::Relaxed)
+            .is_ok()
+    }
+
+    unsafe fn unlock(&self) {
+        if self.state.swap(UNLOCKED, Ordering::Release) == CONTENDED {
+            self.waiters.unpark_one();
+        }
+    }
+
+    fn is_locked(&self) -> bool {
+        !self.is_unlocked()
+    }
+}
+
+impl RawMutex {
+    fn is_unlocked(&self) -> bool {
+        self.state.load(Ordering::Relaxed) == UNLOCKED
+    }
+}
+
+/// The threads parked on a Mutex or Condvar, in FIFO order, behind a small spinlock that is only held
+/// to push or pop a thread.
+pub(crate) struct WaitQueue {
     locked: AtomicBool,
+    threads: UnsafeCell<VecDeque<Arc<Waiter>>>,
 }
 
-unsafe impl<T: Send> Sync for Mutex<T> {}
+// The queue is only accessed under the spinlock.
+unsafe impl Sync for WaitQueue {}
+
+struct Waiter {
+    thread: Thread,
+    // Set before unparking, park can also return spuriously.
+    notified: AtomicBool,
+}
 
-impl<T> Mutex<T> {
-    pub fn new(value: T) -> Self {
-        Self {
-            value: UnsafeCell::new(value),
+impl WaitQueue {
+    pub(crate) const fn new() -> Self {
+        WaitQueue {
             locked: AtomicBool::new(false),
+            threads: UnsafeCell::new(VecDeque::new()),
         }
     }
 
-    pub fn lock(&self) -> MutexGuard<'_, T> {
+    fn with_threads<R>(&self, f: impl FnOnce(&mut VecDeque<Arc<Waiter>>) -> R) -> R {
         while self
             .locked
-            .compare_exchange(false, true, Ordering::Acquire, Ordering::Relaxed)
+            .compare_exchange_weak(false, true, Ordering::Acquire, Ordering::Relaxed)
             .is_err()
         {
-            // std::Mutex uses futex internally using libc hence perform better than us.
             spin_loop();
         }
-        MutexGuard { mutex: self }
+        // SAFETY: the spinlock gives us exclusive access to the queue.
+        let result = f(unsafe { &mut *self.threads.get() });
+        self.locked.store(false, Ordering::Release);
+        result
     }
-}
-
-pub struct MutexGuard<'a, T> {
-    mutex: &'a Mutex<T>,
-}
-
-unsafe impl<T: Sync> Sync for MutexGuard<'_, T> {}
 
-impl<'a, T> Deref for MutexGuard<'a, T> {
-    type Target = T;
-    fn deref(&self) -> &T {
-        unsafe { &*self.mutex.value.get() }
+    /// Parks the current thread until unpark_one picks it, unless `condition` is false.
+    /// The condition is checked under the queue lock, so an unpark_one that follows a change
+    /// making it false can't be missed.
+    pub(crate) fn park_while(&self, condition: impl FnOnce() -> bool) {
+        let waiter = Arc::new(Waiter {
+            thread: thread::current(),
+            notified: AtomicBool::new(false),
+        });
+        let queued = self.with_threads(|threads| {
+            let queued = condition();
+            if queued {
+                threads.push_back(waiter.clone());
+            }
+            queued
+        });
+        if queued {
+            while !waiter.notified.load(Ordering::Acquire) {
+                thread::park();
+            }
+        }
     }
-}
 
-impl<'a, T> DerefMut for MutexGuard<'a, T> {
-    fn deref_mut(&mut self) -> &mut T {
-        unsafe { &mut *self.mutex.value.get() }
+    pub(crate) fn unpark_one(&self) {
+        if let Some(waiter) = self.with_threads(|threads| threads.pop_front()) {
+            waiter.notified.store(true, Ordering::Release);
+            waiter.thread.unpark();
+        }
     }
-}
 
-impl<T> Drop for MutexGuard<'_, T> {
-    fn drop(&mut self) {
-        self.mutex.locked.store(false, Ordering::Release);
+    pub(crate) fn unpark_all(&self) {
+        // Unpark outside of the queue lock, which the woken threads may need right away.
+        for waiter in self.with_threads(std::mem::take) {
+            waiter.notified.store(true, Ordering::Release);
+            waiter.thread.unpark();
+        }
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::Mutex;
+    use crate::lock_api::{self, GuardSend, RawMutex};
     use std::sync::Arc;
+    use std::sync::atomic::{AtomicBool, Ordering};
     use std::thread;
     use std::time::{Duration, SystemTime};
 
@@ -80,9 +228,125 @@ mod tests {
     }
 
     #[test]
-    fn test_mutex_contention_increment() {
+    fn test_try_lock() {
+        let mutex = Mutex::new(1);
+        let guard = mutex.try_lock().unwrap();
+        assert!(mutex.try_lock().is_none());
+        assert!(mutex.is_locked());
+        drop(guard);
+        assert!(!mutex.is_locked());
+        *mutex.try_lock().unwrap() += 1;
+        assert_eq!(*mutex.lock(), 2);
+    }
+
+    #[test]
+    fn test_static_mutex() {
+        static COUNTER: Mutex<usize> = Mutex::new(0);
+        let handles: Vec<_> = (0..4)
+            .map(|_| thread::spawn(|| *COUNTER.lock() += 1))
+            .collect();
+        for h in handles {
+            h.join().unwrap();
+        }
+        assert_eq!(*COUNTER.lock(), 4);
+    }
+
+    #[test]
+    fn test_leak_guard_of_static_mutex() {
+        static BUFFER: Mutex<Vec<u8>> = Mutex::new(Vec::new());
+        let buffer: &'static mut Vec<u8> = super::MutexGuard::leak(BUFFER.lock());
+        buffer.extend_from_slice(b"init");
+        assert!(BUFFER.is_locked());
+        assert!(BUFFER.try_lock().is_none());
+        assert_eq!(buffer, b"init");
+    }
+
+    #[test]
+    fn test_force_unlock() {
+        let mutex = Mutex::new(0);
+        std::mem::forget(mutex.lock());
+        assert!(mutex.try_lock().is_none());
+        unsafe { mutex.force_unlock() };
+        mutex.raw_lock();
+        assert!(mutex.is_locked());
+        unsafe { mutex.force_unlock() };
+        *mutex.lock() += 1;
+        assert_eq!(mutex.into_inner(), 1);
+    }
+
+    #[test]
+    fn test_into_inner_and_get_mut() {
+        let mut mutex = Mutex::new(vec![1]);
+        mutex.get_mut().push(2);
+        assert_eq!(mutex.into_inner(), vec![1, 2]);
+    }
+
+    #[test]
+    fn test_owned_guard_moved_to_thread() {
+        let mutex = Arc::new(Mutex::new(vec![1]));
+        let mut guard = mutex.clone().lock_owned();
+        assert!(mutex.clone().try_lock_owned().is_none());
+        let handle = thread::spawn(move || {
+            guard.push(2);
+            // The lock is released on the spawned thread.
+        });
+        handle.join().unwrap();
+        let guard = mutex.clone().try_lock_owned().unwrap();
+        assert!(Arc::ptr_eq(super::OwnedMutexGuard::mutex(&guard), &mutex));
+        assert_eq!(*guard, [1, 2]);
+    }
+
+    #[test]
+    fn test_waiters_are_parked_and_woken() {
+        let mutex = Arc::new(Mutex::new(0));
+        let guard = mutex.lock();
+        let handles: Vec<_> = (0..4)
+            .map(|_| {
+                let m = Arc::clone(&mutex);
+                thread::spawn(move || *m.lock() += 1)
+            })
+            .collect();
+        // Long enough for the waiters to get past spinning and yielding.
+        thread::sleep(Duration::from_millis(50));
+        assert_eq!(
+            mutex.raw.state.load(super::Ordering::Relaxed),
+            super::CONTENDED
+        );
+        drop(guard);
+        for h in handles {
+            h.join().unwrap();
+        }
+        assert_eq!(*mutex.lock(), 4);
+    }
+
+    // The lock before waiters parked: spinning on the compare_exchange until it succeeds.
+    struct RawSpinMutex(AtomicBool);
+
+    unsafe impl lock_api::RawMutex for RawSpinMutex {
+        const INIT: RawSpinMutex = RawSpinMutex(AtomicBool::new(false));
+        type GuardMarker = GuardSend;
+
+        fn lock(&self) {
+            while !self.try_lock() {
+                std::hint::spin_loop();
+            }
+        }
+
+        fn try_lock(&self) -> bool {
+            self.0
+                .compare_exchange(false, true, Ordering::Acquire, Ordering::Relaxed)
+                .is_ok()
+        }
+
+        unsafe fn unlock(&self) {
+            self.0.store(false, Ordering::Release);
+        }
+    }
+
+    // Runs 40 threads doing 1M increments each under the mutex, returns the time in ms.
+    fn contention<R: RawMutex + Send + Sync + 'static>() -> u128 {
         let time = SystemTime::now();
-        let mutex = Arc::new(Mutex::new(0usize));
+        let mutex = Arc::new(lock_api::Mutex::<R, usize>::new(0));
         let mut handles = vec![];
 
         for _ in 0..40 {
@@ -99,9 +363,73 @@ mod tests {
             h.join().unwrap();
         }
         assert_eq!(*mutex.lock(), 40000000);
+        time.elapsed().unwrap().as_millis()
+    }
+
+    #[test]
+    fn test_mutex_contention_increment() {
+        let parking = contention::<super::RawMutex>();
+        let spinning = contention::<RawSpinMutex>();
         println!(
-            "Time taken in my Mutex: {}ms",
-            time.elapsed().unwrap().as_millis()
+            "Time taken in my Mutex: {parking}ms, spinning until the lock is free: {spinning}ms"
+        );
+    }
+
+    #[test]
+    fn test_tas_vs_ttas_contention() {
+        use std::sync::atomic::{AtomicBool, Ordering};
+
+        // A bare spinlock, with or without the relaxed load before the compare_exchange.
+        // It yields now and then so the benchmark also completes on machines with few cores.
+        fn lock(flag: &AtomicBool, ttas: bool) {
+            let mut spins = 0_u32;
+            loop {
+                if (!ttas || !flag.load(Ordering::Relaxed))
+                    && flag
+                        .compare_exchange(false, true, Ordering::Acquire, Ordering::Relaxed)
+                        .is_ok()
+                {
+                    return;
+                }
+                spins += 1;
+                if spins.is_multiple_of(64) {
+                    thread::yield_now();
+                } else {
+                    std::hint::spin_loop();
+                }
+            }
+        }
+
+        fn run(ttas: bool) -> u128 {
+            let time = SystemTime::now();
+            let flag = Arc::new(AtomicBool::new(false));
+            let counter = Arc::new(std::sync::atomic::AtomicUsize::new(0));
+            let handles: Vec<_> = (0..40)
+                .map(|_| {
+                    let (flag, counter) = (flag.clone(), counter.clone());
+                    thread::spawn(move || {
+                        for _ in 0..10_000 {
+                            lock(&flag, ttas);
+                            counter.fetch_add(1, Ordering::Relaxed);
+                            flag.store(false, Ordering::Release);
+                        }
+                    })
+                })
+                .collect();
+            for h in handles {
+                h.join().unwrap();
+            }
+            assert_eq!(counter.load(Ordering::Relaxed), 400_000);
+            time.elapsed().unwrap().as_millis().max(1)
+        }
+
+        let tas = run(false);
+        let ttas = run(true);
+        // The gap grows with the number of cores fighting over the cache line.
+        println!(
+            "40 threads, 400k locks: TAS {} locks/ms, TTAS {} locks/ms",
+            400_000 / tas,
+            400_000 / ttas
         );
     }
 
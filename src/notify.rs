@@ -0,0 +1,187 @@
+use crate::mutex::Mutex;
+use crate::waker_list::{WakerList, WakerNode};
+use std::cell::{Cell, UnsafeCell};
+use std::future::Future;
+use std::pin::Pin;
+use std::task::{Context, Poll, Waker};
+
+/// Wakes up tasks without passing them any data, e.g. to tell a worker that work is
+/// available, or tasks that they should shut down.
+///
+/// notify_one wakes a task waiting in notified, or if there is none, stores a permit that the
+/// next call to notified consumes right away, so a notification sent just before a task
+/// starts waiting isn't lost. At most one permit is stored. notify_waiters wakes all the
+/// tasks waiting at the time, and stores no permit.
+pub struct Notify {
+    state: Mutex<State>,
+}
+
+struct State {
+    permit: bool,
+    // Each node is set when its task is woken by notify_one rather than notify_waiters.
+    waiters: WakerList<bool>,
+}
+
+impl Notify {
+    pub const fn new() -> Notify {
+        Notify {
+            state: Mutex::new(State {
+                permit: false,
+                waiters: WakerList::new(),
+            }),
+        }
+    }
+
+    /// Waits for a notification, or consumes the stored permit.
+    pub async fn notified(&self) {
+        Notified {
+            notify: self,
+            node: UnsafeCell::new(WakerNode::new(false)),
+            queued: Cell::new(false),
+        }
+        .await
+    }
+
+    /// Wakes up the task that has been waiting the longest, or stores a permit if none is.
+    pub fn notify_one(&self) {
+        let mut state = self.state.lock();
+        let Some(by_notify_one) = state.waiters.front_mut() else {
+            state.permit = true;
+            return;
+        };
+        *by_notify_one = true;
+        let waker = state.waiters.pop_front();
+        drop(state);
+        if let Some(waker) = waker {
+            waker.wake();
+        }
+    }
+
+    /// Wakes up all the tasks waiting.
+    pub fn notify_waiters(&self) {
+        let mut state = self.state.lock();
+        let wakers: Vec<Waker> = std::iter::from_fn(|| state.waiters.pop_front()).collect();
+        drop(state);
+        wakers.into_iter().for_each(Waker::wake);
+    }
+}
+
+impl Default for Notify {
+    fn default() -> Self {
+        Notify::new()
+    }
+}
+
+// The future of Notify::notified.
+struct Notified<'a> {
+    notify: &'a Notify,
+    // In the waiter list of the Notify while queued.
+    node: UnsafeCell<WakerNode<bool>>,
+    queued: Cell<bool>,
+}
+
+// The node is only accessed under the lock of the waiter list.
+unsafe impl Send for Notified<'_> {}
+
+impl Future for Notified<'_> {
+    type Output = ();
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+        // The node must not move once queued, so only ever use a shared reference.
+        let this = self.into_ref().get_ref();
+        let mut state = this.notify.state.lock();
+        let node = unsafe { &mut *this.node.get() };
+        if this.queued.get() {
+            if node.is_notified() {
+                this.queued.set(false);
+                return Poll::Ready(());
+            }
+            node.set_waker(cx.waker());
+            return Poll::Pending;
+        }
+        if state.permit {
+            state.permit = false;
+            return Poll::Ready(());
+        }
+        node.set_waker(cx.waker());
+        unsafe { state.waiters.push_back(node) };
+        this.queued.set(true);
+        Poll::Pending
+    }
+}
+
+impl Drop for Notified<'_> {
+    fn drop(&mut self) {
+        if !self.queued.get() {
+            return;
+        }
+        let mut state = self.notify.state.lock();
+        let node = self.node.get();
+        if unsafe { (*node).is_notified() } {
+            // A notify_one meant for a single task: pass it on rather than lose it.
+            if unsafe { *(*node).value() } {
+                drop(state);
+                self.notify.notify_one();
+            }
+            return;
+        }
+        unsafe { state.waiters.remove(node) };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Notify;
+    use crate::arc::Arc;
+    use std::future::Future;
+    use std::pin::pin;
+    use std::task::{Context, Waker};
+
+    #[test]
+    fn test_permit() {
+        let notify = Notify::new();
+        let mut cx = Context::from_waker(Waker::noop());
+        // Stored when no task waits, only once.
+        notify.notify_one();
+        notify.notify_one();
+        assert!(pin!(notify.notified()).poll(&mut cx).is_ready());
+        let mut waiter = pin!(notify.notified());
+        assert!(waiter.as_mut().poll(&mut cx).is_pending());
+        // notify_waiters stores none.
+        notify.notify_waiters();
+        assert!(waiter.as_mut().poll(&mut cx).is_ready());
+        assert!(pin!(notify.notified()).poll(&mut cx).is_pending());
+    }
+
+    #[test]
+    fn test_notify_one_wakes_in_order() {
+        let notify = Notify::new();
+        let mut cx = Context::from_waker(Waker::noop());
+        let mut first = Box::pin(notify.notified());
+        let mut second = pin!(notify.notified());
+        assert!(first.as_mut().poll(&mut cx).is_pending());
+        assert!(second.as_mut().poll(&mut cx).is_pending());
+        notify.notify_one();
+        assert!(second.as_mut().poll(&mut cx).is_pending());
+        // Passed on when the task it went to is dropped.
+        drop(first);
+        assert!(second.as_mut().poll(&mut cx).is_ready());
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+    async fn test_shutdown_signal() {
+        let shutdown = Arc::new(Notify::new());
+        let handles: Vec<_> = (0..4)
+            .map(|_| {
+                let shutdown = shutdown.clone();
+                tokio::spawn(async move { shutdown.notified().await })
+            })
+            .collect();
+        // Let the tasks start waiting.
+        tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+        shutdown.notify_waiters();
+        for h in handles {
+            h.await.unwrap();
+        }
+    }
+}
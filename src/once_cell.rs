@@ -0,0 +1,146 @@
+use crate::cell::Cell;
+use std::cell::UnsafeCell;
+use std::ops::Deref;
+
+/// A cell which can be written to only once. Unlike Cell, a shared reference to the value
+/// can be handed out, since the value is never replaced once it has been set.
+/// Not Sync, just like Cell.
+pub struct OnceCell<T> {
+    value: UnsafeCell<Option<T>>,
+}
+
+impl<T> OnceCell<T> {
+    pub const fn new() -> Self {
+        Self {
+            value: UnsafeCell::new(None),
+        }
+    }
+
+    /// Gets a reference to the value, or None if the cell is still empty.
+    pub fn get(&self) -> Option<&T> {
+        // SAFETY: once set, the value is never mutated through &self, so references to it stay valid.
+        unsafe { &*self.value.get() }.as_ref()
+    }
+
+    /// Sets the value of the cell, giving it back as Err if the cell was already full.
+    pub fn set(&self, value: T) -> Result<(), T> {
+        if self.get().is_some() {
+            return Err(value);
+        }
+        // SAFETY: the cell is empty, so no reference to its content was handed out.
+        unsafe { *self.value.get() = Some(value) };
+        Ok(())
+    }
+
+    /// Gets the value, initializing it with f if the cell is empty.
+    /// Panics if f itself initializes the cell (reentrant initialization).
+    pub fn get_or_init(&self, f: impl FnOnce() -> T) -> &T {
+        if let Some(value) = self.get() {
+            return value;
+        }
+        let value = f();
+        assert!(self.set(value).is_ok(), "reentrant init");
+        self.get().unwrap()
+    }
+
+    pub fn into_inner(self) -> Option<T> {
+        self.value.into_inner()
+    }
+
+    /// Takes the value out, leaving the cell empty. &mut self guarantees no reference is alive.
+    pub fn take(&mut self) -> Option<T> {
+        self.value.get_mut().take()
+    }
+}
+
+impl<T> Default for OnceCell<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T: std::fmt::Debug> std::fmt::Debug for OnceCell<T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.get() {
+            Some(value) => f.debug_tuple("OnceCell").field(value).finish(),
+            None => f.write_str("OnceCell(<uninit>)"),
+        }
+    }
+}
+
+/// A value which is initialized on first access, by calling the closure given to new.
+/// Derefs to T, so an expensive value can be declared inline and only computed if used.
+/// If the closure panics the LazyCell is poisoned, and later accesses panic as well.
+pub struct LazyCell<T, F = fn() -> T> {
+    cell: OnceCell<T>,
+    init: Cell<Option<F>>,
+}
+
+impl<T, F: FnOnce() -> T> LazyCell<T, F> {
+    pub const fn new(init: F) -> Self {
+        Self {
+            cell: OnceCell::new(),
+            init: Cell::new(Some(init)),
+        }
+    }
+
+    /// Forces the evaluation of the lazy value and returns a reference to it.
+    pub fn force(this: &LazyCell<T, F>) -> &T {
+        this.cell.get_or_init(|| match this.init.take() {
+            Some(init) => init(),
+            None => panic!("LazyCell instance has previously been poisoned"),
+        })
+    }
+}
+
+impl<T, F: FnOnce() -> T> Deref for LazyCell<T, F> {
+    type Target = T;
+    fn deref(&self) -> &T {
+        LazyCell::force(self)
+    }
+}
+
+impl<T: std::fmt::Debug, F> std::fmt::Debug for LazyCell<T, F> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.cell.get() {
+            Some(value) => f.debug_tuple("LazyCell").field(value).finish(),
+            None => f.write_str("LazyCell(<uninit>)"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{LazyCell, OnceCell};
+    use crate::cell::Cell;
+
+    #[test]
+    fn test_once_cell() {
+        let cell = OnceCell::new();
+        assert!(cell.get().is_none());
+        assert_eq!(cell.get_or_init(|| 5), &5);
+        assert_eq!(cell.get_or_init(|| 6), &5);
+        assert_eq!(cell.set(7), Err(7));
+        assert_eq!(cell.into_inner(), Some(5));
+    }
+
+    #[test]
+    #[should_panic(expected = "reentrant init")]
+    fn test_once_cell_reentrant_init() {
+        let cell = OnceCell::new();
+        cell.get_or_init(|| *cell.get_or_init(|| 1) + 1);
+    }
+
+    #[test]
+    fn test_lazy_cell() {
+        let calls = Cell::new(0);
+        let lazy = LazyCell::new(|| {
+            calls.set(calls.get() + 1);
+            vec![1, 2, 3]
+        });
+        assert_eq!(calls.get(), 0);
+        assert_eq!(lazy.len(), 3);
+        assert_eq!(lazy[0], 1);
+        assert_eq!(calls.get(), 1);
+    }
+}
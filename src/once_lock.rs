@@ -0,0 +1,230 @@
+use crate::platform;
+use std::cell::UnsafeCell;
+use std::sync::atomic::{AtomicU32, Ordering};
+
+const INCOMPLETE: u32 = 0;
+const RUNNING: u32 = 1;
+// Running, and other threads are waiting on the futex for it to finish.
+const RUNNING_WAITERS: u32 = 2;
+const COMPLETE: u32 = 3;
+const POISONED: u32 = 4;
+
+/// Runs a one-time initialization, even when many threads ask for it at the same time: one
+/// of them runs it, and the others sleep on a futex until it is done. If the initialization
+/// panics, the Once is poisoned, and the threads calling call_once then panic as well.
+pub struct Once {
+    state: AtomicU32,
+}
+
+impl Once {
+    pub const fn new() -> Self {
+        Once {
+            state: AtomicU32::new(INCOMPLETE),
+        }
+    }
+
+    /// Runs f if no call_once has run yet, otherwise waits for the one running to finish.
+    /// Panics if an earlier f panicked.
+    pub fn call_once(&self, f: impl FnOnce()) {
+        // The fast path, once initialized: a single load.
+        if self.state.load(Ordering::Acquire) == COMPLETE {
+            return;
+        }
+        let mut f = Some(f);
+        loop {
+            match self.state.load(Ordering::Acquire) {
+                COMPLETE => return,
+                POISONED => panic!("Once instance has previously been poisoned"),
+                INCOMPLETE => {
+                    if self
+                        .state
+                        .compare_exchange(INCOMPLETE, RUNNING, Ordering::Acquire, Ordering::Acquire)
+                        .is_err()
+                    {
+                        continue;
+                    }
+                    // If f panics, the guard poisons the Once and wakes the waiters up.
+                    let mut guard = Finish {
+                        once: self,
+                        state: POISONED,
+                    };
+                    (f.take().unwrap())();
+                    guard.state = COMPLETE;
+                    return;
+                }
+                state => {
+                    // Tell the running thread to wake us up, then sleep until it is done.
+                    if state == RUNNING
+                        && self
+                            .state
+                            .compare_exchange(
+                                RUNNING,
+                                RUNNING_WAITERS,
+                                Ordering::Relaxed,
+                                Ordering::Relaxed,
+                            )
+                            .is_err()
+                    {
+                        continue;
+                    }
+                    platform::wait(&self.state, RUNNING_WAITERS);
+                }
+            }
+        }
+    }
+
+    /// Returns true once a call_once has completed.
+    pub fn is_completed(&self) -> bool {
+        self.state.load(Ordering::Acquire) == COMPLETE
+    }
+}
+
+impl Default for Once {
+    fn default() -> Self {
+        Once::new()
+    }
+}
+
+// Sets the final state when call_once is done running f, or when f panics.
+struct Finish<'a> {
+    once: &'a Once,
+    state: u32,
+}
+
+impl Drop for Finish<'_> {
+    fn drop(&mut self) {
+        if self.once.state.swap(self.state, Ordering::Release) == RUNNING_WAITERS {
+            platform::wake_all(&self.once.state);
+        }
+    }
+}
+
+/// A cell which can be written to only once, like OnceCell, but Sync: threads racing to
+/// initialize it wait for the first one, see Once.
+pub struct OnceLock<T> {
+    once: Once,
+    value: UnsafeCell<Option<T>>,
+}
+
+unsafe impl<T: Send> Send for OnceLock<T> {}
+unsafe impl<T: Send + Sync> Sync for OnceLock<T> {}
+
+impl<T> OnceLock<T> {
+    pub const fn new() -> Self {
+        OnceLock {
+            once: Once::new(),
+            value: UnsafeCell::new(None),
+        }
+    }
+
+    /// Gets a reference to the value, or None if the lock isn't initialized yet.
+    pub fn get(&self) -> Option<&T> {
+        if !self.once.is_completed() {
+            return None;
+        }
+        // SAFETY: the value was written before the Once completed, and is never changed after.
+        unsafe { &*self.value.get() }.as_ref()
+    }
+
+    /// Gets the value, initializing it with f if no thread has done it yet. Panics if an
+    /// earlier initialization panicked.
+    pub fn get_or_init(&self, f: impl FnOnce() -> T) -> &T {
+        self.once.call_once(|| {
+            let value = f();
+            // SAFETY: only the thread running call_once can get here, and no reference to
+            // the value is handed out until the Once completes.
+            unsafe { *self.value.get() = Some(value) };
+        });
+        self.get().unwrap()
+    }
+
+    /// Sets the value, giving it back as Err if the lock was already initialized.
+    pub fn set(&self, value: T) -> Result<(), T> {
+        let mut value = Some(value);
+        self.get_or_init(|| value.take().unwrap());
+        match value {
+            None => Ok(()),
+            Some(value) => Err(value),
+        }
+    }
+
+    pub fn into_inner(self) -> Option<T> {
+        self.value.into_inner()
+    }
+}
+
+impl<T> Default for OnceLock<T> {
+    fn default() -> Self {
+        OnceLock::new()
+    }
+}
+
+impl<T: std::fmt::Debug> std::fmt::Debug for OnceLock<T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.get() {
+            Some(value) => f.debug_tuple("OnceLock").field(value).finish(),
+            None => f.write_str("OnceLock(<uninit>)"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{INCOMPLETE, Once, OnceLock};
+    use std::panic::{AssertUnwindSafe, catch_unwind};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::thread;
+    use std::time::Duration;
+
+    #[test]
+    fn test_once_lock() {
+        static CONFIG: OnceLock<String> = OnceLock::new();
+        static CALLS: AtomicUsize = AtomicUsize::new(0);
+        assert!(CONFIG.get().is_none());
+        thread::scope(|s| {
+            for _ in 0..8 {
+                s.spawn(|| {
+                    let config = CONFIG.get_or_init(|| {
+                        CALLS.fetch_add(1, Ordering::Relaxed);
+                        // Long enough for the other threads to wait on the futex.
+                        thread::sleep(Duration::from_millis(20));
+                        "verbose".to_string()
+                    });
+                    assert_eq!(config, "verbose");
+                });
+            }
+        });
+        assert_eq!(CALLS.load(Ordering::Relaxed), 1);
+        assert_eq!(CONFIG.set("quiet".to_string()), Err("quiet".to_string()));
+        assert_eq!(format!("{CONFIG:?}"), r#"OnceLock("verbose")"#);
+
+        let lock = OnceLock::new();
+        assert_eq!(lock.set(1), Ok(()));
+        assert_eq!(lock.into_inner(), Some(1));
+    }
+
+    #[test]
+    fn test_panic_poisons_once() {
+        let once = Once::new();
+        thread::scope(|s| {
+            s.spawn(|| {
+                let _ = catch_unwind(AssertUnwindSafe(|| {
+                    once.call_once(|| {
+                        thread::sleep(Duration::from_millis(20));
+                        panic!("init failed");
+                    })
+                }));
+            });
+            while once.state.load(Ordering::Relaxed) == INCOMPLETE {
+                thread::yield_now();
+            }
+            // Waits for the failed initialization, and panics with it.
+            let waiter = s.spawn(|| once.call_once(|| {}));
+            assert!(waiter.join().is_err());
+        });
+        assert!(!once.is_completed());
+        let result = catch_unwind(AssertUnwindSafe(|| once.call_once(|| {})));
+        let message = *result.unwrap_err().downcast::<&str>().unwrap();
+        assert_eq!(message, "Once instance has previously been poisoned");
+    }
+}
@@ -0,0 +1,153 @@
+//! Thread parking keyed by address, the way parking_lot keeps its locks small: instead of
+//! every lock owning a queue of waiting threads, parked threads go into a global hash table,
+//! in the bucket of the address they wait on. A lock then only needs a couple of bits, to
+//! know whether threads are parked on it.
+
+use std::cell::UnsafeCell;
+use std::hint::spin_loop;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread::{self, Thread};
+use std::time::Instant;
+
+// A power of two, so that the hash can be reduced with a shift. Threads waiting on different
+// addresses can share a bucket, which only costs a little contention on the bucket lock.
+const BUCKET_BITS: u32 = 6;
+
+static BUCKETS: [Bucket; 1 << BUCKET_BITS] = [const { Bucket::new() }; 1 << BUCKET_BITS];
+
+/// The threads parked on the addresses hashed to the same bucket, in FIFO order, behind a
+/// small spinlock that is only held to update the queue.
+struct Bucket {
+    locked: AtomicBool,
+    queue: UnsafeCell<Vec<(usize, Arc<Parked>)>>,
+}
+
+// The queue is only accessed under the spinlock.
+unsafe impl Sync for Bucket {}
+
+struct Parked {
+    thread: Thread,
+    // Set before unparking, park can also return spuriously.
+    unparked: AtomicBool,
+}
+
+impl Bucket {
+    const fn new() -> Bucket {
+        Bucket {
+            locked: AtomicBool::new(false),
+            queue: UnsafeCell::new(Vec::new()),
+        }
+    }
+
+    fn for_key(key: usize) -> &'static Bucket {
+        // Fibonacci hashing, the high bits of the product depend on all bits of the key.
+        let hash = (key as u64).wrapping_mul(0x9E37_79B9_7F4A_7C15) >> (64 - BUCKET_BITS);
+        &BUCKETS[hash as usize]
+    }
+
+    fn with_queue<R>(&self, f: impl FnOnce(&mut Vec<(usize, Arc<Parked>)>) -> R) -> R {
+        while self
+            .locked
+            .compare_exchange_weak(false, true, Ordering::Acquire, Ordering::Relaxed)
+            .is_err()
+        {
+            spin_loop();
+        }
+        // SAFETY: the spinlock gives us exclusive access to the queue.
+        let result = f(unsafe { &mut *self.queue.get() });
+        self.locked.store(false, Ordering::Release);
+        result
+    }
+}
+
+/// Parks the current thread on `key` until unpark_one picks it, unless `validate` returns
+/// false. `validate` runs under the bucket lock, like the callback of unpark_one, so the
+/// thread can't miss an unpark_one that follows a change making `validate` false.
+pub(crate) fn park(key: usize, validate: impl FnOnce() -> bool) {
+    let parked = Arc::new(Parked {
+        thread: thread::current(),
+        unparked: AtomicBool::new(false),
+    });
+    let queued = Bucket::for_key(key).with_queue(|queue| {
+        let queued = validate();
+        if queued {
+            queue.push((key, parked.clone()));
+        }
+        queued
+    });
+    if queued {
+        while !parked.unparked.load(Ordering::Acquire) {
+            thread::park();
+        }
+    }
+}
+
+/// Like park, but gives up at `deadline`. Returns false if it timed out, or if `validate`
+/// returned false.
+pub(crate) fn park_until(key: usize, validate: impl FnOnce() -> bool, deadline: Instant) -> bool {
+    let parked = Arc::new(Parked {
+        thread: thread::current(),
+        unparked: AtomicBool::new(false),
+    });
+    let bucket = Bucket::for_key(key);
+    let queued = bucket.with_queue(|queue| {
+        let queued = validate();
+        if queued {
+            queue.push((key, parked.clone()));
+        }
+        queued
+    });
+    if !queued {
+        return false;
+    }
+    while !parked.unparked.load(Ordering::Acquire) {
+        let now = Instant::now();
+        if now >= deadline {
+            // Leave the queue, unless an unpark took us out of it meanwhile.
+            let removed = bucket.with_queue(|queue| {
+                let position = queue.iter().position(|(_, p)| Arc::ptr_eq(p, &parked));
+                position.map(|i| queue.remove(i)).is_some()
+            });
+            if removed {
+                return false;
+            }
+        } else {
+            thread::park_timeout(deadline - now);
+        }
+    }
+    true
+}
+
+/// Unparks the thread that has been parked on `key` the longest, if any. `callback` runs
+/// under the bucket lock, before the thread is woken up, and is told whether other threads
+/// are still parked on `key`.
+pub(crate) fn unpark_one(key: usize, callback: impl FnOnce(bool)) {
+    let parked = Bucket::for_key(key).with_queue(|queue| {
+        let parked = queue
+            .iter()
+            .position(|&(k, _)| k == key)
+            .map(|i| queue.remove(i).1);
+        callback(queue.iter().any(|&(k, _)| k == key));
+        parked
+    });
+    if let Some(parked) = parked {
+        parked.unparked.store(true, Ordering::Release);
+        parked.thread.unpark();
+    }
+}
+
+/// Unparks all the threads parked on `key`.
+pub(crate) fn unpark_all(key: usize) {
+    let parked: Vec<_> = Bucket::for_key(key).with_queue(|queue| {
+        let (parked, others) = std::mem::take(queue)
+            .into_iter()
+            .partition(|&(k, _)| k == key);
+        *queue = others;
+        parked
+    });
+    for (_, parked) in parked {
+        parked.unparked.store(true, Ordering::Release);
+        parked.thread.unpark();
+    }
+}
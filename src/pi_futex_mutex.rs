@@ -0,0 +1,165 @@
+use crate::lock_api::{self, GuardNoSend};
+use linux_futex::{PiFutex, Private};
+use std::cell::Cell;
+use std::sync::atomic::Ordering;
+use std::{io, ptr};
+
+thread_local! {
+    // The kernel's id of the current thread, learned from the first lock taken by the kernel.
+    static TID: Cell<u32> = const { Cell::new(0) };
+}
+
+/// A mutex built on the kernel's priority-inheritance futex operations (FUTEX_LOCK_PI).
+/// While a thread waits for the lock, the kernel boosts the thread holding it to the
+/// waiter's priority, so a low-priority holder can't be kept off the CPU by medium-priority
+/// threads while a high-priority one waits: the priority inversion problem of real-time
+/// scheduling classes (SCHED_FIFO, SCHED_RR).
+///
+/// The futex holds the id of the owning thread, and the lock must be released by that
+/// thread, which is why the guard can't be sent to another thread.
+pub type PiFutexMutex<T> = lock_api::Mutex<RawPiFutexMutex, T>;
+
+pub type PiFutexMutexGuard<'a, T> = lock_api::MutexGuard<'a, RawPiFutexMutex, T>;
+
+/// The raw lock of PiFutexMutex.
+pub struct RawPiFutexMutex {
+    futex: PiFutex<Private>,
+}
+
+unsafe impl lock_api::RawMutex for RawPiFutexMutex {
+    const INIT: RawPiFutexMutex = RawPiFutexMutex {
+        futex: PiFutex::new(0),
+    };
+
+    // The lock has to be released by the thread that owns it.
+    type GuardMarker = GuardNoSend;
+
+    fn lock(&self) {
+        if !self.try_lock_fast() {
+            self.lock_pi();
+            self.learn_tid();
+        }
+    }
+
+    fn try_lock(&self) -> bool {
+        if !self.try_lock_fast() {
+            if self.futex.trylock_pi().is_err() {
+                return false;
+            }
+            self.learn_tid();
+        }
+        true
+    }
+
+    unsafe fn unlock(&self) {
+        // Without waiters the futex holds just our id. Otherwise the kernel has set the
+        // WAITERS bit, and has to hand the lock over and undo the priority boost.
+        if self
+            .futex
+            .value
+            .compare_exchange(TID.get(), 0, Ordering::Release, Ordering::Relaxed)
+            .is_err()
+        {
+            self.futex.unlock_pi();
+        }
+    }
+
+    fn is_locked(&self) -> bool {
+        self.futex.value.load(Ordering::Relaxed) & PiFutex::<Private>::TID_MASK != 0
+    }
+}
+
+impl RawPiFutexMutex {
+    // Takes a free lock without a system call, once the thread id is known.
+    fn try_lock_fast(&self) -> bool {
+        let tid = TID.get();
+        tid != 0
+            && self
+                .futex
+                .value
+                .compare_exchange(0, tid, Ordering::Acquire, Ordering::Relaxed)
+                .is_ok()
+    }
+
+    // The kernel queues us by priority and boosts the owner. Only EAGAIN (the owner is
+    // exiting) and EINTR go away on a retry, other errors (EDEADLK when relocking, ENOSYS or
+    // EPERM where PI futexes aren't allowed) would make us spin forever.
+    fn lock_pi(&self) {
+        loop {
+            let result = unsafe {
+                libc::syscall(
+                    libc::SYS_futex,
+                    self.futex.value.as_ptr(),
+                    libc::FUTEX_LOCK_PI | libc::FUTEX_PRIVATE_FLAG,
+                    0,
+                    ptr::null::<libc::timespec>(),
+                )
+            };
+            if result == 0 {
+                return;
+            }
+            let error = io::Error::last_os_error();
+            match error.raw_os_error() {
+                Some(libc::EAGAIN | libc::EINTR) => continue,
+                _ => panic!("FUTEX_LOCK_PI failed: {error}"),
+            }
+        }
+    }
+
+    fn learn_tid(&self) {
+        TID.set(self.futex.value.load(Ordering::Relaxed) & PiFutex::<Private>::TID_MASK);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{PiFutexMutex, TID};
+    use crate::lock_api::RawMutex;
+    use linux_futex::{PiFutex, Private};
+    use std::sync::Arc;
+    use std::sync::atomic::Ordering;
+    use std::thread;
+
+    #[test]
+    fn test_owner_tid_in_futex() {
+        let mutex = PiFutexMutex::new(0);
+        let guard = mutex.lock();
+        let owner = mutex.raw.futex.value.load(Ordering::Relaxed) & PiFutex::<Private>::TID_MASK;
+        assert_eq!(owner, TID.get());
+        thread::scope(|s| {
+            s.spawn(|| assert!(mutex.try_lock().is_none()));
+        });
+        drop(guard);
+        assert_eq!(mutex.raw.futex.value.load(Ordering::Relaxed), 0);
+        *mutex.try_lock().unwrap() += 1;
+        assert_eq!(mutex.into_inner(), 1);
+    }
+
+    #[test]
+    #[should_panic(expected = "FUTEX_LOCK_PI failed")]
+    fn test_relock_panics() {
+        let mutex = PiFutexMutex::new(0);
+        let _guard = mutex.lock();
+        // EDEADLK, rather than retrying forever.
+        mutex.raw.lock();
+    }
+
+    #[test]
+    fn test_pi_futex_mutex_contention_increment() {
+        let mutex = Arc::new(PiFutexMutex::new(0usize));
+        let handles: Vec<_> = (0..8)
+            .map(|_| {
+                let m = Arc::clone(&mutex);
+                thread::spawn(move || {
+                    for _ in 0..10_000 {
+                        *m.lock() += 1;
+                    }
+                })
+            })
+            .collect();
+        for h in handles {
+            h.join().unwrap();
+        }
+        assert_eq!(*mutex.lock(), 80_000);
+    }
+}
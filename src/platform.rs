@@ -0,0 +1,271 @@
+//! Waiting for an atomic to change, with the operating system's futex-like operations:
+//! futex on Linux, WaitOnAddress on Windows and __ulock_wait on macOS. Elsewhere, waiting
+//! threads are parked on the address of the atomic with the crate's parking table.
+//!
+//! Windows and macOS can only wake one or all of the waiters, hence wake_one and wake_all
+//! rather than a number of threads to wake.
+
+use std::sync::atomic::AtomicU32;
+use std::time::Duration;
+
+#[cfg(target_os = "linux")]
+mod imp {
+    use linux_futex::{Futex, Private};
+    use std::sync::atomic::AtomicU32;
+    use std::time::Duration;
+
+    fn futex(atomic: &AtomicU32) -> &Futex<Private> {
+        // SAFETY: Futex is a repr(transparent) wrapper of an AtomicU32.
+        unsafe { &*(atomic as *const AtomicU32 as *const Futex<Private>) }
+    }
+
+    pub(super) fn wait(atomic: &AtomicU32, expected: u32) {
+        // Errors are a changed value or a signal, the caller checks the value again anyway.
+        let _ = futex(atomic).wait(expected);
+    }
+
+    pub(super) fn wait_timeout(atomic: &AtomicU32, expected: u32, timeout: Duration) {
+        let _ = futex(atomic).wait_for(expected, timeout);
+    }
+
+    pub(super) fn wake_one(atomic: &AtomicU32) {
+        futex(atomic).wake(1);
+    }
+
+    pub(super) fn wake_all(atomic: &AtomicU32) {
+        futex(atomic).wake(i32::MAX);
+    }
+
+    pub(super) fn requeue(atomic: &AtomicU32, to: &AtomicU32) {
+        futex(atomic).requeue(1, futex(to), i32::MAX);
+    }
+}
+
+#[cfg(windows)]
+mod imp {
+    use std::ffi::c_void;
+    use std::sync::atomic::AtomicU32;
+    use std::time::Duration;
+
+    #[link(name = "synchronization")]
+    unsafe extern "system" {
+        fn WaitOnAddress(
+            address: *const c_void,
+            compare_address: *const c_void,
+            address_size: usize,
+            milliseconds: u32,
+        ) -> i32;
+        fn WakeByAddressSingle(address: *const c_void);
+        fn WakeByAddressAll(address: *const c_void);
+    }
+
+    const INFINITE: u32 = u32::MAX;
+
+    pub(super) fn wait(atomic: &AtomicU32, expected: u32) {
+        let expected_ptr: *const u32 = &expected;
+        unsafe { WaitOnAddress(atomic.as_ptr().cast(), expected_ptr.cast(), 4, INFINITE) };
+    }
+
+    pub(super) fn wait_timeout(atomic: &AtomicU32, expected: u32, timeout: Duration) {
+        let expected_ptr: *const u32 = &expected;
+        // Rounded up, so that it doesn't return early, and below INFINITE.
+        let ms = timeout
+            .as_nanos()
+            .div_ceil(1_000_000)
+            .min(u128::from(INFINITE - 1)) as u32;
+        unsafe { WaitOnAddress(atomic.as_ptr().cast(), expected_ptr.cast(), 4, ms) };
+    }
+
+    pub(super) fn wake_one(atomic: &AtomicU32) {
+        unsafe { WakeByAddressSingle(atomic.as_ptr().cast()) };
+    }
+
+    pub(super) fn wake_all(atomic: &AtomicU32) {
+        unsafe { WakeByAddressAll(atomic.as_ptr().cast()) };
+    }
+
+    pub(super) fn requeue(atomic: &AtomicU32, _to: &AtomicU32) {
+        wake_all(atomic);
+    }
+}
+
+#[cfg(target_os = "macos")]
+mod imp {
+    use std::ffi::{c_int, c_void};
+    use std::sync::atomic::AtomicU32;
+    use std::time::Duration;
+
+    // Not public API, but stable since macOS 10.12, and what libc++ waits on atomics with.
+    unsafe extern "C" {
+        fn __ulock_wait(operation: u32, address: *mut c_void, value: u64, timeout_us: u32)
+        -> c_int;
+        fn __ulock_wake(operation: u32, address: *mut c_void, wake_value: u64) -> c_int;
+    }
+
+    const UL_COMPARE_AND_WAIT: u32 = 1;
+    const ULF_WAKE_ALL: u32 = 0x100;
+    const ULF_NO_ERRNO: u32 = 0x0100_0000;
+
+    pub(super) fn wait(atomic: &AtomicU32, expected: u32) {
+        let operation = UL_COMPARE_AND_WAIT | ULF_NO_ERRNO;
+        unsafe { __ulock_wait(operation, atomic.as_ptr().cast(), expected.into(), 0) };
+    }
+
+    pub(super) fn wait_timeout(atomic: &AtomicU32, expected: u32, timeout: Duration) {
+        let operation = UL_COMPARE_AND_WAIT | ULF_NO_ERRNO;
+        // 0 means no timeout.
+        let us = timeout.as_micros().clamp(1, u128::from(u32::MAX)) as u32;
+        unsafe { __ulock_wait(operation, atomic.as_ptr().cast(), expected.into(), us) };
+    }
+
+    pub(super) fn wake_one(atomic: &AtomicU32) {
+        let operation = UL_COMPARE_AND_WAIT | ULF_NO_ERRNO;
+        unsafe { __ulock_wake(operation, atomic.as_ptr().cast(), 0) };
+    }
+
+    pub(super) fn wake_all(atomic: &AtomicU32) {
+        let operation = UL_COMPARE_AND_WAIT | ULF_WAKE_ALL | ULF_NO_ERRNO;
+        unsafe { __ulock_wake(operation, atomic.as_ptr().cast(), 0) };
+    }
+
+    pub(super) fn requeue(atomic: &AtomicU32, _to: &AtomicU32) {
+        wake_all(atomic);
+    }
+}
+
+#[cfg(not(any(target_os = "linux", windows, target_os = "macos")))]
+use parked as imp;
+
+// Also built for the tests, to test it on every platform.
+#[cfg(any(test, not(any(target_os = "linux", windows, target_os = "macos"))))]
+mod parked {
+    use crate::parking;
+    use std::sync::atomic::{AtomicU32, Ordering};
+    use std::time::{Duration, Instant};
+
+    // The value is checked under the bucket lock, which wake_one and wake_all take after
+    // the value is changed, so a wake can't be missed.
+    pub(super) fn wait(atomic: &AtomicU32, expected: u32) {
+        parking::park(atomic.as_ptr() as usize, || {
+            atomic.load(Ordering::Relaxed) == expected
+        });
+    }
+
+    pub(super) fn wait_timeout(atomic: &AtomicU32, expected: u32, timeout: Duration) {
+        let deadline = Instant::now() + timeout;
+        parking::park_until(
+            atomic.as_ptr() as usize,
+            || atomic.load(Ordering::Relaxed) == expected,
+            deadline,
+        );
+    }
+
+    pub(super) fn wake_one(atomic: &AtomicU32) {
+        parking::unpark_one(atomic.as_ptr() as usize, |_| {});
+    }
+
+    pub(super) fn wake_all(atomic: &AtomicU32) {
+        parking::unpark_all(atomic.as_ptr() as usize);
+    }
+
+    pub(super) fn requeue(atomic: &AtomicU32, _to: &AtomicU32) {
+        wake_all(atomic);
+    }
+}
+
+/// Blocks the current thread while `atomic` holds `expected`. It may also return spuriously,
+/// so callers check the value again in a loop.
+pub(crate) fn wait(atomic: &AtomicU32, expected: u32) {
+    imp::wait(atomic, expected);
+}
+
+/// Like wait, but returns after `timeout` at the latest.
+pub(crate) fn wait_timeout(atomic: &AtomicU32, expected: u32, timeout: Duration) {
+    imp::wait_timeout(atomic, expected, timeout);
+}
+
+/// Wakes up one of the threads waiting on `atomic`, if any.
+pub(crate) fn wake_one(atomic: &AtomicU32) {
+    imp::wake_one(atomic);
+}
+
+/// Wakes up all the threads waiting on `atomic`.
+pub(crate) fn wake_all(atomic: &AtomicU32) {
+    imp::wake_all(atomic);
+}
+
+/// Wakes up one of the threads waiting on `atomic`, and moves the others to wait on `to`
+/// instead, without waking them. Only Linux can move them (FUTEX_REQUEUE), elsewhere they
+/// are all woken up.
+pub(crate) fn requeue(atomic: &AtomicU32, to: &AtomicU32) {
+    imp::requeue(atomic, to);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::parked;
+    use std::sync::atomic::{AtomicU32, Ordering};
+    use std::thread;
+    use std::time::{Duration, Instant};
+
+    // Runs waiters until the value changes from 0, woken by wake_one or wake_all.
+    fn wait_and_wake(
+        wait: fn(&AtomicU32, u32),
+        wake_one: fn(&AtomicU32),
+        wake_all: fn(&AtomicU32),
+    ) {
+        let value = AtomicU32::new(0);
+        // Returns right away when the value isn't the expected one.
+        wait(&value, 1);
+        for (wake, waiters) in [(wake_one, 1), (wake_all, 3)] {
+            value.store(0, Ordering::Relaxed);
+            thread::scope(|s| {
+                for _ in 0..waiters {
+                    s.spawn(|| {
+                        while value.load(Ordering::Acquire) == 0 {
+                            wait(&value, 0);
+                        }
+                    });
+                }
+                thread::sleep(Duration::from_millis(20));
+                value.store(1, Ordering::Release);
+                wake(&value);
+            });
+        }
+    }
+
+    #[test]
+    fn test_platform_wait_and_wake() {
+        wait_and_wake(super::wait, super::wake_one, super::wake_all);
+    }
+
+    #[test]
+    fn test_parked_wait_and_wake() {
+        wait_and_wake(parked::wait, parked::wake_one, parked::wake_all);
+    }
+
+    #[test]
+    fn test_wait_timeout() {
+        let value = AtomicU32::new(0);
+        for wait_timeout in [super::wait_timeout, parked::wait_timeout] {
+            let time = Instant::now();
+            wait_timeout(&value, 0, Duration::from_millis(20));
+            assert!(time.elapsed() >= Duration::from_millis(20));
+            // Woken up before the timeout.
+            thread::scope(|s| {
+                s.spawn(|| {
+                    thread::sleep(Duration::from_millis(20));
+                    value.store(1, Ordering::Release);
+                    super::wake_all(&value);
+                    parked::wake_all(&value);
+                });
+                let time = Instant::now();
+                while value.load(Ordering::Acquire) == 0 {
+                    wait_timeout(&value, 0, Duration::from_secs(10));
+                }
+                assert!(time.elapsed() < Duration::from_secs(5));
+            });
+            value.store(0, Ordering::Relaxed);
+        }
+    }
+}
@@ -0,0 +1,158 @@
+//! Lock poisoning, enabled by the `poison` feature.
+//!
+//! A writer that panics may leave the protected value half updated. Like std's, the lock is
+//! then marked poisoned, and the checked lock methods return a PoisonError, which still
+//! gives access to the guard for callers that can repair or ignore the value. The plain
+//! methods don't look at the poison flag, like parking_lot's.
+
+use std::error::Error;
+use std::fmt;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
+
+/// The result of a checked lock method: the guard, in an error if the lock is poisoned.
+pub type LockResult<G> = Result<G, PoisonError<G>>;
+
+/// The error of a checked lock method on a poisoned lock, holding the guard anyway.
+pub struct PoisonError<G> {
+    guard: G,
+}
+
+impl<G> PoisonError<G> {
+    pub fn new(guard: G) -> Self {
+        PoisonError { guard }
+    }
+
+    /// Returns the guard, to use the value despite the poisoning.
+    pub fn into_inner(self) -> G {
+        self.guard
+    }
+
+    pub fn get_ref(&self) -> &G {
+        &self.guard
+    }
+
+    pub fn get_mut(&mut self) -> &mut G {
+        &mut self.guard
+    }
+}
+
+impl<G> fmt::Debug for PoisonError<G> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("PoisonError").finish_non_exhaustive()
+    }
+}
+
+impl<G> fmt::Display for PoisonError<G> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("poisoned lock: another thread panicked while holding it")
+    }
+}
+
+impl<G> Error for PoisonError<G> {}
+
+pub(crate) struct Flag {
+    poisoned: AtomicBool,
+}
+
+impl Flag {
+    pub(crate) const fn new() -> Self {
+        Flag {
+            poisoned: AtomicBool::new(false),
+        }
+    }
+
+    pub(crate) fn get(&self) -> bool {
+        self.poisoned.load(Ordering::Relaxed)
+    }
+
+    pub(crate) fn clear(&self) {
+        self.poisoned.store(false, Ordering::Relaxed);
+    }
+
+    // Called when the lock is taken, the guard has to be passed to done.
+    pub(crate) fn guard(&self) -> Guard {
+        Guard {
+            panicking: thread::panicking(),
+        }
+    }
+
+    // Called when the lock is released: a panic that started in between poisons the lock.
+    // Locks taken while already unwinding, e.g. in a destructor, don't poison.
+    pub(crate) fn done(&self, guard: &Guard) {
+        if !guard.panicking && thread::panicking() {
+            self.poisoned.store(true, Ordering::Relaxed);
+        }
+    }
+}
+
+#[derive(Clone, Copy)]
+pub(crate) struct Guard {
+    panicking: bool,
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::lock_api::RwLockWriteGuard;
+    use crate::rwlock::RwLock;
+    use std::panic::{AssertUnwindSafe, catch_unwind};
+
+    #[test]
+    fn test_writer_panic_poisons() {
+        let lock = RwLock::new(vec![1]);
+        let _ = catch_unwind(AssertUnwindSafe(|| {
+            let mut guard = lock.write();
+            guard.push(2);
+            panic!("half updated");
+        }));
+        assert!(lock.is_poisoned());
+        // The plain methods ignore poisoning.
+        assert_eq!(*lock.read(), [1, 2]);
+        let err = lock.checked_read().err().unwrap();
+        assert_eq!(
+            err.to_string(),
+            "poisoned lock: another thread panicked while holding it"
+        );
+        assert_eq!(**err.get_ref(), [1, 2]);
+        drop(err);
+        // Repair the value, then clear the flag.
+        let mut guard = lock.checked_write().err().unwrap().into_inner();
+        guard.pop();
+        drop(guard);
+        lock.clear_poison();
+        assert_eq!(*lock.checked_read().unwrap(), [1]);
+    }
+
+    #[test]
+    fn test_reader_panic_and_mapped_writer_panic() {
+        let lock = RwLock::new((0, 0));
+        let _ = catch_unwind(AssertUnwindSafe(|| {
+            let _guard = lock.read();
+            panic!("reader");
+        }));
+        assert!(!lock.is_poisoned());
+        let _ = catch_unwind(AssertUnwindSafe(|| {
+            let mut first = RwLockWriteGuard::map(lock.write(), |pair| &mut pair.0);
+            *first = 1;
+            panic!("mapped writer");
+        }));
+        assert!(lock.checked_write().is_err());
+    }
+
+    #[test]
+    fn test_lock_taken_while_unwinding_does_not_poison() {
+        struct WriteOnDrop<'a>(&'a RwLock<i32>);
+        impl Drop for WriteOnDrop<'_> {
+            fn drop(&mut self) {
+                *self.0.write() += 1;
+            }
+        }
+
+        let lock = RwLock::new(0);
+        let _ = catch_unwind(AssertUnwindSafe(|| {
+            let _on_drop = WriteOnDrop(&lock);
+            panic!("unwinding");
+        }));
+        assert_eq!(*lock.checked_read().unwrap(), 1);
+    }
+}
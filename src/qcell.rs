@@ -0,0 +1,197 @@
+use std::any::TypeId;
+use std::cell::UnsafeCell;
+use std::marker::PhantomData;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+// QCell and TCell sit between RefCell and GhostCell. Like GhostCell, access goes through an
+// owner: a shared borrow of the owner gives &T, an exclusive borrow gives &mut T, so borrow
+// conflicts are caught at compile time and the cells keep no borrow state. Unlike GhostCell,
+// the owner isn't tied to a closure through a lifetime brand, it can be stored and moved
+// around freely; the price is checking that the right owner is used:
+// - QCell checks it at runtime, comparing the owner's id (cheap, but it can panic).
+// - TCell uses the owner's type, and makes sure only one owner of each type exists at a time.
+
+static NEXT_OWNER_ID: AtomicUsize = AtomicUsize::new(0);
+
+/// The owner of a group of QCells, identified by a unique id.
+pub struct QCellOwner {
+    id: usize,
+}
+
+impl QCellOwner {
+    pub fn new() -> Self {
+        Self {
+            id: NEXT_OWNER_ID.fetch_add(1, Ordering::Relaxed),
+        }
+    }
+
+    /// Creates a new cell owned by this owner.
+    pub fn cell<T>(&self, value: T) -> QCell<T> {
+        QCell {
+            owner: self.id,
+            value: UnsafeCell::new(value),
+        }
+    }
+}
+
+impl Default for QCellOwner {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// A cell whose content can only be accessed by borrowing the QCellOwner it was created with.
+/// Using any other owner panics.
+pub struct QCell<T: ?Sized> {
+    owner: usize,
+    value: UnsafeCell<T>,
+}
+
+// Access to the value is synchronized by the owner, just like a &T or &mut T would be.
+unsafe impl<T: ?Sized + Send> Send for QCell<T> {}
+unsafe impl<T: ?Sized + Send + Sync> Sync for QCell<T> {}
+
+impl<T> QCell<T> {
+    pub fn into_inner(self) -> T {
+        self.value.into_inner()
+    }
+}
+
+impl<T: ?Sized> QCell<T> {
+    fn check_owner(&self, owner: &QCellOwner) {
+        assert_eq!(self.owner, owner.id, "QCell accessed with the wrong owner");
+    }
+
+    /// Immutably borrows the cell's content, which requires a shared borrow of its owner.
+    pub fn borrow<'a>(&'a self, owner: &'a QCellOwner) -> &'a T {
+        self.check_owner(owner);
+        // SAFETY: an exclusive borrow needs &mut owner, which can't coexist with &owner.
+        unsafe { &*self.value.get() }
+    }
+
+    /// Mutably borrows the cell's content, which requires an exclusive borrow of its owner.
+    pub fn borrow_mut<'a>(&'a self, owner: &'a mut QCellOwner) -> &'a mut T {
+        self.check_owner(owner);
+        // SAFETY: the owner is exclusively borrowed for 'a, so no other borrow of its cells can exist.
+        unsafe { &mut *self.value.get() }
+    }
+
+    pub fn get_mut(&mut self) -> &mut T {
+        self.value.get_mut()
+    }
+}
+
+// The marker types which currently have a TCellOwner.
+static TCELL_OWNERS: Mutex<Vec<TypeId>> = Mutex::new(Vec::new());
+
+/// The owner of all the TCell<Q, _>. At most one owner per marker type Q exists at a time.
+pub struct TCellOwner<Q: 'static> {
+    _marker: PhantomData<fn() -> Q>,
+}
+
+impl<Q: 'static> TCellOwner<Q> {
+    /// Creates the owner for the marker type Q, panics if it already exists.
+    pub fn new() -> Self {
+        Self::try_new().expect("a TCellOwner already exists for this marker type")
+    }
+
+    /// Creates the owner for the marker type Q, or None if it already exists.
+    pub fn try_new() -> Option<Self> {
+        let mut owners = TCELL_OWNERS.lock().unwrap();
+        if owners.contains(&TypeId::of::<Q>()) {
+            return None;
+        }
+        owners.push(TypeId::of::<Q>());
+        Some(Self {
+            _marker: PhantomData,
+        })
+    }
+}
+
+impl<Q: 'static> Drop for TCellOwner<Q> {
+    fn drop(&mut self) {
+        let mut owners = TCELL_OWNERS.lock().unwrap();
+        owners.retain(|id| *id != TypeId::of::<Q>());
+    }
+}
+
+/// A cell whose content can only be accessed by borrowing the TCellOwner<Q>. As that owner is
+/// unique, no runtime check is needed at the borrow sites.
+pub struct TCell<Q, T: ?Sized> {
+    _owner: PhantomData<fn(Q)>,
+    value: UnsafeCell<T>,
+}
+
+unsafe impl<Q, T: ?Sized + Send> Send for TCell<Q, T> {}
+unsafe impl<Q, T: ?Sized + Send + Sync> Sync for TCell<Q, T> {}
+
+impl<Q: 'static, T> TCell<Q, T> {
+    pub const fn new(value: T) -> Self {
+        Self {
+            _owner: PhantomData,
+            value: UnsafeCell::new(value),
+        }
+    }
+
+    pub fn into_inner(self) -> T {
+        self.value.into_inner()
+    }
+}
+
+impl<Q: 'static, T: ?Sized> TCell<Q, T> {
+    /// Immutably borrows the cell's content, which requires a shared borrow of the owner.
+    pub fn borrow<'a>(&'a self, _owner: &'a TCellOwner<Q>) -> &'a T {
+        // SAFETY: the owner is unique, and an exclusive borrow needs &mut owner.
+        unsafe { &*self.value.get() }
+    }
+
+    /// Mutably borrows the cell's content, which requires an exclusive borrow of the owner.
+    pub fn borrow_mut<'a>(&'a self, _owner: &'a mut TCellOwner<Q>) -> &'a mut T {
+        // SAFETY: the owner is unique and exclusively borrowed for 'a.
+        unsafe { &mut *self.value.get() }
+    }
+
+    pub fn get_mut(&mut self) -> &mut T {
+        self.value.get_mut()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{QCellOwner, TCell, TCellOwner};
+    use crate::rc::Rc;
+
+    #[test]
+    fn test_qcell() {
+        let mut owner = QCellOwner::new();
+        let a = Rc::new(owner.cell(vec![1]));
+        let b = a.clone();
+        a.borrow_mut(&mut owner).push(2);
+        b.borrow_mut(&mut owner).push(3);
+        assert_eq!(*a.borrow(&owner), vec![1, 2, 3]);
+    }
+
+    #[test]
+    #[should_panic(expected = "QCell accessed with the wrong owner")]
+    fn test_qcell_wrong_owner() {
+        let owner1 = QCellOwner::new();
+        let owner2 = QCellOwner::new();
+        let cell = owner1.cell(0);
+        cell.borrow(&owner2);
+    }
+
+    #[test]
+    fn test_tcell() {
+        struct Marker;
+        let mut owner = TCellOwner::<Marker>::new();
+        assert!(TCellOwner::<Marker>::try_new().is_none());
+
+        let cell = TCell::<Marker, _>::new(String::from("a"));
+        cell.borrow_mut(&mut owner).push('b');
+        assert_eq!(cell.borrow(&owner), "ab");
+
+        drop(owner);
+        assert!(TCellOwner::<Marker>::try_new().is_some());
+    }
+}
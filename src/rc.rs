@@ -1,5 +1,9 @@
+use crate::allocator::{Allocator, Global};
 use crate::cell::Cell;
+use std::alloc::{Layout, handle_alloc_error};
+use std::any::Any;
 use std::marker::PhantomData;
+use std::mem::{ManuallyDrop, MaybeUninit};
 use std::ptr::NonNull;
 
 /// Single-threaded reference-counting pointers. ‘Rc’ stands for ‘Reference Counted’.
@@ -7,49 +11,491 @@ use std::ptr::NonNull;
 /// Invoking clone on Rc produces a new pointer to the same allocation in the heap.
 /// When the last Rc pointer to a given allocation is destroyed, the value stored
 /// in that allocation (often referred to as “inner value”) is also dropped.
-pub struct Rc<T> {
+/// The allocation is made with the allocator A, the global allocator unless Rc::new_in is used.
+pub struct Rc<T: ?Sized, A: Allocator = Global> {
     inner: NonNull<RcInner<T>>,
     _marker: PhantomData<RcInner<T>>,
+    alloc: A,
 }
 
-pub struct RcInner<T> {
-    value: T,
+// repr(C) so the counters always come first and the layout of an unsized
+// RcInner can be computed by hand when allocating it.
+#[repr(C)]
+pub struct RcInner<T: ?Sized> {
     owner_count: Cell<usize>,
+    // Number of Weak pointers, plus one shared by all the Rc pointers while any of them exist.
+    weak_count: Cell<usize>,
+    value: ManuallyDrop<T>,
 }
 
-impl<T> Clone for Rc<T> {
+impl<T: ?Sized> RcInner<T> {
+    fn inc_strong(&self) {
+        self.owner_count
+            .set(checked_increment(self.owner_count.get()));
+    }
+
+    fn inc_weak(&self) {
+        self.weak_count
+            .set(checked_increment(self.weak_count.get()));
+    }
+}
+
+/// Increments a reference count, aborting the process if it would overflow.
+/// The count can only get that high by leaking pointers (e.g. with mem::forget), and
+/// letting it wrap to 0 would free the allocation while pointers to it are still in use.
+/// Unwinding is not an option either, as destructors could observe the bogus count.
+pub(crate) fn checked_increment(count: usize) -> usize {
+    match count.checked_add(1) {
+        Some(count) => count,
+        None => std::process::abort(),
+    }
+}
+
+impl<T: ?Sized, A: Allocator + Clone> Clone for Rc<T, A> {
     fn clone(&self) -> Self {
         let inner = unsafe { self.inner.as_ref() };
-        inner.owner_count.set(inner.owner_count.get() + 1);
+        inner.inc_strong();
         Rc {
             inner: self.inner,
             _marker: PhantomData,
+            alloc: self.alloc.clone(),
         }
     }
 }
 
-impl<T> std::ops::Deref for Rc<T> {
+#[cfg(feature = "nightly")]
+impl<T: ?Sized + std::marker::Unsize<U>, U: ?Sized, A: Allocator> std::ops::CoerceUnsized<Rc<U, A>>
+    for Rc<T, A>
+{
+}
+
+#[cfg(feature = "nightly")]
+impl<T: ?Sized + std::marker::Unsize<U>, U: ?Sized> std::ops::DispatchFromDyn<Rc<U>> for Rc<T> {}
+
+impl<T: ?Sized, A: Allocator> std::ops::Deref for Rc<T, A> {
     type Target = T;
     fn deref(&self) -> &Self::Target {
         &unsafe { self.inner.as_ref() }.value
     }
 }
 
+impl<T: ?Sized, A: Allocator> std::borrow::Borrow<T> for Rc<T, A> {
+    fn borrow(&self) -> &T {
+        self
+    }
+}
+
+impl<T: ?Sized, A: Allocator> AsRef<T> for Rc<T, A> {
+    fn as_ref(&self) -> &T {
+        self
+    }
+}
+
+impl<T> From<T> for Rc<T> {
+    fn from(value: T) -> Self {
+        Rc::new(value)
+    }
+}
+
+impl<T: Default> Default for Rc<T> {
+    fn default() -> Self {
+        Rc::new(T::default())
+    }
+}
+
+impl<T: ?Sized + PartialEq, A: Allocator> PartialEq for Rc<T, A> {
+    fn eq(&self, other: &Rc<T, A>) -> bool {
+        **self == **other
+    }
+}
+
+impl<T: ?Sized + Eq, A: Allocator> Eq for Rc<T, A> {}
+
+impl<T: ?Sized + PartialOrd, A: Allocator> PartialOrd for Rc<T, A> {
+    fn partial_cmp(&self, other: &Rc<T, A>) -> Option<std::cmp::Ordering> {
+        (**self).partial_cmp(&**other)
+    }
+}
+
+impl<T: ?Sized + Ord, A: Allocator> Ord for Rc<T, A> {
+    fn cmp(&self, other: &Rc<T, A>) -> std::cmp::Ordering {
+        (**self).cmp(&**other)
+    }
+}
+
+impl<T: ?Sized + std::hash::Hash, A: Allocator> std::hash::Hash for Rc<T, A> {
+    fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
+        (**self).hash(state)
+    }
+}
+
+impl<T: ?Sized + std::fmt::Debug, A: Allocator> std::fmt::Debug for Rc<T, A> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        std::fmt::Debug::fmt(&**self, f)
+    }
+}
+
+impl<T: ?Sized + std::fmt::Display, A: Allocator> std::fmt::Display for Rc<T, A> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        std::fmt::Display::fmt(&**self, f)
+    }
+}
+
+// Rcs are serialized as their value, so two Rcs to the same allocation come back
+// as two separate allocations after a round-trip.
+#[cfg(feature = "serde")]
+impl<T: ?Sized + serde::Serialize, A: Allocator> serde::Serialize for Rc<T, A> {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        (**self).serialize(serializer)
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de, T: serde::Deserialize<'de>> serde::Deserialize<'de> for Rc<T> {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        T::deserialize(deserializer).map(Rc::new)
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de> serde::Deserialize<'de> for Rc<str> {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        String::deserialize(deserializer).map(Rc::from)
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de, T: serde::Deserialize<'de>> serde::Deserialize<'de> for Rc<[T]> {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        Vec::deserialize(deserializer).map(Rc::from)
+    }
+}
+
 impl<T> Rc<T> {
     pub fn new(value: T) -> Self {
-        let inner = Box::new(RcInner {
-            value,
-            owner_count: Cell::new(1),
-        });
+        Rc::new_in(value, Global)
+    }
+
+    /// Constructs a new Rc with uninitialized contents, so a large value can be written in
+    /// place (through Rc::get_mut) instead of being built on the stack and moved in.
+    pub fn new_uninit() -> Rc<MaybeUninit<T>> {
+        let layout = Layout::new::<RcInner<MaybeUninit<T>>>();
+        let inner = match Global.allocate(layout) {
+            Ok(mem) => mem.cast::<RcInner<MaybeUninit<T>>>(),
+            Err(_) => handle_alloc_error(layout),
+        };
+        // Only the counters are written, the value is left as it is.
+        unsafe {
+            (&raw mut (*inner.as_ptr()).owner_count).write(Cell::new(1));
+            (&raw mut (*inner.as_ptr()).weak_count).write(Cell::new(1));
+        }
+        Rc::from_inner(inner)
+    }
+
+    /// Makes a mutable reference into the given Rc.
+    /// If there are other Rc pointers to the same allocation, the inner value is cloned into a
+    /// new allocation to ensure unique ownership (clone-on-write). If there are no other Rc
+    /// pointers but some Weak pointers, the value is moved into a new allocation and the Weak
+    /// pointers are disassociated, so they can no longer be upgraded.
+    pub fn make_mut(this: &mut Rc<T>) -> &mut T
+    where
+        T: Clone,
+    {
+        let inner = unsafe { this.inner.as_ref() };
+        if inner.owner_count.get() != 1 {
+            *this = Rc::new((**this).clone());
+        } else if inner.weak_count.get() != 1 {
+            // SAFETY: we are the only strong pointer, and the strong count is set to 0 right
+            // after, so the value is never read or dropped through the old allocation again.
+            let value = unsafe { ManuallyDrop::take(&mut (*this.inner.as_ptr()).value) };
+            inner.owner_count.set(0);
+            let old = std::mem::replace(this, Rc::new(value));
+            // The value has been moved out, only release the weak reference held by `old`.
+            drop(Weak {
+                inner: old.inner,
+                alloc: Global,
+            });
+            std::mem::forget(old);
+        }
+        // SAFETY: this is now the only pointer (strong or weak) able to reach the value.
+        unsafe { &mut (*this.inner.as_ptr()).value }
+    }
+}
+
+impl<T, A: Allocator> Rc<T, A> {
+    /// Constructs a new Rc<T, A> in the provided allocator, which is also used to free it.
+    pub fn new_in(value: T, alloc: A) -> Rc<T, A> {
+        let layout = Layout::new::<RcInner<T>>();
+        let inner = match alloc.allocate(layout) {
+            Ok(mem) => mem.cast::<RcInner<T>>(),
+            Err(_) => handle_alloc_error(layout),
+        };
+        unsafe {
+            inner.as_ptr().write(RcInner {
+                owner_count: Cell::new(1),
+                weak_count: Cell::new(1),
+                value: ManuallyDrop::new(value),
+            })
+        };
 
         Self {
-            inner: unsafe { NonNull::new_unchecked(Box::into_raw(inner)) },
+            inner,
             _marker: PhantomData,
+            alloc,
         }
     }
 }
 
-impl<T> Drop for Rc<T> {
+impl<T: ?Sized, A: Allocator> Rc<T, A> {
+    /// Creates a new Weak pointer to this allocation.
+    pub fn downgrade(this: &Rc<T, A>) -> Weak<T, A>
+    where
+        A: Clone,
+    {
+        let inner = unsafe { this.inner.as_ref() };
+        inner.inc_weak();
+        Weak {
+            inner: this.inner,
+            alloc: this.alloc.clone(),
+        }
+    }
+
+    /// Gets the number of strong (Rc) pointers to this allocation.
+    pub fn strong_count(this: &Rc<T, A>) -> usize {
+        unsafe { this.inner.as_ref() }.owner_count.get()
+    }
+
+    /// Gets the number of Weak pointers to this allocation.
+    pub fn weak_count(this: &Rc<T, A>) -> usize {
+        // Don't report the weak reference held by the strong pointers.
+        unsafe { this.inner.as_ref() }.weak_count.get() - 1
+    }
+
+    /// Returns true if the two Rc pointers point to the same allocation.
+    pub fn ptr_eq(this: &Rc<T, A>, other: &Rc<T, A>) -> bool {
+        // Only compare the addresses, slice lengths and vtables are irrelevant for identity.
+        std::ptr::addr_eq(this.inner.as_ptr(), other.inner.as_ptr())
+    }
+
+    /// Provides a raw pointer to the value, without affecting the counts.
+    pub fn as_ptr(this: &Rc<T, A>) -> *const T {
+        // ManuallyDrop<T> is repr(transparent), so the cast keeps pointing at the value.
+        unsafe { &raw const (*this.inner.as_ptr()).value as *const T }
+    }
+
+    /// Returns a mutable reference to the value if there are no other Rc or Weak pointers
+    /// to the same allocation, and None otherwise.
+    pub fn get_mut(this: &mut Rc<T, A>) -> Option<&mut T> {
+        let inner = unsafe { this.inner.as_ref() };
+        if inner.owner_count.get() == 1 && inner.weak_count.get() == 1 {
+            // SAFETY: we hold the only pointer to the allocation, and &mut self keeps it that way.
+            Some(unsafe { &mut (*this.inner.as_ptr()).value })
+        } else {
+            None
+        }
+    }
+
+    /// Returns a reference to the allocator the Rc was allocated with.
+    pub fn allocator(this: &Rc<T, A>) -> &A {
+        &this.alloc
+    }
+}
+
+impl<T: ?Sized> Rc<T> {
+    /// Builds an Rc from an RcInner allocated with the global allocator.
+    fn from_inner(inner: NonNull<RcInner<T>>) -> Self {
+        Rc {
+            inner,
+            _marker: PhantomData,
+            alloc: Global,
+        }
+    }
+
+    /// Consumes the Rc and returns the pointer to the value. The strong count is not decremented,
+    /// the pointer has to be converted back with Rc::from_raw to avoid a memory leak.
+    pub fn into_raw(this: Rc<T>) -> *const T {
+        let ptr = Rc::as_ptr(&this);
+        std::mem::forget(this);
+        ptr
+    }
+
+    /// Constructs an Rc from a pointer returned by Rc::into_raw, taking over its strong count.
+    ///
+    /// # Safety
+    /// `ptr` must come from Rc::into_raw (for the same T), and every such pointer may only be
+    /// converted back once.
+    pub unsafe fn from_raw(ptr: *const T) -> Rc<T> {
+        // The value is still alive, so its alignment can be read through a reference.
+        let offset = data_offset(std::mem::align_of_val(unsafe { &*ptr }));
+        // Step back from the value to the counters, keeping the pointer metadata.
+        let inner = unsafe { ptr.byte_sub(offset) } as *mut RcInner<T>;
+        Rc::from_inner(unsafe { NonNull::new_unchecked(inner) })
+    }
+
+    /// Narrows the Rc down to a part of the value, e.g. one of its fields. The returned RcRef
+    /// keeps the whole allocation alive, but only gives access to the projected part.
+    pub fn project<U: ?Sized>(this: Rc<T>, f: impl FnOnce(&T) -> &U) -> RcRef<T, U> {
+        let value = NonNull::from(f(&this));
+        RcRef { owner: this, value }
+    }
+}
+
+/// A shared reference to a part of a value owned by an Rc, created with Rc::project.
+/// Holding an RcRef counts as holding a strong pointer to the whole value.
+pub struct RcRef<T: ?Sized, U: ?Sized> {
+    owner: Rc<T>,
+    // Borrowed from `owner`, which is never mutated while shared and outlives this pointer.
+    value: NonNull<U>,
+}
+
+impl<T: ?Sized, U: ?Sized> RcRef<T, U> {
+    /// Narrows the projection down further.
+    pub fn map<V: ?Sized>(this: RcRef<T, U>, f: impl FnOnce(&U) -> &V) -> RcRef<T, V> {
+        let value = NonNull::from(f(&this));
+        RcRef {
+            owner: this.owner,
+            value,
+        }
+    }
+}
+
+impl<T: ?Sized, U: ?Sized> Clone for RcRef<T, U> {
+    fn clone(&self) -> Self {
+        RcRef {
+            owner: self.owner.clone(),
+            value: self.value,
+        }
+    }
+}
+
+impl<T: ?Sized, U: ?Sized> std::ops::Deref for RcRef<T, U> {
+    type Target = U;
+    fn deref(&self) -> &U {
+        unsafe { self.value.as_ref() }
+    }
+}
+
+impl<T: ?Sized, U: ?Sized + std::fmt::Debug> std::fmt::Debug for RcRef<T, U> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        std::fmt::Debug::fmt(&**self, f)
+    }
+}
+
+impl<T, A: Allocator> Rc<MaybeUninit<T>, A> {
+    /// Converts to Rc<T, A>.
+    ///
+    /// # Safety
+    /// The value must have been fully initialized, as with MaybeUninit::assume_init.
+    pub unsafe fn assume_init(self) -> Rc<T, A> {
+        let this = ManuallyDrop::new(self);
+        Rc {
+            // MaybeUninit<T> has the same layout as T.
+            inner: this.inner.cast::<RcInner<T>>(),
+            _marker: PhantomData,
+            // SAFETY: `this` is never dropped, so the allocator is moved rather than duplicated.
+            alloc: unsafe { std::ptr::read(&this.alloc) },
+        }
+    }
+}
+
+/// Offset of the value inside an RcInner, for a value with the given alignment.
+fn data_offset(align: usize) -> usize {
+    // repr(C) puts the value right after the counters, padded to its alignment.
+    Layout::new::<RcInner<()>>().size().next_multiple_of(align)
+}
+
+impl<T> Rc<[T]> {
+    /// Allocates an RcInner<[T]> holding `len` uninitialized elements, with both counts set to 1.
+    fn allocate_for_slice(len: usize) -> NonNull<RcInner<[T]>> {
+        let (layout, _) = Layout::new::<RcInner<()>>()
+            .extend(Layout::array::<T>(len).expect("Rc<[T]> is too large"))
+            .expect("Rc<[T]> is too large");
+        let layout = layout.pad_to_align();
+
+        let mem = match Global.allocate(layout) {
+            Ok(mem) => mem.cast::<T>(),
+            Err(_) => handle_alloc_error(layout),
+        };
+        // The fat pointer takes the address of the allocation and `len` as metadata.
+        let ptr = std::ptr::slice_from_raw_parts_mut(mem.as_ptr(), len) as *mut RcInner<[T]>;
+        unsafe {
+            (&raw mut (*ptr).owner_count).write(Cell::new(1));
+            (&raw mut (*ptr).weak_count).write(Cell::new(1));
+            NonNull::new_unchecked(ptr)
+        }
+    }
+}
+
+impl<T: Clone> From<&[T]> for Rc<[T]> {
+    /// Clones the elements of the slice into a new Rc<[T]> allocation.
+    /// If cloning panics, the partially filled allocation is leaked.
+    fn from(slice: &[T]) -> Self {
+        let inner = Rc::<[T]>::allocate_for_slice(slice.len());
+        let elems = unsafe { &raw mut (*inner.as_ptr()).value } as *mut T;
+        for (i, item) in slice.iter().enumerate() {
+            unsafe { elems.add(i).write(item.clone()) };
+        }
+        Rc::from_inner(inner)
+    }
+}
+
+impl<T> From<Vec<T>> for Rc<[T]> {
+    /// Moves the elements of the Vec into a new Rc<[T]> allocation.
+    fn from(mut vec: Vec<T>) -> Self {
+        let inner = Rc::<[T]>::allocate_for_slice(vec.len());
+        unsafe {
+            let elems = &raw mut (*inner.as_ptr()).value as *mut T;
+            std::ptr::copy_nonoverlapping(vec.as_ptr(), elems, vec.len());
+            // The elements are now owned by the Rc, only free the Vec's buffer.
+            vec.set_len(0);
+        }
+        Rc::from_inner(inner)
+    }
+}
+
+impl<T: Any> Rc<T> {
+    /// Converts the Rc into a type-erased Rc<dyn Any>, which can be turned back with downcast.
+    pub fn into_any(this: Rc<T>) -> Rc<dyn Any> {
+        // Unsizing the raw pointer attaches the vtable of T.
+        let inner: NonNull<RcInner<dyn Any>> = this.inner;
+        std::mem::forget(this);
+        Rc::from_inner(inner)
+    }
+}
+
+impl Rc<dyn Any> {
+    /// Attempts to downcast the Rc<dyn Any> to a concrete type, giving it back on failure.
+    pub fn downcast<T: Any>(self) -> Result<Rc<T>, Rc<dyn Any>> {
+        if (*self).is::<T>() {
+            // Dropping the vtable is fine, the allocation really holds an RcInner<T>.
+            let inner = self.inner.cast::<RcInner<T>>();
+            std::mem::forget(self);
+            Ok(Rc::from_inner(inner))
+        } else {
+            Err(self)
+        }
+    }
+}
+
+impl From<&str> for Rc<str> {
+    fn from(s: &str) -> Self {
+        let bytes: Rc<[u8]> = Rc::from(s.as_bytes());
+        // SAFETY: str has the same layout as [u8] and the bytes were copied from a valid str.
+        // The cast keeps the length metadata of the fat pointer.
+        let inner = unsafe { NonNull::new_unchecked(bytes.inner.as_ptr() as *mut RcInner<str>) };
+        std::mem::forget(bytes);
+        Rc::from_inner(inner)
+    }
+}
+
+impl From<String> for Rc<str> {
+    /// The string data has to be copied, as the String's buffer has no room for the counters.
+    fn from(s: String) -> Self {
+        Rc::from(s.as_str())
+    }
+}
+
+impl<T: ?Sized, A: Allocator> Drop for Rc<T, A> {
     fn drop(&mut self) {
         let inner = unsafe { self.inner.as_ref() };
 
@@ -57,9 +503,75 @@ impl<T> Drop for Rc<T> {
         inner.owner_count.set(c);
 
         if c == 0 {
-            let _ = inner;
-            // take ownership and drop RcInner + T
-            drop(unsafe { Box::from_raw(self.inner.as_ptr()) });
+            // SAFETY: this was the last strong pointer, so nobody can reach the value anymore.
+            unsafe { ManuallyDrop::drop(&mut (*self.inner.as_ptr()).value) };
+            // Release the weak reference collectively held by the strong pointers,
+            // which frees the allocation if no Weak is left.
+            drop(Weak {
+                inner: self.inner,
+                alloc: &self.alloc,
+            });
+        }
+    }
+}
+
+/// Weak is a version of Rc that holds a non-owning reference to the managed allocation.
+/// The allocation is accessed by calling upgrade on the Weak pointer, which returns an Option<Rc<T>>.
+/// Since a Weak reference does not count towards ownership, it will not prevent the value stored
+/// in the allocation from being dropped, but it keeps the allocation (the counters) alive.
+pub struct Weak<T: ?Sized, A: Allocator = Global> {
+    inner: NonNull<RcInner<T>>,
+    alloc: A,
+}
+
+#[cfg(feature = "nightly")]
+impl<T: ?Sized + std::marker::Unsize<U>, U: ?Sized, A: Allocator>
+    std::ops::CoerceUnsized<Weak<U, A>> for Weak<T, A>
+{
+}
+
+#[cfg(feature = "nightly")]
+impl<T: ?Sized + std::marker::Unsize<U>, U: ?Sized> std::ops::DispatchFromDyn<Weak<U>> for Weak<T> {}
+
+impl<T: ?Sized, A: Allocator + Clone> Weak<T, A> {
+    /// Attempts to upgrade the Weak pointer to an Rc, returning None if the inner value has been dropped.
+    pub fn upgrade(&self) -> Option<Rc<T, A>> {
+        let inner = unsafe { self.inner.as_ref() };
+        if inner.owner_count.get() == 0 {
+            return None;
+        }
+        inner.inc_strong();
+        Some(Rc {
+            inner: self.inner,
+            _marker: PhantomData,
+            alloc: self.alloc.clone(),
+        })
+    }
+}
+
+impl<T: ?Sized, A: Allocator + Clone> Clone for Weak<T, A> {
+    fn clone(&self) -> Self {
+        let inner = unsafe { self.inner.as_ref() };
+        inner.inc_weak();
+        Weak {
+            inner: self.inner,
+            alloc: self.alloc.clone(),
+        }
+    }
+}
+
+impl<T: ?Sized, A: Allocator> Drop for Weak<T, A> {
+    fn drop(&mut self) {
+        let inner = unsafe { self.inner.as_ref() };
+
+        let c = inner.weak_count.get() - 1;
+        inner.weak_count.set(c);
+
+        if c == 0 {
+            // Free RcInner, T has already been dropped. The layout is computed from the
+            // (possibly fat) pointer, which matches the one used to allocate.
+            let layout = Layout::for_value(inner);
+            unsafe { self.alloc.deallocate(self.inner.cast(), layout) };
         }
     }
 }
@@ -120,4 +632,354 @@ mod tests {
         assert_eq!(*a, "hello");
         drop(a);
     }
+
+    #[test]
+    fn test_weak_upgrade() {
+        let a = Rc::new(5);
+        let w = Rc::downgrade(&a);
+        let w2 = w.clone();
+        assert_eq!(*w.upgrade().unwrap(), 5);
+        drop(a);
+        assert!(w.upgrade().is_none());
+        assert!(w2.upgrade().is_none());
+    }
+
+    #[test]
+    fn test_counts() {
+        let a = Rc::new(5);
+        assert_eq!(Rc::strong_count(&a), 1);
+        assert_eq!(Rc::weak_count(&a), 0);
+
+        let b = a.clone();
+        let w = Rc::downgrade(&a);
+        assert_eq!(Rc::strong_count(&a), 2);
+        assert_eq!(Rc::weak_count(&a), 1);
+
+        let c = w.upgrade().unwrap();
+        assert_eq!(Rc::strong_count(&b), 3);
+        drop(w);
+        drop(c);
+        assert_eq!(Rc::strong_count(&a), 2);
+        assert_eq!(Rc::weak_count(&a), 0);
+    }
+
+    #[test]
+    fn test_ptr_eq() {
+        let a = Rc::new(5);
+        let b = a.clone();
+        let c = Rc::new(5);
+        assert!(Rc::ptr_eq(&a, &b));
+        assert!(!Rc::ptr_eq(&a, &c));
+    }
+
+    #[test]
+    fn test_rc_slice() {
+        let a: Rc<[String]> = Rc::from(&["a".to_string(), "b".to_string()][..]);
+        let b = a.clone();
+        assert_eq!(&*a, &["a".to_string(), "b".to_string()]);
+        assert_eq!(b.len(), 2);
+        assert!(Rc::ptr_eq(&a, &b));
+
+        let w = Rc::downgrade(&a);
+        drop(a);
+        drop(b);
+        assert!(w.upgrade().is_none());
+    }
+
+    #[test]
+    fn test_rc_slice_from_vec() {
+        let a: Rc<[u64]> = Rc::from(vec![1, 2, 3]);
+        assert_eq!(&*a, &[1, 2, 3]);
+
+        let empty: Rc<[u8]> = Rc::from(Vec::new());
+        assert!(empty.is_empty());
+
+        let zst: Rc<[()]> = Rc::from(vec![(), ()]);
+        assert_eq!(zst.len(), 2);
+    }
+
+    #[test]
+    fn test_rc_str() {
+        let a: Rc<str> = Rc::from("hello");
+        let b: Rc<str> = Rc::from(String::from("hello"));
+        assert_eq!(&*a, "hello");
+        assert_eq!(&*a, &*b);
+        assert!(!Rc::ptr_eq(&a, &b));
+
+        let empty: Rc<str> = Rc::from("");
+        assert_eq!(&*empty, "");
+    }
+
+    #[test]
+    fn test_downcast() {
+        let any = Rc::into_any(Rc::new(String::from("hello")));
+        let any = match any.downcast::<i32>() {
+            Ok(_) => panic!("downcast to the wrong type"),
+            Err(any) => any,
+        };
+        let Ok(s) = any.downcast::<String>() else {
+            panic!("downcast failed");
+        };
+        assert_eq!(*s, "hello");
+        assert_eq!(Rc::strong_count(&s), 1);
+    }
+
+    #[test]
+    fn test_raw_round_trip() {
+        let a = Rc::new(5u8);
+        let b = a.clone();
+        let ptr = Rc::into_raw(b);
+        assert_eq!(ptr, Rc::as_ptr(&a));
+        assert_eq!(unsafe { *ptr }, 5);
+        assert_eq!(Rc::strong_count(&a), 2);
+
+        let b = unsafe { Rc::from_raw(ptr) };
+        assert!(Rc::ptr_eq(&a, &b));
+        drop(b);
+        assert_eq!(Rc::strong_count(&a), 1);
+
+        #[repr(align(64))]
+        struct Aligned(u8);
+        let c = Rc::new(Aligned(7));
+        let c = unsafe { Rc::from_raw(Rc::into_raw(c)) };
+        assert_eq!(c.0, 7);
+    }
+
+    #[test]
+    fn test_raw_round_trip_unsized() {
+        let a: Rc<str> = Rc::from("hello");
+        let ptr = Rc::into_raw(a);
+        let a = unsafe { Rc::from_raw(ptr) };
+        assert_eq!(&*a, "hello");
+        assert_eq!(Rc::strong_count(&a), 1);
+    }
+
+    #[test]
+    fn test_rc_trait_impls() {
+        use std::collections::{BTreeSet, HashMap};
+
+        let a = Rc::new(1);
+        let b = Rc::new(2);
+        assert_eq!(a, Rc::new(1));
+        assert!(a < b);
+        assert_eq!(format!("{a} {b:?}"), "1 2");
+
+        let mut map = HashMap::new();
+        map.insert(Rc::<str>::from("key"), 1);
+        assert_eq!(map[&Rc::<str>::from("key")], 1);
+
+        let set: BTreeSet<_> = [b.clone(), a.clone()].into_iter().collect();
+        assert_eq!(set.into_iter().collect::<Vec<_>>(), vec![a, b]);
+    }
+
+    #[test]
+    fn test_rc_conversions() {
+        use std::collections::HashSet;
+
+        let a: Rc<String> = Rc::from(String::from("hello"));
+        let s: &String = a.as_ref();
+        assert_eq!(s, "hello");
+
+        // Borrow lets a set of Rc<String> be queried with a plain &String.
+        let set: HashSet<Rc<String>> = [a.clone()].into_iter().collect();
+        assert!(set.contains(&String::from("hello")));
+
+        let d: Rc<Vec<u8>> = Rc::default();
+        assert!(d.is_empty());
+    }
+
+    #[test]
+    fn test_checked_increment() {
+        assert_eq!(checked_increment(0), 1);
+        assert_eq!(checked_increment(usize::MAX - 1), usize::MAX);
+    }
+
+    #[test]
+    fn test_clone_overflow_aborts() {
+        use std::process::Command;
+
+        // Aborting takes the whole process down, so the overflow is triggered in a child process
+        // running only this test.
+        if std::env::var_os("POINTERS_RC_OVERFLOW_CHILD").is_some() {
+            let a = Rc::new(0);
+            unsafe { a.inner.as_ref() }.owner_count.set(usize::MAX);
+            let _b = a.clone();
+            unreachable!("clone must abort on overflow");
+        }
+
+        let status = Command::new(std::env::current_exe().unwrap())
+            .args(["rc::tests::test_clone_overflow_aborts", "--exact"])
+            .env("POINTERS_RC_OVERFLOW_CHILD", "1")
+            .output()
+            .unwrap()
+            .status;
+        assert!(!status.success());
+        #[cfg(unix)]
+        {
+            use std::os::unix::process::ExitStatusExt;
+            assert_eq!(status.signal(), Some(6), "child should die with SIGABRT");
+        }
+    }
+
+    #[cfg(feature = "nightly")]
+    #[test]
+    fn test_coerce_to_trait_object() {
+        let a: Rc<dyn std::fmt::Display> = Rc::new(5);
+        let w: Weak<dyn std::fmt::Display> = Rc::downgrade(&a);
+        assert_eq!(a.to_string(), "5");
+        assert_eq!(w.upgrade().unwrap().to_string(), "5");
+
+        let any: Rc<dyn Any> = Rc::new(String::from("hello"));
+        assert!(any.downcast::<String>().is_ok());
+    }
+
+    #[test]
+    fn test_new_in() {
+        use crate::allocator::AllocError;
+        use std::alloc::Layout;
+
+        // Counts the live allocations, so the test can check Rc frees through it.
+        #[derive(Clone)]
+        struct CountingAlloc<'a>(&'a Cell<usize>);
+
+        unsafe impl Allocator for CountingAlloc<'_> {
+            fn allocate(&self, layout: Layout) -> Result<NonNull<[u8]>, AllocError> {
+                self.0.set(self.0.get() + 1);
+                Global.allocate(layout)
+            }
+
+            unsafe fn deallocate(&self, ptr: NonNull<u8>, layout: Layout) {
+                self.0.set(self.0.get() - 1);
+                unsafe { Global.deallocate(ptr, layout) }
+            }
+        }
+
+        let live = Cell::new(0);
+        let a = Rc::new_in(String::from("hello"), CountingAlloc(&live));
+        assert_eq!(live.get(), 1);
+
+        let b = a.clone();
+        let w = Rc::downgrade(&a);
+        assert_eq!(*b, "hello");
+        drop(a);
+        drop(b);
+        // The Weak keeps the allocation alive until it goes away.
+        assert!(w.upgrade().is_none());
+        assert_eq!(live.get(), 1);
+        drop(w);
+        assert_eq!(live.get(), 0);
+    }
+
+    #[test]
+    fn test_get_mut() {
+        let mut a = Rc::new(5);
+        *Rc::get_mut(&mut a).unwrap() += 1;
+        assert_eq!(*a, 6);
+
+        let b = a.clone();
+        assert!(Rc::get_mut(&mut a).is_none());
+        drop(b);
+        let w = Rc::downgrade(&a);
+        assert!(Rc::get_mut(&mut a).is_none());
+        drop(w);
+        assert!(Rc::get_mut(&mut a).is_some());
+    }
+
+    #[test]
+    fn test_new_uninit() {
+        let mut a = Rc::<[u64; 1024]>::new_uninit();
+        let slot = Rc::get_mut(&mut a).unwrap();
+        // Initialize in place, element by element.
+        let data = slot.as_mut_ptr() as *mut u64;
+        for i in 0..1024 {
+            unsafe { data.add(i).write(i as u64) };
+        }
+        let a = unsafe { a.assume_init() };
+        assert_eq!(a[1023], 1023);
+        assert_eq!(Rc::strong_count(&a), 1);
+    }
+
+    #[test]
+    fn test_project() {
+        struct Config {
+            name: String,
+            ports: Vec<u16>,
+        }
+
+        let config = Rc::new(Config {
+            name: String::from("server"),
+            ports: vec![80, 443],
+        });
+        let name = Rc::project(config.clone(), |c| c.name.as_str());
+        let port = RcRef::map(Rc::project(config.clone(), |c| &c.ports), |p| &p[1]);
+        assert_eq!(Rc::strong_count(&config), 3);
+
+        // The projections keep the whole value alive.
+        drop(config);
+        let name2 = name.clone();
+        assert_eq!(&*name, "server");
+        assert_eq!(&*name2, "server");
+        assert_eq!(*port, 443);
+    }
+
+    #[test]
+    fn test_make_mut() {
+        let mut a = Rc::new(5);
+        let b = a.clone();
+        *Rc::make_mut(&mut a) += 1;
+        assert_eq!(*a, 6);
+        assert_eq!(*b, 5);
+        assert_eq!(Rc::strong_count(&a), 1);
+        assert_eq!(Rc::strong_count(&b), 1);
+
+        // Unique, so no clone is performed.
+        let ptr = &*a as *const i32;
+        *Rc::make_mut(&mut a) += 1;
+        assert_eq!(ptr, &*a as *const i32);
+        assert_eq!(*a, 7);
+    }
+
+    #[test]
+    fn test_make_mut_disassociates_weak() {
+        let mut a = Rc::new("hello".to_string());
+        let w = Rc::downgrade(&a);
+        Rc::make_mut(&mut a).push_str(" world");
+        assert_eq!(*a, "hello world");
+        assert!(w.upgrade().is_none());
+        assert_eq!(Rc::weak_count(&a), 0);
+    }
+
+    #[test]
+    fn test_weak_cycle_drops_value() {
+        struct Node {
+            parent: Cell<Option<Weak<Node>>>,
+            dropped: Rc<Cell<bool>>,
+        }
+
+        impl Drop for Node {
+            fn drop(&mut self) {
+                self.dropped.set(true);
+            }
+        }
+
+        let dropped = Rc::new(Cell::new(false));
+        let node = Rc::new(Node {
+            parent: Cell::new(None),
+            dropped: dropped.clone(),
+        });
+        // A node holding a Weak to itself must still be freed.
+        node.parent.set(Some(Rc::downgrade(&node)));
+        drop(node);
+        assert!(dropped.get());
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_serde_round_trip() {
+        let value: (Rc<i32>, Rc<str>, Rc<[u8]>) = (Rc::new(1), Rc::from("a"), Rc::from(vec![2, 3]));
+        let json = serde_json::to_string(&value).unwrap();
+        assert_eq!(json, r#"[1,"a",[2,3]]"#);
+        let back: (Rc<i32>, Rc<str>, Rc<[u8]>) = serde_json::from_str(&json).unwrap();
+        assert_eq!(back, value);
+    }
 }
@@ -1,10 +1,16 @@
 use crate::cell::Cell;
 use std::cell::UnsafeCell;
+use std::marker::PhantomData;
+use std::panic::Location;
+use std::ptr::NonNull;
 
-#[derive(Debug, Copy, Clone)]
+/// The dynamic borrow state of a RefCell, as returned by RefCell::borrow_state.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
 pub enum RefState {
     Shared(usize),
-    Exclusive,
+    // Number of RefMut guards, more than one only after RefMut::map_split,
+    // in which case each guard points to a disjoint part of the value.
+    Exclusive(usize),
     Unshared,
 }
 
@@ -17,89 +23,484 @@ pub enum RefState {
 pub struct RefCell<T> {
     value: UnsafeCell<T>,
     state: Cell<RefState>,
+    borrows: Borrows,
 }
 
+// Where the live borrows of a cell were taken, oldest first, with the debug_refcell feature.
+// Each guard registers its own location and removes it when dropped.
+#[cfg(feature = "debug_refcell")]
+struct Borrows(UnsafeCell<Vec<&'static Location<'static>>>);
+
+#[cfg(not(feature = "debug_refcell"))]
+struct Borrows;
+
+impl Borrows {
+    const fn new() -> Borrows {
+        #[cfg(feature = "debug_refcell")]
+        return Borrows(UnsafeCell::new(Vec::new()));
+        #[cfg(not(feature = "debug_refcell"))]
+        Borrows
+    }
+
+    /// Records the caller as the location of a new borrow, until the returned Origin is dropped.
+    #[track_caller]
+    fn register(&self) -> Origin<'_> {
+        #[cfg(feature = "debug_refcell")]
+        {
+            let location = Location::caller();
+            // SAFETY: RefCell is not Sync and the list is never borrowed across calls.
+            unsafe { (*self.0.get()).push(location) };
+            Origin {
+                borrows: self,
+                location,
+            }
+        }
+        #[cfg(not(feature = "debug_refcell"))]
+        Origin(PhantomData)
+    }
+
+    /// The oldest borrow still alive.
+    #[cfg(feature = "debug_refcell")]
+    fn oldest(&self) -> &'static Location<'static> {
+        unsafe { (*self.0.get()).first() }
+            .expect("a borrowed RefCell has its live borrows recorded")
+    }
+}
+
+// The location of the borrow held by a guard, in the list of its cell.
+#[cfg(feature = "debug_refcell")]
+struct Origin<'refcell> {
+    borrows: &'refcell Borrows,
+    location: &'static Location<'static>,
+}
+
+#[cfg(not(feature = "debug_refcell"))]
+struct Origin<'refcell>(PhantomData<&'refcell Borrows>);
+
+impl<'refcell> Origin<'refcell> {
+    /// Records the caller as the location of one more borrow of the same cell.
+    #[track_caller]
+    fn another(&self) -> Origin<'refcell> {
+        #[cfg(feature = "debug_refcell")]
+        return self.borrows.register();
+        #[cfg(not(feature = "debug_refcell"))]
+        Origin(PhantomData)
+    }
+}
+
+#[cfg(feature = "debug_refcell")]
+impl Drop for Origin<'_> {
+    fn drop(&mut self) {
+        let borrows = unsafe { &mut *self.borrows.0.get() };
+        let index = borrows
+            .iter()
+            .position(|&location| std::ptr::eq(location, self.location))
+            .expect("a live borrow is in the list of its cell");
+        borrows.remove(index);
+    }
+}
+
+/// An error returned by RefCell::try_borrow, the value is currently mutably borrowed.
+#[derive(Debug)]
+pub struct BorrowError {
+    #[cfg(feature = "debug_refcell")]
+    location: &'static Location<'static>,
+}
+
+impl std::fmt::Display for BorrowError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("already mutably borrowed")?;
+        #[cfg(feature = "debug_refcell")]
+        write!(f, " (mutable borrow taken at {})", self.location)?;
+        Ok(())
+    }
+}
+
+impl std::error::Error for BorrowError {}
+
+/// An error returned by RefCell::try_borrow_mut, the value is currently borrowed.
+#[derive(Debug)]
+pub struct BorrowMutError {
+    #[cfg(feature = "debug_refcell")]
+    location: &'static Location<'static>,
+}
+
+impl std::fmt::Display for BorrowMutError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("already borrowed")?;
+        #[cfg(feature = "debug_refcell")]
+        write!(f, " (first borrow taken at {})", self.location)?;
+        Ok(())
+    }
+}
+
+impl std::error::Error for BorrowMutError {}
+
 impl<T> RefCell<T> {
-    fn new(value: T) -> RefCell<T> {
+    pub const fn new(value: T) -> RefCell<T> {
         Self {
             value: UnsafeCell::new(value),
             state: Cell::new(RefState::Unshared),
+            borrows: Borrows::new(),
         }
     }
 
-    fn borrow(&self) -> Option<Ref<'_, T>> {
+    /// Immutably borrows the wrapped value, failing if the value is currently mutably borrowed.
+    /// Multiple immutable borrows can be taken out at the same time.
+    #[track_caller]
+    pub fn try_borrow(&self) -> Result<Ref<'_, T>, BorrowError> {
         match self.state.get() {
-            RefState::Exclusive => None,
+            RefState::Exclusive(_) => Err(BorrowError {
+                #[cfg(feature = "debug_refcell")]
+                location: self.borrows.oldest(),
+            }),
             RefState::Shared(ref_count) => {
                 // SAFETY: No exclusive reference given before.
                 self.state.set(RefState::Shared(ref_count + 1));
-                Some(Ref { cell: self })
+                Ok(Ref::new(self))
             }
             RefState::Unshared => {
                 // SAFETY: No reference given before.
                 self.state.set(RefState::Shared(1));
-                Some(Ref { cell: self })
+                Ok(Ref::new(self))
             }
         }
     }
 
-    fn borrow_mut(&self) -> Option<RefMut<'_, T>> {
+    /// Mutably borrows the wrapped value, failing if the value is currently borrowed.
+    #[track_caller]
+    pub fn try_borrow_mut(&self) -> Result<RefMut<'_, T>, BorrowMutError> {
         match self.state.get() {
-            RefState::Exclusive | RefState::Shared(_) => None,
+            RefState::Exclusive(_) | RefState::Shared(_) => Err(BorrowMutError {
+                #[cfg(feature = "debug_refcell")]
+                location: self.borrows.oldest(),
+            }),
             RefState::Unshared => {
                 // SAFETY: No other references given as state unshared
-                self.state.set(RefState::Exclusive);
-                Some(RefMut { cell: self })
+                self.state.set(RefState::Exclusive(1));
+                Ok(RefMut::new(self))
             }
         }
     }
+
+    /// Immutably borrows the wrapped value.
+    /// Panics if the value is currently mutably borrowed, see try_borrow for a non-panicking variant.
+    #[track_caller]
+    pub fn borrow(&self) -> Ref<'_, T> {
+        match self.try_borrow() {
+            Ok(borrow) => borrow,
+            Err(err) => panic!("{err}"),
+        }
+    }
+
+    /// Mutably borrows the wrapped value.
+    /// Panics if the value is currently borrowed, see try_borrow_mut for a non-panicking variant.
+    #[track_caller]
+    pub fn borrow_mut(&self) -> RefMut<'_, T> {
+        match self.try_borrow_mut() {
+            Ok(borrow) => borrow,
+            Err(err) => panic!("{err}"),
+        }
+    }
+
+    /// Returns the current borrow state, e.g. to assert in tests that no borrow was leaked.
+    pub fn borrow_state(&self) -> RefState {
+        self.state.get()
+    }
+
+    /// Consumes the RefCell, returning the wrapped value.
+    pub fn into_inner(self) -> T {
+        // No guard can be alive as they borrow self.
+        self.value.into_inner()
+    }
+
+    /// Returns a mutable reference to the wrapped value, no borrow tracking is needed
+    /// as the &mut self guarantees there is no outstanding Ref or RefMut.
+    pub fn get_mut(&mut self) -> &mut T {
+        self.value.get_mut()
+    }
+
+    /// Replaces the wrapped value with a new one, returning the old value.
+    /// Panics if the value is currently borrowed.
+    #[track_caller]
+    pub fn replace(&self, value: T) -> T {
+        std::mem::replace(&mut *self.borrow_mut(), value)
+    }
+
+    /// Replaces the wrapped value with a new one computed from f, returning the old value.
+    /// Panics if the value is currently borrowed.
+    #[track_caller]
+    pub fn replace_with(&self, f: impl FnOnce(&mut T) -> T) -> T {
+        let mut borrow = self.borrow_mut();
+        let new = f(&mut borrow);
+        std::mem::replace(&mut *borrow, new)
+    }
+
+    /// Swaps the wrapped value of self with the wrapped value of other.
+    /// Panics if either value is currently borrowed, or if self and other are the same cell.
+    #[track_caller]
+    pub fn swap(&self, other: &RefCell<T>) {
+        std::mem::swap(&mut *self.borrow_mut(), &mut *other.borrow_mut())
+    }
+
+    /// Takes the wrapped value, leaving Default::default() in its place.
+    /// Panics if the value is currently borrowed.
+    #[track_caller]
+    pub fn take(&self) -> T
+    where
+        T: Default,
+    {
+        self.replace(T::default())
+    }
+}
+
+// Serializing needs a shared borrow, so it fails while the value is mutably borrowed.
+#[cfg(feature = "serde")]
+impl<T: serde::Serialize> serde::Serialize for RefCell<T> {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match self.try_borrow() {
+            Ok(value) => value.serialize(serializer),
+            Err(err) => Err(serde::ser::Error::custom(err)),
+        }
+    }
 }
 
-struct Ref<'refcell, T> {
-    cell: &'refcell RefCell<T>,
+#[cfg(feature = "serde")]
+impl<'de, T: serde::Deserialize<'de>> serde::Deserialize<'de> for RefCell<T> {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        T::deserialize(deserializer).map(RefCell::new)
+    }
 }
 
-impl<'a, T> Drop for Ref<'a, T> {
+/// A wrapper type for an immutably borrowed value from a RefCell<T>, releasing the borrow on drop.
+/// The guard only keeps the borrow state of the cell, so it can be narrowed down to a part of
+/// the value with Ref::map.
+pub struct Ref<'refcell, T: ?Sized> {
+    value: NonNull<T>,
+    state: &'refcell Cell<RefState>,
+    origin: Origin<'refcell>,
+}
+
+impl<'refcell, T: ?Sized> Ref<'refcell, T> {
+    #[track_caller]
+    fn new(cell: &'refcell RefCell<T>) -> Self
+    where
+        T: Sized,
+    {
+        Ref {
+            value: unsafe { NonNull::new_unchecked(cell.value.get()) },
+            state: &cell.state,
+            origin: cell.borrows.register(),
+        }
+    }
+
+    /// Copies a Ref, taking one more shared borrow of the RefCell.
+    /// This is an associated function rather than a Clone impl, so that `r.clone()`
+    /// keeps cloning the borrowed value through Deref, like with std's Ref.
+    #[allow(clippy::should_implement_trait)]
+    #[track_caller]
+    pub fn clone(orig: &Ref<'refcell, T>) -> Ref<'refcell, T> {
+        match orig.state.get() {
+            RefState::Shared(ref_count) => orig.state.set(RefState::Shared(ref_count + 1)),
+            RefState::Exclusive(_) | RefState::Unshared => unreachable!(),
+        }
+        Ref {
+            value: orig.value,
+            state: orig.state,
+            origin: orig.origin.another(),
+        }
+    }
+
+    /// Makes a new Ref for a component of the borrowed data, e.g. an element of a Vec.
+    /// The RefCell stays immutably borrowed until the returned Ref is dropped.
+    pub fn map<U: ?Sized>(orig: Ref<'refcell, T>, f: impl FnOnce(&T) -> &U) -> Ref<'refcell, U> {
+        let value = NonNull::from(f(&orig));
+        let state = orig.state;
+        // The borrow is handed over to the new guard.
+        let origin = unsafe { std::ptr::read(&orig.origin) };
+        std::mem::forget(orig);
+        Ref {
+            value,
+            state,
+            origin,
+        }
+    }
+
+    /// Splits a Ref into two Refs for different components of the borrowed data.
+    /// The RefCell stays immutably borrowed until both returned Refs are dropped.
+    #[track_caller]
+    pub fn map_split<U: ?Sized, V: ?Sized>(
+        orig: Ref<'refcell, T>,
+        f: impl FnOnce(&T) -> (&U, &V),
+    ) -> (Ref<'refcell, U>, Ref<'refcell, V>) {
+        let (u, v) = f(&orig);
+        let (u, v) = (NonNull::from(u), NonNull::from(v));
+        let state = orig.state;
+        // One shared borrow is handed over, the other one is new.
+        let (u_origin, v_origin) = (orig.origin.another(), unsafe {
+            std::ptr::read(&orig.origin)
+        });
+        std::mem::forget(orig);
+        match state.get() {
+            RefState::Shared(ref_count) => state.set(RefState::Shared(ref_count + 1)),
+            RefState::Exclusive(_) | RefState::Unshared => unreachable!(),
+        }
+        (
+            Ref {
+                value: u,
+                state,
+                origin: u_origin,
+            },
+            Ref {
+                value: v,
+                state,
+                origin: v_origin,
+            },
+        )
+    }
+}
+
+impl<T: ?Sized> Drop for Ref<'_, T> {
     fn drop(&mut self) {
-        match self.cell.state.get() {
-            RefState::Exclusive | RefState::Unshared => unreachable!(),
+        match self.state.get() {
+            RefState::Exclusive(_) | RefState::Unshared => unreachable!(),
             RefState::Shared(1) => {
-                self.cell.state.set(RefState::Unshared);
+                self.state.set(RefState::Unshared);
             }
             RefState::Shared(ref_count) => {
-                self.cell.state.set(RefState::Shared(ref_count - 1));
+                self.state.set(RefState::Shared(ref_count - 1));
             }
         }
     }
 }
 
-struct RefMut<'refcell, T> {
-    cell: &'refcell RefCell<T>,
+/// A wrapper type for a mutably borrowed value from a RefCell<T>, releasing the borrow on drop.
+pub struct RefMut<'refcell, T: ?Sized> {
+    value: NonNull<T>,
+    state: &'refcell Cell<RefState>,
+    origin: Origin<'refcell>,
+    // A RefMut behaves like a &mut T, which is invariant in T.
+    _marker: PhantomData<&'refcell mut T>,
 }
 
-impl<'a, T> Drop for RefMut<'a, T> {
+impl<'refcell, T: ?Sized> RefMut<'refcell, T> {
+    #[track_caller]
+    fn new(cell: &'refcell RefCell<T>) -> Self
+    where
+        T: Sized,
+    {
+        RefMut {
+            value: unsafe { NonNull::new_unchecked(cell.value.get()) },
+            state: &cell.state,
+            origin: cell.borrows.register(),
+            _marker: PhantomData,
+        }
+    }
+
+    /// Makes a new RefMut for a component of the borrowed data, e.g. a field of a struct.
+    /// The RefCell stays mutably borrowed until the returned RefMut is dropped.
+    pub fn map<U: ?Sized>(
+        mut orig: RefMut<'refcell, T>,
+        f: impl FnOnce(&mut T) -> &mut U,
+    ) -> RefMut<'refcell, U> {
+        let value = NonNull::from(f(&mut orig));
+        let state = orig.state;
+        // The borrow is handed over to the new guard.
+        let origin = unsafe { std::ptr::read(&orig.origin) };
+        std::mem::forget(orig);
+        RefMut {
+            value,
+            state,
+            origin,
+            _marker: PhantomData,
+        }
+    }
+
+    /// Splits a RefMut into two RefMuts for different components of the borrowed data, e.g. the
+    /// two halves of a slice. The RefCell stays mutably borrowed until both are dropped.
+    #[track_caller]
+    pub fn map_split<U: ?Sized, V: ?Sized>(
+        mut orig: RefMut<'refcell, T>,
+        f: impl FnOnce(&mut T) -> (&mut U, &mut V),
+    ) -> (RefMut<'refcell, U>, RefMut<'refcell, V>) {
+        // The two &mut returned by f can't alias, so neither can the two guards.
+        let (u, v) = f(&mut orig);
+        let (u, v) = (NonNull::from(u), NonNull::from(v));
+        let state = orig.state;
+        let (u_origin, v_origin) = (orig.origin.another(), unsafe {
+            std::ptr::read(&orig.origin)
+        });
+        std::mem::forget(orig);
+        match state.get() {
+            RefState::Exclusive(count) => state.set(RefState::Exclusive(count + 1)),
+            RefState::Shared(_) | RefState::Unshared => unreachable!(),
+        }
+        (
+            RefMut {
+                value: u,
+                state,
+                origin: u_origin,
+                _marker: PhantomData,
+            },
+            RefMut {
+                value: v,
+                state,
+                origin: v_origin,
+                _marker: PhantomData,
+            },
+        )
+    }
+
+    /// Turns a RefMut into a Ref to the same data, without releasing the borrow in between,
+    /// so no other RefMut can be taken before the Ref is.
+    /// Panics if the RefMut was split with map_split and the other part is still alive,
+    /// as that part could still be written to.
+    #[track_caller]
+    pub fn downgrade(orig: RefMut<'refcell, T>) -> Ref<'refcell, T> {
+        let value = orig.value;
+        let state = orig.state;
+        match state.get() {
+            RefState::Exclusive(1) => state.set(RefState::Shared(1)),
+            RefState::Exclusive(_) => panic!("cannot downgrade a RefMut split with map_split"),
+            RefState::Shared(_) | RefState::Unshared => unreachable!(),
+        }
+        let origin = unsafe { std::ptr::read(&orig.origin) };
+        std::mem::forget(orig);
+        Ref {
+            value,
+            state,
+            origin,
+        }
+    }
+}
+
+impl<T: ?Sized> Drop for RefMut<'_, T> {
     fn drop(&mut self) {
-        self.cell.state.set(RefState::Unshared);
+        match self.state.get() {
+            RefState::Shared(_) | RefState::Unshared => unreachable!(),
+            RefState::Exclusive(1) => self.state.set(RefState::Unshared),
+            RefState::Exclusive(count) => self.state.set(RefState::Exclusive(count - 1)),
+        }
     }
 }
 
-impl<T> std::ops::Deref for Ref<'_, T> {
+impl<T: ?Sized> std::ops::Deref for Ref<'_, T> {
     type Target = T;
     fn deref(&self) -> &Self::Target {
-        unsafe { &*self.cell.value.get() }
+        unsafe { self.value.as_ref() }
     }
 }
 
-impl<T> std::ops::Deref for RefMut<'_, T> {
+impl<T: ?Sized> std::ops::Deref for RefMut<'_, T> {
     type Target = T;
     fn deref(&self) -> &Self::Target {
-        unsafe { &*self.cell.value.get() }
+        unsafe { self.value.as_ref() }
     }
 }
 
-impl<T> std::ops::DerefMut for RefMut<'_, T> {
+impl<T: ?Sized> std::ops::DerefMut for RefMut<'_, T> {
     fn deref_mut(&mut self) -> &mut Self::Target {
-        unsafe { &mut *self.cell.value.get() }
+        unsafe { self.value.as_mut() }
     }
 }
 
@@ -110,24 +511,246 @@ mod tests {
     #[test]
     fn test_refcell_multiple_borrow() {
         let c = RefCell::new(5);
-        let b1 = c.borrow().unwrap();
+        let b1 = c.borrow();
         assert_eq!(*b1, 5);
-        let b2 = c.borrow().unwrap();
+        let b2 = c.borrow();
         assert_eq!(*b2, 5);
     }
 
+    #[test]
+    fn test_const_thread_local() {
+        thread_local! {
+            static STACK: RefCell<Vec<u8>> = const { RefCell::new(Vec::new()) };
+            static DEPTH: crate::cell::Cell<u8> = const { crate::cell::Cell::new(0) };
+        }
+        STACK.with(|stack| stack.borrow_mut().push(1));
+        DEPTH.with(|depth| depth.set(depth.get() + 1));
+        assert_eq!(STACK.with(|stack| stack.borrow().len()), 1);
+        assert_eq!(DEPTH.with(|depth| depth.get()), 1);
+    }
+
     #[test]
     fn test_refcell_borrow_mut() {
         let c = RefCell::new(5);
-        let b1 = c.borrow().unwrap();
+        let b1 = c.borrow();
         assert_eq!(*b1, 5);
-        assert!(c.borrow_mut().is_none());
+        assert!(c.try_borrow_mut().is_err());
         drop(b1);
-        let mut b_mut = c.borrow_mut().unwrap();
+        let mut b_mut = c.borrow_mut();
         assert_eq!(*b_mut, 5);
-        assert!(c.borrow_mut().is_none());
+        assert!(c.try_borrow_mut().is_err());
+        assert!(c.try_borrow().is_err());
         *b_mut = 2;
         drop(b_mut);
-        assert_eq!(*c.borrow().unwrap(), 2);
+        assert_eq!(*c.borrow(), 2);
+    }
+
+    #[test]
+    #[should_panic(expected = "already borrowed")]
+    fn test_refcell_borrow_mut_panics() {
+        let c = RefCell::new(5);
+        let _b = c.borrow();
+        c.borrow_mut();
+    }
+
+    #[test]
+    #[should_panic(expected = "already mutably borrowed")]
+    fn test_refcell_borrow_panics() {
+        let c = RefCell::new(5);
+        let _b = c.borrow_mut();
+        c.borrow();
+    }
+
+    #[test]
+    fn test_whole_value_apis() {
+        let c = RefCell::new(vec![1]);
+        assert_eq!(c.replace(vec![2]), vec![1]);
+        assert_eq!(
+            c.replace_with(|v| v.iter().map(|x| x * 10).collect()),
+            vec![2]
+        );
+        assert_eq!(*c.borrow(), vec![20]);
+
+        let other = RefCell::new(vec![3]);
+        c.swap(&other);
+        assert_eq!(*c.borrow(), vec![3]);
+        assert_eq!(c.take(), vec![3]);
+        assert!(c.borrow().is_empty());
+
+        let mut other = other;
+        other.get_mut().push(4);
+        assert_eq!(other.into_inner(), vec![20, 4]);
+    }
+
+    #[test]
+    #[should_panic(expected = "already borrowed")]
+    fn test_replace_while_borrowed_panics() {
+        let c = RefCell::new(5);
+        let _b = c.borrow();
+        c.replace(6);
+    }
+
+    #[cfg(feature = "debug_refcell")]
+    #[test]
+    fn test_borrow_error_reports_origin() {
+        let c = RefCell::new(5);
+        let line = line!() + 1;
+        let _b = c.borrow();
+        let _b2 = c.borrow();
+        let Err(err) = c.try_borrow_mut() else {
+            panic!("cell is borrowed");
+        };
+        let msg = err.to_string();
+        assert!(msg.starts_with("already borrowed"));
+        assert!(msg.contains(&format!("{}:{line}:", file!())), "{msg}");
+    }
+
+    #[cfg(feature = "debug_refcell")]
+    #[test]
+    fn test_borrow_error_reports_live_borrow() {
+        let c = RefCell::new([1, 2]);
+        let b1 = c.borrow();
+        let line = line!() + 1;
+        let b2 = c.borrow();
+        drop(b1);
+        let Err(err) = c.try_borrow_mut() else {
+            panic!("cell is borrowed");
+        };
+        assert!(err.to_string().contains(&format!("{}:{line}:", file!())));
+        drop(b2);
+
+        // Split guards each carry their own borrow.
+        let line = line!() + 1;
+        let (left, right) = RefMut::map_split(c.borrow_mut(), |a| a.split_at_mut(1));
+        drop(right);
+        let Err(err) = c.try_borrow() else {
+            panic!("cell is mutably borrowed");
+        };
+        assert!(err.to_string().contains(&format!("{}:{line}:", file!())));
+        drop(left);
+        assert!(c.try_borrow_mut().is_ok());
+    }
+
+    #[test]
+    fn test_guards_can_be_returned() {
+        struct Registry {
+            names: RefCell<Vec<String>>,
+        }
+
+        impl Registry {
+            fn names(&self) -> Ref<'_, Vec<String>> {
+                self.names.borrow()
+            }
+        }
+
+        let registry = Registry {
+            names: RefCell::new(vec![String::from("a")]),
+        };
+        registry.names.borrow_mut().push(String::from("b"));
+        assert_eq!(registry.names().len(), 2);
+    }
+
+    #[test]
+    fn test_ref_map() {
+        let c = RefCell::new((vec![1, 2, 3], String::from("name")));
+        let second = Ref::map(c.borrow(), |(v, _)| &v[1]);
+        assert_eq!(*second, 2);
+        // The projected guard still holds the shared borrow.
+        assert!(c.try_borrow_mut().is_err());
+        let name = Ref::map(c.borrow(), |(_, s)| s.as_str());
+        assert_eq!(&*name, "name");
+        drop(second);
+        drop(name);
+
+        {
+            let mut first = RefMut::map(c.borrow_mut(), |(v, _)| &mut v[0]);
+            *first = 10;
+            assert!(c.try_borrow().is_err());
+        }
+        assert_eq!(c.borrow().0, vec![10, 2, 3]);
+    }
+
+    #[test]
+    fn test_ref_clone() {
+        let c = RefCell::new(vec![1]);
+        let b1 = c.borrow();
+        let b2 = Ref::clone(&b1);
+        drop(b1);
+        assert_eq!(*b2, vec![1]);
+        assert!(c.try_borrow_mut().is_err());
+        // Method syntax still clones the value itself.
+        let v: Vec<i32> = b2.clone();
+        drop(b2);
+        assert!(c.try_borrow_mut().is_ok());
+        assert_eq!(v, vec![1]);
+    }
+
+    #[test]
+    fn test_borrow_state() {
+        let c = RefCell::new([1, 2]);
+        assert_eq!(c.borrow_state(), RefState::Unshared);
+        let b1 = c.borrow();
+        let b2 = c.borrow();
+        assert_eq!(c.borrow_state(), RefState::Shared(2));
+        drop((b1, b2));
+        let parts = RefMut::map_split(c.borrow_mut(), |a| a.split_at_mut(1));
+        assert_eq!(c.borrow_state(), RefState::Exclusive(2));
+        drop(parts);
+        assert_eq!(c.borrow_state(), RefState::Unshared);
+    }
+
+    #[test]
+    fn test_downgrade() {
+        let c = RefCell::new(vec![1]);
+        let mut b = c.borrow_mut();
+        b.push(2);
+        let r = RefMut::downgrade(b);
+        assert_eq!(*r, vec![1, 2]);
+        // Readers can join, writers still can't.
+        assert_eq!(c.borrow().len(), 2);
+        assert!(c.try_borrow_mut().is_err());
+        drop(r);
+        assert!(c.try_borrow_mut().is_ok());
+    }
+
+    #[test]
+    #[should_panic(expected = "cannot downgrade")]
+    fn test_downgrade_split_panics() {
+        let c = RefCell::new([1, 2]);
+        let (left, _right) = RefMut::map_split(c.borrow_mut(), |a| a.split_at_mut(1));
+        RefMut::downgrade(left);
+    }
+
+    #[test]
+    fn test_map_split() {
+        let c = RefCell::new([1, 2, 3, 4]);
+        let (left, right) = Ref::map_split(c.borrow(), |a| a.split_at(2));
+        assert_eq!(*left, [1, 2]);
+        assert_eq!(*right, [3, 4]);
+        drop(left);
+        assert!(c.try_borrow_mut().is_err());
+        drop(right);
+
+        let (mut left, mut right) = RefMut::map_split(c.borrow_mut(), |a| a.split_at_mut(2));
+        left[0] = 10;
+        right[0] = 30;
+        drop(right);
+        // Still mutably borrowed through `left`.
+        assert!(c.try_borrow().is_err());
+        drop(left);
+        assert_eq!(*c.borrow(), [10, 2, 30, 4]);
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_serde_round_trip() {
+        let c = RefCell::new(vec![String::from("a")]);
+        let json = serde_json::to_string(&c).unwrap();
+        assert_eq!(json, r#"["a"]"#);
+        let back: RefCell<Vec<String>> = serde_json::from_str(&json).unwrap();
+        assert_eq!(back.into_inner(), vec![String::from("a")]);
+
+        let _b = c.borrow_mut();
+        assert!(serde_json::to_string(&c).is_err());
     }
 }
@@ -1,95 +1,231 @@
-use std::cell::UnsafeCell;
-use std::ops::{Deref, DerefMut};
-use std::sync::atomic::AtomicIsize;
-use std::sync::atomic::Ordering;
+use crate::lock_api::{self, GuardSend};
+use std::hint::spin_loop;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::thread;
 
 /// This type of lock allows a number of readers or at most one writer at any point in time.
 /// The write portion of this lock typically allows modification of the underlying data (exclusive access)
 /// and the read portion of this lock typically allows for read-only access (shared access).
-pub struct RwLock<T> {
-    value: UnsafeCell<T>,
-    // -1 -> Write, 0 -> Nobody >1 -> Read
-    state: AtomicIsize,
+///
+/// Writers are preferred: a waiting writer holds back new readers. So a thread taking a
+/// read lock it already holds can deadlock, if a writer started waiting in between.
+pub type RwLock<T> = lock_api::RwLock<RawRwLock, T>;
+
+/// The guard of a read-locked RwLock, which can't be sent to another thread:
+/// ```compile_fail
+/// use pointers::rwlock::RwLock;
+/// let lock = RwLock::new(0);
+/// let guard = lock.read();
+/// std::thread::scope(|s| {
+///     s.spawn(move || drop(guard));
+/// });
+/// ```
+pub type RwLockReadGuard<'a, T> = lock_api::RwLockReadGuard<'a, RawRwLock, T>;
+
+/// The guard of a write-locked RwLock, which can't be sent to another thread:
+/// ```compile_fail
+/// use pointers::rwlock::RwLock;
+/// let lock = RwLock::new(0);
+/// let guard = lock.write();
+/// std::thread::scope(|s| {
+///     s.spawn(move || drop(guard));
+/// });
+/// ```
+///
+/// Nor shared with other threads when T isn't Sync:
+/// ```compile_fail
+/// use pointers::rwlock::RwLock;
+/// let lock = RwLock::new(std::cell::Cell::new(0));
+/// let guard = lock.write();
+/// std::thread::scope(|s| {
+///     s.spawn(|| guard.set(1));
+/// });
+/// ```
+pub type RwLockWriteGuard<'a, T> = lock_api::RwLockWriteGuard<'a, RawRwLock, T>;
+
+/// A guard returned by RwLock::read_owned, which holds a read lock as long as it lives.
+/// It has no lifetime, so it can be moved into spawned threads:
+/// ```
+/// use pointers::rwlock::RwLock;
+/// use std::sync::Arc;
+/// let lock = Arc::new(RwLock::new(1));
+/// let guard = lock.clone().read_owned();
+/// assert_eq!(std::thread::spawn(move || *guard).join().unwrap(), 1);
+/// ```
+pub type OwnedRwLockReadGuard<T> = lock_api::OwnedRwLockReadGuard<RawRwLock, T>;
+
+/// A guard returned by RwLock::write_owned, which holds the write lock as long as it lives.
+pub type OwnedRwLockWriteGuard<T> = lock_api::OwnedRwLockWriteGuard<RawRwLock, T>;
+
+/// A read guard for a part of the value of an RwLock, made by RwLockReadGuard::map.
+pub type MappedRwLockReadGuard<'a, T> = lock_api::MappedRwLockReadGuard<'a, RawRwLock, T>;
+
+/// A write guard for a part of the value of an RwLock, made by RwLockWriteGuard::map.
+pub type MappedRwLockWriteGuard<'a, T> = lock_api::MappedRwLockWriteGuard<'a, RawRwLock, T>;
+
+/// The guard of an upgradeable read lock of an RwLock, which can be turned into a write guard
+/// with RwLockUpgradeableReadGuard::upgrade.
+pub type RwLockUpgradeableReadGuard<'a, T> = lock_api::RwLockUpgradeableReadGuard<'a, RawRwLock, T>;
+
+/// The raw lock of RwLock. It prefers writers: once a writer waits for the lock, new readers
+/// wait behind it, so a steady stream of overlapping readers can't starve writers.
+pub struct RawRwLock {
+    // WRITE_LOCKED, WRITER_WAITING and UPGRADEABLE flags, and the number of readers times
+    // READER.
+    state: AtomicUsize,
 }
 
-unsafe impl<T: Send> Send for RwLock<T> {}
-unsafe impl<T: Send + Sync> Sync for RwLock<T> {}
+const WRITE_LOCKED: usize = 1;
+// A writer is waiting for the readers to leave, new readers have to wait.
+const WRITER_WAITING: usize = 2;
+// An upgradeable reader holds the lock: other readers can come in, writers can't.
+const UPGRADEABLE: usize = 4;
+const READER: usize = 8;
+// Past this, one more reader would overflow into the flags. Only reachable by leaking guards.
+const MAX_READERS_STATE: usize = usize::MAX & !(READER - 1);
+
+unsafe impl lock_api::RawRwLock for RawRwLock {
+    const INIT: RawRwLock = RawRwLock {
+        state: AtomicUsize::new(0),
+    };
+
+    type GuardMarker = GuardSend;
 
-impl<T> RwLock<T> {
-    pub fn new(value: T) -> RwLock<T> {
-        RwLock {
-            value: UnsafeCell::new(value),
-            state: AtomicIsize::new(0),
+    fn lock_shared(&self) {
+        let mut spins = 0_u32;
+        while !self.try_lock_shared() {
+            backoff(&mut spins);
         }
     }
 
-    pub fn read(&self) -> RwLockReadGuard<'_, T> {
-        while self
-            .state
-            .fetch_update(Ordering::Acquire, Ordering::Relaxed, |x| {
-                if x < 0 { None } else { Some(x + 1) }
+    fn try_lock_shared(&self) -> bool {
+        self.state
+            .fetch_update(Ordering::Acquire, Ordering::Relaxed, |state| {
+                if state & (WRITE_LOCKED | WRITER_WAITING) != 0 {
+                    None
+                } else {
+                    assert!(
+                        state & !(UPGRADEABLE | WRITER_WAITING) < MAX_READERS_STATE,
+                        "too many readers of an RwLock"
+                    );
+                    Some(state + READER)
+                }
             })
-            .is_err()
-        {}
-        RwLockReadGuard { lock: self }
+            .is_ok()
     }
 
-    pub fn write(&self) -> RwLockWriteGuard<'_, T> {
-        while self
-            .state
-            .compare_exchange(0, -1, Ordering::Acquire, Ordering::Relaxed)
-            .is_err()
-        {
-            std::hint::spin_loop();
+    unsafe fn unlock_shared(&self) {
+        let prev_value = self.state.fetch_sub(READER, Ordering::Release);
+        assert!(prev_value >= READER);
+        debug_assert!(prev_value & WRITE_LOCKED == 0);
+    }
+
+    fn lock_exclusive(&self) {
+        let mut spins = 0_u32;
+        while !self.try_lock_exclusive() {
+            // Hold back new readers until we get the lock. Taking it clears the flag, other
+            // waiting writers set it again on their next round.
+            let state = self.state.load(Ordering::Relaxed);
+            if state & WRITER_WAITING == 0 {
+                self.state.fetch_or(WRITER_WAITING, Ordering::Relaxed);
+            }
+            backoff(&mut spins);
         }
-        RwLockWriteGuard { lock: self }
     }
-}
 
-pub struct RwLockReadGuard<'a, T> {
-    lock: &'a RwLock<T>,
-}
+    fn try_lock_exclusive(&self) -> bool {
+        self.state
+            .fetch_update(Ordering::Acquire, Ordering::Relaxed, |state| {
+                (state & !WRITER_WAITING == 0).then_some(WRITE_LOCKED)
+            })
+            .is_ok()
+    }
 
-impl<T> Deref for RwLockReadGuard<'_, T> {
-    type Target = T;
-    fn deref(&self) -> &Self::Target {
-        unsafe { &*self.lock.value.get() }
+    unsafe fn unlock_exclusive(&self) {
+        // Keeps the WRITER_WAITING flag of other writers.
+        let prev_value = self.state.fetch_and(!WRITE_LOCKED, Ordering::Release);
+        debug_assert!(prev_value & !WRITER_WAITING == WRITE_LOCKED);
     }
-}
 
-impl<T> Drop for RwLockReadGuard<'_, T> {
-    fn drop(&mut self) {
-        let prev_value = self.lock.state.fetch_sub(1, Ordering::Release);
-        assert!(prev_value >= 1);
+    fn is_locked(&self) -> bool {
+        self.state.load(Ordering::Relaxed) & !WRITER_WAITING != 0
     }
-}
 
-pub struct RwLockWriteGuard<'a, T> {
-    lock: &'a RwLock<T>,
-}
+    fn is_locked_exclusive(&self) -> bool {
+        self.state.load(Ordering::Relaxed) & WRITE_LOCKED != 0
+    }
 
-impl<T> Deref for RwLockWriteGuard<'_, T> {
-    type Target = T;
-    fn deref(&self) -> &Self::Target {
-        unsafe { &*self.lock.value.get() }
+    fn reader_count(&self) -> usize {
+        let state = self.state.load(Ordering::Relaxed);
+        state / READER + usize::from(state & UPGRADEABLE != 0)
     }
 }
 
-impl<T> DerefMut for RwLockWriteGuard<'_, T> {
-    fn deref_mut(&mut self) -> &mut Self::Target {
-        unsafe { &mut *self.lock.value.get() }
+unsafe impl lock_api::RawRwLockUpgrade for RawRwLock {
+    fn lock_upgradeable(&self) {
+        let mut spins = 0_u32;
+        while !self.try_lock_upgradeable() {
+            backoff(&mut spins);
+        }
+    }
+
+    fn try_lock_upgradeable(&self) -> bool {
+        self.state
+            .fetch_update(Ordering::Acquire, Ordering::Relaxed, |state| {
+                if state & (WRITE_LOCKED | WRITER_WAITING | UPGRADEABLE) != 0 {
+                    None
+                } else {
+                    Some(state | UPGRADEABLE)
+                }
+            })
+            .is_ok()
+    }
+
+    unsafe fn unlock_upgradeable(&self) {
+        let prev_value = self.state.fetch_and(!UPGRADEABLE, Ordering::Release);
+        assert!(prev_value & UPGRADEABLE != 0);
+        debug_assert!(prev_value & WRITE_LOCKED == 0);
+    }
+
+    unsafe fn upgrade(&self) {
+        let mut spins = 0_u32;
+        while !unsafe { self.try_upgrade() } {
+            // Like a waiting writer, hold back new readers.
+            let state = self.state.load(Ordering::Relaxed);
+            if state & WRITER_WAITING == 0 {
+                self.state.fetch_or(WRITER_WAITING, Ordering::Relaxed);
+            }
+            backoff(&mut spins);
+        }
+    }
+
+    unsafe fn try_upgrade(&self) -> bool {
+        self.state
+            .fetch_update(Ordering::Acquire, Ordering::Relaxed, |state| {
+                (state & !WRITER_WAITING == UPGRADEABLE).then_some(WRITE_LOCKED)
+            })
+            .is_ok()
     }
 }
 
-impl<T> Drop for RwLockWriteGuard<'_, T> {
-    fn drop(&mut self) {
-        self.lock.state.store(0, Ordering::Release);
+// Spins, yielding now and then, between attempts to take a lock.
+pub(crate) fn backoff(spins: &mut u32) {
+    *spins += 1;
+    // Let the lock holders run if they were preempted.
+    if spins.is_multiple_of(64) {
+        thread::yield_now();
+    } else {
+        spin_loop();
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use super::RwLock;
+    use super::{
+        MappedRwLockReadGuard, MappedRwLockWriteGuard, OwnedRwLockReadGuard, OwnedRwLockWriteGuard,
+        RwLock, RwLockReadGuard, RwLockUpgradeableReadGuard, RwLockWriteGuard,
+    };
+    use std::thread;
 
     #[test]
     fn test_rwlock() {
@@ -111,6 +247,191 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_try_read_and_try_write() {
+        let lock = RwLock::new(1);
+        let r = lock.try_read().unwrap();
+        assert!(lock.try_write().is_none());
+        assert!(lock.is_locked() && !lock.is_locked_exclusive());
+        assert_eq!(lock.reader_count(), 1);
+        drop(r);
+        let mut w = lock.try_write().unwrap();
+        *w += 1;
+        assert!(lock.try_read().is_none());
+        assert!(lock.is_locked_exclusive() && lock.is_write_locked());
+        assert_eq!(lock.reader_count(), 0);
+        drop(w);
+        assert!(!lock.is_locked());
+        assert_eq!(lock.into_inner(), 2);
+    }
+
+    #[test]
+    fn test_force_unlock() {
+        let lock = RwLock::new(0);
+        lock.raw_read();
+        std::mem::forget(lock.read());
+        assert!(lock.try_write().is_none());
+        unsafe {
+            lock.force_unlock_read();
+            lock.force_unlock_read();
+        }
+        std::mem::forget(lock.write());
+        assert!(lock.try_read().is_none());
+        unsafe { lock.force_unlock_write() };
+        lock.raw_write();
+        assert!(lock.is_locked_exclusive());
+        unsafe { lock.force_unlock_write() };
+        assert!(!lock.is_locked());
+    }
+
+    #[test]
+    fn test_upgradeable_read() {
+        let lock = RwLock::new(1);
+        let upgradeable = lock.upgradeable_read();
+        // Readers can come in, writers and other upgradeable readers can't.
+        let reader = lock.try_read().unwrap();
+        assert!(lock.try_upgradeable_read().is_none());
+        assert!(lock.try_write().is_none());
+        assert!(!lock.is_locked_exclusive());
+        assert_eq!(lock.reader_count(), 2);
+        let upgradeable = RwLockUpgradeableReadGuard::try_upgrade(upgradeable)
+            .err()
+            .unwrap();
+        drop(reader);
+        let mut writer = RwLockUpgradeableReadGuard::try_upgrade(upgradeable)
+            .ok()
+            .unwrap();
+        *writer += 1;
+        assert!(lock.is_locked_exclusive());
+        assert!(lock.try_read().is_none());
+        drop(writer);
+        assert!(!lock.is_locked());
+        drop(lock.upgradeable_read());
+        assert!(lock.try_write().is_some());
+        assert_eq!(lock.into_inner(), 2);
+    }
+
+    #[test]
+    fn test_upgrade_check_then_act() {
+        // Each thread checks the value and upgrades to update it: no update is lost,
+        // since no writer can get in between the check and the write.
+        let lock = RwLock::new(0);
+        thread::scope(|s| {
+            for _ in 0..4 {
+                s.spawn(|| {
+                    for _ in 0..1000 {
+                        let guard = lock.upgradeable_read();
+                        let value = *guard;
+                        let mut guard = RwLockUpgradeableReadGuard::upgrade(guard);
+                        assert_eq!(*guard, value);
+                        *guard = value + 1;
+                    }
+                });
+                s.spawn(|| {
+                    for _ in 0..1000 {
+                        let _ = *lock.read();
+                    }
+                });
+            }
+        });
+        assert_eq!(lock.into_inner(), 4000);
+    }
+
+    #[test]
+    #[should_panic(expected = "too many readers")]
+    fn test_reader_overflow() {
+        use std::sync::atomic::Ordering;
+
+        let lock = RwLock::new(0);
+        // As if usize::MAX / 8 read guards had been leaked.
+        lock.raw
+            .state
+            .store(super::MAX_READERS_STATE, Ordering::Relaxed);
+        assert!(lock.is_locked() && !lock.is_locked_exclusive());
+        let _ = lock.try_read();
+    }
+
+    #[test]
+    fn test_map_guards() {
+        use std::collections::HashMap;
+
+        let lock = RwLock::new(HashMap::from([("a", vec![1, 2]), ("b", vec![3])]));
+        let a = RwLockReadGuard::map(lock.read(), |map| &map["a"]);
+        let first = MappedRwLockReadGuard::map(a, |a| &a[0]);
+        assert_eq!(*first, 1);
+        assert!(lock.try_write().is_none());
+        drop(first);
+
+        let mut b = RwLockWriteGuard::map(lock.write(), |map| map.get_mut("b").unwrap());
+        b.push(4);
+        assert!(lock.try_read().is_none());
+        let mut slice = MappedRwLockWriteGuard::map(b, |b| b.as_mut_slice());
+        slice[0] = 5;
+        drop(slice);
+        assert!(!lock.is_locked());
+        assert_eq!(lock.read()["b"], [5, 4]);
+    }
+
+    #[test]
+    fn test_owned_guards_moved_to_threads() {
+        use std::sync::Arc;
+
+        let lock = Arc::new(RwLock::new(vec![1]));
+        let mut writer = lock.clone().write_owned();
+        assert!(lock.clone().try_read_owned().is_none());
+        thread::spawn(move || writer.push(2)).join().unwrap();
+        let reader = lock.clone().try_read_owned().unwrap();
+        assert!(Arc::ptr_eq(OwnedRwLockReadGuard::rwlock(&reader), &lock));
+        let readers: Vec<_> = (0..2)
+            .map(|_| {
+                let reader = lock.clone().read_owned();
+                thread::spawn(move || reader.len())
+            })
+            .collect();
+        for r in readers {
+            assert_eq!(r.join().unwrap(), 2);
+        }
+        assert!(lock.clone().try_write_owned().is_none());
+        drop(reader);
+        let writer = lock.clone().try_write_owned().unwrap();
+        assert!(Arc::ptr_eq(OwnedRwLockWriteGuard::rwlock(&writer), &lock));
+        drop(writer);
+        assert!(!lock.is_locked());
+    }
+
+    #[test]
+    fn test_writer_is_not_starved_by_readers() {
+        use std::sync::atomic::{AtomicBool, Ordering};
+        use std::time::{Duration, Instant};
+
+        let lock = RwLock::new(0);
+        let stop = AtomicBool::new(false);
+        let deadline = Instant::now() + Duration::from_secs(5);
+        thread::scope(|s| {
+            // Overlapping readers: at any time, one of them holds the read lock.
+            for _ in 0..4 {
+                s.spawn(|| {
+                    while !stop.load(Ordering::Relaxed) && Instant::now() < deadline {
+                        let _r = lock.read();
+                        thread::sleep(Duration::from_millis(1));
+                    }
+                });
+            }
+            thread::sleep(Duration::from_millis(20));
+            *lock.write() += 1;
+            stop.store(true, Ordering::Relaxed);
+            assert!(Instant::now() < deadline, "the writer was starved");
+        });
+        assert_eq!(lock.into_inner(), 1);
+    }
+
+    #[test]
+    fn test_static_rwlock() {
+        static CONFIG: RwLock<Vec<&str>> = RwLock::new(Vec::new());
+        CONFIG.write().push("verbose");
+        assert_eq!(*CONFIG.read(), ["verbose"]);
+    }
+
     #[test]
     fn test_parallel_readers() {
         use std::sync::Arc;
@@ -123,7 +444,7 @@ mod tests {
         for _ in 0..10 {
             let lk = lock.clone();
             threads.push(thread::spawn(move || {
-                for _ in 0..1_000_00 {
+                for _ in 0..100_000 {
                     let r = lk.read();
                     assert_eq!(*r, 123);
                 }
@@ -0,0 +1,180 @@
+use std::cell::UnsafeCell;
+use std::hint::spin_loop;
+use std::ops::{Deref, DerefMut};
+use std::ptr;
+use std::sync::atomic::{AtomicUsize, Ordering, fence};
+use std::thread;
+
+/// A sequence lock, as used in kernels for small data that is read far more often than it is
+/// written, like the current time. Readers take no lock and write nothing shared: they copy
+/// the value and retry if a writer was active meanwhile, which they tell from the sequence
+/// number. Writers exclude each other and are never held up by readers.
+///
+/// The value is copied out while a writer may be changing it, and the torn copy thrown away,
+/// hence T: Copy (no destructor may run on it).
+pub struct SeqLock<T: Copy> {
+    // Odd while a writer holds the lock, bumped by each lock and unlock.
+    seq: AtomicUsize,
+    value: UnsafeCell<T>,
+}
+
+unsafe impl<T: Copy + Send> Send for SeqLock<T> {}
+unsafe impl<T: Copy + Send> Sync for SeqLock<T> {}
+
+impl<T: Copy> SeqLock<T> {
+    pub const fn new(value: T) -> Self {
+        Self {
+            seq: AtomicUsize::new(0),
+            value: UnsafeCell::new(value),
+        }
+    }
+
+    /// Returns a copy of the value, retrying while writers change it.
+    pub fn read(&self) -> T {
+        let mut spins = 0_u32;
+        loop {
+            if let Some(value) = self.try_read() {
+                return value;
+            }
+            spins += 1;
+            // Let the writer run if it was preempted.
+            if spins.is_multiple_of(64) {
+                thread::yield_now();
+            } else {
+                spin_loop();
+            }
+        }
+    }
+
+    /// Returns a copy of the value, or None if a writer was active while copying it.
+    pub fn try_read(&self) -> Option<T> {
+        let seq = self.seq.load(Ordering::Acquire);
+        if seq & 1 != 0 {
+            return None;
+        }
+        // The copy may race with a writer, it is volatile so that the compiler doesn't
+        // assume it can't, and it is only used if the sequence number didn't change.
+        let value = unsafe { ptr::read_volatile(self.value.get()) };
+        // Keeps the copy from moving after the second load of the sequence number.
+        fence(Ordering::Acquire);
+        (self.seq.load(Ordering::Relaxed) == seq).then_some(value)
+    }
+
+    /// Locks out the other writers, returning a guard through which the value can be changed.
+    /// Readers retry until the guard is dropped.
+    pub fn write(&self) -> SeqLockWriteGuard<'_, T> {
+        let mut spins = 0_u32;
+        loop {
+            let seq = self.seq.load(Ordering::Relaxed);
+            if seq & 1 == 0
+                && self
+                    .seq
+                    .compare_exchange_weak(seq, seq + 1, Ordering::Acquire, Ordering::Relaxed)
+                    .is_ok()
+            {
+                // Keeps the writes to the value from moving before the odd sequence number,
+                // readers that see them then see the sequence number change.
+                fence(Ordering::Release);
+                return SeqLockWriteGuard { lock: self, seq };
+            }
+            spins += 1;
+            if spins.is_multiple_of(64) {
+                thread::yield_now();
+            } else {
+                spin_loop();
+            }
+        }
+    }
+
+    /// Consumes the lock, returning the value.
+    pub fn into_inner(self) -> T {
+        self.value.into_inner()
+    }
+
+    /// Returns a mutable reference to the value, no locking is needed with &mut self.
+    pub fn get_mut(&mut self) -> &mut T {
+        self.value.get_mut()
+    }
+}
+
+/// The guard of a SeqLock writer.
+pub struct SeqLockWriteGuard<'a, T: Copy> {
+    lock: &'a SeqLock<T>,
+    // The sequence number before locking.
+    seq: usize,
+}
+
+impl<T: Copy> Deref for SeqLockWriteGuard<'_, T> {
+    type Target = T;
+    fn deref(&self) -> &T {
+        unsafe { &*self.lock.value.get() }
+    }
+}
+
+impl<T: Copy> DerefMut for SeqLockWriteGuard<'_, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        unsafe { &mut *self.lock.value.get() }
+    }
+}
+
+impl<T: Copy> Drop for SeqLockWriteGuard<'_, T> {
+    fn drop(&mut self) {
+        self.lock
+            .seq
+            .store(self.seq.wrapping_add(2), Ordering::Release);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::SeqLock;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::thread;
+
+    #[test]
+    fn test_seqlock() {
+        let lock = SeqLock::new((1, 2));
+        assert_eq!(lock.read(), (1, 2));
+        let mut guard = lock.write();
+        guard.0 = 3;
+        // Readers don't get a value while the writer is active.
+        assert_eq!(lock.try_read(), None);
+        drop(guard);
+        assert_eq!(lock.try_read(), Some((3, 2)));
+        *lock.write() = (5, 6);
+        assert_eq!(lock.into_inner(), (5, 6));
+    }
+
+    #[test]
+    fn test_readers_never_see_torn_values() {
+        // A timestamp in seconds and nanoseconds, whose halves are always updated together.
+        static CLOCK: SeqLock<(u64, u64)> = SeqLock::new((0, 0));
+        let done = AtomicBool::new(false);
+        thread::scope(|s| {
+            for _ in 0..4 {
+                s.spawn(|| {
+                    while !done.load(Ordering::Relaxed) {
+                        let (secs, nanos) = CLOCK.read();
+                        assert_eq!(secs * 1_000_000_000, nanos);
+                    }
+                });
+            }
+            for _ in 0..2 {
+                s.spawn(|| {
+                    for _ in 0..10_000 {
+                        let mut now = CLOCK.write();
+                        now.0 += 1;
+                        now.1 = now.0 * 1_000_000_000;
+                    }
+                });
+            }
+            s.spawn(|| {
+                while CLOCK.read().0 < 20_000 {
+                    thread::yield_now();
+                }
+                done.store(true, Ordering::Relaxed);
+            });
+        });
+        assert_eq!(CLOCK.read(), (20_000, 20_000_000_000_000));
+    }
+}
@@ -0,0 +1,220 @@
+use crate::lock_api::{self, GuardNoSend};
+use crate::rwlock::backoff;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+
+const SHARDS: usize = 16;
+
+thread_local! {
+    // The shard the current thread counts itself in as a reader, assigned round-robin.
+    static SHARD: usize = {
+        static NEXT: AtomicUsize = AtomicUsize::new(0);
+        NEXT.fetch_add(1, Ordering::Relaxed) % SHARDS
+    };
+}
+
+/// A reader-writer lock for read-mostly data. With RwLock, every reader writes the one
+/// counter of the lock, so readers on different cores keep taking its cache line from each
+/// other. Here the reader count is spread over shards, each in its own cache line, and a
+/// thread only writes the counter of its shard. Writers pay for it: they have to look at
+/// every shard. Like RwLock, it prefers writers.
+pub type ShardedRwLock<T> = lock_api::RwLock<RawShardedRwLock, T>;
+
+pub type ShardedRwLockReadGuard<'a, T> = lock_api::RwLockReadGuard<'a, RawShardedRwLock, T>;
+
+pub type ShardedRwLockWriteGuard<'a, T> = lock_api::RwLockWriteGuard<'a, RawShardedRwLock, T>;
+
+/// The raw lock of ShardedRwLock.
+pub struct RawShardedRwLock {
+    // Set by a writer that holds the lock or waits for the readers to leave.
+    writer: AtomicBool,
+    readers: [Shard; SHARDS],
+}
+
+// Aligned to its own cache line (two on some CPUs, which prefetch lines in pairs).
+#[repr(align(128))]
+struct Shard(AtomicUsize);
+
+unsafe impl lock_api::RawRwLock for RawShardedRwLock {
+    const INIT: RawShardedRwLock = RawShardedRwLock {
+        writer: AtomicBool::new(false),
+        readers: [const { Shard(AtomicUsize::new(0)) }; SHARDS],
+    };
+
+    // A reader has to be released from the shard of the thread that took it.
+    type GuardMarker = GuardNoSend;
+
+    fn lock_shared(&self) {
+        let mut spins = 0_u32;
+        while !self.try_lock_shared() {
+            // Wait for the writer without touching our shard, which it is waiting on.
+            while self.writer.load(Ordering::Relaxed) {
+                backoff(&mut spins);
+            }
+        }
+    }
+
+    fn try_lock_shared(&self) -> bool {
+        let shard = &self.readers[SHARD.with(|shard| *shard)].0;
+        // Count ourselves in first, then check for a writer. A writer does the opposite, and
+        // with SeqCst at least one of the two sees the other.
+        shard.fetch_add(1, Ordering::SeqCst);
+        if self.writer.load(Ordering::SeqCst) {
+            shard.fetch_sub(1, Ordering::Release);
+            return false;
+        }
+        true
+    }
+
+    unsafe fn unlock_shared(&self) {
+        let prev_value = self.readers[SHARD.with(|shard| *shard)]
+            .0
+            .fetch_sub(1, Ordering::Release);
+        assert!(prev_value > 0);
+    }
+
+    fn lock_exclusive(&self) {
+        let mut spins = 0_u32;
+        while self
+            .writer
+            .compare_exchange_weak(false, true, Ordering::SeqCst, Ordering::Relaxed)
+            .is_err()
+        {
+            backoff(&mut spins);
+        }
+        // New readers back off now, wait for the ones already in.
+        for shard in &self.readers {
+            while shard.0.load(Ordering::SeqCst) != 0 {
+                backoff(&mut spins);
+            }
+        }
+    }
+
+    fn try_lock_exclusive(&self) -> bool {
+        if self
+            .writer
+            .compare_exchange(false, true, Ordering::SeqCst, Ordering::Relaxed)
+            .is_err()
+        {
+            return false;
+        }
+        if self
+            .readers
+            .iter()
+            .any(|shard| shard.0.load(Ordering::SeqCst) != 0)
+        {
+            self.writer.store(false, Ordering::Release);
+            return false;
+        }
+        true
+    }
+
+    unsafe fn unlock_exclusive(&self) {
+        self.writer.store(false, Ordering::Release);
+    }
+
+    fn is_locked(&self) -> bool {
+        self.writer.load(Ordering::Relaxed)
+            || self
+                .readers
+                .iter()
+                .any(|shard| shard.0.load(Ordering::Relaxed) != 0)
+    }
+
+    fn is_locked_exclusive(&self) -> bool {
+        // A writer still waiting for readers doesn't hold the lock yet.
+        self.writer.load(Ordering::Relaxed)
+            && self
+                .readers
+                .iter()
+                .all(|shard| shard.0.load(Ordering::Relaxed) == 0)
+    }
+
+    fn reader_count(&self) -> usize {
+        // Readers backing off from a writer are counted for a moment.
+        self.readers
+            .iter()
+            .map(|shard| shard.0.load(Ordering::Relaxed))
+            .sum()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{RawShardedRwLock, SHARDS, ShardedRwLock};
+    use crate::rwlock::RwLock;
+    use std::sync::Arc;
+    use std::thread;
+    use std::time::SystemTime;
+
+    #[test]
+    fn test_sharded_rwlock() {
+        assert!(size_of::<RawShardedRwLock>() >= SHARDS * 128);
+        let lock = ShardedRwLock::new(1);
+        let r1 = lock.read();
+        let r2 = thread::scope(|s| s.spawn(|| *lock.read()).join().unwrap());
+        assert_eq!(*r1, r2);
+        assert!(lock.try_write().is_none());
+        assert!(lock.is_locked() && !lock.is_locked_exclusive());
+        assert_eq!(lock.reader_count(), 1);
+        drop(r1);
+        *lock.write() += 1;
+        let w = lock.try_write().unwrap();
+        assert!(lock.is_locked_exclusive());
+        assert!(lock.try_read().is_none());
+        drop(w);
+        assert!(!lock.is_locked());
+        assert_eq!(lock.into_inner(), 2);
+    }
+
+    #[test]
+    fn test_writers_and_readers() {
+        // The two halves of the pair are only ever seen equal by readers.
+        let lock = ShardedRwLock::new((0usize, 0usize));
+        thread::scope(|s| {
+            for _ in 0..4 {
+                s.spawn(|| {
+                    for _ in 0..1000 {
+                        let mut w = lock.write();
+                        w.0 += 1;
+                        thread::yield_now();
+                        w.1 += 1;
+                    }
+                });
+                s.spawn(|| {
+                    for _ in 0..10_000 {
+                        let r = lock.read();
+                        assert_eq!(r.0, r.1);
+                    }
+                });
+            }
+        });
+        assert_eq!(lock.into_inner(), (4000, 4000));
+    }
+
+    // Runs 8 threads doing 100k reads each, returns the time in ms.
+    fn read_heavy<L: Send + Sync + 'static>(lock: L, read: fn(&L) -> usize) -> u128 {
+        let time = SystemTime::now();
+        let lock = Arc::new(lock);
+        let handles: Vec<_> = (0..8)
+            .map(|_| {
+                let lock = Arc::clone(&lock);
+                thread::spawn(move || {
+                    for _ in 0..100_000 {
+                        assert_eq!(read(&lock), 7);
+                    }
+                })
+            })
+            .collect();
+        for h in handles {
+            h.join().unwrap();
+        }
+        time.elapsed().unwrap().as_millis()
+    }
+
+    #[test]
+    fn test_sharded_rwlock_read_heavy_vs_rwlock() {
+        let sharded = read_heavy(ShardedRwLock::new(7), |l| *l.read());
+        let rwlock = read_heavy(RwLock::new(7), |l| *l.read());
+        println!("Time taken in ShardedRwLock: {sharded}ms, in RwLock: {rwlock}ms");
+    }
+}
@@ -0,0 +1,380 @@
+use crate::lock_api::{self, GuardNoSend};
+use linux_futex::{Futex, Shared};
+use std::cell::Cell;
+use std::mem::offset_of;
+use std::ptr;
+use std::sync::Once;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+// The bits of the lock word defined by the kernel, the rest is the owner's thread id.
+const WAITERS: u32 = 0x8000_0000;
+const OWNER_DIED: u32 = 0x4000_0000;
+const TID_MASK: u32 = 0x3fff_ffff;
+
+/// A mutex that can live in memory shared between processes (e.g. a MAP_SHARED mapping),
+/// and that survives the death of its owner: the lock word holds the owner's thread id, and
+/// every thread registers the locks it holds with the kernel (set_robust_list). When a thread
+/// exits or is killed holding one, the kernel marks the lock OWNER_DIED and wakes a waiter,
+/// which takes the lock over. The data may have been left half-updated, so the new owner is
+/// told through SharedFutexMutexGuard::owner_died, until it calls mark_consistent: the
+/// EOWNERDEAD protocol of robust pthread mutexes.
+///
+/// The kernel keeps one robust list per thread, and the one the C library registers for its
+/// own robust mutexes is replaced in the threads that take a SharedFutexMutex.
+///
+/// The data is shared as is, so it must not hold pointers, which are only valid in one
+/// process. The mutex is put in place with ptr::write.
+///
+/// While locked, the mutex is linked into the robust list of the thread holding it, so it
+/// must stay at the same address until unlocked: see new.
+pub struct SharedFutexMutex<T> {
+    mutex: lock_api::Mutex<RawSharedFutexMutex, T>,
+}
+
+impl<T> SharedFutexMutex<T> {
+    /// # Safety
+    ///
+    /// While the mutex is locked, it must not be moved, nor freed other than by being
+    /// dropped by the thread holding the lock. A lock held past a guard's lifetime, with
+    /// mem::forget or a raw lock, would otherwise leave its thread's robust list pointing
+    /// at the old address, which the kernel and the next lock of the thread write to.
+    pub const unsafe fn new(value: T) -> Self {
+        SharedFutexMutex {
+            mutex: lock_api::Mutex::new(value),
+        }
+    }
+
+    pub fn get_mut(&mut self) -> &mut T {
+        self.mutex.get_mut()
+    }
+
+    pub fn into_inner(self) -> T {
+        self.mutex.into_inner()
+    }
+}
+
+impl<T> std::ops::Deref for SharedFutexMutex<T> {
+    type Target = lock_api::Mutex<RawSharedFutexMutex, T>;
+    fn deref(&self) -> &Self::Target {
+        &self.mutex
+    }
+}
+
+pub type SharedFutexMutexGuard<'a, T> = lock_api::MutexGuard<'a, RawSharedFutexMutex, T>;
+
+/// The raw lock of SharedFutexMutex.
+#[repr(C)]
+pub struct RawSharedFutexMutex {
+    futex: Futex<Shared>,
+    // Set when the lock is taken over from a dead owner, until mark_consistent.
+    owner_died: AtomicBool,
+    // The lock's entry in the robust list of the thread holding it.
+    link: Link,
+}
+
+// Only the thread holding the lock touches its link.
+unsafe impl Sync for RawSharedFutexMutex {}
+unsafe impl Send for RawSharedFutexMutex {}
+
+// A robust list entry: the kernel follows next, and finds the lock word at FUTEX_OFFSET.
+#[repr(C)]
+struct Link {
+    next: Cell<*const Link>,
+    // Null for the first entry, to unlink the locks in any order.
+    prev: Cell<*const Link>,
+}
+
+const FUTEX_OFFSET: isize = offset_of!(RawSharedFutexMutex, futex) as isize
+    - offset_of!(RawSharedFutexMutex, link) as isize;
+
+// struct robust_list_head of the kernel. The list ends with a link back to the head.
+#[repr(C)]
+struct RobustListHead {
+    list: Cell<*const Link>,
+    futex_offset: isize,
+    // The lock being locked or unlocked, which may be out of the list when the thread dies.
+    list_op_pending: Cell<*const Link>,
+}
+
+thread_local! {
+    // Registered with the kernel on the first lock, when tid is set.
+    static HEAD: RobustListHead = const {
+        RobustListHead {
+            list: Cell::new(ptr::null()),
+            futex_offset: FUTEX_OFFSET,
+            list_op_pending: Cell::new(ptr::null()),
+        }
+    };
+    static TID: Cell<u32> = const { Cell::new(0) };
+}
+
+// Registers the current thread's robust list if it isn't yet, and runs f with it and the
+// thread id.
+fn with_robust_list<R>(f: impl FnOnce(&RobustListHead, u32) -> R) -> R {
+    HEAD.with(|head| {
+        let mut tid = TID.get();
+        if tid == 0 {
+            static AT_FORK: Once = Once::new();
+            // The child of a fork is a new thread, with a copy of our thread locals.
+            AT_FORK.call_once(|| unsafe {
+                libc::pthread_atfork(None, None, Some(forget_robust_list));
+            });
+            let end = ptr::from_ref(head).cast::<Link>();
+            head.list.set(end);
+            let size = size_of::<RobustListHead>();
+            let result =
+                unsafe { libc::syscall(libc::SYS_set_robust_list, ptr::from_ref(head), size) };
+            assert_eq!(result, 0, "set_robust_list failed");
+            tid = unsafe { libc::syscall(libc::SYS_gettid) } as u32;
+            TID.set(tid);
+        }
+        f(head, tid)
+    })
+}
+
+extern "C" fn forget_robust_list() {
+    TID.set(0);
+}
+
+unsafe impl lock_api::RawMutex for RawSharedFutexMutex {
+    const INIT: RawSharedFutexMutex = RawSharedFutexMutex {
+        futex: Futex::new(0),
+        owner_died: AtomicBool::new(false),
+        link: Link {
+            next: Cell::new(ptr::null()),
+            prev: Cell::new(ptr::null()),
+        },
+    };
+
+    // The lock is in the robust list of the thread that took it.
+    type GuardMarker = GuardNoSend;
+
+    fn lock(&self) {
+        with_robust_list(|head, tid| {
+            head.list_op_pending.set(&self.link);
+            // Once we have slept, others may be sleeping too, so the lock is taken with
+            // WAITERS set, to wake the next one on unlock.
+            let mut waiters = 0;
+            while !self.acquire(tid, waiters) {
+                let value = self.futex.value.load(Ordering::Relaxed);
+                if value & TID_MASK == 0
+                    || (value & WAITERS == 0
+                        && self
+                            .futex
+                            .value
+                            .compare_exchange(
+                                value,
+                                value | WAITERS,
+                                Ordering::Relaxed,
+                                Ordering::Relaxed,
+                            )
+                            .is_err())
+                {
+                    continue;
+                }
+                let _ = self.futex.wait(value | WAITERS);
+                waiters = WAITERS;
+            }
+            self.push(head);
+            head.list_op_pending.set(ptr::null());
+        });
+    }
+
+    fn try_lock(&self) -> bool {
+        with_robust_list(|head, tid| {
+            head.list_op_pending.set(&self.link);
+            let locked = self.acquire(tid, 0);
+            if locked {
+                self.push(head);
+            }
+            head.list_op_pending.set(ptr::null());
+            locked
+        })
+    }
+
+    unsafe fn unlock(&self) {
+        with_robust_list(|head, _| {
+            head.list_op_pending.set(&self.link);
+            self.remove(head);
+            if self.futex.value.swap(0, Ordering::Release) & WAITERS != 0 {
+                self.futex.wake(1);
+            }
+            head.list_op_pending.set(ptr::null());
+        });
+    }
+
+    fn is_locked(&self) -> bool {
+        self.futex.value.load(Ordering::Relaxed) & TID_MASK != 0
+    }
+}
+
+impl Drop for RawSharedFutexMutex {
+    fn drop(&mut self) {
+        // Still locked by this thread, after a guard was forgotten: leave its robust list,
+        // which would otherwise point into freed memory.
+        let owner = self.futex.value.load(Ordering::Relaxed) & TID_MASK;
+        if owner != 0 && owner == TID.get() {
+            HEAD.with(|head| self.remove(head));
+        }
+    }
+}
+
+impl RawSharedFutexMutex {
+    // Takes the lock if it has no owner: it is free, or its owner died and the kernel
+    // cleared the owner and set OWNER_DIED.
+    fn acquire(&self, tid: u32, waiters: u32) -> bool {
+        let value = self.futex.value.load(Ordering::Relaxed);
+        if value & TID_MASK != 0 {
+            return false;
+        }
+        let locked = tid | waiters | (value & WAITERS);
+        if self
+            .futex
+            .value
+            .compare_exchange(value, locked, Ordering::Acquire, Ordering::Relaxed)
+            .is_err()
+        {
+            return false;
+        }
+        if value & OWNER_DIED != 0 {
+            self.owner_died.store(true, Ordering::Relaxed);
+        }
+        true
+    }
+
+    fn push(&self, head: &RobustListHead) {
+        let first = head.list.get();
+        self.link.next.set(first);
+        self.link.prev.set(ptr::null());
+        if first != ptr::from_ref(head).cast() {
+            unsafe { (*first).prev.set(&self.link) };
+        }
+        head.list.set(&self.link);
+    }
+
+    fn remove(&self, head: &RobustListHead) {
+        let (next, prev) = (self.link.next.get(), self.link.prev.get());
+        match unsafe { prev.as_ref() } {
+            None => head.list.set(next),
+            Some(prev) => prev.next.set(next),
+        }
+        if next != ptr::from_ref(head).cast() {
+            unsafe { (*next).prev.set(prev) };
+        }
+    }
+}
+
+impl<T> lock_api::MutexGuard<'_, RawSharedFutexMutex, T> {
+    /// Returns true if the lock was taken over from a thread or process that died holding
+    /// it, and the data hasn't been marked consistent since.
+    pub fn owner_died(this: &Self) -> bool {
+        this.mutex.raw.owner_died.load(Ordering::Relaxed)
+    }
+
+    /// Tells the next owners that the data was checked or repaired after an owner died.
+    pub fn mark_consistent(this: &Self) {
+        this.mutex.raw.owner_died.store(false, Ordering::Relaxed);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{HEAD, RawSharedFutexMutex, SharedFutexMutex, SharedFutexMutexGuard};
+    use crate::lock_api::RawMutex;
+    use std::sync::atomic::Ordering;
+    use std::{mem, ptr, thread};
+
+    #[test]
+    fn test_shared_futex_mutex() {
+        let mutex = unsafe { SharedFutexMutex::new(0) };
+        thread::scope(|s| {
+            for _ in 0..4 {
+                s.spawn(|| {
+                    for _ in 0..10_000 {
+                        *mutex.lock() += 1;
+                    }
+                });
+            }
+        });
+        // Unlocked in any order.
+        let other = unsafe { SharedFutexMutex::new(()) };
+        let (a, b) = (mutex.lock(), other.lock());
+        assert!(other.try_lock().is_none());
+        drop(a);
+        drop(b);
+        assert!(!SharedFutexMutexGuard::owner_died(&mutex.lock()));
+        assert_eq!(mutex.into_inner(), 40_000);
+    }
+
+    #[test]
+    fn test_dropped_while_locked() {
+        let mutex = Box::new(unsafe { SharedFutexMutex::new(0) });
+        mem::forget(mutex.lock());
+        // Dropped by the thread holding it, it leaves the thread's robust list.
+        drop(mutex);
+        HEAD.with(|head| assert_eq!(head.list.get(), ptr::from_ref(head).cast()));
+        let other = unsafe { SharedFutexMutex::new(0) };
+        *other.lock() += 1;
+        assert_eq!(other.into_inner(), 1);
+    }
+
+    #[test]
+    fn test_thread_dies_holding_the_lock() {
+        let mutex = unsafe { SharedFutexMutex::new(0) };
+        let other = unsafe { SharedFutexMutex::new(0) };
+        thread::scope(|s| {
+            s.spawn(|| {
+                *other.lock() += 1;
+                let mut guard = mutex.lock();
+                *guard += 1;
+                mem::forget(guard);
+            });
+        });
+        // The kernel released it when the thread exited, and marked it.
+        let guard = mutex.lock();
+        assert!(SharedFutexMutexGuard::owner_died(&guard));
+        assert_eq!(*guard, 1);
+        SharedFutexMutexGuard::mark_consistent(&guard);
+        drop(guard);
+        assert!(!SharedFutexMutexGuard::owner_died(&mutex.lock()));
+        assert!(!SharedFutexMutexGuard::owner_died(&other.lock()));
+    }
+
+    #[test]
+    fn test_process_dies_holding_the_lock() {
+        let size = size_of::<SharedFutexMutex<u32>>();
+        let mutex = unsafe {
+            let memory = libc::mmap(
+                ptr::null_mut(),
+                size,
+                libc::PROT_READ | libc::PROT_WRITE,
+                libc::MAP_SHARED | libc::MAP_ANONYMOUS,
+                -1,
+                0,
+            );
+            assert_ne!(memory, libc::MAP_FAILED);
+            let mutex = memory.cast::<SharedFutexMutex<u32>>();
+            mutex.write(SharedFutexMutex::new(0));
+            &*mutex
+        };
+        let guard = mutex.lock();
+        let pid = unsafe { libc::fork() };
+        if pid == 0 {
+            // The child waits for the parent to unlock, then exits holding the lock. Only
+            // system calls here, another thread may have held a lock of the allocator.
+            mutex.raw.lock();
+            unsafe {
+                *mutex.data_ptr() = 7;
+                libc::_exit(0);
+            }
+        }
+        drop(guard);
+        let mut status = 0;
+        assert_eq!(unsafe { libc::waitpid(pid, &mut status, 0) }, pid);
+        let guard = mutex.lock();
+        assert!(SharedFutexMutexGuard::owner_died(&guard));
+        assert_eq!(*guard, 7);
+        drop(guard);
+        unsafe { libc::munmap(ptr::from_ref(mutex).cast_mut().cast(), size) };
+    }
+}
@@ -0,0 +1,95 @@
+use std::cell::UnsafeCell;
+
+/// An UnsafeCell which is Sync when T is Sync, so it can be shared between threads (for
+/// example in a static). It adds no synchronization: it is the building block for the thread-safe
+/// primitives of this crate, which pair it with an atomic protocol (a lock word, a sequence
+/// number, ...) deciding who may access the value and when.
+///
+/// # Safety contract
+/// As with UnsafeCell, the pointer returned by get can be freely created, but the user must
+/// make sure that while a &mut T derived from it exists, no other reference (shared or
+/// exclusive) to the value exists, on any thread. In addition, as the cell can be accessed from
+/// several threads, every access must be ordered by some synchronization (atomics, a lock...)
+/// with any concurrent write, or it is a data race.
+#[repr(transparent)]
+pub struct SyncUnsafeCell<T: ?Sized> {
+    value: UnsafeCell<T>,
+}
+
+unsafe impl<T: ?Sized + Sync> Sync for SyncUnsafeCell<T> {}
+
+impl<T> SyncUnsafeCell<T> {
+    pub const fn new(value: T) -> Self {
+        Self {
+            value: UnsafeCell::new(value),
+        }
+    }
+
+    pub fn into_inner(self) -> T {
+        self.value.into_inner()
+    }
+}
+
+impl<T: ?Sized> SyncUnsafeCell<T> {
+    /// Gets a mutable pointer to the wrapped value. See the safety contract on the type.
+    pub const fn get(&self) -> *mut T {
+        self.value.get()
+    }
+
+    /// Gets a mutable pointer to the wrapped value from a raw pointer to the cell, without
+    /// creating a reference to the cell (e.g. when the cell may not be fully initialized yet).
+    pub const fn raw_get(this: *const Self) -> *mut T {
+        // repr(transparent) makes the cell and its value share the same address.
+        this as *const T as *mut T
+    }
+
+    /// Returns a mutable reference to the value, &mut self already proves exclusivity.
+    pub fn get_mut(&mut self) -> &mut T {
+        self.value.get_mut()
+    }
+}
+
+impl<T: Default> Default for SyncUnsafeCell<T> {
+    fn default() -> Self {
+        Self::new(T::default())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::SyncUnsafeCell;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::thread;
+
+    #[test]
+    fn test_sync_unsafe_cell_in_static() {
+        // A minimal "publish once" protocol: the writer fills the cell, then releases the flag.
+        static DATA: SyncUnsafeCell<[u32; 4]> = SyncUnsafeCell::new([0; 4]);
+        static READY: AtomicBool = AtomicBool::new(false);
+
+        let writer = thread::spawn(|| {
+            // SAFETY: readers don't touch DATA before READY is set.
+            unsafe { *DATA.get() = [1, 2, 3, 4] };
+            READY.store(true, Ordering::Release);
+        });
+        let reader = thread::spawn(|| {
+            while !READY.load(Ordering::Acquire) {
+                std::hint::spin_loop();
+            }
+            // SAFETY: the Acquire load synchronizes with the write, which is never repeated.
+            unsafe { *DATA.get() }
+        });
+
+        writer.join().unwrap();
+        assert_eq!(reader.join().unwrap(), [1, 2, 3, 4]);
+    }
+
+    #[test]
+    fn test_raw_get() {
+        let cell = SyncUnsafeCell::new(5);
+        let ptr = SyncUnsafeCell::raw_get(&cell);
+        assert_eq!(ptr, cell.get());
+        unsafe { *ptr = 6 };
+        assert_eq!(cell.into_inner(), 6);
+    }
+}
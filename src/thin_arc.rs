@@ -0,0 +1,220 @@
+use crate::arc::check_refcount;
+use std::alloc::{Layout, alloc, dealloc, handle_alloc_error};
+use std::marker::PhantomData;
+use std::ops::Deref;
+use std::ptr::NonNull;
+use std::sync::atomic::{AtomicUsize, Ordering, fence};
+
+/// A thread-safe reference-counted pointer to a header followed by a slice, all in one
+/// allocation, and one pointer wide. It has no Weak support, which saves the weak count:
+///
+/// ```text
+/// ThinArc ──> [ count | header | len | item 0 | item 1 | ... ]
+/// ```
+///
+/// Meant for large amounts of immutable data (e.g. syntax tree nodes with their children),
+/// where both the handle size and the per-allocation overhead add up.
+pub struct ThinArc<H, T> {
+    // Thin pointer to the allocation, the length needed to rebuild the fat pointer to
+    // ThinArcInner<H, [T]> is read from the header.
+    ptr: NonNull<ThinArcInner<H, [T; 0]>>,
+    _marker: PhantomData<ThinArcInner<H, [T]>>,
+}
+
+unsafe impl<H: Send + Sync, T: Send + Sync> Send for ThinArc<H, T> {}
+unsafe impl<H: Send + Sync, T: Send + Sync> Sync for ThinArc<H, T> {}
+
+/// The data a ThinArc points to: a header and a slice of items.
+#[repr(C)]
+pub struct HeaderSlice<H, S: ?Sized> {
+    pub header: H,
+    // Only read through the thin pointer, the fat pointer carries the length too.
+    len: usize,
+    pub slice: S,
+}
+
+#[repr(C)]
+struct ThinArcInner<H, S: ?Sized> {
+    count: AtomicUsize,
+    data: HeaderSlice<H, S>,
+}
+
+impl<H, T> ThinArc<H, T> {
+    /// Layout of the allocation for `len` items, computed like repr(C) lays out
+    /// ThinArcInner<H, [T]>.
+    fn layout(len: usize) -> Layout {
+        let (data, _) = Layout::new::<H>()
+            .extend(Layout::new::<usize>())
+            .and_then(|(layout, _)| layout.extend(Layout::array::<T>(len)?))
+            .expect("ThinArc is too large");
+        let (layout, _) = Layout::new::<AtomicUsize>()
+            .extend(data.pad_to_align())
+            .expect("ThinArc is too large");
+        layout.pad_to_align()
+    }
+
+    /// Allocates a ThinArc holding `header` and the items produced by `items`.
+    /// If `items` panics, the allocation is leaked.
+    pub fn from_header_and_iter<I>(header: H, items: I) -> Self
+    where
+        I: ExactSizeIterator<Item = T>,
+    {
+        let len = items.len();
+        let layout = Self::layout(len);
+        let mem = unsafe { alloc(layout) };
+        if mem.is_null() {
+            handle_alloc_error(layout);
+        }
+        let thin = mem as *mut ThinArcInner<H, [T; 0]>;
+        unsafe {
+            let data = &raw mut (*thin).data.slice as *mut T;
+            let mut written = 0;
+            for item in items.take(len) {
+                data.add(written).write(item);
+                written += 1;
+            }
+            assert_eq!(written, len, "ThinArc got fewer items than announced");
+            (&raw mut (*thin).count).write(AtomicUsize::new(1));
+            (&raw mut (*thin).data.header).write(header);
+            (&raw mut (*thin).data.len).write(len);
+        }
+        ThinArc {
+            ptr: unsafe { NonNull::new_unchecked(thin) },
+            _marker: PhantomData,
+        }
+    }
+
+    /// Allocates a ThinArc holding `header` and clones of the items of `slice`.
+    pub fn from_header_and_slice(header: H, slice: &[T]) -> Self
+    where
+        T: Clone,
+    {
+        ThinArc::from_header_and_iter(header, slice.iter().cloned())
+    }
+
+    fn inner(&self) -> &ThinArcInner<H, [T]> {
+        let thin = self.ptr.as_ptr();
+        // The header and length are at the same offsets for [T; 0] and [T].
+        let len = unsafe { (*thin).data.len };
+        // Casting a slice pointer keeps its length as the metadata of the unsized struct.
+        unsafe { &*(std::ptr::slice_from_raw_parts(thin as *const T, len) as *const _) }
+    }
+
+    /// Gets the number of ThinArc pointers to this allocation.
+    pub fn strong_count(this: &ThinArc<H, T>) -> usize {
+        this.inner().count.load(Ordering::Relaxed)
+    }
+
+    /// Returns true if the two ThinArc pointers point to the same allocation.
+    pub fn ptr_eq(this: &ThinArc<H, T>, other: &ThinArc<H, T>) -> bool {
+        this.ptr == other.ptr
+    }
+}
+
+impl<H, T> Clone for ThinArc<H, T> {
+    fn clone(&self) -> Self {
+        check_refcount(self.inner().count.fetch_add(1, Ordering::Relaxed));
+        ThinArc {
+            ptr: self.ptr,
+            _marker: PhantomData,
+        }
+    }
+}
+
+impl<H, T> Deref for ThinArc<H, T> {
+    type Target = HeaderSlice<H, [T]>;
+    fn deref(&self) -> &Self::Target {
+        &self.inner().data
+    }
+}
+
+impl<H, T> Drop for ThinArc<H, T> {
+    fn drop(&mut self) {
+        if self.inner().count.fetch_sub(1, Ordering::Release) != 1 {
+            return;
+        }
+        fence(Ordering::Acquire);
+        let inner = self.inner() as *const ThinArcInner<H, [T]> as *mut ThinArcInner<H, [T]>;
+        unsafe {
+            let layout = Layout::for_value(&*inner);
+            debug_assert_eq!(layout, Self::layout((*inner).data.len));
+            // Drops the header and every item.
+            std::ptr::drop_in_place(&raw mut (*inner).data);
+            dealloc(inner as *mut u8, layout);
+        }
+    }
+}
+
+impl<H: std::fmt::Debug, S: ?Sized + std::fmt::Debug> std::fmt::Debug for HeaderSlice<H, S> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("HeaderSlice")
+            .field("header", &self.header)
+            .field("slice", &&self.slice)
+            .finish()
+    }
+}
+
+impl<H: std::fmt::Debug, T: std::fmt::Debug> std::fmt::Debug for ThinArc<H, T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        std::fmt::Debug::fmt(&**self, f)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::ThinArc;
+    use std::mem::size_of;
+    use std::thread;
+
+    #[test]
+    fn test_thin_arc_is_one_pointer() {
+        assert_eq!(size_of::<ThinArc<u8, u64>>(), size_of::<usize>());
+        assert_eq!(
+            size_of::<Option<ThinArc<String, String>>>(),
+            size_of::<usize>()
+        );
+    }
+
+    #[test]
+    fn test_header_and_slice() {
+        let a = ThinArc::from_header_and_iter(String::from("node"), vec![1_u16, 2, 3].into_iter());
+        assert_eq!(a.header, "node");
+        assert_eq!(a.slice, [1, 2, 3]);
+        let b = a.clone();
+        assert!(ThinArc::ptr_eq(&a, &b));
+        assert_eq!(ThinArc::strong_count(&a), 2);
+        assert_eq!(
+            format!("{b:?}"),
+            r#"HeaderSlice { header: "node", slice: [1, 2, 3] }"#
+        );
+
+        let empty = ThinArc::<(), u8>::from_header_and_slice((), &[]);
+        assert!(empty.slice.is_empty());
+
+        #[repr(align(64))]
+        #[derive(Clone, Debug, PartialEq)]
+        struct Aligned(u8);
+        let aligned = ThinArc::from_header_and_slice(1_u8, &[Aligned(1), Aligned(2)]);
+        assert_eq!(aligned.slice[1], Aligned(2));
+        assert_eq!(aligned.slice.as_ptr() as usize % 64, 0);
+    }
+
+    #[test]
+    fn test_shared_between_threads() {
+        let children: Vec<String> = (0..10).map(|i| i.to_string()).collect();
+        let a = ThinArc::from_header_and_slice(String::from("root"), &children);
+        let handles: Vec<_> = (0..8)
+            .map(|_| {
+                let a = a.clone();
+                thread::spawn(move || {
+                    assert_eq!(a.header, "root");
+                    assert_eq!(a.slice[9], "9");
+                })
+            })
+            .collect();
+        for h in handles {
+            h.join().unwrap();
+        }
+        assert_eq!(ThinArc::strong_count(&a), 1);
+    }
+}
@@ -0,0 +1,239 @@
+use crate::cell::Cell;
+use crate::rc::checked_increment;
+use std::alloc::{Layout, alloc, dealloc, handle_alloc_error};
+use std::marker::PhantomData;
+use std::ops::Deref;
+use std::ptr::NonNull;
+
+/// A single-threaded reference-counted pointer to a slice or str that is one pointer wide.
+/// Rc<[T]> and Rc<str> are fat pointers carrying the length next to the address, ThinRc
+/// instead stores the length in the heap header, right after the count:
+///
+/// ```text
+/// ThinRc ──> [ owner_count | len | item 0 | item 1 | ... ]
+/// ```
+///
+/// Reading the length costs a pointer chase, but it halves the size of every handle,
+/// which matters when a table stores millions of shared strings.
+pub struct ThinRc<T: ?Sized + ThinDst> {
+    ptr: NonNull<ThinHeader>,
+    _marker: PhantomData<T>,
+}
+
+#[repr(C)]
+struct ThinHeader {
+    owner_count: Cell<usize>,
+    len: usize,
+}
+
+/// Unsized types that a ThinRc can point to: a run of `len` items stored inline.
+///
+/// # Safety
+/// `from_raw_parts` must return a pointer to exactly `len` items starting at `data`.
+pub unsafe trait ThinDst {
+    type Item;
+
+    fn from_raw_parts(data: *const Self::Item, len: usize) -> *const Self;
+}
+
+unsafe impl<T> ThinDst for [T] {
+    type Item = T;
+
+    fn from_raw_parts(data: *const T, len: usize) -> *const [T] {
+        std::ptr::slice_from_raw_parts(data, len)
+    }
+}
+
+unsafe impl ThinDst for str {
+    type Item = u8;
+
+    fn from_raw_parts(data: *const u8, len: usize) -> *const str {
+        std::ptr::slice_from_raw_parts(data, len) as *const str
+    }
+}
+
+impl<T: ?Sized + ThinDst> ThinRc<T> {
+    /// Layout of the allocation and offset of the first item, for `len` items.
+    fn layout(len: usize) -> (Layout, usize) {
+        let (layout, offset) = Layout::new::<ThinHeader>()
+            .extend(Layout::array::<T::Item>(len).expect("ThinRc is too large"))
+            .expect("ThinRc is too large");
+        (layout.pad_to_align(), offset)
+    }
+
+    /// Allocates a ThinRc and fills it with the `len` items produced by `items`.
+    /// If `items` yields fewer items or panics, the allocation is leaked.
+    fn from_items(len: usize, items: impl Iterator<Item = T::Item>) -> Self {
+        let (layout, offset) = Self::layout(len);
+        let mem = unsafe { alloc(layout) };
+        if mem.is_null() {
+            handle_alloc_error(layout);
+        }
+        unsafe {
+            let data = mem.add(offset) as *mut T::Item;
+            let mut written = 0;
+            for item in items.take(len) {
+                data.add(written).write(item);
+                written += 1;
+            }
+            assert_eq!(written, len, "ThinRc got fewer items than announced");
+            (mem as *mut ThinHeader).write(ThinHeader {
+                owner_count: Cell::new(1),
+                len,
+            });
+        }
+        ThinRc {
+            ptr: unsafe { NonNull::new_unchecked(mem as *mut ThinHeader) },
+            _marker: PhantomData,
+        }
+    }
+
+    fn header(&self) -> &ThinHeader {
+        unsafe { self.ptr.as_ref() }
+    }
+
+    fn data(&self) -> *mut T::Item {
+        let (_, offset) = Self::layout(self.header().len);
+        unsafe { (self.ptr.as_ptr() as *mut u8).add(offset) as *mut T::Item }
+    }
+
+    /// Gets the number of ThinRc pointers to this allocation.
+    pub fn strong_count(this: &ThinRc<T>) -> usize {
+        this.header().owner_count.get()
+    }
+
+    /// Returns true if the two ThinRc pointers point to the same allocation.
+    pub fn ptr_eq(this: &ThinRc<T>, other: &ThinRc<T>) -> bool {
+        this.ptr == other.ptr
+    }
+}
+
+impl<T: ?Sized + ThinDst> Clone for ThinRc<T> {
+    fn clone(&self) -> Self {
+        let header = self.header();
+        header
+            .owner_count
+            .set(checked_increment(header.owner_count.get()));
+        ThinRc {
+            ptr: self.ptr,
+            _marker: PhantomData,
+        }
+    }
+}
+
+impl<T: ?Sized + ThinDst> Deref for ThinRc<T> {
+    type Target = T;
+    fn deref(&self) -> &T {
+        // The fat pointer is rebuilt from the length stored in the header.
+        unsafe { &*T::from_raw_parts(self.data(), self.header().len) }
+    }
+}
+
+impl<T: ?Sized + ThinDst> Drop for ThinRc<T> {
+    fn drop(&mut self) {
+        let header = self.header();
+        let c = header.owner_count.get() - 1;
+        header.owner_count.set(c);
+
+        if c == 0 {
+            let len = header.len;
+            unsafe {
+                std::ptr::drop_in_place(std::ptr::slice_from_raw_parts_mut(self.data(), len));
+                dealloc(self.ptr.as_ptr() as *mut u8, Self::layout(len).0);
+            }
+        }
+    }
+}
+
+impl<T: Clone> From<&[T]> for ThinRc<[T]> {
+    fn from(slice: &[T]) -> Self {
+        ThinRc::from_items(slice.len(), slice.iter().cloned())
+    }
+}
+
+impl<T> From<Vec<T>> for ThinRc<[T]> {
+    fn from(vec: Vec<T>) -> Self {
+        ThinRc::from_items(vec.len(), vec.into_iter())
+    }
+}
+
+impl From<&str> for ThinRc<str> {
+    fn from(s: &str) -> Self {
+        // The bytes come from a valid str, so Deref can hand them out as one.
+        ThinRc::from_items(s.len(), s.bytes())
+    }
+}
+
+impl From<String> for ThinRc<str> {
+    fn from(s: String) -> Self {
+        ThinRc::from(s.as_str())
+    }
+}
+
+impl<T: ?Sized + ThinDst + PartialEq> PartialEq for ThinRc<T> {
+    fn eq(&self, other: &ThinRc<T>) -> bool {
+        **self == **other
+    }
+}
+
+impl<T: ?Sized + ThinDst + Eq> Eq for ThinRc<T> {}
+
+impl<T: ?Sized + ThinDst + std::hash::Hash> std::hash::Hash for ThinRc<T> {
+    fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
+        (**self).hash(state)
+    }
+}
+
+impl<T: ?Sized + ThinDst + std::fmt::Debug> std::fmt::Debug for ThinRc<T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        std::fmt::Debug::fmt(&**self, f)
+    }
+}
+
+impl std::fmt::Display for ThinRc<str> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        std::fmt::Display::fmt(&**self, f)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::ThinRc;
+    use std::mem::size_of;
+
+    #[test]
+    fn test_thin_rc_is_one_pointer() {
+        assert_eq!(size_of::<ThinRc<str>>(), size_of::<usize>());
+        assert_eq!(size_of::<ThinRc<[u64]>>(), size_of::<usize>());
+        assert_eq!(size_of::<Option<ThinRc<str>>>(), size_of::<usize>());
+    }
+
+    #[test]
+    fn test_thin_rc_str() {
+        let a = ThinRc::<str>::from("hello");
+        let b = a.clone();
+        assert_eq!(&*a, "hello");
+        assert!(ThinRc::ptr_eq(&a, &b));
+        assert_eq!(ThinRc::strong_count(&a), 2);
+        assert_eq!(a, ThinRc::from(String::from("hello")));
+        assert_eq!(format!("{a} {b:?}"), "hello \"hello\"");
+    }
+
+    #[test]
+    fn test_thin_rc_slice() {
+        let a = ThinRc::<[String]>::from(vec!["a".to_string(), "b".to_string()]);
+        let b = ThinRc::<[String]>::from(&a[..]);
+        assert_eq!(a, b);
+        assert!(!ThinRc::ptr_eq(&a, &b));
+
+        let empty = ThinRc::<[u8]>::from(Vec::new());
+        assert!(empty.is_empty());
+
+        #[repr(align(64))]
+        #[derive(Clone, Debug, PartialEq)]
+        struct Aligned(u8);
+        let aligned = ThinRc::<[Aligned]>::from(vec![Aligned(1), Aligned(2)]);
+        assert_eq!(aligned[1], Aligned(2));
+        assert_eq!(aligned.as_ptr() as usize % 64, 0);
+    }
+}
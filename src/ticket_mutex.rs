@@ -0,0 +1,135 @@
+use crate::lock_api::{self, GuardSend};
+use std::hint::spin_loop;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::thread;
+
+/// A fair mutex: threads get the lock in the order they asked for it, like customers taking
+/// a numbered ticket at a counter. `next_ticket` hands out the tickets and `now_serving`
+/// says whose turn it is.
+///
+/// Unlike Mutex, a thread can't barge in and take the lock while others are waiting, which
+/// rules out starvation but costs throughput: the lock sits idle until the next thread in
+/// line is scheduled, even if another one is running and ready to take it.
+pub type TicketMutex<T> = lock_api::Mutex<RawTicketMutex, T>;
+
+pub type TicketMutexGuard<'a, T> = lock_api::MutexGuard<'a, RawTicketMutex, T>;
+
+/// The raw lock of TicketMutex.
+pub struct RawTicketMutex {
+    next_ticket: AtomicUsize,
+    now_serving: AtomicUsize,
+}
+
+unsafe impl lock_api::RawMutex for RawTicketMutex {
+    const INIT: RawTicketMutex = RawTicketMutex {
+        next_ticket: AtomicUsize::new(0),
+        now_serving: AtomicUsize::new(0),
+    };
+
+    type GuardMarker = GuardSend;
+
+    fn lock(&self) {
+        // Wrapping is fine, tickets are only compared for equality.
+        let ticket = self.next_ticket.fetch_add(1, Ordering::Relaxed);
+        let mut spins = 0_u32;
+        while self.now_serving.load(Ordering::Acquire) != ticket {
+            spins = spins.wrapping_add(1);
+            // Let the threads ahead in line run if they were preempted.
+            if spins.is_multiple_of(64) {
+                thread::yield_now();
+            } else {
+                spin_loop();
+            }
+        }
+    }
+
+    fn try_lock(&self) -> bool {
+        let serving = self.now_serving.load(Ordering::Relaxed);
+        // Only take a ticket if it is served right away.
+        self.next_ticket
+            .compare_exchange(
+                serving,
+                serving.wrapping_add(1),
+                Ordering::Acquire,
+                Ordering::Relaxed,
+            )
+            .is_ok()
+    }
+
+    unsafe fn unlock(&self) {
+        // Only the lock holder writes now_serving, so this doesn't need a read-modify-write.
+        let serving = self.now_serving.load(Ordering::Relaxed);
+        self.now_serving
+            .store(serving.wrapping_add(1), Ordering::Release);
+    }
+
+    fn is_locked(&self) -> bool {
+        // Some ticket handed out hasn't been served yet.
+        self.now_serving.load(Ordering::Relaxed) != self.next_ticket.load(Ordering::Relaxed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::TicketMutex;
+    use std::sync::Arc;
+    use std::sync::atomic::Ordering;
+    use std::thread;
+    use std::time::SystemTime;
+
+    #[test]
+    fn test_lock_and_try_lock() {
+        let mutex = TicketMutex::new(0);
+        let guard = mutex.lock();
+        assert!(mutex.try_lock().is_none());
+        assert!(mutex.is_locked());
+        drop(guard);
+        assert!(!mutex.is_locked());
+        *mutex.try_lock().unwrap() += 1;
+        assert_eq!(mutex.into_inner(), 1);
+    }
+
+    #[test]
+    fn test_fifo_order() {
+        let mutex = Arc::new(TicketMutex::new(Vec::new()));
+        let guard = mutex.lock();
+        let mut handles = vec![];
+        for i in 0..8 {
+            let m = Arc::clone(&mutex);
+            handles.push(thread::spawn(move || m.lock().push(i)));
+            // Wait for thread i to take its ticket before starting the next one.
+            while mutex.raw.next_ticket.load(Ordering::Relaxed) != i + 2 {
+                thread::yield_now();
+            }
+        }
+        drop(guard);
+        for h in handles {
+            h.join().unwrap();
+        }
+        assert_eq!(*mutex.lock(), (0..8).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn test_ticket_mutex_contention_increment() {
+        let time = SystemTime::now();
+        let mutex = Arc::new(TicketMutex::new(0usize));
+        let handles: Vec<_> = (0..8)
+            .map(|_| {
+                let m = Arc::clone(&mutex);
+                thread::spawn(move || {
+                    for _ in 0..10_000 {
+                        *m.lock() += 1;
+                    }
+                })
+            })
+            .collect();
+        for h in handles {
+            h.join().unwrap();
+        }
+        assert_eq!(*mutex.lock(), 80_000);
+        println!(
+            "Time taken in TicketMutex: {}ms",
+            time.elapsed().unwrap().as_millis()
+        );
+    }
+}
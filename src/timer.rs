@@ -0,0 +1,149 @@
+use crate::mutex::Mutex;
+use crate::once_lock::Once;
+use crate::platform;
+use std::collections::BTreeMap;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicU32, AtomicU64, Ordering};
+use std::task::{Context, Poll, Waker};
+use std::thread;
+use std::time::{Duration, Instant};
+
+// The timer used by the timeouts of the async primitives, so that they don't depend on the
+// timer of any runtime: a thread, started on first use, which sleeps on a futex until the
+// earliest deadline and wakes the tasks whose deadline has passed.
+struct Timer {
+    // The wakers of the pending sleeps, by deadline and then id.
+    sleeps: Mutex<BTreeMap<(Instant, u64), Waker>>,
+    next_id: AtomicU64,
+    // Bumped when a sleep becomes the earliest, to wake the thread up.
+    seq: AtomicU32,
+    started: Once,
+}
+
+static TIMER: Timer = Timer {
+    sleeps: Mutex::new(BTreeMap::new()),
+    next_id: AtomicU64::new(0),
+    seq: AtomicU32::new(0),
+    started: Once::new(),
+};
+
+impl Timer {
+    fn run(&self) {
+        loop {
+            let seq = self.seq.load(Ordering::Acquire);
+            let mut sleeps = self.sleeps.lock();
+            let now = Instant::now();
+            let mut wakers = Vec::new();
+            while let Some(entry) = sleeps.first_entry() {
+                if entry.key().0 > now {
+                    break;
+                }
+                wakers.push(entry.remove());
+            }
+            let next = sleeps.first_key_value().map(|(&(deadline, _), _)| deadline);
+            drop(sleeps);
+            wakers.into_iter().for_each(Waker::wake);
+            match next {
+                Some(deadline) => platform::wait_timeout(
+                    &self.seq,
+                    seq,
+                    deadline.saturating_duration_since(Instant::now()),
+                ),
+                None => platform::wait(&self.seq, seq),
+            }
+        }
+    }
+
+    fn register(&'static self, deadline: Instant, waker: &Waker) -> (Instant, u64) {
+        self.started.call_once(|| {
+            thread::Builder::new()
+                .name("pointers-timer".to_string())
+                .spawn(|| self.run())
+                .expect("failed to spawn the timer thread");
+        });
+        let key = (deadline, self.next_id.fetch_add(1, Ordering::Relaxed));
+        let mut sleeps = self.sleeps.lock();
+        sleeps.insert(key, waker.clone());
+        let earliest = sleeps
+            .first_key_value()
+            .is_some_and(|(&first, _)| first == key);
+        drop(sleeps);
+        if earliest {
+            self.seq.fetch_add(1, Ordering::Release);
+            platform::wake_one(&self.seq);
+        }
+        key
+    }
+}
+
+/// A future which completes once `timeout` has elapsed, for any executor.
+pub(crate) struct Sleep {
+    // None when the timeout is too long to be represented, the sleep never completes.
+    deadline: Option<Instant>,
+    // The entry in the timer, once registered.
+    key: Option<(Instant, u64)>,
+}
+
+impl Sleep {
+    pub(crate) fn new(timeout: Duration) -> Sleep {
+        Sleep {
+            deadline: Instant::now().checked_add(timeout),
+            key: None,
+        }
+    }
+}
+
+impl Future for Sleep {
+    type Output = ();
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+        let Some(deadline) = self.deadline else {
+            return Poll::Pending;
+        };
+        if Instant::now() >= deadline {
+            return Poll::Ready(());
+        }
+        if let Some(key) = self.key {
+            // Still registered: the timer only removes it once the deadline has passed.
+            if let Some(waker) = TIMER.sleeps.lock().get_mut(&key) {
+                waker.clone_from(cx.waker());
+                return Poll::Pending;
+            }
+        }
+        self.key = Some(TIMER.register(deadline, cx.waker()));
+        Poll::Pending
+    }
+}
+
+impl Drop for Sleep {
+    fn drop(&mut self) {
+        if let Some(key) = self.key {
+            TIMER.sleeps.lock().remove(&key);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Sleep;
+    use std::future::Future;
+    use std::task::{Context, Waker};
+    use std::time::{Duration, Instant};
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+    async fn test_sleeps_wake_in_order() {
+        let start = Instant::now();
+        let long = tokio::spawn(Sleep::new(Duration::from_millis(60)));
+        Sleep::new(Duration::from_millis(20)).await;
+        let elapsed = start.elapsed();
+        assert!(elapsed >= Duration::from_millis(20));
+        assert!(!long.is_finished());
+        long.await.unwrap();
+        assert!(start.elapsed() >= Duration::from_millis(60));
+        // Too long to be represented: it never completes.
+        let mut never = Box::pin(Sleep::new(Duration::MAX));
+        let mut cx = Context::from_waker(Waker::noop());
+        assert!(never.as_mut().poll(&mut cx).is_pending());
+    }
+}
@@ -0,0 +1,215 @@
+use crate::arc::Arc;
+use crate::mutex::Mutex;
+use crate::waker_list::{WakerList, WakerNode};
+use std::cell::{Cell, UnsafeCell};
+use std::future::Future;
+use std::pin::Pin;
+use std::task::{Context, Poll, Waker};
+
+/// Waits for a set of tasks to finish, like Go's sync.WaitGroup: the tasks are counted in
+/// with add, or by taking a token, and counted out with done, or by dropping their token.
+/// wait completes once the count is back to zero, so a task can wait for subtasks it keeps
+/// spawning without holding on to their JoinHandles.
+///
+/// A WaitGroup is a handle: clones share the same count. It can be reused once the count is
+/// back to zero.
+#[derive(Clone)]
+pub struct WaitGroup {
+    state: Arc<Mutex<State>>,
+}
+
+struct State {
+    count: usize,
+    waiters: WakerList,
+}
+
+impl WaitGroup {
+    pub fn new() -> WaitGroup {
+        WaitGroup {
+            state: Arc::new(Mutex::new(State {
+                count: 0,
+                waiters: WakerList::new(),
+            })),
+        }
+    }
+
+    /// Counts in `n` more tasks, each to be counted out by a call to done.
+    pub fn add(&self, n: usize) {
+        self.state.lock().count += n;
+    }
+
+    /// Counts out a task, waking the waiting tasks if it was the last one. Panics if the
+    /// count is already zero.
+    pub fn done(&self) {
+        let mut state = self.state.lock();
+        assert!(
+            state.count > 0,
+            "WaitGroup::done called more times than add"
+        );
+        state.count -= 1;
+        if state.count > 0 {
+            return;
+        }
+        let wakers: Vec<Waker> = std::iter::from_fn(|| state.waiters.pop_front()).collect();
+        drop(state);
+        wakers.into_iter().for_each(Waker::wake);
+    }
+
+    /// Counts in a task, which is counted out when the returned token is dropped, even if
+    /// the task panics or is cancelled.
+    pub fn token(&self) -> WaitGroupToken {
+        self.add(1);
+        WaitGroupToken {
+            group: self.clone(),
+        }
+    }
+
+    /// The number of tasks counted in and not out yet.
+    pub fn count(&self) -> usize {
+        self.state.lock().count
+    }
+
+    /// Waits until the count is zero.
+    pub async fn wait(&self) {
+        Wait {
+            group: self,
+            node: UnsafeCell::new(WakerNode::new(())),
+            queued: Cell::new(false),
+        }
+        .await
+    }
+}
+
+impl Default for WaitGroup {
+    fn default() -> Self {
+        WaitGroup::new()
+    }
+}
+
+impl std::fmt::Debug for WaitGroup {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("WaitGroup")
+            .field("count", &self.count())
+            .finish()
+    }
+}
+
+/// A task counted in a WaitGroup, counted out when dropped.
+#[derive(Debug)]
+pub struct WaitGroupToken {
+    group: WaitGroup,
+}
+
+impl Drop for WaitGroupToken {
+    fn drop(&mut self) {
+        self.group.done();
+    }
+}
+
+// The future of WaitGroup::wait.
+struct Wait<'a> {
+    group: &'a WaitGroup,
+    // In the waiter list of the group while queued.
+    node: UnsafeCell<WakerNode>,
+    queued: Cell<bool>,
+}
+
+// The node is only accessed under the lock of the waiter list.
+unsafe impl Send for Wait<'_> {}
+
+impl Future for Wait<'_> {
+    type Output = ();
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+        // The node must not move once queued, so only ever use a shared reference.
+        let this = self.into_ref().get_ref();
+        let mut state = this.group.state.lock();
+        let node = unsafe { &mut *this.node.get() };
+        if this.queued.get() {
+            if node.is_notified() {
+                this.queued.set(false);
+                return Poll::Ready(());
+            }
+            node.set_waker(cx.waker());
+            return Poll::Pending;
+        }
+        if state.count == 0 {
+            return Poll::Ready(());
+        }
+        node.set_waker(cx.waker());
+        unsafe { state.waiters.push_back(node) };
+        this.queued.set(true);
+        Poll::Pending
+    }
+}
+
+impl Drop for Wait<'_> {
+    fn drop(&mut self) {
+        if !self.queued.get() {
+            return;
+        }
+        let mut state = self.group.state.lock();
+        if unsafe { !(*self.node.get()).is_notified() } {
+            unsafe { state.waiters.remove(self.node.get()) };
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::WaitGroup;
+    use crate::arc::Arc;
+    use std::future::Future;
+    use std::pin::pin;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::task::{Context, Waker};
+    use std::time::Duration;
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+    async fn test_wait_for_subtasks() {
+        let group = WaitGroup::new();
+        let finished = Arc::new(AtomicUsize::new(0));
+        for i in 0..8 {
+            let token = group.token();
+            let finished = finished.clone();
+            tokio::spawn(async move {
+                tokio::time::sleep(Duration::from_millis(i * 5)).await;
+                finished.fetch_add(1, Ordering::Relaxed);
+                drop(token);
+            });
+        }
+        group.wait().await;
+        assert_eq!(finished.load(Ordering::Relaxed), 8);
+        assert_eq!(group.count(), 0);
+    }
+
+    #[test]
+    fn test_add_and_done() {
+        let group = WaitGroup::new();
+        let mut cx = Context::from_waker(Waker::noop());
+        assert!(pin!(group.wait()).poll(&mut cx).is_ready());
+        group.add(2);
+        let mut first = pin!(group.wait());
+        let mut second = Box::pin(group.wait());
+        assert!(first.as_mut().poll(&mut cx).is_pending());
+        assert!(second.as_mut().poll(&mut cx).is_pending());
+        drop(second);
+        group.done();
+        assert!(first.as_mut().poll(&mut cx).is_pending());
+        group.clone().done();
+        assert!(first.as_mut().poll(&mut cx).is_ready());
+        // Reused once back to zero.
+        let token = group.token();
+        assert_eq!(format!("{group:?}"), "WaitGroup { count: 1 }");
+        let mut again = pin!(group.wait());
+        assert!(again.as_mut().poll(&mut cx).is_pending());
+        drop(token);
+        assert!(again.as_mut().poll(&mut cx).is_ready());
+    }
+
+    #[test]
+    #[should_panic(expected = "more times than add")]
+    fn test_done_without_add() {
+        WaitGroup::new().done();
+    }
+}
@@ -0,0 +1,131 @@
+use std::marker::PhantomPinned;
+use std::ptr;
+use std::task::Waker;
+
+/// The tasks waiting on an async primitive, in FIFO order. The list is intrusive: each node
+/// lives in the future of its task, which is pinned while the node is in the list, so
+/// waiting allocates nothing. The list and its nodes are only touched under the lock of the
+/// primitive owning the list. A node can carry a value for the primitive, e.g. the number of
+/// permits a task waits for.
+pub(crate) struct WakerList<T = ()> {
+    head: *mut WakerNode<T>,
+    tail: *mut WakerNode<T>,
+}
+
+// The nodes are only accessed under the lock of the list.
+unsafe impl<T: Send> Send for WakerList<T> {}
+
+/// A task's entry in a WakerList.
+pub(crate) struct WakerNode<T = ()> {
+    value: T,
+    waker: Option<Waker>,
+    linked: bool,
+    // Set when the node is taken off the list by pop_front: the task got what it waited for.
+    notified: bool,
+    prev: *mut WakerNode<T>,
+    next: *mut WakerNode<T>,
+    _pinned: PhantomPinned,
+}
+
+impl<T> WakerNode<T> {
+    pub(crate) const fn new(value: T) -> Self {
+        WakerNode {
+            value,
+            waker: None,
+            linked: false,
+            notified: false,
+            prev: ptr::null_mut(),
+            next: ptr::null_mut(),
+            _pinned: PhantomPinned,
+        }
+    }
+
+    pub(crate) fn value(&self) -> &T {
+        &self.value
+    }
+
+    pub(crate) fn is_linked(&self) -> bool {
+        self.linked
+    }
+
+    pub(crate) fn is_notified(&self) -> bool {
+        self.notified
+    }
+
+    /// Keeps the waker to wake the task with, unless the one kept would wake it already.
+    pub(crate) fn set_waker(&mut self, waker: &Waker) {
+        if !self.waker.as_ref().is_some_and(|w| w.will_wake(waker)) {
+            self.waker = Some(waker.clone());
+        }
+    }
+}
+
+impl<T> WakerList<T> {
+    pub(crate) const fn new() -> Self {
+        WakerList {
+            head: ptr::null_mut(),
+            tail: ptr::null_mut(),
+        }
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.head.is_null()
+    }
+
+    /// Adds the node at the end of the list.
+    ///
+    /// # Safety
+    /// The node must not be in a list, and must stay in place until it is removed.
+    pub(crate) unsafe fn push_back(&mut self, node: *mut WakerNode<T>) {
+        unsafe {
+            debug_assert!(!(*node).linked);
+            (*node).linked = true;
+            (*node).notified = false;
+            (*node).prev = self.tail;
+            (*node).next = ptr::null_mut();
+            match self.tail.as_mut() {
+                Some(tail) => tail.next = node,
+                None => self.head = node,
+            }
+        }
+        self.tail = node;
+    }
+
+    /// Removes the node from the list, if it is still in it.
+    ///
+    /// # Safety
+    /// The node must not be in another list.
+    pub(crate) unsafe fn remove(&mut self, node: *mut WakerNode<T>) {
+        let node = unsafe { &mut *node };
+        if !node.linked {
+            return;
+        }
+        match unsafe { node.prev.as_mut() } {
+            Some(prev) => prev.next = node.next,
+            None => self.head = node.next,
+        }
+        match unsafe { node.next.as_mut() } {
+            Some(next) => next.prev = node.prev,
+            None => self.tail = node.prev,
+        }
+        node.linked = false;
+    }
+
+    /// Returns the value of the first node.
+    pub(crate) fn front(&self) -> Option<&T> {
+        unsafe { self.head.as_ref() }.map(|node| &node.value)
+    }
+
+    pub(crate) fn front_mut(&mut self) -> Option<&mut T> {
+        unsafe { self.head.as_mut() }.map(|node| &mut node.value)
+    }
+
+    /// Takes the first node off the list, marking it notified, and returns the waker of its
+    /// task. The caller wakes it once it has released the lock of the list.
+    pub(crate) fn pop_front(&mut self) -> Option<Waker> {
+        let node = unsafe { self.head.as_mut()? };
+        unsafe { self.remove(node) };
+        node.notified = true;
+        node.waker.take()
+    }
+}
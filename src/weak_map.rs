@@ -0,0 +1,249 @@
+use crate::rc::{Rc, Weak};
+use std::borrow::Borrow;
+use std::collections::HashMap;
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hash};
+
+/// A hash map whose keys are held through Weak pointers, so being a key doesn't keep a value alive.
+/// Once the last Rc to a key is dropped the entry is considered gone: it's skipped by lookups and
+/// its memory is reclaimed the next time the map purges expired entries, which insert does
+/// automatically every time the number of stored entries has doubled.
+/// Useful to attach extra data to objects owned elsewhere (e.g. observers or per-node metadata).
+pub struct WeakKeyHashMap<K, V> {
+    // Keys can't be hashed anymore once they are dropped, so entries are grouped by the hash
+    // computed at insertion instead of being stored in a HashMap<Weak<K>, V>.
+    buckets: HashMap<u64, Vec<(Weak<K>, V)>>,
+    hasher: RandomState,
+    // Number of entries stored, including expired ones which have not been purged yet.
+    stored: usize,
+    purge_at: usize,
+}
+
+impl<K: Hash + Eq, V> WeakKeyHashMap<K, V> {
+    pub fn new() -> Self {
+        WeakKeyHashMap {
+            buckets: HashMap::new(),
+            hasher: RandomState::new(),
+            stored: 0,
+            purge_at: 8,
+        }
+    }
+
+    /// Inserts a value for the key, returning the previous value if the key was already present.
+    pub fn insert(&mut self, key: &Rc<K>, value: V) -> Option<V> {
+        if let Some(old) = self.get_mut(key) {
+            return Some(std::mem::replace(old, value));
+        }
+
+        if self.stored >= self.purge_at {
+            self.remove_expired();
+            self.purge_at = (self.stored * 2).max(8);
+        }
+        let hash = self.hasher.hash_one(&**key);
+        self.buckets
+            .entry(hash)
+            .or_default()
+            .push((Rc::downgrade(key), value));
+        self.stored += 1;
+        None
+    }
+
+    fn position(&self, key: &K) -> Option<(u64, usize)> {
+        let hash = self.hasher.hash_one(key);
+        let bucket = self.buckets.get(&hash)?;
+        let index = bucket
+            .iter()
+            .position(|(k, _)| k.upgrade().is_some_and(|k| *k == *key))?;
+        Some((hash, index))
+    }
+
+    pub fn get(&self, key: &K) -> Option<&V> {
+        let (hash, index) = self.position(key)?;
+        Some(&self.buckets[&hash][index].1)
+    }
+
+    pub fn get_mut(&mut self, key: &K) -> Option<&mut V> {
+        let (hash, index) = self.position(key)?;
+        Some(&mut self.buckets.get_mut(&hash)?[index].1)
+    }
+
+    pub fn contains_key(&self, key: &K) -> bool {
+        self.position(key).is_some()
+    }
+
+    pub fn remove(&mut self, key: &K) -> Option<V> {
+        let (hash, index) = self.position(key)?;
+        let bucket = self.buckets.get_mut(&hash)?;
+        let (_, value) = bucket.swap_remove(index);
+        if bucket.is_empty() {
+            self.buckets.remove(&hash);
+        }
+        self.stored -= 1;
+        Some(value)
+    }
+
+    /// Drops the entries whose key has been freed.
+    pub fn remove_expired(&mut self) {
+        self.buckets.retain(|_, bucket| {
+            bucket.retain(|(k, _)| k.upgrade().is_some());
+            !bucket.is_empty()
+        });
+        self.stored = self.buckets.values().map(Vec::len).sum();
+    }
+
+    /// Returns the number of entries whose key is still alive.
+    pub fn len(&self) -> usize {
+        self.iter().count()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Iterates over the entries whose key is still alive.
+    pub fn iter(&self) -> impl Iterator<Item = (Rc<K>, &V)> {
+        self.buckets
+            .values()
+            .flatten()
+            .filter_map(|(k, v)| Some((k.upgrade()?, v)))
+    }
+}
+
+impl<K: Hash + Eq, V> Default for WeakKeyHashMap<K, V> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// A hash map whose values are held through Weak pointers, so the map doesn't keep them alive.
+/// An entry is considered gone once the last Rc to its value is dropped, and lookups return None
+/// for it. Typically used as a cache handing out shared values for as long as someone uses them.
+pub struct WeakValueHashMap<K, V> {
+    map: HashMap<K, Weak<V>>,
+    purge_at: usize,
+}
+
+impl<K: Hash + Eq, V> WeakValueHashMap<K, V> {
+    pub fn new() -> Self {
+        WeakValueHashMap {
+            map: HashMap::new(),
+            purge_at: 8,
+        }
+    }
+
+    /// Inserts a value for the key, returning the previous value if it was still alive.
+    pub fn insert(&mut self, key: K, value: &Rc<V>) -> Option<Rc<V>> {
+        if self.map.len() >= self.purge_at {
+            self.remove_expired();
+            self.purge_at = (self.map.len() * 2).max(8);
+        }
+        self.map
+            .insert(key, Rc::downgrade(value))
+            .and_then(|old| old.upgrade())
+    }
+
+    pub fn get<Q>(&self, key: &Q) -> Option<Rc<V>>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        self.map.get(key)?.upgrade()
+    }
+
+    pub fn contains_key<Q>(&self, key: &Q) -> bool
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        self.get(key).is_some()
+    }
+
+    pub fn remove<Q>(&mut self, key: &Q) -> Option<Rc<V>>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        self.map.remove(key)?.upgrade()
+    }
+
+    /// Drops the entries whose value has been freed.
+    pub fn remove_expired(&mut self) {
+        self.map.retain(|_, v| v.upgrade().is_some());
+    }
+
+    /// Returns the number of entries whose value is still alive.
+    pub fn len(&self) -> usize {
+        self.iter().count()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Iterates over the entries whose value is still alive.
+    pub fn iter(&self) -> impl Iterator<Item = (&K, Rc<V>)> {
+        self.map.iter().filter_map(|(k, v)| Some((k, v.upgrade()?)))
+    }
+}
+
+impl<K: Hash + Eq, V> Default for WeakValueHashMap<K, V> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{WeakKeyHashMap, WeakValueHashMap};
+    use crate::rc::Rc;
+
+    #[test]
+    fn test_weak_key_map() {
+        let a = Rc::new(String::from("a"));
+        let b = Rc::new(String::from("b"));
+        let mut map = WeakKeyHashMap::new();
+        assert_eq!(map.insert(&a, 1), None);
+        assert_eq!(map.insert(&b, 2), None);
+        assert_eq!(map.insert(&a, 3), Some(1));
+        assert_eq!(map.get(&String::from("a")), Some(&3));
+        assert_eq!(map.len(), 2);
+
+        // The map doesn't keep the key alive.
+        assert_eq!(Rc::strong_count(&a), 1);
+        drop(a);
+        assert_eq!(map.get(&String::from("a")), None);
+        assert_eq!(map.len(), 1);
+
+        assert_eq!(map.remove(&b), Some(2));
+        assert!(map.is_empty());
+    }
+
+    #[test]
+    fn test_weak_key_map_purges_expired() {
+        let mut map = WeakKeyHashMap::new();
+        for i in 0..1000 {
+            map.insert(&Rc::new(i), i);
+        }
+        let alive = Rc::new(-1);
+        map.insert(&alive, -1);
+        assert_eq!(map.len(), 1);
+        // Expired entries are purged as the map grows, so it doesn't keep all 1000 of them.
+        assert!(map.stored < 16);
+    }
+
+    #[test]
+    fn test_weak_value_map() {
+        let mut cache = WeakValueHashMap::new();
+        let value = Rc::new(vec![1, 2, 3]);
+        assert!(cache.insert(String::from("key"), &value).is_none());
+        assert!(Rc::ptr_eq(&cache.get("key").unwrap(), &value));
+        assert_eq!(cache.len(), 1);
+
+        drop(value);
+        assert!(cache.get("key").is_none());
+        assert!(!cache.contains_key("key"));
+        assert!(cache.is_empty());
+        cache.remove_expired();
+        assert!(cache.map.is_empty());
+    }
+}
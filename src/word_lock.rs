@@ -0,0 +1,204 @@
+use crate::lock_api::{self, GuardSend};
+use crate::parking;
+use std::hint::spin_loop;
+use std::sync::atomic::{AtomicU8, Ordering};
+
+const LOCKED: u8 = 1;
+// Some threads may be parked on the lock.
+const PARKED: u8 = 2;
+
+// Rounds of spinning before a thread parks itself.
+const SPIN_LIMIT: u32 = 100;
+
+/// A mutex whose state takes a single byte, like parking_lot's: the threads waiting for it
+/// are parked in a global table keyed by the address of the lock (see the parking module),
+/// so the lock itself only records whether it is held, and whether threads are parked on it.
+/// That makes it cheap to put a lock in every element of a large collection.
+pub type WordLock<T> = lock_api::Mutex<RawWordLock, T>;
+
+pub type WordLockGuard<'a, T> = lock_api::MutexGuard<'a, RawWordLock, T>;
+
+/// The raw lock of WordLock.
+pub struct RawWordLock {
+    state: AtomicU8,
+}
+
+unsafe impl lock_api::RawMutex for RawWordLock {
+    const INIT: RawWordLock = RawWordLock {
+        state: AtomicU8::new(0),
+    };
+
+    type GuardMarker = GuardSend;
+
+    fn lock(&self) {
+        if self
+            .state
+            .compare_exchange(0, LOCKED, Ordering::Acquire, Ordering::Relaxed)
+            .is_err()
+        {
+            self.lock_slow();
+        }
+    }
+
+    fn try_lock(&self) -> bool {
+        let state = self.state.load(Ordering::Relaxed);
+        state & LOCKED == 0
+            && self
+                .state
+                .compare_exchange(state, state | LOCKED, Ordering::Acquire, Ordering::Relaxed)
+                .is_ok()
+    }
+
+    unsafe fn unlock(&self) {
+        if self
+            .state
+            .compare_exchange(LOCKED, 0, Ordering::Release, Ordering::Relaxed)
+            .is_ok()
+        {
+            return;
+        }
+        // Threads are parked: wake one up, and keep the PARKED bit if others remain. The
+        // state is updated under the bucket lock, so a thread that is about to park either
+        // sees the lock released or is counted as remaining.
+        parking::unpark_one(self.key(), |more_parked| {
+            let state = if more_parked { PARKED } else { 0 };
+            self.state.store(state, Ordering::Release);
+        });
+    }
+
+    fn is_locked(&self) -> bool {
+        self.state.load(Ordering::Relaxed) & LOCKED != 0
+    }
+}
+
+impl RawWordLock {
+    fn lock_slow(&self) {
+        let mut spins = 0;
+        let mut state = self.state.load(Ordering::Relaxed);
+        loop {
+            // Take the lock if it is free, keeping the PARKED bit for the other threads.
+            if state & LOCKED == 0 {
+                match self.state.compare_exchange_weak(
+                    state,
+                    state | LOCKED,
+                    Ordering::Acquire,
+                    Ordering::Relaxed,
+                ) {
+                    Ok(_) => return,
+                    Err(s) => state = s,
+                }
+                continue;
+            }
+            // Spin a bit if nobody is parked yet, the lock may be released soon.
+            if state & PARKED == 0 && spins < SPIN_LIMIT {
+                spins += 1;
+                spin_loop();
+                state = self.state.load(Ordering::Relaxed);
+                continue;
+            }
+            // Tell the unlock to look for parked threads, then park.
+            if state & PARKED == 0
+                && let Err(s) = self.state.compare_exchange_weak(
+                    state,
+                    state | PARKED,
+                    Ordering::Relaxed,
+                    Ordering::Relaxed,
+                )
+            {
+                state = s;
+                continue;
+            }
+            parking::park(self.key(), || {
+                self.state.load(Ordering::Relaxed) == LOCKED | PARKED
+            });
+            spins = 0;
+            state = self.state.load(Ordering::Relaxed);
+        }
+    }
+
+    fn key(&self) -> usize {
+        self as *const RawWordLock as usize
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{LOCKED, PARKED, WordLock};
+    use std::mem::size_of;
+    use std::sync::Arc;
+    use std::sync::atomic::Ordering;
+    use std::thread;
+    use std::time::{Duration, Instant, SystemTime};
+
+    #[test]
+    fn test_word_lock_is_one_byte() {
+        assert_eq!(size_of::<super::RawWordLock>(), 1);
+        #[cfg(not(feature = "deadlock_detection"))]
+        assert_eq!(size_of::<WordLock<u8>>(), 2);
+    }
+
+    #[test]
+    fn test_lock_and_try_lock() {
+        let lock = WordLock::new(0);
+        let guard = lock.lock();
+        assert!(lock.try_lock().is_none());
+        assert!(lock.is_locked());
+        drop(guard);
+        assert!(!lock.is_locked());
+        *lock.try_lock().unwrap() += 1;
+        assert_eq!(lock.into_inner(), 1);
+    }
+
+    #[test]
+    fn test_waiters_are_parked_and_woken() {
+        let lock = Arc::new(WordLock::new(0));
+        let guard = lock.lock();
+        let handles: Vec<_> = (0..4)
+            .map(|_| {
+                let lock = Arc::clone(&lock);
+                thread::spawn(move || *lock.lock() += 1)
+            })
+            .collect();
+        // Wait for a waiter to park, rather than for a fixed time.
+        let deadline = Instant::now() + Duration::from_secs(10);
+        while lock.raw.state.load(Ordering::Relaxed) & PARKED == 0 {
+            assert!(Instant::now() < deadline, "no waiter parked");
+            thread::sleep(Duration::from_millis(1));
+        }
+        assert_eq!(lock.raw.state.load(Ordering::Relaxed), LOCKED | PARKED);
+        drop(guard);
+        for h in handles {
+            h.join().unwrap();
+        }
+        // The last thread to wake up cleared the PARKED bit.
+        let guard = lock.lock();
+        assert_eq!(*guard, 4);
+        assert_eq!(lock.raw.state.load(Ordering::Relaxed), LOCKED);
+    }
+
+    #[test]
+    fn test_word_lock_contention_increment() {
+        let time = SystemTime::now();
+        // Many locks in one slice, which all share the global parking table.
+        let locks: Arc<Vec<WordLock<usize>>> = Arc::new((0..16).map(WordLock::new).collect());
+        let handles: Vec<_> = (0..8)
+            .map(|t| {
+                let locks = Arc::clone(&locks);
+                thread::spawn(move || {
+                    for i in 0..20_000 {
+                        *locks[(t + i) % 16].lock() += 1;
+                    }
+                })
+            })
+            .collect();
+        for h in handles {
+            h.join().unwrap();
+        }
+        let total: usize = locks.iter().map(|lock| *lock.lock()).sum();
+        assert_eq!(total, (0..16).sum::<usize>() + 160_000);
+        println!(
+            "Time taken in WordLock: {}ms",
+            time.elapsed().unwrap().as_millis()
+        );
+    }
+}